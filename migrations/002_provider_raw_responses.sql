@@ -0,0 +1,3 @@
+-- Redacted provider responses, exposed only through the admin debug endpoint
+
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS provider_raw_response JSONB;
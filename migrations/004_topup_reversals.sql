@@ -0,0 +1,7 @@
+-- Top-up reversals
+
+ALTER TABLE wallets ADD COLUMN IF NOT EXISTS allow_overdraft BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE wallet_transactions ADD COLUMN IF NOT EXISTS reversed_amount DECIMAL(20, 4) NOT NULL DEFAULT 0;
+ALTER TABLE wallet_transactions ADD COLUMN IF NOT EXISTS reversed_at TIMESTAMPTZ;
+ALTER TABLE wallet_transactions ADD COLUMN IF NOT EXISTS reverses_id UUID REFERENCES wallet_transactions(id);
@@ -0,0 +1,6 @@
+-- Card expiry tracking
+
+ALTER TABLE payment_methods ADD COLUMN IF NOT EXISTS exp_month SMALLINT;
+ALTER TABLE payment_methods ADD COLUMN IF NOT EXISTS exp_year SMALLINT;
+ALTER TABLE payment_methods ADD COLUMN IF NOT EXISTS expiry_notified_at TIMESTAMPTZ;
+ALTER TABLE payment_methods ADD COLUMN IF NOT EXISTS expired_at TIMESTAMPTZ;
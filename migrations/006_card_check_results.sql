@@ -0,0 +1,5 @@
+-- Acquirer verification results from the latest charge attempt
+
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS avs_result VARCHAR(20);
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS cvv_result VARCHAR(20);
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS network_response_code VARCHAR(20);
@@ -0,0 +1,19 @@
+-- Recurring billing subscriptions
+
+CREATE TABLE IF NOT EXISTS subscriptions (
+    id UUID PRIMARY KEY,
+    customer_id UUID NOT NULL,
+    plan_id VARCHAR(100) NOT NULL,
+    amount DECIMAL(19, 4) NOT NULL,
+    currency VARCHAR(3) NOT NULL,
+    billing_cycle VARCHAR(20) NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'active',
+    current_period_start DATE NOT NULL,
+    current_period_end DATE NOT NULL,
+    cancel_at_period_end BOOLEAN NOT NULL DEFAULT FALSE,
+    metadata JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_subscriptions_customer ON subscriptions(customer_id);
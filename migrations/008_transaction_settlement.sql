@@ -0,0 +1,6 @@
+-- Provider settlement tracking for reconciliation and payouts
+
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS settled_at TIMESTAMPTZ;
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS settlement_id VARCHAR(100);
+
+CREATE INDEX IF NOT EXISTS idx_transactions_unsettled ON transactions(currency) WHERE settled_at IS NULL;
@@ -0,0 +1,6 @@
+-- Lifetime revenue and payment-health counters for subscriptions
+
+ALTER TABLE subscriptions ADD COLUMN IF NOT EXISTS total_paid DECIMAL(19, 4) NOT NULL DEFAULT 0;
+ALTER TABLE subscriptions ADD COLUMN IF NOT EXISTS renewal_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE subscriptions ADD COLUMN IF NOT EXISTS consecutive_failures INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE subscriptions ADD COLUMN IF NOT EXISTS last_payment_failed_at TIMESTAMPTZ;
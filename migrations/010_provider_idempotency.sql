@@ -0,0 +1,5 @@
+-- Provider-side idempotency for charge attempts, plus what is needed to resend one
+
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS provider_idempotency_key VARCHAR(64);
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS callback_url TEXT;
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS statement_descriptor VARCHAR(22);
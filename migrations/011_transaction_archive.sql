@@ -0,0 +1,13 @@
+-- Transactions exported to cold storage. A row here means the transaction is archived;
+-- `record` holds the full export only when rows were moved out of the hot tables.
+
+CREATE TABLE IF NOT EXISTS transactions_archive (
+    id UUID PRIMARY KEY,
+    reference VARCHAR(100) NOT NULL,
+    export_key TEXT NOT NULL,
+    record JSONB,
+    transaction_created_at TIMESTAMPTZ NOT NULL,
+    archived_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_transactions_archive_export_key ON transactions_archive(export_key);
@@ -0,0 +1,20 @@
+-- Per-currency wallet balances: a wallet holds one balance per currency it has been
+-- funded in. wallets.currency remains the default for requests that name none.
+
+CREATE TABLE IF NOT EXISTS wallet_balances (
+    wallet_id UUID NOT NULL REFERENCES wallets(id),
+    currency VARCHAR(3) NOT NULL,
+    balance DECIMAL(20, 4) NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (wallet_id, currency)
+);
+
+INSERT INTO wallet_balances (wallet_id, currency, balance, updated_at)
+SELECT id, COALESCE(currency, 'NGN'), COALESCE(balance, 0), updated_at FROM wallets
+ON CONFLICT (wallet_id, currency) DO NOTHING;
+
+ALTER TABLE wallets DROP COLUMN IF EXISTS balance;
+
+ALTER TABLE wallet_transactions ADD COLUMN IF NOT EXISTS currency VARCHAR(3);
+UPDATE wallet_transactions wt SET currency = COALESCE(w.currency, 'NGN') FROM wallets w WHERE w.id = wt.wallet_id AND wt.currency IS NULL;
+ALTER TABLE wallet_transactions ALTER COLUMN currency SET NOT NULL;
@@ -0,0 +1,24 @@
+-- Double-entry ledger: every balance change is posted as matching debit and credit rows
+
+CREATE TABLE IF NOT EXISTS ledger_entries (
+    id UUID PRIMARY KEY,
+    account VARCHAR(100) NOT NULL,
+    direction VARCHAR(6) NOT NULL CHECK (direction IN ('debit', 'credit')),
+    amount DECIMAL(20, 4) NOT NULL CHECK (amount >= 0),
+    currency VARCHAR(3) NOT NULL,
+    -- The wallet transaction, transfer or refund that caused the posting
+    reference VARCHAR(100) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_ledger_entries_account ON ledger_entries(account, created_at);
+CREATE INDEX IF NOT EXISTS idx_ledger_entries_reference ON ledger_entries(reference);
+
+-- Existing balances are opened against equity so wallet accounts reconcile from day one
+INSERT INTO ledger_entries (id, account, direction, amount, currency, reference, created_at)
+SELECT gen_random_uuid(), 'wallet:' || wallet_id, CASE WHEN balance > 0 THEN 'credit' ELSE 'debit' END, ABS(balance), currency, 'opening:' || wallet_id, NOW()
+FROM wallet_balances WHERE balance <> 0;
+
+INSERT INTO ledger_entries (id, account, direction, amount, currency, reference, created_at)
+SELECT gen_random_uuid(), 'equity:opening_balances', CASE WHEN balance > 0 THEN 'debit' ELSE 'credit' END, ABS(balance), currency, 'opening:' || wallet_id, NOW()
+FROM wallet_balances WHERE balance <> 0;
@@ -0,0 +1,10 @@
+-- Provider webhook events already applied, so redeliveries are acknowledged without
+-- applying them again. Rows are written in the same transaction as the event's effect.
+
+CREATE TABLE IF NOT EXISTS processed_webhook_events (
+    provider VARCHAR(50) NOT NULL,
+    event_id VARCHAR(255) NOT NULL,
+    event_type VARCHAR(100) NOT NULL,
+    processed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (provider, event_id)
+);
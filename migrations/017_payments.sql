@@ -0,0 +1,20 @@
+-- Payment aggregates saved through PaymentRepository. Ids are the aggregate's own
+-- PaymentId strings; events raised before a save go to event_outbox in the same
+-- transaction as the row.
+
+CREATE TABLE IF NOT EXISTS payments (
+    id VARCHAR(64) PRIMARY KEY,
+    customer_id VARCHAR(255) NOT NULL,
+    amount DECIMAL(20, 4) NOT NULL,
+    currency VARCHAR(3) NOT NULL,
+    status VARCHAR(20) NOT NULL,
+    payment_method JSONB,
+    description TEXT,
+    metadata JSONB NOT NULL DEFAULT '{}',
+    refunded_amount DECIMAL(20, 4) NOT NULL DEFAULT 0,
+    failure_reason TEXT,
+    created_at TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_payments_customer ON payments(customer_id);
@@ -0,0 +1,4 @@
+-- Optimistic concurrency for payments: each save updates only the version it loaded
+-- and bumps it, so a stale save changes no rows and is rejected.
+
+ALTER TABLE payments ADD COLUMN IF NOT EXISTS version BIGINT NOT NULL DEFAULT 1;
@@ -0,0 +1,23 @@
+-- Merchant API keys. Only the SHA-256 of a key is stored; a key is shown once, when issued.
+-- Transactions and idempotency keys are scoped to the merchant whose key created them.
+
+CREATE TABLE IF NOT EXISTS api_keys (
+    id UUID PRIMARY KEY,
+    merchant_id UUID NOT NULL,
+    key_hash CHAR(64) NOT NULL UNIQUE,
+    -- The first characters of the key, so a merchant can tell their keys apart
+    prefix VARCHAR(16) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_api_keys_merchant ON api_keys(merchant_id);
+
+-- Transactions from before keys existed have no merchant and are not listed to anyone
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS merchant_id UUID;
+CREATE INDEX IF NOT EXISTS idx_transactions_merchant_created ON transactions(merchant_id, created_at DESC, id DESC);
+
+-- Two merchants may pick the same Idempotency-Key; earlier keys belong to the nil merchant
+ALTER TABLE idempotency_keys ADD COLUMN IF NOT EXISTS merchant_id UUID NOT NULL DEFAULT '00000000-0000-0000-0000-000000000000';
+ALTER TABLE idempotency_keys DROP CONSTRAINT IF EXISTS idempotency_keys_pkey;
+ALTER TABLE idempotency_keys ADD PRIMARY KEY (merchant_id, key);
@@ -0,0 +1,2 @@
+-- When the provider confirmed or rejected a refund; NULL while it is pending
+ALTER TABLE refunds ADD COLUMN IF NOT EXISTS processed_at TIMESTAMPTZ;
@@ -0,0 +1,17 @@
+-- At most one default payment method per customer, and one for every customer with any
+UPDATE payment_methods SET is_default = FALSE WHERE is_default IS NULL;
+ALTER TABLE payment_methods ALTER COLUMN is_default SET NOT NULL;
+
+-- Keep the newest of several defaults
+UPDATE payment_methods p SET is_default = FALSE
+WHERE p.is_default AND EXISTS (
+    SELECT 1 FROM payment_methods newer
+    WHERE newer.customer_id = p.customer_id AND newer.is_default AND (newer.created_at, newer.id) > (p.created_at, p.id)
+);
+
+-- Customers without one get their newest method
+UPDATE payment_methods p SET is_default = TRUE
+WHERE p.id = (SELECT id FROM payment_methods WHERE customer_id = p.customer_id ORDER BY created_at DESC, id DESC LIMIT 1)
+  AND NOT EXISTS (SELECT 1 FROM payment_methods d WHERE d.customer_id = p.customer_id AND d.is_default);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_payment_methods_one_default ON payment_methods(customer_id) WHERE is_default;
@@ -0,0 +1,7 @@
+-- Deleted payment methods are kept for the transactions and subscriptions that reference them
+
+ALTER TABLE payment_methods ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
+-- The stored method a subscription renews with; none means the default gateway's checkout
+ALTER TABLE subscriptions ADD COLUMN IF NOT EXISTS payment_method_id UUID REFERENCES payment_methods(id);
+CREATE INDEX IF NOT EXISTS idx_subscriptions_payment_method ON subscriptions(payment_method_id) WHERE payment_method_id IS NOT NULL;
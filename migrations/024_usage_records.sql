@@ -0,0 +1,15 @@
+-- Metered usage billed on top of a subscription's flat amount. A record is unbilled until
+-- a renewal claims it by setting billed_reference to that renewal's transaction reference.
+
+CREATE TABLE IF NOT EXISTS usage_records (
+    id UUID PRIMARY KEY,
+    subscription_id UUID NOT NULL REFERENCES subscriptions(id) ON DELETE CASCADE,
+    quantity BIGINT NOT NULL CHECK (quantity > 0),
+    -- In the subscription's currency, major units
+    unit_amount DECIMAL(19, 4) NOT NULL CHECK (unit_amount >= 0),
+    amount DECIMAL(19, 4) NOT NULL,
+    billed_reference VARCHAR(255),
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_usage_records_unbilled ON usage_records(subscription_id) WHERE billed_reference IS NULL;
@@ -0,0 +1,30 @@
+-- Invoice aggregates saved through InvoiceRepository: the breakdown behind a charge.
+-- Totals are stored for reporting; the aggregate recomputes them from the lines.
+
+CREATE TABLE IF NOT EXISTS invoices (
+    id VARCHAR(64) PRIMARY KEY,
+    subscription_id VARCHAR(64) NOT NULL,
+    -- The transaction that charges the invoice
+    reference VARCHAR(255) NOT NULL UNIQUE,
+    currency VARCHAR(3) NOT NULL,
+    subtotal DECIMAL(19, 4) NOT NULL,
+    tax_rate DECIMAL(9, 4) NOT NULL DEFAULT 0,
+    tax DECIMAL(19, 4) NOT NULL,
+    total DECIMAL(19, 4) NOT NULL,
+    -- draft or finalized; a finalized invoice is never rewritten
+    status VARCHAR(20) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    finalized_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_invoices_subscription ON invoices(subscription_id, created_at);
+
+CREATE TABLE IF NOT EXISTS invoice_lines (
+    invoice_id VARCHAR(64) NOT NULL REFERENCES invoices(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    description TEXT NOT NULL,
+    quantity BIGINT NOT NULL,
+    unit_amount DECIMAL(19, 4) NOT NULL,
+    amount DECIMAL(19, 4) NOT NULL,
+    PRIMARY KEY (invoice_id, position)
+);
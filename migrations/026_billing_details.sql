@@ -0,0 +1,5 @@
+-- Where a subscription's customer is billed, for tax on its renewal invoices. Invoice tax
+-- now comes from that address at finalization rather than one flat rate.
+
+ALTER TABLE subscriptions ADD COLUMN IF NOT EXISTS billing_details JSONB;
+ALTER TABLE invoices DROP COLUMN IF EXISTS tax_rate;
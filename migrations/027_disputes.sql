@@ -0,0 +1,22 @@
+-- Disputes (chargebacks) raised against a charge. While one is open the charge sits in
+-- `disputed`; transaction_status is what it was before, and what a won dispute restores.
+
+CREATE TABLE IF NOT EXISTS disputes (
+    id UUID PRIMARY KEY,
+    transaction_id UUID NOT NULL REFERENCES transactions(id),
+    -- The provider's id, when the dispute came in by webhook
+    provider_dispute_id VARCHAR(255) UNIQUE,
+    reason VARCHAR(255) NOT NULL,
+    status VARCHAR(50) NOT NULL DEFAULT 'open',
+    -- In the charge's currency, major units
+    amount DECIMAL(19, 4) NOT NULL CHECK (amount > 0),
+    evidence JSONB,
+    evidence_due_by TIMESTAMPTZ,
+    transaction_status VARCHAR(50) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    closed_at TIMESTAMPTZ
+);
+
+-- A charge has at most one dispute in progress
+CREATE UNIQUE INDEX IF NOT EXISTS idx_disputes_open ON disputes(transaction_id) WHERE status IN ('open', 'evidence_submitted');
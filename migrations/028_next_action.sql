@@ -0,0 +1,4 @@
+-- What the customer must do to complete a charge in requires_action (e.g. a 3DS redirect),
+-- kept so verify and confirm can hand it back. Cleared once the charge moves on.
+
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS next_action JSONB;
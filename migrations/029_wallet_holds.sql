@@ -0,0 +1,18 @@
+-- Funds reserved for a pending wallet-funded payment. A hold counts against what the
+-- wallet can spend (balance - held) without moving the balance itself; capturing it
+-- debits the balance, releasing it frees the funds.
+
+ALTER TABLE wallet_balances ADD COLUMN IF NOT EXISTS held DECIMAL(20, 4) NOT NULL DEFAULT 0 CHECK (held >= 0);
+
+CREATE TABLE IF NOT EXISTS wallet_holds (
+    id UUID PRIMARY KEY,
+    wallet_id UUID NOT NULL REFERENCES wallets(id),
+    currency VARCHAR(3) NOT NULL,
+    amount DECIMAL(20, 4) NOT NULL CHECK (amount > 0),
+    status VARCHAR(20) NOT NULL DEFAULT 'held' CHECK (status IN ('held', 'captured', 'released')),
+    reference VARCHAR(100),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    resolved_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_wallet_holds_wallet ON wallet_holds(wallet_id, created_at DESC);
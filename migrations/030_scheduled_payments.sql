@@ -0,0 +1,7 @@
+-- Payments initiated for a later time. They wait in `scheduled` with the request that
+-- created them, which the scheduler replays through initiation once scheduled_at passes.
+
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS scheduled_at TIMESTAMPTZ;
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS scheduled_request JSONB;
+
+CREATE INDEX IF NOT EXISTS idx_transactions_scheduled ON transactions(scheduled_at) WHERE status = 'scheduled';
@@ -0,0 +1,24 @@
+-- A total split into installments. Each installment is a scheduled transaction, which
+-- the scheduler charges when it falls due; its row here names it by reference.
+
+CREATE TABLE IF NOT EXISTS payment_plans (
+    id UUID PRIMARY KEY,
+    merchant_id UUID NOT NULL,
+    customer_id UUID,
+    customer_email VARCHAR(255) NOT NULL,
+    total DECIMAL(20, 4) NOT NULL CHECK (total > 0),
+    currency VARCHAR(3) NOT NULL,
+    billing_cycle VARCHAR(20) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_payment_plans_merchant ON payment_plans(merchant_id, created_at DESC);
+
+CREATE TABLE IF NOT EXISTS payment_plan_installments (
+    plan_id UUID NOT NULL REFERENCES payment_plans(id),
+    number INT NOT NULL CHECK (number > 0),
+    amount DECIMAL(20, 4) NOT NULL CHECK (amount > 0),
+    due_on DATE NOT NULL,
+    reference VARCHAR(100) NOT NULL UNIQUE,
+    PRIMARY KEY (plan_id, number)
+);
@@ -0,0 +1,10 @@
+-- Payments made with `capture: false`. The provider only authorizes them; they wait in
+-- `authorized` until captured, voided, or cancelled by the expiry worker once
+-- authorization_expires_at passes. A partial capture lowers `amount` to what was taken
+-- and keeps the original hold in authorized_amount.
+
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS manual_capture BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS authorized_amount DECIMAL(20, 4);
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS authorization_expires_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS idx_transactions_authorization_expiry ON transactions(authorization_expires_at) WHERE status = 'authorized';
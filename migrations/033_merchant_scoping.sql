@@ -0,0 +1,13 @@
+-- Wallets, payment methods, subscriptions and webhook endpoints belong to the merchant whose
+-- key created them, as transactions do. Rows from before this have no merchant and are not
+-- visible to anyone.
+
+ALTER TABLE wallets ADD COLUMN IF NOT EXISTS merchant_id UUID;
+ALTER TABLE payment_methods ADD COLUMN IF NOT EXISTS merchant_id UUID;
+ALTER TABLE subscriptions ADD COLUMN IF NOT EXISTS merchant_id UUID;
+ALTER TABLE webhook_endpoints ADD COLUMN IF NOT EXISTS merchant_id UUID;
+
+CREATE INDEX IF NOT EXISTS idx_wallets_merchant_customer ON wallets(merchant_id, customer_id);
+CREATE INDEX IF NOT EXISTS idx_payment_methods_merchant_customer ON payment_methods(merchant_id, customer_id);
+CREATE INDEX IF NOT EXISTS idx_subscriptions_merchant ON subscriptions(merchant_id);
+CREATE INDEX IF NOT EXISTS idx_webhook_endpoints_merchant ON webhook_endpoints(merchant_id);
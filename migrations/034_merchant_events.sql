@@ -0,0 +1,4 @@
+-- The merchant an outbox event belongs to. Its webhooks go only to that merchant's
+-- endpoints; events with no merchant, e.g. from before keys existed, go to none.
+
+ALTER TABLE event_outbox ADD COLUMN IF NOT EXISTS merchant_id UUID;
@@ -0,0 +1,5 @@
+-- An invoice belongs to its subscription's merchant, as the renewal transaction it
+-- breaks down does.
+
+ALTER TABLE invoices ADD COLUMN IF NOT EXISTS merchant_id UUID;
+CREATE INDEX IF NOT EXISTS idx_invoices_merchant ON invoices(merchant_id, created_at);
@@ -0,0 +1,10 @@
+-- Settings a merchant can have apart from the deployment's. A NULL column, or no row at
+-- all, means the deployment's setting applies.
+
+CREATE TABLE IF NOT EXISTS merchant_settings (
+    merchant_id UUID PRIMARY KEY,
+    -- Overrides REQUIRE_IDEMPOTENCY_KEY for the merchant's money-moving requests
+    require_idempotency_key BOOLEAN,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,3 @@
+-- Overrides MAX_REFUNDS_PER_TRANSACTION for the merchant's transactions when set
+
+ALTER TABLE merchant_settings ADD COLUMN IF NOT EXISTS max_refunds_per_transaction BIGINT;
@@ -0,0 +1,16 @@
+-- Merchants' late-fee terms, per currency: a daily rate in percent of the invoice total,
+-- capped at max_fee in all. Invoices whose charge failed accrue the fee daily; a finalized
+-- invoice's lines never change, so the fee is kept beside them.
+
+CREATE TABLE IF NOT EXISTS late_fee_policies (
+    merchant_id UUID NOT NULL,
+    currency VARCHAR(3) NOT NULL,
+    daily_rate DECIMAL(9, 4) NOT NULL CHECK (daily_rate >= 0),
+    max_fee DECIMAL(19, 4) NOT NULL CHECK (max_fee >= 0),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (merchant_id, currency)
+);
+
+ALTER TABLE invoices ADD COLUMN IF NOT EXISTS late_fee DECIMAL(19, 4) NOT NULL DEFAULT 0;
+-- The day the fee was last brought up to date, so a scan accrues each invoice once a day
+ALTER TABLE invoices ADD COLUMN IF NOT EXISTS late_fee_accrued_on DATE;
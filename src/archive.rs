@@ -0,0 +1,158 @@
+//! Cold-storage export of old transactions
+//!
+//! Archived batches are newline-delimited JSON, one record per line, written to an
+//! `ArchiveStore`. Batch keys are derived from the records they contain, so a run
+//! that crashes after uploading can be repeated without duplicating or losing a batch.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::crypto;
+
+/// Statuses a transaction never leaves. Anything else may still change and is never archived.
+pub const TERMINAL_STATUSES: &[&str] = &["succeeded", "failed", "cancelled", "refunded", "partially_refunded", "charged_back"];
+
+pub fn is_terminal(status: &str) -> bool { TERMINAL_STATUSES.contains(&status) }
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveError { Io(String), Encoding(String), InvalidKey(String) }
+impl std::error::Error for ArchiveError {}
+impl std::fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self { Self::Io(m) => write!(f, "Archive I/O error: {}", m), Self::Encoding(m) => write!(f, "Archive encoding error: {}", m), Self::InvalidKey(k) => write!(f, "Invalid archive key: {}", k) }
+    }
+}
+
+pub fn to_ndjson<T: Serialize>(records: &[T]) -> Result<Vec<u8>, ArchiveError> {
+    let mut out = Vec::new();
+    for record in records {
+        serde_json::to_writer(&mut out, record).map_err(|e| ArchiveError::Encoding(e.to_string()))?;
+        out.push(b'\n');
+    }
+    Ok(out)
+}
+
+pub fn from_ndjson<T: DeserializeOwned>(bytes: &[u8]) -> Result<Vec<T>, ArchiveError> {
+    bytes.split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_slice(line).map_err(|e| ArchiveError::Encoding(e.to_string())))
+        .collect()
+}
+
+/// `transactions/{prefix}/{first_id}-{digest}{extension}`, where the digest covers every
+/// id in the batch: retrying the same batch reuses the key, a different batch never does.
+pub fn batch_key(prefix: &str, ids: &[impl AsRef<str>], extension: &str) -> String {
+    let joined = ids.iter().map(AsRef::as_ref).collect::<Vec<_>>().join(",");
+    let digest = crypto::sha256_hex(joined.as_bytes());
+    let first = ids.first().map(AsRef::as_ref).unwrap_or("empty");
+    format!("transactions/{}/{}-{}{}", prefix, first, &digest[..16], extension)
+}
+
+/// Where export batches are written, e.g. local disk or an object store bucket.
+#[async_trait]
+pub trait ArchiveStore: Send + Sync {
+    async fn exists(&self, key: &str) -> Result<bool, ArchiveError>;
+    async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), ArchiveError>;
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, ArchiveError>;
+}
+
+/// Stores batches as files under `root`, mirroring the key's path.
+pub struct LocalDirStore { root: PathBuf }
+
+impl LocalDirStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self { Self { root: root.into() } }
+
+    fn path(&self, key: &str) -> Result<PathBuf, ArchiveError> {
+        if key.is_empty() || key.starts_with('/') || key.split('/').any(|part| part.is_empty() || part == "..") {
+            return Err(ArchiveError::InvalidKey(key.to_string()));
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl ArchiveStore for LocalDirStore {
+    async fn exists(&self, key: &str) -> Result<bool, ArchiveError> {
+        tokio::fs::try_exists(self.path(key)?).await.map_err(|e| ArchiveError::Io(e.to_string()))
+    }
+
+    /// Writes to a temporary file and renames it, so a partial write is never visible.
+    async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), ArchiveError> {
+        let path = self.path(key)?;
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await.map_err(|e| ArchiveError::Io(e.to_string()))?;
+        }
+        let tmp = path.with_extension("partial");
+        tokio::fs::write(&tmp, body).await.map_err(|e| ArchiveError::Io(e.to_string()))?;
+        tokio::fs::rename(&tmp, &path).await.map_err(|e| ArchiveError::Io(e.to_string()))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, ArchiveError> {
+        match tokio::fs::read(self.path(key)?).await {
+            Ok(body) => Ok(Some(body)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(ArchiveError::Io(e.to_string())),
+        }
+    }
+}
+
+/// In-memory store for tests and dry runs.
+#[derive(Default)]
+pub struct MemoryStore { objects: Mutex<BTreeMap<String, Vec<u8>>> }
+
+impl MemoryStore {
+    pub fn keys(&self) -> Vec<String> { self.objects.lock().unwrap().keys().cloned().collect() }
+}
+
+#[async_trait]
+impl ArchiveStore for MemoryStore {
+    async fn exists(&self, key: &str) -> Result<bool, ArchiveError> { Ok(self.objects.lock().unwrap().contains_key(key)) }
+    async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), ArchiveError> {
+        self.objects.lock().unwrap().insert(key.to_string(), body);
+        Ok(())
+    }
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, ArchiveError> { Ok(self.objects.lock().unwrap().get(key).cloned()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Row { id: String, status: String }
+
+    #[test]
+    fn test_ndjson_round_trip() {
+        let rows = vec![Row { id: "a".into(), status: "succeeded".into() }, Row { id: "b\nc".into(), status: "refunded".into() }];
+        let encoded = to_ndjson(&rows).unwrap();
+        assert_eq!(encoded.iter().filter(|b| **b == b'\n').count(), 2);
+        assert_eq!(from_ndjson::<Row>(&encoded).unwrap(), rows);
+        assert!(from_ndjson::<Row>(b"{not json}\n").is_err());
+    }
+
+    #[test]
+    fn test_terminal_statuses_and_keys() {
+        assert!(is_terminal("refunded") && !is_terminal("pending") && !is_terminal("requires_action"));
+        let key = batch_key("2026/01/31", &["a", "b"], ".ndjson.gz");
+        assert!(key.starts_with("transactions/2026/01/31/a-") && key.ends_with(".ndjson.gz"));
+        assert_eq!(key, batch_key("2026/01/31", &["a", "b"], ".ndjson.gz"));
+        assert_ne!(key, batch_key("2026/01/31", &["a", "c"], ".ndjson.gz"));
+    }
+
+    #[tokio::test]
+    async fn test_local_dir_store() {
+        let store = LocalDirStore::new(std::env::temp_dir().join(format!("sase-archive-{}", uuid::Uuid::new_v4())));
+        let key = "transactions/2026/01/31/batch.ndjson.gz";
+        assert!(!store.exists(key).await.unwrap());
+        store.put(key, b"payload".to_vec()).await.unwrap();
+        assert!(store.exists(key).await.unwrap());
+        assert_eq!(store.get(key).await.unwrap(), Some(b"payload".to_vec()));
+        assert_eq!(store.get("transactions/missing").await.unwrap(), None);
+        assert!(matches!(store.put("../escape", vec![]).await, Err(ArchiveError::InvalidKey(_))));
+        tokio::fs::remove_dir_all(&store.root).await.unwrap();
+    }
+}
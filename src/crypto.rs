@@ -0,0 +1,91 @@
+//! HMAC signing and random token helpers
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+
+pub fn hmac_sha256_hex(key: &[u8], message: &[u8]) -> String { sign_hex(hmac::HMAC_SHA256, key, message) }
+
+pub fn hmac_sha512_hex(key: &[u8], message: &[u8]) -> String { sign_hex(hmac::HMAC_SHA512, key, message) }
+
+/// Constant-time check of a hex-encoded HMAC-SHA512 signature.
+pub fn verify_hmac_sha512_hex(key: &[u8], message: &[u8], signature_hex: &str) -> bool { verify_hex(hmac::HMAC_SHA512, key, message, signature_hex) }
+
+/// Constant-time check of a hex-encoded HMAC-SHA256 signature.
+pub fn verify_hmac_sha256_hex(key: &[u8], message: &[u8], signature_hex: &str) -> bool { verify_hex(hmac::HMAC_SHA256, key, message, signature_hex) }
+
+/// Compares two secrets in constant time; only their lengths can leak.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// `len` bytes from the system CSPRNG, hex encoded.
+pub fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    SystemRandom::new().fill(&mut bytes).expect("system RNG unavailable");
+    to_hex(&bytes)
+}
+
+/// A uniformly random `u64` from the system CSPRNG.
+pub fn random_u64() -> u64 {
+    let mut bytes = [0u8; 8];
+    SystemRandom::new().fill(&mut bytes).expect("system RNG unavailable");
+    u64::from_le_bytes(bytes)
+}
+
+pub fn sha256_hex(data: &[u8]) -> String { to_hex(ring::digest::digest(&ring::digest::SHA256, data).as_ref()) }
+
+pub fn to_hex(bytes: &[u8]) -> String { bytes.iter().map(|b| format!("{:02x}", b)).collect() }
+
+pub fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) { return None; }
+    (0..s.len()).step_by(2).map(|i| s.get(i..i + 2).and_then(|p| u8::from_str_radix(p, 16).ok())).collect()
+}
+
+fn sign_hex(algorithm: hmac::Algorithm, key: &[u8], message: &[u8]) -> String {
+    to_hex(hmac::sign(&hmac::Key::new(algorithm, key), message).as_ref())
+}
+
+fn verify_hex(algorithm: hmac::Algorithm, key: &[u8], message: &[u8], signature_hex: &str) -> bool {
+    match from_hex(signature_hex.trim()) {
+        Some(signature) => hmac::verify(&hmac::Key::new(algorithm, key), message, &signature).is_ok(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const FOX: &[u8] = b"The quick brown fox jumps over the lazy dog";
+
+    #[test]
+    fn test_known_hmac_vectors() {
+        assert_eq!(hmac_sha256_hex(b"key", FOX), "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8");
+        assert_eq!(
+            hmac_sha512_hex(b"key", FOX),
+            "b42af09057bac1e2d41708e48a902e09b5ff7f12ab428a4fe86653c73dd248fb82f948a549f7b791a5b41915ee4d1ec3935357e4e2317250d0372afa2ebeeb3a"
+        );
+        assert_eq!(sha256_hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    #[test]
+    fn test_verify_rejects_tampering() {
+        let sig = hmac_sha512_hex(b"key", FOX);
+        assert!(verify_hmac_sha512_hex(b"key", FOX, &sig));
+        assert!(!verify_hmac_sha512_hex(b"key", b"The quick brown fox jumps over the lazy cat", &sig));
+        assert!(!verify_hmac_sha512_hex(b"other", FOX, &sig));
+        assert!(!verify_hmac_sha512_hex(b"key", FOX, "not-hex"));
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret-hash", b"secret-hash"));
+        assert!(!constant_time_eq(b"secret-hash", b"secret-hasH"));
+        assert!(!constant_time_eq(b"secret-hash", b"secret"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[test]
+    fn test_hex_round_trip() {
+        assert_eq!(from_hex(&to_hex(&[0, 15, 255])), Some(vec![0, 15, 255]));
+        assert_eq!(random_hex(16).len(), 32);
+    }
+}
@@ -0,0 +1,212 @@
+//! Invoice Aggregate
+//!
+//! The breakdown behind a charge: line items in one currency, tax on their subtotal, and
+//! the total that is charged. An invoice is built as a draft and finalized before the
+//! charge is made, which is when its tax is worked out; from then on it is a record of
+//! what was billed and can't change.
+use chrono::{DateTime, Utc};
+use crate::domain::services::tax::TaxCalculator;
+use crate::domain::value_objects::{BillingDetails, Money};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InvoiceLine {
+    pub description: String,
+    pub quantity: i64,
+    pub unit_amount: Money,
+    /// `quantity` times `unit_amount`.
+    pub amount: Money,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum InvoiceStatus { #[default] Draft, Finalized }
+impl InvoiceStatus {
+    pub fn as_str(&self) -> &'static str { match self { Self::Draft => "draft", Self::Finalized => "finalized" } }
+    pub fn parse(s: &str) -> Option<Self> { match s { "draft" => Some(Self::Draft), "finalized" => Some(Self::Finalized), _ => None } }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Invoice {
+    id: String,
+    subscription_id: String,
+    /// The reference of the transaction that charges it.
+    reference: String,
+    currency: String,
+    lines: Vec<InvoiceLine>,
+    /// Zero until finalized.
+    tax: Money,
+    status: InvoiceStatus,
+    created_at: DateTime<Utc>,
+    finalized_at: Option<DateTime<Utc>>,
+}
+
+/// An invoice's stored state, as saved and as handed to `Invoice::from_record`. The
+/// subtotal and total aren't stored here; they always follow from the lines and the tax.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InvoiceRecord {
+    pub id: String,
+    pub subscription_id: String,
+    pub reference: String,
+    pub currency: String,
+    pub lines: Vec<InvoiceLine>,
+    pub tax: Money,
+    pub status: InvoiceStatus,
+    pub created_at: DateTime<Utc>,
+    pub finalized_at: Option<DateTime<Utc>>,
+}
+
+impl Invoice {
+    pub fn create(subscription_id: impl Into<String>, reference: impl Into<String>, currency: &str) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(), subscription_id: subscription_id.into(), reference: reference.into(), currency: currency.to_string(),
+            lines: vec![], tax: Money::zero(currency), status: InvoiceStatus::Draft, created_at: Utc::now(), finalized_at: None,
+        }
+    }
+
+    pub fn from_record(record: InvoiceRecord) -> Self {
+        Self {
+            id: record.id, subscription_id: record.subscription_id, reference: record.reference, currency: record.currency, lines: record.lines,
+            tax: record.tax, status: record.status, created_at: record.created_at, finalized_at: record.finalized_at,
+        }
+    }
+
+    pub fn to_record(&self) -> InvoiceRecord {
+        InvoiceRecord {
+            id: self.id.clone(), subscription_id: self.subscription_id.clone(), reference: self.reference.clone(), currency: self.currency.clone(),
+            lines: self.lines.clone(), tax: self.tax.clone(), status: self.status.clone(), created_at: self.created_at, finalized_at: self.finalized_at,
+        }
+    }
+
+    pub fn id(&self) -> &str { &self.id }
+    pub fn subscription_id(&self) -> &str { &self.subscription_id }
+    pub fn reference(&self) -> &str { &self.reference }
+    pub fn currency(&self) -> &str { &self.currency }
+    pub fn lines(&self) -> &[InvoiceLine] { &self.lines }
+    pub fn tax(&self) -> &Money { &self.tax }
+    pub fn status(&self) -> &InvoiceStatus { &self.status }
+    pub fn created_at(&self) -> DateTime<Utc> { self.created_at }
+    pub fn finalized_at(&self) -> Option<DateTime<Utc>> { self.finalized_at }
+    pub fn is_finalized(&self) -> bool { self.status == InvoiceStatus::Finalized }
+
+    /// Adds `quantity` of `unit_amount`, which must be in the invoice's currency.
+    pub fn add_line(&mut self, description: impl Into<String>, quantity: i64, unit_amount: Money) -> Result<(), InvoiceError> {
+        self.ensure_draft()?;
+        if quantity <= 0 { return Err(InvoiceError::InvalidQuantity(quantity)); }
+        if unit_amount.currency != self.currency {
+            return Err(InvoiceError::CurrencyMismatch { expected: self.currency.clone(), actual: unit_amount.currency });
+        }
+        if unit_amount.is_negative() { return Err(InvoiceError::InvalidAmount(format!("unit amount {} is negative", unit_amount.amount))); }
+        let amount = unit_amount.amount.checked_mul(quantity.into())
+            .ok_or_else(|| InvoiceError::InvalidAmount(format!("{} x {} overflows", quantity, unit_amount.amount)))?;
+        self.lines.push(InvoiceLine { description: description.into(), quantity, amount: Money::new(amount, &self.currency), unit_amount });
+        Ok(())
+    }
+
+    pub fn subtotal(&self) -> Result<Money, InvoiceError> {
+        self.lines.iter().try_fold(Money::zero(&self.currency), |sum, line| sum.checked_add(&line.amount))
+            .map_err(|e| InvoiceError::InvalidAmount(e.to_string()))
+    }
+
+    pub fn total(&self) -> Result<Money, InvoiceError> {
+        self.subtotal()?.checked_add(&self.tax).map_err(|e| InvoiceError::InvalidAmount(e.to_string()))
+    }
+
+    /// Works out the tax for `billing` and locks the invoice. An exempt customer pays none
+    /// whatever the calculator says, and an invoice with nothing on it can't be finalized.
+    pub fn finalize(&mut self, calculator: &dyn TaxCalculator, billing: &BillingDetails) -> Result<(), InvoiceError> {
+        self.ensure_draft()?;
+        if self.lines.is_empty() { return Err(InvoiceError::Empty); }
+        let tax = if billing.tax_exempt { Money::zero(&self.currency) } else { calculator.tax_for(&self.lines, billing.address.as_ref())? };
+        if tax.currency != self.currency {
+            return Err(InvoiceError::CurrencyMismatch { expected: self.currency.clone(), actual: tax.currency });
+        }
+        if tax.is_negative() { return Err(InvoiceError::InvalidAmount(format!("tax {} is negative", tax.amount))); }
+        self.tax = tax;
+        self.total()?;
+        self.status = InvoiceStatus::Finalized;
+        self.finalized_at = Some(Utc::now());
+        Ok(())
+    }
+
+    fn ensure_draft(&self) -> Result<(), InvoiceError> {
+        if self.is_finalized() { Err(InvoiceError::Finalized) } else { Ok(()) }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvoiceError { Finalized, Empty, InvalidQuantity(i64), InvalidAmount(String), CurrencyMismatch { expected: String, actual: String } }
+impl std::error::Error for InvoiceError {}
+impl std::fmt::Display for InvoiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Finalized => write!(f, "Invoice is finalized"),
+            Self::Empty => write!(f, "Invoice has no line items"),
+            Self::InvalidQuantity(q) => write!(f, "Invalid quantity: {}", q),
+            Self::InvalidAmount(m) => write!(f, "Invalid amount: {}", m),
+            Self::CurrencyMismatch { expected, actual } => write!(f, "Line item is in {} but the invoice is in {}", actual, expected),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+    use crate::domain::services::tax::TaxRates;
+    use crate::domain::value_objects::Address;
+
+    fn billed_in(country: &str, tax_exempt: bool) -> BillingDetails {
+        BillingDetails { address: Some(Address { country: country.into(), ..Address::default() }), tax_exempt, ..BillingDetails::default() }
+    }
+
+    #[test]
+    fn test_lines_sum_to_total() {
+        let rates = TaxRates::parse("NG=7.5,GB=0").unwrap();
+        let draft = || {
+            let mut invoice = Invoice::create("sub_1", "SUB-1-20260101", "USD");
+            invoice.add_line("PLAN_PRO", 1, Money::usd(Decimal::new(4900, 2))).unwrap();
+            invoice.add_line("API calls", 3, Money::usd(Decimal::new(125, 2))).unwrap();
+            invoice
+        };
+        let mut invoice = draft();
+        assert_eq!(invoice.lines()[1].amount, Money::usd(Decimal::new(375, 2)));
+        assert_eq!(invoice.subtotal().unwrap(), Money::usd(Decimal::new(5275, 2)));
+        assert!(invoice.tax().is_zero());
+
+        // Taxable: 7.5% of 52.75 is 3.95625, rounded to the cent
+        invoice.finalize(&rates, &billed_in("NG", false)).unwrap();
+        assert_eq!(invoice.tax(), &Money::usd(Decimal::new(396, 2)));
+        assert_eq!(invoice.total().unwrap(), Money::usd(Decimal::new(5671, 2)));
+
+        // A zero-rate address and an exempt customer pay the subtotal
+        for billing in [billed_in("GB", false), billed_in("NG", true), BillingDetails::default()] {
+            let mut invoice = draft();
+            invoice.finalize(&rates, &billing).unwrap();
+            assert_eq!(invoice.total().unwrap(), Money::usd(Decimal::new(5275, 2)), "{:?}", billing);
+        }
+
+        assert!(matches!(invoice.add_line("Naira", 1, Money::new(Decimal::ONE, "NGN")), Err(InvoiceError::Finalized)));
+        let mut other = Invoice::create("sub_1", "SUB-2", "USD");
+        assert!(matches!(other.add_line("Naira", 1, Money::new(Decimal::ONE, "NGN")), Err(InvoiceError::CurrencyMismatch { .. })));
+        assert_eq!(other.add_line("Nothing", 0, Money::usd(Decimal::ONE)), Err(InvoiceError::InvalidQuantity(0)));
+        assert_eq!(other.finalize(&rates, &BillingDetails::default()), Err(InvoiceError::Empty));
+    }
+
+    #[test]
+    fn test_finalized_invoice_is_locked() {
+        let rates = TaxRates::default();
+        let mut invoice = Invoice::create("sub_1", "SUB-1-20260101", "NGN");
+        invoice.add_line("PLAN_PRO", 1, Money::new(Decimal::from(2500), "NGN")).unwrap();
+        invoice.finalize(&rates, &BillingDetails::default()).unwrap();
+        assert!(invoice.is_finalized() && invoice.finalized_at().is_some());
+
+        let before = invoice.clone();
+        assert_eq!(invoice.add_line("Extra", 1, Money::new(Decimal::ONE, "NGN")), Err(InvoiceError::Finalized));
+        assert_eq!(invoice.finalize(&TaxRates::parse("NG=7.5").unwrap(), &billed_in("NG", false)), Err(InvoiceError::Finalized));
+        assert_eq!(invoice, before);
+
+        // Loading it back doesn't unlock it
+        let mut loaded = Invoice::from_record(invoice.to_record());
+        assert_eq!(loaded.add_line("Extra", 1, Money::new(Decimal::ONE, "NGN")), Err(InvoiceError::Finalized));
+        assert_eq!(loaded.total().unwrap(), Money::new(Decimal::from(2500), "NGN"));
+    }
+}
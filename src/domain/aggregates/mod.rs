@@ -1,5 +1,9 @@
 //! Aggregates
+pub mod invoice;
 pub mod payment;
+pub mod payment_plan;
 pub mod subscription;
-pub use payment::{Payment, PaymentError, PaymentStatus};
-pub use subscription::{Subscription, SubscriptionError, SubscriptionStatus, BillingCycle};
+pub use invoice::{Invoice, InvoiceError, InvoiceLine, InvoiceRecord, InvoiceStatus};
+pub use payment::{Payment, PaymentError, PaymentRecord, PaymentStatus};
+pub use payment_plan::{Installment, PaymentPlan, PaymentPlanError};
+pub use subscription::{Subscription, SubscriptionError, SubscriptionRecord, SubscriptionStatus, BillingCycle, DunningOutcome, DunningPolicy};
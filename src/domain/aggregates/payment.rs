@@ -1,7 +1,7 @@
 //! Payment Aggregate
 use chrono::{DateTime, Utc};
 use rust_decimal::Decimal;
-use crate::domain::value_objects::{PaymentId, PaymentMethod, Money};
+use crate::domain::value_objects::{DeclineCode, PaymentId, PaymentMethod, Money, ProviderErrorKind, TransactionStatus};
 use crate::domain::events::{DomainEvent, PaymentEvent};
 
 #[derive(Clone, Debug)]
@@ -14,20 +14,76 @@ pub struct Payment {
     description: Option<String>,
     metadata: std::collections::HashMap<String, String>,
     refunded_amount: Decimal,
+    failure_reason: Option<String>,
     created_at: DateTime<Utc>,
+    /// Saves so far; 0 until the payment is first saved.
+    version: u64,
     events: Vec<DomainEvent>,
 }
 
+/// The aggregate's view of a payment. Each status is stored as the `TransactionStatus`
+/// it maps to below, and that mapping is the only one: the string form and the reverse
+/// conversion are both derived from it.
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub enum PaymentStatus { #[default] Pending, Processing, Succeeded, Failed, Cancelled, Refunded, PartiallyRefunded }
 
+impl PaymentStatus {
+    pub const ALL: [Self; 7] = [Self::Pending, Self::Processing, Self::Succeeded, Self::Failed, Self::Cancelled, Self::Refunded, Self::PartiallyRefunded];
+
+    pub fn as_str(&self) -> &'static str { TransactionStatus::from(self.clone()).as_str() }
+}
+
+impl From<PaymentStatus> for TransactionStatus {
+    fn from(status: PaymentStatus) -> Self {
+        match status {
+            PaymentStatus::Pending => Self::Pending, PaymentStatus::Processing => Self::Processing, PaymentStatus::Succeeded => Self::Succeeded,
+            PaymentStatus::Failed => Self::Failed, PaymentStatus::Cancelled => Self::Cancelled, PaymentStatus::Refunded => Self::Refunded,
+            PaymentStatus::PartiallyRefunded => Self::PartiallyRefunded,
+        }
+    }
+}
+
+/// Fails for the statuses only a stored transaction has, e.g. `requires_action`.
+impl TryFrom<TransactionStatus> for PaymentStatus {
+    type Error = String;
+    fn try_from(status: TransactionStatus) -> Result<Self, Self::Error> {
+        Self::ALL.into_iter().find(|s| TransactionStatus::from(s.clone()) == status)
+            .ok_or_else(|| format!("transaction status '{}' has no payment status", status))
+    }
+}
+
+impl std::fmt::Display for PaymentStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { f.write_str(self.as_str()) }
+}
+
+impl std::str::FromStr for PaymentStatus {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> { s.parse::<TransactionStatus>()?.try_into() }
+}
+
+/// Everything a repository stores for a payment: its state without the pending events.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PaymentRecord {
+    pub id: PaymentId,
+    pub customer_id: String,
+    pub amount: Money,
+    pub status: PaymentStatus,
+    pub payment_method: Option<PaymentMethod>,
+    pub description: Option<String>,
+    pub metadata: std::collections::HashMap<String, String>,
+    pub refunded_amount: Decimal,
+    pub failure_reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub version: u64,
+}
+
 impl Payment {
     pub fn create(customer_id: impl Into<String>, amount: Money) -> Self {
         let id = PaymentId::new();
         let mut p = Self {
             id: id.clone(), customer_id: customer_id.into(), amount: amount.clone(), status: PaymentStatus::Pending,
             payment_method: None, description: None, metadata: std::collections::HashMap::new(),
-            refunded_amount: Decimal::ZERO, created_at: Utc::now(), events: vec![],
+            refunded_amount: Decimal::ZERO, failure_reason: None, created_at: Utc::now(), version: 0, events: vec![],
         };
         p.raise_event(DomainEvent::Payment(PaymentEvent::Created { payment_id: id, amount: amount.amount }));
         p
@@ -36,6 +92,12 @@ impl Payment {
     pub fn id(&self) -> &PaymentId { &self.id }
     pub fn amount(&self) -> &Money { &self.amount }
     pub fn status(&self) -> &PaymentStatus { &self.status }
+    pub fn customer_id(&self) -> &str { &self.customer_id }
+    pub fn description(&self) -> Option<&str> { self.description.as_deref() }
+    pub fn metadata(&self) -> &std::collections::HashMap<String, String> { &self.metadata }
+    pub fn created_at(&self) -> DateTime<Utc> { self.created_at }
+    pub fn failure_reason(&self) -> Option<&str> { self.failure_reason.as_deref() }
+    pub fn version(&self) -> u64 { self.version }
     
     pub fn process(&mut self, method: PaymentMethod) -> Result<(), PaymentError> {
         if self.status != PaymentStatus::Pending { return Err(PaymentError::InvalidStatus); }
@@ -51,10 +113,20 @@ impl Payment {
         Ok(())
     }
     
-    pub fn fail(&mut self, reason: impl Into<String>) { self.status = PaymentStatus::Failed; }
+    /// Only a payment that hasn't completed yet can fail.
+    pub fn fail(&mut self, reason: impl Into<String>) -> Result<(), PaymentError> {
+        if self.status != PaymentStatus::Pending && self.status != PaymentStatus::Processing { return Err(PaymentError::InvalidStatus); }
+        let reason = reason.into();
+        self.status = PaymentStatus::Failed;
+        self.failure_reason = Some(reason.clone());
+        self.raise_event(DomainEvent::Payment(PaymentEvent::Failed { payment_id: self.id.clone(), reason }));
+        Ok(())
+    }
     
-    pub fn refund(&mut self, amount: Decimal) -> Result<(), PaymentError> {
+    pub fn refund(&mut self, refund: Money) -> Result<(), PaymentError> {
         if self.status != PaymentStatus::Succeeded && self.status != PaymentStatus::PartiallyRefunded { return Err(PaymentError::NotRefundable); }
+        if refund.currency != self.amount.currency { return Err(PaymentError::CurrencyMismatch { expected: self.amount.currency.clone(), actual: refund.currency }); }
+        let amount = refund.amount;
         let new_total = self.refunded_amount + amount;
         if new_total > self.amount.amount { return Err(PaymentError::RefundExceedsPayment); }
         self.refunded_amount = new_total;
@@ -63,15 +135,57 @@ impl Payment {
         Ok(())
     }
     
+    pub fn refunded_amount(&self) -> Decimal { self.refunded_amount }
+
+    pub fn to_record(&self) -> PaymentRecord {
+        PaymentRecord {
+            id: self.id.clone(), customer_id: self.customer_id.clone(), amount: self.amount.clone(), status: self.status.clone(),
+            payment_method: self.payment_method.clone(), description: self.description.clone(), metadata: self.metadata.clone(),
+            refunded_amount: self.refunded_amount, failure_reason: self.failure_reason.clone(), created_at: self.created_at, version: self.version,
+        }
+    }
+
+    /// Rebuilds a stored payment. Nothing is raised: its events were published when it was saved.
+    pub fn from_record(record: PaymentRecord) -> Self {
+        Self {
+            id: record.id, customer_id: record.customer_id, amount: record.amount, status: record.status,
+            payment_method: record.payment_method, description: record.description, metadata: record.metadata,
+            refunded_amount: record.refunded_amount, failure_reason: record.failure_reason, created_at: record.created_at,
+            version: record.version, events: vec![],
+        }
+    }
+
+    /// Events raised since the last `take_events`, left in place.
+    pub fn pending_events(&self) -> &[DomainEvent] { &self.events }
     pub fn take_events(&mut self) -> Vec<DomainEvent> { std::mem::take(&mut self.events) }
+
+    /// Called by a repository once a save at `version()` committed: moves to the next
+    /// version and drains the events that were saved with it.
+    pub fn mark_saved(&mut self) -> Vec<DomainEvent> {
+        self.version += 1;
+        self.take_events()
+    }
     fn raise_event(&mut self, e: DomainEvent) { self.events.push(e); }
 }
 
-#[derive(Debug, Clone)] pub enum PaymentError { InvalidStatus, NotRefundable, RefundExceedsPayment }
+#[derive(Debug, Clone, PartialEq, Eq)] pub enum PaymentError { InvalidStatus, NotRefundable, RefundExceedsPayment, InvalidAmount(String), InvalidCurrency(String), CurrencyMismatch { expected: String, actual: String }, InsufficientFunds(String), AlreadyReversed, UnsupportedCurrency { currency: String, provider: String }, InvalidDescriptor(String), Declined(DeclineCode), ProviderError { kind: ProviderErrorKind, message: String }, InvalidTransition { from: String, to: String }, RefundFailed(String), ConcurrencyConflict, UnknownExchangeRate { from: String, to: String }, PaymentNotFound(String) }
 impl std::error::Error for PaymentError {}
+impl PaymentError {
+    /// A stable snake_case name for the variant, for API error bodies.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidStatus => "invalid_status", Self::NotRefundable => "not_refundable", Self::RefundExceedsPayment => "refund_exceeds_payment",
+            Self::InvalidAmount(_) => "invalid_amount", Self::InvalidCurrency(_) => "invalid_currency", Self::CurrencyMismatch { .. } => "currency_mismatch",
+            Self::InsufficientFunds(_) => "insufficient_funds", Self::AlreadyReversed => "already_reversed", Self::UnsupportedCurrency { .. } => "unsupported_currency",
+            Self::InvalidDescriptor(_) => "invalid_descriptor", Self::Declined(_) => "card_declined", Self::ProviderError { .. } => "provider_error",
+            Self::InvalidTransition { .. } => "invalid_transition", Self::RefundFailed(_) => "refund_failed", Self::ConcurrencyConflict => "concurrency_conflict",
+            Self::UnknownExchangeRate { .. } => "unknown_exchange_rate", Self::PaymentNotFound(_) => "payment_not_found",
+        }
+    }
+}
 impl std::fmt::Display for PaymentError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self { Self::InvalidStatus => write!(f, "Invalid status"), Self::NotRefundable => write!(f, "Not refundable"), Self::RefundExceedsPayment => write!(f, "Refund exceeds payment") }
+        match self { Self::InvalidStatus => write!(f, "Invalid status"), Self::NotRefundable => write!(f, "Not refundable"), Self::RefundExceedsPayment => write!(f, "Refund exceeds payment"), Self::InvalidAmount(m) => write!(f, "Invalid amount: {}", m), Self::InvalidCurrency(c) => write!(f, "Invalid currency: {}", c), Self::CurrencyMismatch { expected, actual } => write!(f, "Currency mismatch: expected {}, got {}", expected, actual), Self::InsufficientFunds(m) => write!(f, "Insufficient funds: {}", m), Self::AlreadyReversed => write!(f, "Already fully reversed"), Self::UnsupportedCurrency { currency, provider } => write!(f, "Currency {} is not supported by provider {}", currency, provider), Self::InvalidDescriptor(m) => write!(f, "Invalid statement descriptor: {}", m), Self::Declined(code) => write!(f, "Card declined: {}", code.as_str()), Self::ProviderError { kind, message } => write!(f, "Provider error ({:?}): {}", kind, message), Self::InvalidTransition { from, to } => write!(f, "Cannot move transaction from {} to {}", from, to), Self::RefundFailed(m) => write!(f, "Refund failed: {}", m), Self::ConcurrencyConflict => write!(f, "Payment was changed by another request; reload and retry"), Self::UnknownExchangeRate { from, to } => write!(f, "No exchange rate from {} to {}", from, to), Self::PaymentNotFound(r) => write!(f, "Payment '{}' not found", r) }
     }
 }
 
@@ -85,4 +199,70 @@ mod tests {
         p.succeed().unwrap();
         assert_eq!(p.status(), &PaymentStatus::Succeeded);
     }
+
+    fn succeeded_payment(amount: Money) -> Payment {
+        let mut p = Payment::create("CUST001", amount);
+        p.process(PaymentMethod { method_type: crate::domain::value_objects::PaymentMethodType::Card, last_four: None, brand: None, exp_month: None, exp_year: None }).unwrap();
+        p.succeed().unwrap();
+        p
+    }
+
+    #[test]
+    fn test_fail_records_reason_and_event() {
+        let mut p = Payment::create("CUST001", Money::usd(Decimal::new(100, 0)));
+        p.take_events();
+        p.fail("card declined: insufficient_funds").unwrap();
+        assert_eq!(p.status(), &PaymentStatus::Failed);
+        assert_eq!(p.failure_reason(), Some("card declined: insufficient_funds"));
+        let events = p.take_events();
+        assert!(matches!(events.as_slice(), [DomainEvent::Payment(PaymentEvent::Failed { payment_id, reason })]
+            if payment_id == p.id() && reason == "card declined: insufficient_funds"));
+
+        assert_eq!(p.fail("again"), Err(PaymentError::InvalidStatus));
+        assert_eq!(p.failure_reason(), Some("card declined: insufficient_funds"));
+        let mut succeeded = succeeded_payment(Money::usd(Decimal::new(100, 0)));
+        assert_eq!(succeeded.fail("late"), Err(PaymentError::InvalidStatus));
+        assert_eq!(succeeded.status(), &PaymentStatus::Succeeded);
+    }
+
+    #[test]
+    fn test_refund_ceiling_same_currency() {
+        let mut p = succeeded_payment(Money::usd(Decimal::new(100, 0)));
+        p.refund(Money::usd(Decimal::new(60, 0))).unwrap();
+        assert_eq!(p.status(), &PaymentStatus::PartiallyRefunded);
+        assert_eq!(p.refund(Money::usd(Decimal::new(41, 0))).unwrap_err(), PaymentError::RefundExceedsPayment);
+        p.refund(Money::usd(Decimal::new(40, 0))).unwrap();
+        assert_eq!(p.status(), &PaymentStatus::Refunded);
+    }
+
+    #[test]
+    fn test_refund_rejects_other_currency() {
+        let mut p = succeeded_payment(Money::usd(Decimal::new(100, 0)));
+        let err = p.refund(Money::new(Decimal::new(10, 0), "EUR")).unwrap_err();
+        assert_eq!(err, PaymentError::CurrencyMismatch { expected: "USD".into(), actual: "EUR".into() });
+        assert_eq!(p.status(), &PaymentStatus::Succeeded);
+    }
+
+    #[test]
+    fn test_status_conversions() {
+        for status in PaymentStatus::ALL {
+            let stored = TransactionStatus::from(status.clone());
+            assert_eq!(stored.as_str(), status.as_str());
+            assert_eq!(PaymentStatus::try_from(stored), Ok(status.clone()));
+            assert_eq!(status.to_string().parse::<PaymentStatus>(), Ok(status.clone()));
+            assert_eq!(status.to_string().parse::<TransactionStatus>(), Ok(stored));
+        }
+        // Statuses only a stored transaction has convert one way
+        for stored in TransactionStatus::ALL {
+            let converted = PaymentStatus::try_from(stored);
+            match stored {
+                TransactionStatus::Scheduled | TransactionStatus::RequiresAction | TransactionStatus::Authorized | TransactionStatus::Disputed | TransactionStatus::ChargedBack => {
+                    assert_eq!(converted, Err(format!("transaction status '{}' has no payment status", stored)));
+                    assert_eq!(stored.as_str().parse::<PaymentStatus>(), converted);
+                }
+                _ => assert_eq!(TransactionStatus::from(converted.unwrap()), stored),
+            }
+        }
+        assert_eq!("paid".parse::<PaymentStatus>(), Err("unknown transaction status 'paid'".to_string()));
+    }
 }
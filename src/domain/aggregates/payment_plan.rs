@@ -0,0 +1,121 @@
+//! Payment Plan Aggregate
+//!
+//! A total split into equal installments, one due per billing cycle from the first due
+//! date. Amounts are whole minor units: what doesn't divide evenly goes on the first
+//! installment, so the installments always add back up to the total exactly.
+use chrono::{DateTime, NaiveDate, Utc};
+use crate::domain::aggregates::BillingCycle;
+use crate::domain::value_objects::Money;
+
+/// Most installments one plan may have.
+pub const MAX_INSTALLMENTS: u32 = 60;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Installment {
+    /// 1-based position in the plan.
+    pub number: u32,
+    pub amount: Money,
+    pub due_on: NaiveDate,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PaymentPlan {
+    id: String,
+    total: Money,
+    cycle: BillingCycle,
+    installments: Vec<Installment>,
+    created_at: DateTime<Utc>,
+}
+
+impl PaymentPlan {
+    /// Splits `total` into `count` installments, the first due on `first_due` and each
+    /// after it one `cycle` later. Every installment must come to at least one minor unit.
+    pub fn create(total: Money, count: u32, cycle: BillingCycle, first_due: NaiveDate) -> Result<Self, PaymentPlanError> {
+        if !(1..=MAX_INSTALLMENTS).contains(&count) { return Err(PaymentPlanError::InvalidCount(count)); }
+        let minor = total.to_minor_units().map_err(|e| PaymentPlanError::InvalidAmount(e.to_string()))?;
+        let each = minor / i64::from(count);
+        if each <= 0 {
+            return Err(PaymentPlanError::InvalidAmount(format!("{} can't be split into {} installments", total.format_with_code(), count)));
+        }
+        let first = minor - each * i64::from(count - 1);
+
+        let mut due_on = first_due;
+        let mut installments = Vec::with_capacity(count as usize);
+        for number in 1..=count {
+            let units = if number == 1 { first } else { each };
+            let amount = Money::from_minor_units(units, &total.currency).map_err(|e| PaymentPlanError::InvalidAmount(e.to_string()))?;
+            installments.push(Installment { number, amount, due_on });
+            due_on = cycle.period_end(due_on);
+        }
+        Ok(Self { id: uuid::Uuid::new_v4().to_string(), total, cycle, installments, created_at: Utc::now() })
+    }
+
+    pub fn id(&self) -> &str { &self.id }
+    pub fn total(&self) -> &Money { &self.total }
+    pub fn cycle(&self) -> &BillingCycle { &self.cycle }
+    pub fn installments(&self) -> &[Installment] { &self.installments }
+    pub fn created_at(&self) -> DateTime<Utc> { self.created_at }
+
+    /// Installments due by `today`.
+    pub fn due(&self, today: NaiveDate) -> impl Iterator<Item = &Installment> {
+        self.installments.iter().filter(move |i| i.due_on <= today)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaymentPlanError { InvalidCount(u32), InvalidAmount(String) }
+impl std::error::Error for PaymentPlanError {}
+impl std::fmt::Display for PaymentPlanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidCount(n) => write!(f, "A payment plan needs 1 to {} installments, not {}", MAX_INSTALLMENTS, n),
+            Self::InvalidAmount(m) => write!(f, "Invalid amount: {}", m),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+
+    fn day(d: u32) -> NaiveDate { NaiveDate::from_ymd_opt(2026, 1, d).unwrap() }
+
+    #[test]
+    fn test_installments_sum_to_total() {
+        // 100.00 / 3 is 33.33 each with a cent left over, which the first one takes
+        let total = Money::usd(Decimal::new(10000, 2));
+        let plan = PaymentPlan::create(total.clone(), 3, BillingCycle::Weekly, day(1)).unwrap();
+        let amounts: Vec<Decimal> = plan.installments().iter().map(|i| i.amount.amount).collect();
+        assert_eq!(amounts, vec![Decimal::new(3334, 2), Decimal::new(3333, 2), Decimal::new(3333, 2)]);
+        let sum = plan.installments().iter().try_fold(Money::zero("USD"), |sum, i| sum.checked_add(&i.amount)).unwrap();
+        assert_eq!(sum, total);
+
+        for (minor, count, currency) in [(1_000_001, 7, "NGN"), (99, 4, "USD"), (50_000, 6, "JPY"), (12, 12, "USD"), (5000, 1, "USD")] {
+            let total = Money::from_minor_units(minor, currency).unwrap();
+            let plan = PaymentPlan::create(total.clone(), count, BillingCycle::Monthly, day(1)).unwrap();
+            let installments = plan.installments();
+            assert_eq!(installments.len(), count as usize);
+            let units: Vec<i64> = installments.iter().map(|i| i.amount.to_minor_units().unwrap()).collect();
+            assert_eq!(units.iter().sum::<i64>(), minor, "{} {}", minor, count);
+            // Equal apart from the first, which is never short
+            assert!(units[1..].iter().all(|&u| u == minor / i64::from(count)), "{:?}", units);
+            assert_eq!(units[0], minor / i64::from(count) + minor % i64::from(count));
+        }
+    }
+
+    #[test]
+    fn test_installment_schedule() {
+        let plan = PaymentPlan::create(Money::usd(Decimal::new(9000, 2)), 3, BillingCycle::Weekly, day(1)).unwrap();
+        let due: Vec<(u32, NaiveDate)> = plan.installments().iter().map(|i| (i.number, i.due_on)).collect();
+        assert_eq!(due, vec![(1, day(1)), (2, day(8)), (3, day(15))]);
+        assert_eq!(plan.due(day(8)).count(), 2);
+        assert_eq!(plan.due(day(1) - chrono::Duration::days(1)).count(), 0);
+
+        assert_eq!(PaymentPlan::create(Money::usd(Decimal::ONE), 0, BillingCycle::Weekly, day(1)), Err(PaymentPlanError::InvalidCount(0)));
+        assert_eq!(PaymentPlan::create(Money::usd(Decimal::ONE), MAX_INSTALLMENTS + 1, BillingCycle::Weekly, day(1)), Err(PaymentPlanError::InvalidCount(61)));
+        // Three cents can't make four installments of at least a cent
+        assert!(matches!(PaymentPlan::create(Money::usd(Decimal::new(3, 2)), 4, BillingCycle::Weekly, day(1)), Err(PaymentPlanError::InvalidAmount(_))));
+        assert!(matches!(PaymentPlan::create(Money::usd(Decimal::new(1001, 3)), 2, BillingCycle::Weekly, day(1)), Err(PaymentPlanError::InvalidAmount(_))));
+    }
+}
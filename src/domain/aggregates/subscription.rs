@@ -1,8 +1,9 @@
 //! Subscription Aggregate
 use chrono::{DateTime, NaiveDate, Utc};
-use rust_decimal::Decimal;
 use crate::domain::value_objects::Money;
+use crate::domain::value_objects::metadata::{self, Metadata};
 use crate::domain::events::{DomainEvent, SubscriptionEvent};
+use crate::domain::services::subscription_metrics::{churn_risk, monthly_recurring_revenue, SubscriptionMetrics};
 
 #[derive(Clone, Debug)]
 pub struct Subscription {
@@ -16,38 +17,243 @@ pub struct Subscription {
     amount: Money,
     cancel_at_period_end: bool,
     cancelled_at: Option<DateTime<Utc>>,
+    metadata: Metadata,
+    total_paid: Money,
+    renewals: u32,
+    consecutive_failures: u32,
+    last_payment_failed_at: Option<DateTime<Utc>>,
+    dunning: DunningPolicy,
     created_at: DateTime<Utc>,
     events: Vec<DomainEvent>,
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
-pub enum SubscriptionStatus { #[default] Active, PastDue, Cancelled, Trialing, Paused }
+pub enum SubscriptionStatus { #[default] Active, PastDue, Cancelled, Trialing, Paused, Unpaid }
+impl SubscriptionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self { Self::Active => "active", Self::PastDue => "past_due", Self::Cancelled => "cancelled", Self::Trialing => "trialing", Self::Paused => "paused", Self::Unpaid => "unpaid" }
+    }
+    pub fn parse(s: &str) -> Option<Self> {
+        match s { "active" => Some(Self::Active), "past_due" => Some(Self::PastDue), "cancelled" => Some(Self::Cancelled), "trialing" => Some(Self::Trialing), "paused" => Some(Self::Paused), "unpaid" => Some(Self::Unpaid), _ => None }
+    }
+}
 
-#[derive(Clone, Debug, Default)]
+/// A subscription's stored state, as saved and as handed to `Subscription::from_record`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubscriptionRecord {
+    pub id: String,
+    pub customer_id: String,
+    pub plan_id: String,
+    pub status: SubscriptionStatus,
+    pub current_period_start: NaiveDate,
+    pub current_period_end: NaiveDate,
+    pub billing_cycle: BillingCycle,
+    pub amount: Money,
+    pub cancel_at_period_end: bool,
+    pub metadata: Metadata,
+    pub total_paid: Money,
+    pub renewals: u32,
+    pub consecutive_failures: u32,
+    pub last_payment_failed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Where a subscription ends up once its renewal has failed `max_attempts` times.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DunningOutcome { #[default] Unpaid, Cancelled }
+impl DunningOutcome {
+    pub fn parse(s: &str) -> Option<Self> { match s { "unpaid" => Some(Self::Unpaid), "cancelled" => Some(Self::Cancelled), _ => None } }
+}
+
+/// How many failed renewal attempts a subscription stays `PastDue` through, retried
+/// `retry_after_days` after each failure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DunningPolicy { pub max_attempts: u32, pub outcome: DunningOutcome, pub retry_after_days: u32 }
+impl Default for DunningPolicy {
+    fn default() -> Self { Self { max_attempts: 4, outcome: DunningOutcome::Unpaid, retry_after_days: 3 } }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub enum BillingCycle { #[default] Monthly, Yearly, Weekly }
+impl BillingCycle {
+    /// End of a period of this cycle that starts on `start`.
+    pub fn period_end(&self, start: NaiveDate) -> NaiveDate {
+        start + chrono::Duration::days(match self { Self::Monthly => 30, Self::Yearly => 365, Self::Weekly => 7 })
+    }
+    pub fn as_str(&self) -> &'static str { match self { Self::Monthly => "monthly", Self::Yearly => "yearly", Self::Weekly => "weekly" } }
+    pub fn parse(s: &str) -> Option<Self> { match s { "monthly" => Some(Self::Monthly), "yearly" => Some(Self::Yearly), "weekly" => Some(Self::Weekly), _ => None } }
+}
 
 impl Subscription {
     pub fn create(customer_id: impl Into<String>, plan_id: impl Into<String>, amount: Money, cycle: BillingCycle) -> Self {
         let id = uuid::Uuid::new_v4().to_string();
         let now = Utc::now().date_naive();
-        let period_end = match cycle { BillingCycle::Monthly => now + chrono::Duration::days(30), BillingCycle::Yearly => now + chrono::Duration::days(365), BillingCycle::Weekly => now + chrono::Duration::days(7) };
+        let total_paid = Money::zero(&amount.currency);
+        let period_end = cycle.period_end(now);
         let mut s = Self {
             id: id.clone(), customer_id: customer_id.into(), plan_id: plan_id.into(), status: SubscriptionStatus::Active,
             current_period_start: now, current_period_end: period_end, billing_cycle: cycle, amount,
-            cancel_at_period_end: false, cancelled_at: None, created_at: Utc::now(), events: vec![],
+            cancel_at_period_end: false, cancelled_at: None, metadata: Metadata::new(), total_paid, renewals: 0, consecutive_failures: 0, last_payment_failed_at: None,
+            dunning: DunningPolicy::default(), created_at: Utc::now(), events: vec![],
         };
         s.raise_event(DomainEvent::Subscription(SubscriptionEvent::Created { subscription_id: id }));
         s
     }
+
+    /// Starts with `trial_days` free: the first billing period begins when the trial ends.
+    /// A zero-day trial is an ordinary subscription.
+    pub fn create_with_trial(customer_id: impl Into<String>, plan_id: impl Into<String>, amount: Money, cycle: BillingCycle, trial_days: u32) -> Self {
+        let mut s = Self::create(customer_id, plan_id, amount, cycle);
+        if trial_days == 0 { return s; }
+        s.events.clear();
+        let trial_end = s.current_period_start + chrono::Duration::days(i64::from(trial_days));
+        s.status = SubscriptionStatus::Trialing;
+        s.current_period_start = trial_end;
+        s.current_period_end = s.billing_cycle.period_end(trial_end);
+        s.raise_event(DomainEvent::Subscription(SubscriptionEvent::TrialStarted { subscription_id: s.id.clone(), trial_end }));
+        s
+    }
+
+    /// Ends the trial and starts billing, raising the `Created` event a subscription
+    /// without a trial raises straight away.
+    pub fn convert_trial(&mut self) -> Result<(), SubscriptionError> {
+        if self.status != SubscriptionStatus::Trialing { return Err(SubscriptionError::NotTrialing); }
+        self.status = SubscriptionStatus::Active;
+        self.raise_event(DomainEvent::Subscription(SubscriptionEvent::Created { subscription_id: self.id.clone() }));
+        Ok(())
+    }
     
     pub fn id(&self) -> &str { &self.id }
     pub fn status(&self) -> &SubscriptionStatus { &self.status }
     pub fn amount(&self) -> &Money { &self.amount }
+    pub fn customer_id(&self) -> &str { &self.customer_id }
+    pub fn plan_id(&self) -> &str { &self.plan_id }
+    pub fn created_at(&self) -> DateTime<Utc> { self.created_at }
+    pub fn billing_cycle(&self) -> &BillingCycle { &self.billing_cycle }
+    pub fn current_period_start(&self) -> NaiveDate { self.current_period_start }
+    pub fn current_period_end(&self) -> NaiveDate { self.current_period_end }
+    pub fn metadata(&self) -> &Metadata { &self.metadata }
+
+    pub fn with_metadata(mut self, metadata: Metadata) -> Result<Self, SubscriptionError> {
+        metadata::validate(&metadata).map_err(SubscriptionError::InvalidMetadata)?;
+        self.metadata = metadata;
+        Ok(self)
+    }
+
+    /// Merges `patch` into the existing metadata; an empty value removes that key.
+    pub fn update_metadata(&mut self, patch: &Metadata) -> Result<(), SubscriptionError> {
+        self.metadata = metadata::merge(&self.metadata, patch).map_err(SubscriptionError::InvalidMetadata)?;
+        Ok(())
+    }
+    pub fn with_dunning(mut self, policy: DunningPolicy) -> Self {
+        self.dunning = policy;
+        self
+    }
+
     pub fn is_active(&self) -> bool { self.status == SubscriptionStatus::Active }
+
+    /// Active with its current period over by `today`, so the next one must be paid for, or
+    /// `PastDue` with the dunning policy's next retry reached.
+    pub fn is_due(&self, today: NaiveDate) -> bool {
+        match self.status {
+            SubscriptionStatus::Active => self.current_period_end <= today,
+            SubscriptionStatus::PastDue => self.last_payment_failed_at
+                .is_none_or(|failed| failed.date_naive() + chrono::Duration::days(i64::from(self.dunning.retry_after_days)) <= today),
+            _ => false,
+        }
+    }
+    pub fn cancel_at_period_end(&self) -> bool { self.cancel_at_period_end }
+
+    pub fn to_record(&self) -> SubscriptionRecord {
+        SubscriptionRecord {
+            id: self.id.clone(), customer_id: self.customer_id.clone(), plan_id: self.plan_id.clone(), status: self.status.clone(),
+            current_period_start: self.current_period_start, current_period_end: self.current_period_end, billing_cycle: self.billing_cycle.clone(),
+            amount: self.amount.clone(), cancel_at_period_end: self.cancel_at_period_end, metadata: self.metadata.clone(),
+            total_paid: self.total_paid.clone(), renewals: self.renewals, consecutive_failures: self.consecutive_failures,
+            last_payment_failed_at: self.last_payment_failed_at, created_at: self.created_at,
+        }
+    }
+
+    /// Rebuilds a stored subscription, to be billed under `dunning`. Nothing is raised: its
+    /// events were published when it was saved.
+    pub fn from_record(record: SubscriptionRecord, dunning: DunningPolicy) -> Self {
+        Self {
+            id: record.id, customer_id: record.customer_id, plan_id: record.plan_id, status: record.status,
+            current_period_start: record.current_period_start, current_period_end: record.current_period_end, billing_cycle: record.billing_cycle,
+            amount: record.amount, cancel_at_period_end: record.cancel_at_period_end, cancelled_at: None, metadata: record.metadata,
+            total_paid: record.total_paid, renewals: record.renewals, consecutive_failures: record.consecutive_failures,
+            last_payment_failed_at: record.last_payment_failed_at, dunning, created_at: record.created_at, events: vec![],
+        }
+    }
     
+    /// Adds a successful charge to the lifetime total and clears the failure streak.
+    pub fn record_payment(&mut self, paid: &Money) -> Result<(), SubscriptionError> {
+        self.total_paid = self.total_paid.checked_add(paid).map_err(|e| SubscriptionError::InvalidAmount(e.to_string()))?;
+        self.consecutive_failures = 0;
+        Ok(())
+    }
+
+    /// A renewal charge of `paid` went through: a delinquent subscription recovers, and the
+    /// next period starts.
+    pub fn pay_renewal(&mut self, paid: &Money) -> Result<(), SubscriptionError> {
+        match self.status {
+            SubscriptionStatus::Active | SubscriptionStatus::PastDue | SubscriptionStatus::Unpaid => {}
+            SubscriptionStatus::Cancelled => return Err(SubscriptionError::AlreadyCancelled),
+            _ => return Err(SubscriptionError::NotBillable),
+        }
+        self.record_payment(paid)?;
+        if self.is_delinquent() { self.recover()?; }
+        self.renew();
+        Ok(())
+    }
+
+    /// Records that renewal attempt `attempt` (1-based) failed. The subscription is
+    /// `PastDue` until the policy's last attempt fails, then `Unpaid` or cancelled.
+    pub fn mark_payment_failed(&mut self, attempt: u32) -> Result<(), SubscriptionError> {
+        match self.status {
+            SubscriptionStatus::Active | SubscriptionStatus::PastDue => {}
+            SubscriptionStatus::Cancelled => return Err(SubscriptionError::AlreadyCancelled),
+            _ => return Err(SubscriptionError::NotBillable),
+        }
+        let attempt = attempt.max(1);
+        self.consecutive_failures = attempt;
+        self.last_payment_failed_at = Some(Utc::now());
+        self.raise_event(DomainEvent::Subscription(SubscriptionEvent::PaymentFailed { subscription_id: self.id.clone(), attempt }));
+        if attempt < self.dunning.max_attempts {
+            self.status = SubscriptionStatus::PastDue;
+        } else {
+            match self.dunning.outcome {
+                DunningOutcome::Unpaid => self.status = SubscriptionStatus::Unpaid,
+                DunningOutcome::Cancelled => self.cancel(false),
+            }
+        }
+        Ok(())
+    }
+
+    /// A retried renewal went through: back to `Active` with the failure streak cleared.
+    pub fn recover(&mut self) -> Result<(), SubscriptionError> {
+        if !self.is_delinquent() { return Err(SubscriptionError::NotPastDue); }
+        self.status = SubscriptionStatus::Active;
+        self.consecutive_failures = 0;
+        Ok(())
+    }
+
+    fn is_delinquent(&self) -> bool { matches!(self.status, SubscriptionStatus::PastDue | SubscriptionStatus::Unpaid) }
+
+    pub fn metrics(&self) -> SubscriptionMetrics {
+        SubscriptionMetrics {
+            total_paid: self.total_paid.clone(),
+            renewals: self.renewals,
+            mrr: monthly_recurring_revenue(&self.amount, &self.billing_cycle),
+            consecutive_failures: self.consecutive_failures,
+            churn_risk: churn_risk(self.consecutive_failures, self.is_delinquent(), self.cancel_at_period_end),
+        }
+    }
+
     pub fn renew(&mut self) {
+        self.renewals += 1;
         self.current_period_start = self.current_period_end;
-        self.current_period_end = match self.billing_cycle { BillingCycle::Monthly => self.current_period_start + chrono::Duration::days(30), BillingCycle::Yearly => self.current_period_start + chrono::Duration::days(365), BillingCycle::Weekly => self.current_period_start + chrono::Duration::days(7) };
+        self.current_period_end = self.billing_cycle.period_end(self.current_period_start);
         self.raise_event(DomainEvent::Subscription(SubscriptionEvent::Renewed { subscription_id: self.id.clone() }));
     }
     
@@ -57,6 +263,32 @@ impl Subscription {
         self.raise_event(DomainEvent::Subscription(SubscriptionEvent::Cancelled { subscription_id: self.id.clone(), at_period_end }));
     }
     
+    /// Moves to `new_plan_id` at `new_amount` for the rest of the current period and returns
+    /// what to charge now: the new price for the remaining days minus a credit for the unused
+    /// part of the old one. A downgrade comes out negative, i.e. a credit owed to the customer.
+    pub fn change_plan(&mut self, new_plan_id: impl Into<String>, new_amount: Money, now: NaiveDate) -> Result<Money, SubscriptionError> {
+        if self.status == SubscriptionStatus::Cancelled { return Err(SubscriptionError::AlreadyCancelled); }
+        if new_amount.currency != self.amount.currency {
+            return Err(SubscriptionError::InvalidAmount(format!("plan is billed in {}, not {}", self.amount.currency, new_amount.currency)));
+        }
+        if now < self.current_period_start || now > self.current_period_end {
+            return Err(SubscriptionError::OutsideCurrentPeriod(now));
+        }
+        let total_days = (self.current_period_end - self.current_period_start).num_days();
+        let remaining_days = (self.current_period_end - now).num_days();
+        let credit = prorate(&self.amount, remaining_days, total_days);
+        let charge = prorate(&new_amount, remaining_days, total_days);
+        let net = charge.checked_sub(&credit).map_err(|e| SubscriptionError::InvalidAmount(e.to_string()))?;
+
+        let new_plan_id = new_plan_id.into();
+        let old_plan_id = std::mem::replace(&mut self.plan_id, new_plan_id.clone());
+        self.amount = new_amount;
+        self.raise_event(DomainEvent::Subscription(SubscriptionEvent::PlanChanged {
+            subscription_id: self.id.clone(), old_plan_id, new_plan_id, prorated_amount: net.amount,
+        }));
+        Ok(net)
+    }
+
     pub fn pause(&mut self) { self.status = SubscriptionStatus::Paused; }
     pub fn resume(&mut self) { if self.status == SubscriptionStatus::Paused { self.status = SubscriptionStatus::Active; } }
     
@@ -64,15 +296,24 @@ impl Subscription {
     fn raise_event(&mut self, e: DomainEvent) { self.events.push(e); }
 }
 
-#[derive(Debug, Clone)] pub enum SubscriptionError { AlreadyCancelled, NotPaused }
+/// `amount` scaled to `remaining` of `total` days, rounded to the currency's minor units.
+fn prorate(amount: &Money, remaining: i64, total: i64) -> Money {
+    if total <= 0 { return Money::zero(&amount.currency); }
+    Money::new(amount.amount * rust_decimal::Decimal::from(remaining) / rust_decimal::Decimal::from(total), &amount.currency).round()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)] pub enum SubscriptionError { AlreadyCancelled, NotPaused, InvalidMetadata(String), InvalidAmount(String), OutsideCurrentPeriod(NaiveDate), NotTrialing, NotBillable, NotPastDue }
 impl std::error::Error for SubscriptionError {}
 impl std::fmt::Display for SubscriptionError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { write!(f, "Subscription error") }
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self { Self::InvalidMetadata(m) => write!(f, "Invalid metadata: {}", m), Self::InvalidAmount(m) => write!(f, "Invalid amount: {}", m), Self::OutsideCurrentPeriod(d) => write!(f, "{} is outside the current billing period", d), _ => write!(f, "Subscription error") }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rust_decimal::Decimal;
     #[test]
     fn test_subscription() {
         let mut s = Subscription::create("CUST001", "PLAN_PRO", Money::usd(Decimal::new(49, 0)), BillingCycle::Monthly);
@@ -80,4 +321,192 @@ mod tests {
         s.cancel(true);
         assert!(s.cancel_at_period_end);
     }
+
+    #[test]
+    fn test_subscription_metadata() {
+        let meta: Metadata = [("contract_id".to_string(), "C-42".to_string())].into();
+        let mut s = Subscription::create("CUST001", "PLAN_PRO", Money::usd(Decimal::new(49, 0)), BillingCycle::Monthly)
+            .with_metadata(meta.clone())
+            .unwrap();
+        assert_eq!(s.metadata(), &meta);
+
+        s.update_metadata(&[("seats".to_string(), "10".to_string())].into()).unwrap();
+        assert_eq!(s.metadata().len(), 2);
+        let too_long: Metadata = [("note".to_string(), "x".repeat(metadata::MAX_VALUE_LEN + 1))].into();
+        assert!(matches!(s.update_metadata(&too_long), Err(SubscriptionError::InvalidMetadata(_))));
+        assert_eq!(s.metadata().len(), 2);
+    }
+
+    #[test]
+    fn test_lifetime_metrics() {
+        let price = Money::usd(Decimal::new(49000, 2));
+        let mut s = Subscription::create("CUST001", "PLAN_PRO", price.clone(), BillingCycle::Yearly);
+        for _ in 0..2 {
+            s.record_payment(&price).unwrap();
+            s.renew();
+        }
+        let metrics = s.metrics();
+        assert_eq!(metrics.total_paid, Money::usd(Decimal::new(98000, 2)));
+        assert_eq!(metrics.renewals, 2);
+        assert_eq!(metrics.mrr, Money::usd(Decimal::new(4083, 2)));
+        assert_eq!(metrics.churn_risk, crate::domain::services::ChurnRisk::Low);
+
+        s.mark_payment_failed(1).unwrap();
+        assert_eq!(s.status(), &SubscriptionStatus::PastDue);
+        assert_eq!(s.metrics().churn_risk, crate::domain::services::ChurnRisk::High);
+        assert!(s.record_payment(&Money::new(Decimal::ONE, "EUR")).is_err());
+    }
+
+    #[test]
+    fn test_change_plan_proration() {
+        let usd = |cents| Money::usd(Decimal::new(cents, 2));
+        let upgrade = |now_offset: i64| {
+            let mut s = Subscription::create("CUST001", "PLAN_BASIC", usd(3000), BillingCycle::Monthly);
+            let now = s.current_period_start() + chrono::Duration::days(now_offset);
+            s.take_events();
+            let net = s.change_plan("PLAN_PRO", usd(6000), now);
+            (s, net)
+        };
+
+        // Same day: full credit for the old plan, full price for the new one
+        let (mut s, net) = upgrade(0);
+        assert_eq!(net, Ok(usd(3000)));
+        assert_eq!((s.plan_id(), s.amount()), ("PLAN_PRO", &usd(6000)));
+        assert!(matches!(s.take_events().as_slice(), [DomainEvent::Subscription(SubscriptionEvent::PlanChanged { old_plan_id, new_plan_id, prorated_amount, .. })]
+            if old_plan_id == "PLAN_BASIC" && new_plan_id == "PLAN_PRO" && *prorated_amount == Decimal::new(3000, 2)));
+
+        // Halfway through a 30-day period
+        assert_eq!(upgrade(15).1, Ok(usd(1500)));
+        // Last day of the period: one day left, rounded to cents
+        assert_eq!(upgrade(29).1, Ok(usd(100)));
+        // At the period end nothing is left to prorate
+        assert_eq!(upgrade(30).1, Ok(usd(0)));
+        assert_eq!(upgrade(31).1, Err(SubscriptionError::OutsideCurrentPeriod(upgrade(0).0.current_period_start() + chrono::Duration::days(31))));
+
+        let mut s = Subscription::create("CUST001", "PLAN_PRO", usd(6000), BillingCycle::Monthly);
+        let start = s.current_period_start();
+        assert_eq!(s.change_plan("PLAN_BASIC", usd(3000), start + chrono::Duration::days(10)), Ok(usd(-2000)));
+        assert!(matches!(s.change_plan("PLAN_EUR", Money::new(Decimal::ONE, "EUR"), start), Err(SubscriptionError::InvalidAmount(_))));
+        assert_eq!(s.plan_id(), "PLAN_BASIC");
+        s.cancel(false);
+        assert_eq!(s.change_plan("PLAN_PRO", usd(6000), start), Err(SubscriptionError::AlreadyCancelled));
+    }
+
+    #[test]
+    fn test_trial_period() {
+        let price = Money::usd(Decimal::new(49, 0));
+        let today = Utc::now().date_naive();
+        let mut s = Subscription::create_with_trial("CUST001", "PLAN_PRO", price.clone(), BillingCycle::Monthly, 14);
+        assert_eq!(s.status(), &SubscriptionStatus::Trialing);
+        assert!(!s.is_active());
+        let trial_end = today + chrono::Duration::days(14);
+        assert_eq!(s.current_period_start(), trial_end);
+        assert_eq!(s.current_period_end(), trial_end + chrono::Duration::days(30));
+        assert!(matches!(s.take_events().as_slice(), [DomainEvent::Subscription(SubscriptionEvent::TrialStarted { trial_end: end, .. })] if *end == trial_end));
+
+        s.convert_trial().unwrap();
+        assert!(s.is_active());
+        assert_eq!(s.current_period_start(), trial_end);
+        assert!(matches!(s.take_events().as_slice(), [DomainEvent::Subscription(SubscriptionEvent::Created { .. })]));
+        assert_eq!(s.convert_trial(), Err(SubscriptionError::NotTrialing));
+
+        let mut no_trial = Subscription::create_with_trial("CUST001", "PLAN_PRO", price, BillingCycle::Weekly, 0);
+        assert!(no_trial.is_active());
+        assert_eq!(no_trial.current_period_start(), today);
+        assert_eq!(no_trial.current_period_end(), today + chrono::Duration::days(7));
+        assert!(matches!(no_trial.take_events().as_slice(), [DomainEvent::Subscription(SubscriptionEvent::Created { .. })]));
+        assert_eq!(no_trial.convert_trial(), Err(SubscriptionError::NotTrialing));
+    }
+
+    #[test]
+    fn test_dunning_escalation() {
+        let price = Money::usd(Decimal::new(49, 0));
+        let mut s = Subscription::create("CUST001", "PLAN_PRO", price.clone(), BillingCycle::Monthly)
+            .with_dunning(DunningPolicy { max_attempts: 3, outcome: DunningOutcome::Unpaid, retry_after_days: 2 });
+        s.take_events();
+        for attempt in 1..=2 {
+            s.mark_payment_failed(attempt).unwrap();
+            assert_eq!(s.status(), &SubscriptionStatus::PastDue);
+            assert_eq!(s.metrics().consecutive_failures, attempt);
+            assert!(matches!(s.take_events().as_slice(), [DomainEvent::Subscription(SubscriptionEvent::PaymentFailed { attempt: a, .. })] if *a == attempt));
+        }
+
+        // A retry that goes through recovers the subscription
+        s.recover().unwrap();
+        assert!(s.is_active());
+        assert_eq!(s.metrics().consecutive_failures, 0);
+        assert_eq!(s.recover(), Err(SubscriptionError::NotPastDue));
+
+        for attempt in 1..=3 { s.mark_payment_failed(attempt).unwrap(); }
+        assert_eq!(s.status(), &SubscriptionStatus::Unpaid);
+        assert_eq!(s.metrics().churn_risk, crate::domain::services::ChurnRisk::High);
+        assert_eq!(s.mark_payment_failed(4), Err(SubscriptionError::NotBillable));
+        s.recover().unwrap();
+        assert!(s.is_active());
+
+        let mut cancelling = Subscription::create("CUST001", "PLAN_PRO", price, BillingCycle::Monthly)
+            .with_dunning(DunningPolicy { max_attempts: 1, outcome: DunningOutcome::Cancelled, ..DunningPolicy::default() });
+        cancelling.take_events();
+        cancelling.mark_payment_failed(1).unwrap();
+        assert_eq!(cancelling.status(), &SubscriptionStatus::Cancelled);
+        assert!(matches!(cancelling.take_events().as_slice(), [
+            DomainEvent::Subscription(SubscriptionEvent::PaymentFailed { attempt: 1, .. }),
+            DomainEvent::Subscription(SubscriptionEvent::Cancelled { at_period_end: false, .. }),
+        ]));
+        assert_eq!(cancelling.recover(), Err(SubscriptionError::NotPastDue));
+        assert_eq!(cancelling.mark_payment_failed(2), Err(SubscriptionError::AlreadyCancelled));
+    }
+
+    #[test]
+    fn test_dunning_retry_schedule() {
+        let price = Money::usd(Decimal::new(49, 0));
+        let policy = DunningPolicy { max_attempts: 2, outcome: DunningOutcome::Unpaid, retry_after_days: 3 };
+        let mut s = Subscription::create("CUST001", "PLAN_PRO", price.clone(), BillingCycle::Monthly).with_dunning(policy);
+        let end = s.current_period_end();
+        s.mark_payment_failed(1).unwrap();
+        let today = Utc::now().date_naive();
+        assert!(!s.is_due(today + chrono::Duration::days(2)));
+        assert!(s.is_due(today + chrono::Duration::days(3)));
+        // The policy comes from whoever loads it, not the record
+        let stored = Subscription::from_record(s.to_record(), DunningPolicy { retry_after_days: 1, ..policy });
+        assert!(stored.is_due(today + chrono::Duration::days(1)));
+
+        // A paid retry recovers the subscription and bills the period it was late for
+        s.pay_renewal(&price).unwrap();
+        assert!(s.is_active());
+        assert_eq!((s.current_period_start(), s.metrics().renewals, s.metrics().consecutive_failures), (end, 1, 0));
+
+        s.mark_payment_failed(1).unwrap();
+        s.mark_payment_failed(2).unwrap();
+        assert_eq!(s.status(), &SubscriptionStatus::Unpaid);
+        assert!(!s.is_due(today + chrono::Duration::days(30)));
+        s.pay_renewal(&price).unwrap();
+        assert!(s.is_active());
+        s.cancel(false);
+        assert_eq!(s.pay_renewal(&price), Err(SubscriptionError::AlreadyCancelled));
+        assert_eq!(s.metrics().renewals, 2);
+    }
+
+    #[test]
+    fn test_record_round_trip_and_due() {
+        let mut s = Subscription::create("CUST001", "PLAN_PRO", Money::usd(Decimal::new(49, 0)), BillingCycle::Weekly);
+        s.take_events();
+        let end = s.current_period_end();
+        assert!(!s.is_due(end - chrono::Duration::days(1)));
+        assert!(s.is_due(end));
+
+        s.record_payment(&Money::usd(Decimal::new(49, 0))).unwrap();
+        s.renew();
+        let mut restored = Subscription::from_record(s.to_record(), DunningPolicy::default());
+        assert_eq!(restored.to_record(), s.to_record());
+        assert!(restored.take_events().is_empty());
+        assert_eq!((restored.current_period_start(), restored.metrics().renewals), (end, 1));
+        assert!(!restored.is_due(end));
+
+        for status in [SubscriptionStatus::Active, SubscriptionStatus::PastDue, SubscriptionStatus::Cancelled, SubscriptionStatus::Trialing, SubscriptionStatus::Paused, SubscriptionStatus::Unpaid] {
+            assert_eq!(SubscriptionStatus::parse(status.as_str()), Some(status));
+        }
+        let paused = Subscription::from_record(SubscriptionRecord { status: SubscriptionStatus::Paused, ..s.to_record() }, DunningPolicy::default());
+        assert!(!paused.is_due(end + chrono::Duration::days(30)));
+    }
 }
@@ -1,22 +1,161 @@
 //! Payment domain events
+//!
+//! Events serialize as flat JSON objects whose `type` is the event name, e.g.
+//! `{"type":"payment.refunded","payment_id":"pay_…","amount":"25.00"}`. The names are
+//! part of the wire format: consumers route on them, so they must not change.
+pub mod publisher;
+
+use chrono::NaiveDate;
 use rust_decimal::Decimal;
-use crate::domain::value_objects::PaymentId;
+use serde::{Deserialize, Serialize};
+use crate::domain::value_objects::{decimal_str, PaymentId};
+
+/// The aggregate wrapper adds nothing to the JSON; the inner `type` already names it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DomainEvent { Payment(PaymentEvent), Subscription(SubscriptionEvent), PaymentMethod(PaymentMethodEvent), Dispute(DisputeEvent) }
 
-#[derive(Clone, Debug)]
-pub enum DomainEvent { Payment(PaymentEvent), Subscription(SubscriptionEvent) }
+impl DomainEvent {
+    /// `<aggregate>.<event>`, the same string serialized as `type`.
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            Self::Payment(e) => match e {
+                PaymentEvent::Created { .. } => "payment.created",
+                PaymentEvent::Succeeded { .. } => "payment.succeeded",
+                PaymentEvent::Failed { .. } => "payment.failed",
+                PaymentEvent::Refunded { .. } => "payment.refunded",
+                PaymentEvent::Cancelled { .. } => "payment.cancelled",
+            },
+            Self::Subscription(e) => match e {
+                SubscriptionEvent::Created { .. } => "subscription.created",
+                SubscriptionEvent::TrialStarted { .. } => "subscription.trial_started",
+                SubscriptionEvent::Renewed { .. } => "subscription.renewed",
+                SubscriptionEvent::Cancelled { .. } => "subscription.cancelled",
+                SubscriptionEvent::PaymentFailed { .. } => "subscription.payment_failed",
+                SubscriptionEvent::PlanChanged { .. } => "subscription.plan_changed",
+                SubscriptionEvent::UsageRecorded { .. } => "subscription.usage_recorded",
+            },
+            Self::PaymentMethod(e) => match e {
+                PaymentMethodEvent::Expiring { .. } => "payment_method.expiring",
+            },
+            Self::Dispute(e) => match e {
+                DisputeEvent::Opened { .. } => "dispute.opened",
+                DisputeEvent::EvidenceSubmitted { .. } => "dispute.evidence_submitted",
+                DisputeEvent::Won { .. } => "dispute.won",
+                DisputeEvent::Lost { .. } => "dispute.lost",
+            },
+        }
+    }
+}
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum PaymentEvent {
-    Created { payment_id: PaymentId, amount: Decimal },
+    #[serde(rename = "payment.created")]
+    Created { payment_id: PaymentId, #[serde(with = "decimal_str")] amount: Decimal },
+    #[serde(rename = "payment.succeeded")]
     Succeeded { payment_id: PaymentId },
+    #[serde(rename = "payment.failed")]
     Failed { payment_id: PaymentId, reason: String },
-    Refunded { payment_id: PaymentId, amount: Decimal },
+    #[serde(rename = "payment.refunded")]
+    Refunded { payment_id: PaymentId, #[serde(with = "decimal_str")] amount: Decimal },
+    /// Voided before it settled; nothing was taken.
+    #[serde(rename = "payment.cancelled")]
+    Cancelled { payment_id: PaymentId },
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum SubscriptionEvent {
+    #[serde(rename = "subscription.created")]
     Created { subscription_id: String },
+    /// Billing starts on `trial_end`.
+    #[serde(rename = "subscription.trial_started")]
+    TrialStarted { subscription_id: String, trial_end: NaiveDate },
+    #[serde(rename = "subscription.renewed")]
     Renewed { subscription_id: String },
+    #[serde(rename = "subscription.cancelled")]
     Cancelled { subscription_id: String, at_period_end: bool },
-    PaymentFailed { subscription_id: String },
+    /// Renewal attempt `attempt` (1-based) failed.
+    #[serde(rename = "subscription.payment_failed")]
+    PaymentFailed { subscription_id: String, attempt: u32 },
+    /// `prorated_amount` is what was charged (or, when negative, credited) for the change.
+    #[serde(rename = "subscription.plan_changed")]
+    PlanChanged { subscription_id: String, old_plan_id: String, new_plan_id: String, #[serde(with = "decimal_str")] prorated_amount: Decimal },
+    /// `amount` is `quantity` times the unit amount, billed with the next renewal.
+    #[serde(rename = "subscription.usage_recorded")]
+    UsageRecorded { subscription_id: String, quantity: i64, #[serde(with = "decimal_str")] amount: Decimal },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum PaymentMethodEvent {
+    #[serde(rename = "payment_method.expiring")]
+    Expiring { payment_method_id: String, customer_id: String, exp_month: u32, exp_year: i32 },
+}
+
+/// `payment_id` is the disputed charge's reference.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum DisputeEvent {
+    #[serde(rename = "dispute.opened")]
+    Opened { dispute_id: String, payment_id: PaymentId, #[serde(with = "decimal_str")] amount: Decimal, reason: String },
+    #[serde(rename = "dispute.evidence_submitted")]
+    EvidenceSubmitted { dispute_id: String, payment_id: PaymentId },
+    #[serde(rename = "dispute.won")]
+    Won { dispute_id: String, payment_id: PaymentId },
+    /// `amount` went back to the cardholder.
+    #[serde(rename = "dispute.lost")]
+    Lost { dispute_id: String, payment_id: PaymentId, #[serde(with = "decimal_str")] amount: Decimal },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_json_shape() {
+        let id = || PaymentId::from_string("pay_1");
+        let sub = || "sub_1".to_string();
+        let cases = vec![
+            (DomainEvent::Payment(PaymentEvent::Created { payment_id: id(), amount: Decimal::new(5000, 2) }),
+                json!({ "type": "payment.created", "payment_id": "pay_1", "amount": "50.00" })),
+            (DomainEvent::Payment(PaymentEvent::Succeeded { payment_id: id() }),
+                json!({ "type": "payment.succeeded", "payment_id": "pay_1" })),
+            (DomainEvent::Payment(PaymentEvent::Failed { payment_id: id(), reason: "declined".into() }),
+                json!({ "type": "payment.failed", "payment_id": "pay_1", "reason": "declined" })),
+            (DomainEvent::Payment(PaymentEvent::Refunded { payment_id: id(), amount: Decimal::new(2500, 2) }),
+                json!({ "type": "payment.refunded", "payment_id": "pay_1", "amount": "25.00" })),
+            (DomainEvent::Payment(PaymentEvent::Cancelled { payment_id: id() }),
+                json!({ "type": "payment.cancelled", "payment_id": "pay_1" })),
+            (DomainEvent::Subscription(SubscriptionEvent::Created { subscription_id: sub() }),
+                json!({ "type": "subscription.created", "subscription_id": "sub_1" })),
+            (DomainEvent::Subscription(SubscriptionEvent::TrialStarted { subscription_id: sub(), trial_end: NaiveDate::from_ymd_opt(2026, 2, 1).unwrap() }),
+                json!({ "type": "subscription.trial_started", "subscription_id": "sub_1", "trial_end": "2026-02-01" })),
+            (DomainEvent::Subscription(SubscriptionEvent::Renewed { subscription_id: sub() }),
+                json!({ "type": "subscription.renewed", "subscription_id": "sub_1" })),
+            (DomainEvent::Subscription(SubscriptionEvent::Cancelled { subscription_id: sub(), at_period_end: true }),
+                json!({ "type": "subscription.cancelled", "subscription_id": "sub_1", "at_period_end": true })),
+            (DomainEvent::Subscription(SubscriptionEvent::PaymentFailed { subscription_id: sub(), attempt: 2 }),
+                json!({ "type": "subscription.payment_failed", "subscription_id": "sub_1", "attempt": 2 })),
+            (DomainEvent::Subscription(SubscriptionEvent::PlanChanged { subscription_id: sub(), old_plan_id: "basic".into(), new_plan_id: "pro".into(), prorated_amount: Decimal::new(-1050, 2) }),
+                json!({ "type": "subscription.plan_changed", "subscription_id": "sub_1", "old_plan_id": "basic", "new_plan_id": "pro", "prorated_amount": "-10.50" })),
+            (DomainEvent::Subscription(SubscriptionEvent::UsageRecorded { subscription_id: sub(), quantity: 3, amount: Decimal::new(450, 2) }),
+                json!({ "type": "subscription.usage_recorded", "subscription_id": "sub_1", "quantity": 3, "amount": "4.50" })),
+            (DomainEvent::PaymentMethod(PaymentMethodEvent::Expiring { payment_method_id: "pm_1".into(), customer_id: "cus_1".into(), exp_month: 3, exp_year: 2027 }),
+                json!({ "type": "payment_method.expiring", "payment_method_id": "pm_1", "customer_id": "cus_1", "exp_month": 3, "exp_year": 2027 })),
+            (DomainEvent::Dispute(DisputeEvent::Opened { dispute_id: "dp_1".into(), payment_id: id(), amount: Decimal::new(5000, 2), reason: "fraudulent".into() }),
+                json!({ "type": "dispute.opened", "dispute_id": "dp_1", "payment_id": "pay_1", "amount": "50.00", "reason": "fraudulent" })),
+            (DomainEvent::Dispute(DisputeEvent::Lost { dispute_id: "dp_1".into(), payment_id: id(), amount: Decimal::new(5000, 2) }),
+                json!({ "type": "dispute.lost", "dispute_id": "dp_1", "payment_id": "pay_1", "amount": "50.00" })),
+        ];
+        for (event, expected) in cases {
+            let encoded = serde_json::to_value(&event).unwrap();
+            assert_eq!(encoded, expected);
+            assert_eq!(encoded["type"], event.event_name());
+            assert_eq!(serde_json::from_value::<DomainEvent>(encoded).unwrap(), event);
+        }
+        assert!(serde_json::from_value::<DomainEvent>(json!({ "type": "payment.settled", "payment_id": "pay_1" })).is_err());
+    }
 }
@@ -0,0 +1,79 @@
+//! Publishing domain events to a message bus
+//!
+//! Each event goes out as JSON on `payments.<aggregate>.<event>`, e.g.
+//! `payments.payment.succeeded`. The bus sits behind `EventPublisher` so callers don't
+//! depend on a particular client.
+
+use std::sync::Mutex;
+use async_trait::async_trait;
+use super::DomainEvent;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PublishError { Encoding(String), Transport(String) }
+impl std::error::Error for PublishError {}
+impl std::fmt::Display for PublishError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self { Self::Encoding(m) => write!(f, "Event encoding error: {}", m), Self::Transport(m) => write!(f, "Event publish failed: {}", m) }
+    }
+}
+
+/// A message bus, e.g. a NATS connection.
+#[async_trait]
+pub trait EventPublisher: Send + Sync {
+    async fn publish_bytes(&self, subject: String, payload: Vec<u8>) -> Result<(), PublishError>;
+
+    /// Checks the bus is reachable, for readiness probes. Publishers without a
+    /// connection to lose are always reachable.
+    async fn ping(&self) -> Result<(), PublishError> { Ok(()) }
+}
+
+/// The subject `event` is published on.
+pub fn subject(event: &DomainEvent) -> String { format!("payments.{}", event.event_name()) }
+
+/// Serializes `event` to JSON and publishes it on `subject`.
+pub async fn publish(client: &dyn EventPublisher, subject: &str, event: &DomainEvent) -> Result<(), PublishError> {
+    let payload = serde_json::to_vec(event).map_err(|e| PublishError::Encoding(e.to_string()))?;
+    client.publish_bytes(subject.to_string(), payload).await
+}
+
+/// Keeps every published message in memory, for tests and dry runs.
+#[derive(Default)]
+pub struct RecordingPublisher { messages: Mutex<Vec<(String, Vec<u8>)>> }
+
+impl RecordingPublisher {
+    /// `(subject, payload)` pairs in the order they were published.
+    pub fn messages(&self) -> Vec<(String, Vec<u8>)> { self.messages.lock().unwrap().clone() }
+}
+
+#[async_trait]
+impl EventPublisher for RecordingPublisher {
+    async fn publish_bytes(&self, subject: String, payload: Vec<u8>) -> Result<(), PublishError> {
+        self.messages.lock().unwrap().push((subject, payload));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+    use crate::domain::events::{PaymentEvent, PaymentMethodEvent};
+    use crate::domain::value_objects::PaymentId;
+
+    #[tokio::test]
+    async fn test_publish_serializes_to_subject() {
+        let bus = RecordingPublisher::default();
+        let event = DomainEvent::Payment(PaymentEvent::Refunded { payment_id: PaymentId::from_string("TXN-1"), amount: Decimal::new(2500, 2) });
+        assert_eq!(subject(&event), "payments.payment.refunded");
+        publish(&bus, &subject(&event), &event).await.unwrap();
+
+        let messages = bus.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].0, "payments.payment.refunded");
+        let payload: serde_json::Value = serde_json::from_slice(&messages[0].1).unwrap();
+        assert_eq!(payload, serde_json::json!({ "type": "payment.refunded", "payment_id": "TXN-1", "amount": "25.00" }));
+
+        let expiring = DomainEvent::PaymentMethod(PaymentMethodEvent::Expiring { payment_method_id: "pm_1".into(), customer_id: "c".into(), exp_month: 1, exp_year: 2027 });
+        assert_eq!(subject(&expiring), "payments.payment_method.expiring");
+    }
+}
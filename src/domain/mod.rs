@@ -2,6 +2,9 @@
 pub mod aggregates;
 pub mod value_objects;
 pub mod events;
+pub mod services;
+pub mod repositories;
 pub use aggregates::*;
 pub use value_objects::*;
 pub use events::*;
+pub use services::*;
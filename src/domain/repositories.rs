@@ -0,0 +1,184 @@
+//! Persistence for aggregates
+//!
+//! A repository stores an aggregate's state and, in the same unit of work, hands its
+//! pending events to the outbox, so a saved change and its events are never split.
+//! Events stay on the aggregate until the save succeeds.
+//!
+//! Saves are optimistic: a save only applies over the version that was loaded, so of two
+//! requests that loaded the same payment, the second to save gets
+//! `PaymentError::ConcurrencyConflict` and must reload instead of overwriting the first.
+//! Invoices need no versions: only a draft is ever rewritten, and a finalized one never is.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use async_trait::async_trait;
+use crate::domain::aggregates::{Invoice, InvoiceError, InvoiceRecord, InvoiceStatus, Payment, PaymentError};
+use crate::domain::events::DomainEvent;
+use crate::domain::value_objects::PaymentId;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepositoryError { Storage(String), Corrupt(String), Payment(PaymentError), Invoice(InvoiceError) }
+impl std::error::Error for RepositoryError {}
+impl std::fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self { Self::Storage(m) => write!(f, "Repository storage error: {}", m), Self::Corrupt(m) => write!(f, "Stored aggregate is invalid: {}", m), Self::Payment(e) => e.fmt(f), Self::Invoice(e) => e.fmt(f) }
+    }
+}
+impl From<PaymentError> for RepositoryError {
+    fn from(e: PaymentError) -> Self { Self::Payment(e) }
+}
+impl From<InvoiceError> for RepositoryError {
+    fn from(e: InvoiceError) -> Self { Self::Invoice(e) }
+}
+
+#[async_trait]
+pub trait PaymentRepository: Send + Sync {
+    /// Inserts the payment, or updates it if the stored version is still `payment.version()`,
+    /// then drains its pending events into the outbox and bumps its version. Fails with
+    /// `PaymentError::ConcurrencyConflict` if someone else saved it first.
+    async fn save(&self, payment: &mut Payment) -> Result<(), RepositoryError>;
+    async fn load(&self, id: &PaymentId) -> Result<Option<Payment>, RepositoryError>;
+}
+
+/// Keeps payments and their outbox in memory, for tests.
+#[derive(Default)]
+pub struct InMemoryPaymentRepository {
+    payments: Mutex<HashMap<PaymentId, crate::domain::aggregates::PaymentRecord>>,
+    outbox: Mutex<Vec<DomainEvent>>,
+}
+
+impl InMemoryPaymentRepository {
+    /// Every event saved so far, oldest first.
+    pub fn outbox(&self) -> Vec<DomainEvent> { self.outbox.lock().unwrap().clone() }
+}
+
+#[async_trait]
+impl PaymentRepository for InMemoryPaymentRepository {
+    async fn save(&self, payment: &mut Payment) -> Result<(), RepositoryError> {
+        let mut payments = self.payments.lock().unwrap();
+        let stored = payments.get(payment.id()).map_or(0, |record| record.version);
+        if stored != payment.version() { return Err(PaymentError::ConcurrencyConflict.into()); }
+        let mut record = payment.to_record();
+        record.version += 1;
+        payments.insert(record.id.clone(), record);
+        self.outbox.lock().unwrap().extend(payment.mark_saved());
+        Ok(())
+    }
+
+    async fn load(&self, id: &PaymentId) -> Result<Option<Payment>, RepositoryError> {
+        Ok(self.payments.lock().unwrap().get(id).cloned().map(Payment::from_record))
+    }
+}
+
+#[async_trait]
+pub trait InvoiceRepository: Send + Sync {
+    /// Inserts the invoice or rewrites its stored draft. Fails with `InvoiceError::Finalized`
+    /// if the stored invoice was already finalized.
+    async fn save(&self, invoice: &Invoice) -> Result<(), RepositoryError>;
+    async fn load(&self, id: &str) -> Result<Option<Invoice>, RepositoryError>;
+}
+
+/// Keeps invoices in memory, for tests.
+#[derive(Default)]
+pub struct InMemoryInvoiceRepository {
+    invoices: Mutex<HashMap<String, InvoiceRecord>>,
+}
+
+#[async_trait]
+impl InvoiceRepository for InMemoryInvoiceRepository {
+    async fn save(&self, invoice: &Invoice) -> Result<(), RepositoryError> {
+        let mut invoices = self.invoices.lock().unwrap();
+        if invoices.get(invoice.id()).is_some_and(|stored| stored.status == InvoiceStatus::Finalized) {
+            return Err(InvoiceError::Finalized.into());
+        }
+        invoices.insert(invoice.id().to_string(), invoice.to_record());
+        Ok(())
+    }
+
+    async fn load(&self, id: &str) -> Result<Option<Invoice>, RepositoryError> {
+        Ok(self.invoices.lock().unwrap().get(id).cloned().map(Invoice::from_record))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+    use crate::domain::aggregates::{PaymentRecord, PaymentStatus};
+    use crate::domain::events::PaymentEvent;
+    use crate::domain::services::TaxRates;
+    use crate::domain::value_objects::{BillingDetails, Money, PaymentMethod, PaymentMethodType};
+
+    #[tokio::test]
+    async fn test_refunded_payment_round_trip() {
+        let repository = InMemoryPaymentRepository::default();
+        let mut payment = Payment::create("CUST001", Money::usd(Decimal::new(10000, 2)));
+        payment.process(PaymentMethod { method_type: PaymentMethodType::Card, last_four: Some("4242".into()), brand: Some("Visa".into()), exp_month: Some(12), exp_year: Some(2030) }).unwrap();
+        payment.succeed().unwrap();
+        payment.refund(Money::usd(Decimal::new(2500, 2))).unwrap();
+        repository.save(&mut payment).await.unwrap();
+        assert!(payment.pending_events().is_empty());
+        assert_eq!(payment.version(), 1);
+
+        let mut loaded = repository.load(payment.id()).await.unwrap().unwrap();
+        assert_eq!(loaded.to_record(), payment.to_record());
+        assert_eq!(loaded.status(), &PaymentStatus::PartiallyRefunded);
+        assert_eq!(loaded.refunded_amount(), Decimal::new(2500, 2));
+        assert!(loaded.pending_events().is_empty());
+
+        // The loaded payment keeps enforcing the refund ceiling
+        assert!(loaded.refund(Money::usd(Decimal::new(7501, 2))).is_err());
+        loaded.refund(Money::usd(Decimal::new(7500, 2))).unwrap();
+        repository.save(&mut loaded).await.unwrap();
+        assert_eq!(loaded.version(), 2);
+        assert_eq!(repository.load(payment.id()).await.unwrap().unwrap().status(), &PaymentStatus::Refunded);
+
+        let names: Vec<&str> = repository.outbox().iter().map(DomainEvent::event_name).collect();
+        assert_eq!(names, ["payment.created", "payment.succeeded", "payment.refunded", "payment.refunded"]);
+        assert!(matches!(repository.outbox().last(), Some(DomainEvent::Payment(PaymentEvent::Refunded { amount, .. })) if *amount == Decimal::new(7500, 2)));
+        assert!(repository.load(&PaymentId::new()).await.unwrap().is_none());
+        assert_eq!("partially_refunded".parse::<PaymentStatus>(), Ok(PaymentStatus::PartiallyRefunded));
+    }
+
+    #[tokio::test]
+    async fn test_stale_save_conflicts() {
+        let repository = InMemoryPaymentRepository::default();
+        let mut payment = Payment::create("CUST001", Money::usd(Decimal::new(10000, 2)));
+        payment.process(PaymentMethod { method_type: PaymentMethodType::Card, last_four: None, brand: None, exp_month: None, exp_year: None }).unwrap();
+        payment.succeed().unwrap();
+        repository.save(&mut payment).await.unwrap();
+
+        // Two requests load the same payment and each refunds most of it
+        let mut first = repository.load(payment.id()).await.unwrap().unwrap();
+        let mut second = repository.load(payment.id()).await.unwrap().unwrap();
+        first.refund(Money::usd(Decimal::new(6000, 2))).unwrap();
+        second.refund(Money::usd(Decimal::new(6000, 2))).unwrap();
+        repository.save(&mut first).await.unwrap();
+        assert_eq!(repository.save(&mut second).await, Err(RepositoryError::Payment(PaymentError::ConcurrencyConflict)));
+        assert_eq!(second.pending_events().len(), 1, "a failed save keeps its events");
+
+        let stored = repository.load(payment.id()).await.unwrap().unwrap();
+        assert_eq!((stored.refunded_amount(), stored.version()), (Decimal::new(6000, 2), 2));
+        assert_eq!(repository.outbox().len(), 3);
+
+        // A new aggregate can't overwrite a saved one with the same id
+        let mut copy = Payment::from_record(PaymentRecord { version: 0, ..stored.to_record() });
+        assert!(repository.save(&mut copy).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_finalized_invoice_is_not_overwritten() {
+        let repository = InMemoryInvoiceRepository::default();
+        let mut invoice = Invoice::create("sub_1", "SUB-1-20260101", "USD");
+        invoice.add_line("PLAN_PRO", 1, Money::usd(Decimal::new(4900, 2))).unwrap();
+        repository.save(&invoice).await.unwrap();
+        invoice.finalize(&TaxRates::default(), &BillingDetails::default()).unwrap();
+        repository.save(&invoice).await.unwrap();
+        assert_eq!(repository.load(invoice.id()).await.unwrap(), Some(invoice.clone()));
+
+        // A stale draft of the same invoice can't replace what was billed
+        let draft = Invoice::from_record(InvoiceRecord { status: InvoiceStatus::Draft, lines: vec![], ..invoice.to_record() });
+        assert_eq!(repository.save(&draft).await, Err(RepositoryError::Invoice(InvoiceError::Finalized)));
+        assert_eq!(repository.load(invoice.id()).await.unwrap().unwrap().total().unwrap(), Money::usd(Decimal::new(4900, 2)));
+    }
+}
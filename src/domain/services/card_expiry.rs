@@ -0,0 +1,50 @@
+//! Card expiry rules
+use chrono::NaiveDate;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CardExpiry { Valid, ExpiringSoon, Expired }
+
+/// First day the card is no longer usable. Cards are valid through the last day of their expiry month.
+pub fn expires_on(exp_month: u32, exp_year: i32) -> Option<NaiveDate> {
+    if !(1..=12).contains(&exp_month) { return None; }
+    let (year, month) = if exp_month == 12 { (exp_year + 1, 1) } else { (exp_year, exp_month + 1) };
+    NaiveDate::from_ymd_opt(year, month, 1)
+}
+
+/// Classifies a card as of `today`, warning `notice_days` before it lapses.
+pub fn card_expiry(exp_month: u32, exp_year: i32, today: NaiveDate, notice_days: i64) -> Option<CardExpiry> {
+    let lapses = expires_on(exp_month, exp_year)?;
+    Some(if today >= lapses { CardExpiry::Expired }
+        else if (lapses - today).num_days() <= notice_days { CardExpiry::ExpiringSoon }
+        else { CardExpiry::Valid })
+}
+
+/// Whether the card can no longer be charged; an invalid expiry counts as expired.
+pub fn is_expired(exp_month: u32, exp_year: i32, today: NaiveDate) -> bool {
+    !matches!(expires_on(exp_month, exp_year), Some(lapses) if today < lapses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate { NaiveDate::from_ymd_opt(y, m, d).unwrap() }
+
+    #[test]
+    fn test_valid_through_end_of_month() {
+        assert_eq!(expires_on(12, 2026), Some(date(2027, 1, 1)));
+        assert_eq!(card_expiry(10, 2026, date(2026, 10, 31), 0), Some(CardExpiry::Valid));
+        assert_eq!(card_expiry(10, 2026, date(2026, 10, 31), 1), Some(CardExpiry::ExpiringSoon));
+        assert_eq!(card_expiry(10, 2026, date(2026, 11, 1), 30), Some(CardExpiry::Expired));
+        assert!(!is_expired(10, 2026, date(2026, 10, 31)));
+        assert!(is_expired(10, 2026, date(2026, 11, 1)));
+    }
+
+    #[test]
+    fn test_notice_window() {
+        let today = date(2026, 10, 14);
+        assert_eq!(card_expiry(11, 2026, today, 60), Some(CardExpiry::ExpiringSoon));
+        assert_eq!(card_expiry(11, 2026, today, 30), Some(CardExpiry::Valid));
+        assert_eq!(card_expiry(5, 2028, today, 30), Some(CardExpiry::Valid));
+        assert_eq!(card_expiry(13, 2026, today, 30), None);
+    }
+}
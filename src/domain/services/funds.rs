@@ -0,0 +1,26 @@
+//! Balance sufficiency checks shared by every debit path
+use crate::domain::aggregates::PaymentError;
+use crate::domain::value_objects::Money;
+
+/// Fails with `InsufficientFunds` naming the shortfall when `available` cannot cover `needed`.
+pub fn ensure_sufficient(available: &Money, needed: &Money) -> Result<(), PaymentError> {
+    if available.cmp_checked(needed)?.is_ge() { return Ok(()); }
+    Err(PaymentError::InsufficientFunds(format!("short by {} {}", needed.amount - available.amount, needed.currency)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn test_ensure_sufficient() {
+        let available = Money::usd(Decimal::new(1000, 2));
+        assert!(ensure_sufficient(&available, &Money::usd(Decimal::new(1000, 2))).is_ok());
+        assert_eq!(
+            ensure_sufficient(&available, &Money::usd(Decimal::new(1250, 2))),
+            Err(PaymentError::InsufficientFunds("short by 2.50 USD".into()))
+        );
+        assert!(matches!(ensure_sufficient(&available, &Money::new(Decimal::ONE, "EUR")), Err(PaymentError::CurrencyMismatch { .. })));
+    }
+}
@@ -0,0 +1,63 @@
+//! Late-fee accrual for past-due balances
+use rust_decimal::Decimal;
+use crate::domain::aggregates::PaymentError;
+use crate::domain::value_objects::{currency, Money, Percentage};
+
+/// Simple (non-compounding) interest on `principal` at `daily_rate` for `days`,
+/// rounded half-even to the currency's minor units.
+pub fn accrue_late_fee(principal: &Money, daily_rate: Percentage, days: u32) -> Result<Money, PaymentError> {
+    if !currency::is_known(&principal.currency) { return Err(PaymentError::InvalidCurrency(principal.currency.clone())); }
+    if principal.amount.is_sign_negative() { return Err(PaymentError::InvalidAmount("late fee principal must not be negative".into())); }
+    let fee = principal.amount.checked_mul(daily_rate.as_fraction())
+        .and_then(|daily| daily.checked_mul(Decimal::from(days)))
+        .ok_or_else(|| PaymentError::InvalidAmount("late fee overflow".into()))?;
+    Ok(Money::new(fee, &principal.currency).round())
+}
+
+/// A merchant's late-fee terms: a daily rate, capped at `max_fee` in total.
+#[derive(Clone, Debug)]
+pub struct LateFeePolicy { pub daily_rate: Percentage, pub max_fee: Money }
+
+impl LateFeePolicy {
+    pub fn new(daily_rate: Percentage, max_fee: Money) -> Self { Self { daily_rate, max_fee } }
+
+    pub fn fee_for(&self, principal: &Money, days_overdue: u32) -> Result<Money, PaymentError> {
+        if self.max_fee.currency != principal.currency { return Err(PaymentError::InvalidCurrency(principal.currency.clone())); }
+        let fee = accrue_late_fee(principal, self.daily_rate, days_overdue)?;
+        Ok(if fee > self.max_fee { self.max_fee.clone() } else { fee })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn pct(s: &str) -> Percentage { Percentage::new(s.parse().unwrap()).unwrap() }
+
+    #[test]
+    fn test_accrual_over_several_days() {
+        let principal = Money::usd(Decimal::new(100_000, 2));
+        assert_eq!(accrue_late_fee(&principal, pct("0.05"), 0).unwrap().amount, Decimal::ZERO);
+        assert_eq!(accrue_late_fee(&principal, pct("0.05"), 1).unwrap().amount, Decimal::new(50, 2));
+        assert_eq!(accrue_late_fee(&principal, pct("0.05"), 30).unwrap().amount, Decimal::new(1500, 2));
+        // 1234.57 * 0.0333% * 7 = 2.87772... -> 2.88
+        let odd = Money::usd(Decimal::new(123_457, 2));
+        assert_eq!(accrue_late_fee(&odd, pct("0.0333"), 7).unwrap().amount, Decimal::new(288, 2));
+        let yen = Money::new(Decimal::new(10_001, 0), "JPY");
+        assert_eq!(accrue_late_fee(&yen, pct("0.1"), 3).unwrap().amount, Decimal::new(30, 0));
+    }
+
+    #[test]
+    fn test_accrual_rejects_unknown_currency() {
+        let principal = Money::new(Decimal::new(100, 0), "XXY");
+        assert_eq!(accrue_late_fee(&principal, pct("1"), 1), Err(PaymentError::InvalidCurrency("XXY".into())));
+    }
+
+    #[test]
+    fn test_policy_caps_fee() {
+        let policy = LateFeePolicy::new(pct("1"), Money::usd(Decimal::new(2500, 2)));
+        let principal = Money::usd(Decimal::new(100_000, 2));
+        assert_eq!(policy.fee_for(&principal, 2).unwrap().amount, Decimal::new(2000, 2));
+        assert_eq!(policy.fee_for(&principal, 3).unwrap().amount, Decimal::new(2500, 2));
+        assert_eq!(policy.fee_for(&principal, 90).unwrap().amount, Decimal::new(2500, 2));
+    }
+}
@@ -0,0 +1,83 @@
+//! Double-entry bookkeeping for balance changes
+//!
+//! Every movement of money is posted as entries that debit one account and credit
+//! another by the same amount, so per currency the debits and credits of a posting
+//! always net to zero. Wallets are liabilities: a credit raises a wallet's balance.
+use std::collections::BTreeMap;
+use std::fmt;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use crate::domain::value_objects::Money;
+
+/// Funds entering wallets from outside, e.g. card top-ups.
+pub const TOPUP_CLEARING: &str = "clearing:topups";
+/// Money paid back to customers on refunded charges.
+pub const REFUNDS_PAYABLE: &str = "refunds:payable";
+/// The merchant's share of captured payments, which refunds come out of.
+pub const MERCHANT_REVENUE: &str = "revenue:payments";
+/// Fees charged on wallet-to-wallet transfers.
+pub const TRANSFER_FEES: &str = "revenue:transfer_fees";
+/// Balances that predate the ledger.
+pub const OPENING_BALANCES: &str = "equity:opening_balances";
+
+pub fn wallet_account(wallet_id: impl fmt::Display) -> String { format!("wallet:{}", wallet_id) }
+
+/// Where both legs of a currency conversion meet.
+pub fn fx_account(from: &str, to: &str) -> String { format!("fx:{}-{}", from, to) }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction { Debit, Credit }
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str { match self { Self::Debit => "debit", Self::Credit => "credit" } }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LedgerEntry {
+    pub account: String,
+    pub direction: Direction,
+    pub amount: Money,
+}
+
+/// Moves `amount` from `debit` to `credit`.
+pub fn posting(debit: &str, credit: &str, amount: &Money) -> Vec<LedgerEntry> {
+    vec![
+        LedgerEntry { account: debit.to_string(), direction: Direction::Debit, amount: amount.clone() },
+        LedgerEntry { account: credit.to_string(), direction: Direction::Credit, amount: amount.clone() },
+    ]
+}
+
+/// Whether debits equal credits in every currency the entries touch.
+pub fn is_balanced(entries: &[LedgerEntry]) -> bool {
+    let mut net: BTreeMap<&str, Decimal> = BTreeMap::new();
+    for entry in entries {
+        let signed = match entry.direction { Direction::Debit => entry.amount.amount, Direction::Credit => -entry.amount.amount };
+        *net.entry(entry.amount.currency.as_str()).or_default() += signed;
+    }
+    net.values().all(Decimal::is_zero)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_postings_balance_per_currency() {
+        let ngn = Money::new(Decimal::new(5000, 2), "NGN");
+        let usd = Money::new(Decimal::new(1000, 2), "USD");
+        let topup = posting(TOPUP_CLEARING, &wallet_account("w1"), &ngn);
+        assert_eq!(topup[1], LedgerEntry { account: "wallet:w1".into(), direction: Direction::Credit, amount: ngn.clone() });
+        assert!(is_balanced(&topup));
+
+        // A conversion balances as two postings through the FX account, not one across currencies
+        let fx = fx_account("USD", "NGN");
+        let mut conversion = posting(&wallet_account("w1"), &fx, &usd);
+        conversion.extend(posting(&fx, &wallet_account("w2"), &ngn));
+        assert!(is_balanced(&conversion));
+        let mut lopsided = posting(&wallet_account("w1"), &fx, &usd);
+        lopsided.push(LedgerEntry { account: wallet_account("w2"), direction: Direction::Credit, amount: ngn });
+        assert!(!is_balanced(&lopsided));
+        assert!(is_balanced(&[]));
+    }
+}
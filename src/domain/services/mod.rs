@@ -0,0 +1,16 @@
+//! Domain services
+pub mod card_expiry;
+pub mod funds;
+pub mod late_fees;
+pub mod ledger;
+pub mod subscription_metrics;
+pub mod tax;
+pub mod transfers;
+pub mod wallets;
+pub use card_expiry::{card_expiry, expires_on, is_expired, CardExpiry};
+pub use funds::ensure_sufficient;
+pub use late_fees::{accrue_late_fee, LateFeePolicy};
+pub use subscription_metrics::{churn_risk, monthly_recurring_revenue, ChurnRisk, SubscriptionMetrics};
+pub use tax::{TaxCalculator, TaxRates};
+pub use transfers::{preview_fx_transfer, preview_transfer, transfer_fee, FxConversion, TransferPreview};
+pub use wallets::topup_reversal_amount;
@@ -0,0 +1,59 @@
+//! Revenue and churn indicators for subscriptions
+use rust_decimal::Decimal;
+use serde::Serialize;
+use crate::domain::aggregates::BillingCycle;
+use crate::domain::value_objects::Money;
+
+/// The subscription's price normalized to one month: yearly divides by 12, weekly
+/// multiplies by 52/12. Rounded to the currency's minor units.
+pub fn monthly_recurring_revenue(amount: &Money, cycle: &BillingCycle) -> Money {
+    let monthly = match cycle {
+        BillingCycle::Monthly => amount.amount,
+        BillingCycle::Yearly => amount.amount / Decimal::from(12),
+        BillingCycle::Weekly => amount.amount * Decimal::from(52) / Decimal::from(12),
+    };
+    Money::new(monthly, &amount.currency).round()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChurnRisk { Low, Medium, High }
+
+/// High once payments keep failing, the subscription is past due, or it is set to lapse;
+/// medium after a single failed payment.
+pub fn churn_risk(consecutive_failures: u32, past_due: bool, cancel_at_period_end: bool) -> ChurnRisk {
+    if cancel_at_period_end || past_due || consecutive_failures >= 2 { ChurnRisk::High }
+    else if consecutive_failures == 1 { ChurnRisk::Medium }
+    else { ChurnRisk::Low }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SubscriptionMetrics {
+    pub total_paid: Money,
+    pub renewals: u32,
+    pub mrr: Money,
+    pub consecutive_failures: u32,
+    pub churn_risk: ChurnRisk,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mrr_normalization() {
+        let usd = |minor| Money::usd(Decimal::new(minor, 2));
+        assert_eq!(monthly_recurring_revenue(&usd(4900), &BillingCycle::Monthly), usd(4900));
+        assert_eq!(monthly_recurring_revenue(&usd(49000), &BillingCycle::Yearly), usd(4083));
+        assert_eq!(monthly_recurring_revenue(&usd(1000), &BillingCycle::Weekly), usd(4333));
+    }
+
+    #[test]
+    fn test_churn_risk() {
+        assert_eq!(churn_risk(0, false, false), ChurnRisk::Low);
+        assert_eq!(churn_risk(1, false, false), ChurnRisk::Medium);
+        assert_eq!(churn_risk(2, false, false), ChurnRisk::High);
+        assert_eq!(churn_risk(0, true, false), ChurnRisk::High);
+        assert_eq!(churn_risk(0, false, true), ChurnRisk::High);
+    }
+}
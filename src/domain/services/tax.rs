@@ -0,0 +1,84 @@
+//! Tax on invoices
+//!
+//! A `TaxCalculator` prices the tax on an invoice's line items for a billing address.
+//! `TaxRates` is the simple one: a rate per country, or per state where a country taxes by
+//! state, with anything unlisted taxed at zero. Exempt customers are handled by the
+//! invoice, before any calculator is asked.
+
+use std::collections::HashMap;
+use rust_decimal::Decimal;
+use crate::domain::aggregates::{InvoiceError, InvoiceLine};
+use crate::domain::value_objects::{Address, Money, Percentage};
+
+pub trait TaxCalculator: Send + Sync {
+    /// Tax on `lines` for a customer billed at `address`, in the lines' currency.
+    fn tax_for(&self, lines: &[InvoiceLine], address: Option<&Address>) -> Result<Money, InvoiceError>;
+}
+
+/// Rates keyed by country (`NG`) or country and state (`US-CA`); a state's rate wins over
+/// its country's.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TaxRates { rates: HashMap<String, Percentage> }
+
+impl TaxRates {
+    /// Parses `NG=7.5,US-CA=7.25,GB=20`. A malformed entry is an error rather than skipped,
+    /// since skipping it would quietly stop charging that tax.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut rates = HashMap::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (region, rate) = entry.split_once('=').ok_or_else(|| format!("tax rate '{}' has no '='", entry))?;
+            let rate = rate.trim().parse::<Decimal>().ok().and_then(Percentage::new)
+                .ok_or_else(|| format!("tax rate '{}' needs a non-negative percentage", entry))?;
+            rates.insert(region.trim().to_ascii_uppercase(), rate);
+        }
+        Ok(Self { rates })
+    }
+
+    /// The rate for `address`: its state's, else its country's, else zero.
+    pub fn rate_for(&self, address: Option<&Address>) -> Percentage {
+        let Some(address) = address else { return Percentage::default() };
+        let country = address.country.trim().to_ascii_uppercase();
+        let state = address.state.as_deref().map(|s| format!("{}-{}", country, s.trim().to_ascii_uppercase()));
+        state.and_then(|key| self.rates.get(&key)).or_else(|| self.rates.get(&country)).copied().unwrap_or_default()
+    }
+}
+
+impl TaxCalculator for TaxRates {
+    fn tax_for(&self, lines: &[InvoiceLine], address: Option<&Address>) -> Result<Money, InvoiceError> {
+        let first = lines.first().ok_or(InvoiceError::Empty)?;
+        let subtotal = lines[1..].iter().try_fold(first.amount.clone(), |sum, line| sum.checked_add(&line.amount))
+            .map_err(|e| InvoiceError::InvalidAmount(e.to_string()))?;
+        let tax = subtotal.amount.checked_mul(self.rate_for(address).as_fraction())
+            .ok_or_else(|| InvoiceError::InvalidAmount("tax overflows".into()))?;
+        Ok(Money::new(tax, &subtotal.currency).round())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(country: &str, state: Option<&str>) -> Address {
+        Address { country: country.into(), state: state.map(String::from), ..Address::default() }
+    }
+
+    #[test]
+    fn test_rate_table() {
+        let rates = TaxRates::parse("NG=7.5, US-CA=7.25, us=0, GB=20").unwrap();
+        let line = |amount: i64| InvoiceLine { description: "PLAN".into(), quantity: 1, unit_amount: Money::usd(Decimal::new(amount, 2)), amount: Money::usd(Decimal::new(amount, 2)) };
+        let lines = [line(4900), line(375)];
+
+        // 7.25% of 52.75 is 3.824375
+        assert_eq!(rates.tax_for(&lines, Some(&address("US", Some("ca")))).unwrap(), Money::usd(Decimal::new(382, 2)));
+        assert_eq!(rates.tax_for(&lines, Some(&address("ng", None))).unwrap(), Money::usd(Decimal::new(396, 2)));
+        // A state without its own rate falls back to the country's
+        assert_eq!(rates.rate_for(Some(&address("US", Some("TX")))), Percentage::default());
+        assert!(rates.tax_for(&lines, Some(&address("FR", None))).unwrap().is_zero());
+        assert!(rates.tax_for(&lines, None).unwrap().is_zero());
+        assert_eq!(rates.tax_for(&[], None), Err(InvoiceError::Empty));
+
+        for bad in ["NG", "NG=abc", "NG=-5"] {
+            assert!(TaxRates::parse(bad).is_err(), "{:?}", bad);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! Balance effects of wallet-to-wallet transfers
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use crate::domain::aggregates::PaymentError;
+use crate::domain::value_objects::{decimal_str, Money};
+use super::funds::ensure_sufficient;
+
+/// What a transfer will do to both wallets, computed without moving any money.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TransferPreview {
+    pub amount: Money,
+    pub fee: Money,
+    pub total_debit: Money,
+    /// What the destination receives: `amount`, converted when the transfer has an FX step.
+    pub credit: Money,
+    pub source_balance_after: Money,
+    pub destination_balance_after: Money,
+}
+
+/// Transfers currently carry no fee; kept as one function so preview and execution agree.
+pub fn transfer_fee(amount: &Money) -> Money { Money::zero(&amount.currency) }
+
+/// An explicit currency conversion for one transfer, at a rate the caller has quoted.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FxConversion {
+    pub to_currency: String,
+    /// Units of `to_currency` per unit of the source currency.
+    #[serde(with = "decimal_str")]
+    pub rate: Decimal,
+}
+
+pub fn preview_transfer(source_balance: &Money, destination_balance: &Money, amount: &Money) -> Result<TransferPreview, PaymentError> {
+    if destination_balance.currency != source_balance.currency {
+        return Err(PaymentError::CurrencyMismatch { expected: source_balance.currency.clone(), actual: destination_balance.currency.clone() });
+    }
+    preview(source_balance, destination_balance, amount, amount.clone())
+}
+
+/// Like `preview_transfer`, but credits the destination in `fx.to_currency`, rounded to
+/// its minor units.
+pub fn preview_fx_transfer(source_balance: &Money, destination_balance: &Money, amount: &Money, fx: &FxConversion) -> Result<TransferPreview, PaymentError> {
+    if destination_balance.currency != fx.to_currency {
+        return Err(PaymentError::CurrencyMismatch { expected: fx.to_currency.clone(), actual: destination_balance.currency.clone() });
+    }
+    if fx.rate <= Decimal::ZERO { return Err(PaymentError::InvalidAmount("FX rate must be positive".into())); }
+    let credit = Money::new(amount.amount * fx.rate, &fx.to_currency).round();
+    preview(source_balance, destination_balance, amount, credit)
+}
+
+fn preview(source_balance: &Money, destination_balance: &Money, amount: &Money, credit: Money) -> Result<TransferPreview, PaymentError> {
+    if amount.currency != source_balance.currency {
+        return Err(PaymentError::CurrencyMismatch { expected: source_balance.currency.clone(), actual: amount.currency.clone() });
+    }
+    if !amount.is_positive() { return Err(PaymentError::InvalidAmount("transfer amount must be positive".into())); }
+    let fee = transfer_fee(amount);
+    let total_debit = Money::new(amount.amount + fee.amount, &amount.currency);
+    ensure_sufficient(source_balance, &total_debit)?;
+    Ok(TransferPreview {
+        source_balance_after: Money::new(source_balance.amount - total_debit.amount, &amount.currency),
+        destination_balance_after: Money::new(destination_balance.amount + credit.amount, &credit.currency),
+        amount: amount.clone(),
+        fee,
+        total_debit,
+        credit,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn ngn(minor: i64) -> Money { Money::new(Decimal::new(minor, 2), "NGN") }
+
+    #[test]
+    fn test_preview_projects_balances() {
+        let preview = preview_transfer(&ngn(10_000), &ngn(500), &ngn(2_500)).unwrap();
+        assert_eq!(preview.total_debit, ngn(2_500));
+        assert_eq!(preview.fee, Money::zero("NGN"));
+        assert_eq!(preview.source_balance_after, ngn(7_500));
+        assert_eq!(preview.destination_balance_after, ngn(3_000));
+        assert_eq!(preview.credit, ngn(2_500));
+    }
+
+    #[test]
+    fn test_preview_rejects_overdraw_and_mixed_currency() {
+        assert_eq!(preview_transfer(&ngn(100), &ngn(0), &ngn(101)), Err(PaymentError::InsufficientFunds("short by 0.01 NGN".into())));
+        let usd = Money::new(Decimal::ZERO, "USD");
+        assert!(matches!(preview_transfer(&ngn(100), &usd, &ngn(50)), Err(PaymentError::CurrencyMismatch { .. })));
+    }
+
+    #[test]
+    fn test_fx_preview_credits_converted_amount() {
+        let usd = |minor: i64| Money::new(Decimal::new(minor, 2), "USD");
+        let fx = FxConversion { to_currency: "NGN".into(), rate: Decimal::new(1_550_125, 3) };
+        let preview = preview_fx_transfer(&usd(10_000), &ngn(0), &usd(1_001), &fx).unwrap();
+        assert_eq!(preview.total_debit, usd(1_001));
+        assert_eq!(preview.source_balance_after, usd(8_999));
+        // 10.01 * 1550.125 = 15516.75125, rounded to kobo
+        assert_eq!(preview.credit, ngn(1_551_675));
+        assert_eq!(preview.destination_balance_after, ngn(1_551_675));
+
+        assert!(matches!(preview_fx_transfer(&usd(10_000), &usd(0), &usd(100), &fx), Err(PaymentError::CurrencyMismatch { .. })));
+        let free = FxConversion { rate: Decimal::ZERO, ..fx };
+        assert!(matches!(preview_fx_transfer(&usd(10_000), &ngn(0), &usd(100), &free), Err(PaymentError::InvalidAmount(_))));
+    }
+}
@@ -0,0 +1,50 @@
+//! Wallet balance rules
+use rust_decimal::Decimal;
+use crate::domain::aggregates::PaymentError;
+use crate::domain::value_objects::Money;
+use super::funds::ensure_sufficient;
+
+/// How much of a prior top-up to claw back. `requested` defaults to whatever has not
+/// been reversed yet; the reversal may not exceed that remainder, nor take the wallet
+/// below zero unless overdraft is allowed.
+pub fn topup_reversal_amount(
+    topup_amount: Decimal,
+    already_reversed: Decimal,
+    requested: Option<Decimal>,
+    balance: &Money,
+    allow_overdraft: bool,
+) -> Result<Decimal, PaymentError> {
+    let remaining = topup_amount - already_reversed;
+    if remaining <= Decimal::ZERO { return Err(PaymentError::AlreadyReversed); }
+    let amount = requested.unwrap_or(remaining);
+    if amount <= Decimal::ZERO { return Err(PaymentError::InvalidAmount("reversal amount must be positive".into())); }
+    if amount > remaining { return Err(PaymentError::InvalidAmount(format!("reversal exceeds remaining top-up of {}", remaining))); }
+    if !allow_overdraft { ensure_sufficient(balance, &Money::new(amount, &balance.currency))?; }
+    Ok(amount)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn d(minor: i64) -> Decimal { Decimal::new(minor, 2) }
+    fn bal(minor: i64) -> Money { Money::new(d(minor), "NGN") }
+
+    #[test]
+    fn test_full_and_partial_reversal() {
+        assert_eq!(topup_reversal_amount(d(5000), d(0), None, &bal(8000), false), Ok(d(5000)));
+        assert_eq!(topup_reversal_amount(d(5000), d(2000), None, &bal(8000), false), Ok(d(3000)));
+        assert_eq!(topup_reversal_amount(d(5000), d(0), Some(d(1500)), &bal(8000), false), Ok(d(1500)));
+    }
+
+    #[test]
+    fn test_double_and_over_reversal_rejected() {
+        assert_eq!(topup_reversal_amount(d(5000), d(5000), None, &bal(8000), false), Err(PaymentError::AlreadyReversed));
+        assert!(matches!(topup_reversal_amount(d(5000), d(2000), Some(d(3001)), &bal(8000), false), Err(PaymentError::InvalidAmount(_))));
+    }
+
+    #[test]
+    fn test_balance_floor_unless_overdraft() {
+        assert_eq!(topup_reversal_amount(d(5000), d(0), None, &bal(1000), false), Err(PaymentError::InsufficientFunds("short by 40.00 NGN".into())));
+        assert_eq!(topup_reversal_amount(d(5000), d(0), None, &bal(1000), true), Ok(d(5000)));
+    }
+}
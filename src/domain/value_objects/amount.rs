@@ -0,0 +1,166 @@
+//! API boundary amounts
+//!
+//! The HTTP API speaks integer minor units while `Money` and the database hold
+//! `Decimal` major units. `Amount` is the one place the two are converted, using the
+//! currency's ISO 4217 exponent rather than assuming two decimal places.
+
+use std::collections::HashMap;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use crate::domain::aggregates::PaymentError;
+use super::currency::CurrencyPolicy;
+use super::Money;
+
+/// Currency assumed when a request leaves it out.
+pub const DEFAULT_CURRENCY: &str = "NGN";
+
+fn default_currency() -> String { DEFAULT_CURRENCY.to_string() }
+
+/// Integer minor units plus currency, as sent and received over the API. Serializes as
+/// `{"amount": 1050, "currency": "USD"}` so it can be flattened into DTOs.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Amount {
+    #[serde(rename = "amount")]
+    pub minor_units: i64,
+    #[serde(default = "default_currency")]
+    pub currency: String,
+}
+
+impl Amount {
+    pub fn new(minor_units: i64, currency: &str) -> Self { Self { minor_units, currency: currency.to_string() } }
+
+    pub fn from_money(money: &Money) -> Result<Self, PaymentError> {
+        Ok(Self::new(money.to_minor_units()?, &money.currency))
+    }
+
+    /// The database representation: major units, e.g. 10.50 for 1050 USD cents.
+    pub fn from_decimal(amount: Decimal, currency: &str) -> Result<Self, PaymentError> {
+        Self::from_money(&Money::new(amount, currency))
+    }
+
+    pub fn to_money(&self) -> Result<Money, PaymentError> {
+        self.to_money_in(&CurrencyPolicy::default())
+    }
+
+    /// Like `to_money`, but lets the policy supply exponents for allow-listed unknown codes.
+    pub fn to_money_in(&self, policy: &CurrencyPolicy) -> Result<Money, PaymentError> {
+        let exponent = policy.minor_units(&self.currency)?;
+        Ok(Money::new(Decimal::new(self.minor_units, exponent), &self.currency))
+    }
+
+    pub fn to_decimal(&self) -> Result<Decimal, PaymentError> { Ok(self.to_money()?.amount) }
+
+    pub fn ensure_positive(&self) -> Result<(), PaymentError> {
+        if self.minor_units > 0 { return Ok(()); }
+        Err(PaymentError::InvalidAmount(format!("{} must be at least 1 minor unit", self.minor_units)))
+    }
+
+    /// Checks the amount against its currency's limit, if it has one.
+    pub fn ensure_within(&self, limits: &AmountLimits) -> Result<(), PaymentError> {
+        let Some(limit) = limits.get(&self.currency) else { return Ok(()) };
+        if let Some(min) = limit.min.filter(|min| self.minor_units < *min) {
+            return Err(PaymentError::InvalidAmount(format!("{} is below the {} minimum of {} minor units", self.minor_units, self.currency, min)));
+        }
+        if let Some(max) = limit.max.filter(|max| self.minor_units > *max) {
+            return Err(PaymentError::InvalidAmount(format!("{} is above the {} maximum of {} minor units", self.minor_units, self.currency, max)));
+        }
+        Ok(())
+    }
+}
+
+/// Smallest and largest amount accepted in one currency, in minor units.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AmountLimit {
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+}
+
+/// Keyed by currency code; currencies without an entry accept any positive amount.
+pub type AmountLimits = HashMap<String, AmountLimit>;
+
+/// Parses `NGN=10000:1000000000;USD=50:` into per-currency limits. Either bound may be
+/// left empty. A malformed entry is an error: dropping it would lift that currency's limit.
+pub fn parse_amount_limits(spec: &str) -> Result<AmountLimits, String> {
+    let mut limits = AmountLimits::new();
+    for entry in spec.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let malformed = || format!("amount limit '{}' should look like CUR=min:max", entry);
+        let (currency, range) = entry.split_once('=').ok_or_else(malformed)?;
+        let (min, max) = range.split_once(':').ok_or_else(malformed)?;
+        let currency = currency.trim().to_ascii_uppercase();
+        if currency.is_empty() { return Err(malformed()); }
+        let bound = |s: &str| match s.trim() {
+            "" => Ok(None),
+            s => s.parse::<i64>().map(Some).map_err(|_| format!("amount limit '{}' has a bound that isn't a whole number of minor units", entry)),
+        };
+        let limit = AmountLimit { min: bound(min)?, max: bound(max)? };
+        if let (Some(min), Some(max)) = (limit.min, limit.max) {
+            if min > max { return Err(format!("amount limit '{}' has its minimum above its maximum", entry)); }
+        }
+        limits.insert(currency, limit);
+    }
+    Ok(limits)
+}
+
+/// Converts a minor-unit count in a currency already known to be valid (e.g. a stored
+/// wallet's) to its database `Decimal`.
+pub fn minor_to_decimal(minor_units: i64, currency: &str) -> Result<Decimal, PaymentError> {
+    Amount::new(minor_units, currency).to_decimal()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips() {
+        for (minor, code, major) in [(1050, "USD", Decimal::new(1050, 2)), (500, "JPY", Decimal::from(500)), (1250, "KWD", Decimal::new(1250, 3))] {
+            let amount = Amount::new(minor, code);
+
+            let json = serde_json::to_value(&amount).unwrap();
+            assert_eq!(json, serde_json::json!({ "amount": minor, "currency": code }));
+            assert_eq!(serde_json::from_value::<Amount>(json).unwrap(), amount);
+
+            let money = amount.to_money().unwrap();
+            assert_eq!(money.amount, major);
+            assert_eq!(Amount::from_money(&money).unwrap(), amount);
+
+            let stored = amount.to_decimal().unwrap();
+            assert_eq!(Amount::from_decimal(stored, code).unwrap(), amount);
+        }
+    }
+
+    #[test]
+    fn test_boundary_errors() {
+        assert_eq!(serde_json::from_str::<Amount>(r#"{"amount": 10}"#).unwrap().currency, DEFAULT_CURRENCY);
+        assert!(Amount::from_decimal(Decimal::new(1005, 3), "USD").is_err());
+        assert_eq!(Amount::new(1, "ZZT").to_money(), Err(PaymentError::InvalidCurrency("ZZT".into())));
+        let lenient = CurrencyPolicy { fallback_exponent: Some(2), allowed_unknown: vec!["ZZT".into()] };
+        assert_eq!(Amount::new(150, "ZZT").to_money_in(&lenient).unwrap().amount, Decimal::new(150, 2));
+        assert!(Amount::new(0, "USD").ensure_positive().is_err());
+    }
+
+    #[test]
+    fn test_amount_limits() {
+        let limits = parse_amount_limits("NGN=10000:100000000; usd=50: ;").unwrap();
+        assert_eq!(limits.len(), 2);
+        assert_eq!(limits["USD"], AmountLimit { min: Some(50), max: None });
+
+        assert_eq!(Amount::new(9999, "NGN").ensure_within(&limits),
+            Err(PaymentError::InvalidAmount("9999 is below the NGN minimum of 10000 minor units".into())));
+        assert_eq!(Amount::new(100_000_001, "NGN").ensure_within(&limits),
+            Err(PaymentError::InvalidAmount("100000001 is above the NGN maximum of 100000000 minor units".into())));
+        assert!(Amount::new(10000, "NGN").ensure_within(&limits).is_ok());
+        assert!(Amount::new(49, "USD").ensure_within(&limits).is_err());
+        assert!(Amount::new(i64::MAX, "USD").ensure_within(&limits).is_ok());
+        // No entry, no limit
+        assert!(Amount::new(1, "EUR").ensure_within(&limits).is_ok());
+        assert!(parse_amount_limits("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_malformed_amount_limits_are_rejected() {
+        for bad in ["NGN=100:abc", "EUR=x:1", "broken", "USD=50", "=1:2", "NGN=1.5:", "GBP=100:10"] {
+            assert!(parse_amount_limits(&format!("USD=50:;{}", bad)).is_err(), "{:?}", bad);
+        }
+    }
+}
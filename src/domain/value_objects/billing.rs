@@ -0,0 +1,38 @@
+//! Who is billed, and where
+//!
+//! The address is what tax is worked out from: its country, and for countries taxed by
+//! region, its state. A customer marked `tax_exempt` is never charged tax.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Address {
+    pub line1: Option<String>,
+    pub line2: Option<String>,
+    pub city: Option<String>,
+    /// State, province or region code, e.g. `CA` or `LA` (Lagos).
+    pub state: Option<String>,
+    pub postal_code: Option<String>,
+    /// ISO 3166-1 alpha-2, e.g. `NG`.
+    pub country: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BillingDetails {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub address: Option<Address>,
+    #[serde(default)]
+    pub tax_exempt: bool,
+}
+
+impl BillingDetails {
+    /// Accepts a two-letter country, so a rate lookup can't silently miss on "Nigeria".
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.address {
+            Some(address) if address.country.len() != 2 || !address.country.bytes().all(|b| b.is_ascii_alphabetic()) =>
+                Err(format!("country '{}' must be a two-letter ISO code", address.country)),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Raw card details, held only long enough to exchange them for a provider token
+//!
+//! A `CardNumber` is only read through `expose`, by the code that sends it to the
+//! provider. Its `Debug` output is masked and it has no `Serialize`, so a PAN can't end
+//! up in a log line, an error body or a database row by accident. Only the token, the
+//! last four digits and the brand are kept.
+
+use std::fmt;
+use serde::Deserialize;
+
+/// A primary account number that passed the length and Luhn checks.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct CardNumber(String);
+
+impl CardNumber {
+    /// Accepts 12 to 19 digits, ignoring spaces and dashes. Errors never echo the input.
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let digits: String = input.chars().filter(|c| *c != ' ' && *c != '-').collect();
+        if !(12..=19).contains(&digits.len()) || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err("card number must be 12-19 digits".into());
+        }
+        if !luhn_valid(&digits) { return Err("card number is not valid".into()); }
+        Ok(Self(digits))
+    }
+
+    pub fn last_four(&self) -> &str { &self.0[self.0.len() - 4..] }
+
+    pub fn brand(&self) -> Option<CardBrand> { CardBrand::of(&self.0) }
+
+    /// The full number, for the request to the provider and nothing else.
+    pub fn expose(&self) -> &str { &self.0 }
+}
+
+impl TryFrom<String> for CardNumber {
+    type Error = String;
+    fn try_from(value: String) -> Result<Self, Self::Error> { Self::parse(&value) }
+}
+
+impl fmt::Debug for CardNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "CardNumber(****{})", self.last_four()) }
+}
+
+/// The 3-4 digit security code, masked the same way as the number.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Cvc(String);
+
+impl Cvc {
+    pub fn expose(&self) -> &str { &self.0 }
+}
+
+impl TryFrom<String> for Cvc {
+    type Error = String;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if !(3..=4).contains(&value.len()) || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err("cvc must be 3 or 4 digits".into());
+        }
+        Ok(Self(value))
+    }
+}
+
+impl fmt::Debug for Cvc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str("Cvc(***)") }
+}
+
+/// What a customer types in to save a card.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct CardDetails {
+    pub number: CardNumber,
+    pub exp_month: u8,
+    pub exp_year: u16,
+    pub cvc: Cvc,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CardBrand { Visa, Mastercard, Amex, Discover, Verve }
+
+impl CardBrand {
+    /// The brand from the number's leading digits, where we recognise them.
+    pub fn of(number: &str) -> Option<Self> {
+        let prefix = |n: usize| number.get(..n).and_then(|p| p.parse::<u32>().ok());
+        match (prefix(1)?, prefix(2)?, prefix(4)?, prefix(6)?) {
+            (_, _, _, 506099..=506198 | 650002..=650027) => Some(Self::Verve),
+            (4, ..) => Some(Self::Visa),
+            (_, 51..=55, ..) | (_, _, 2221..=2720, _) => Some(Self::Mastercard),
+            (_, 34 | 37, ..) => Some(Self::Amex),
+            (_, 65, ..) | (_, _, 6011, _) => Some(Self::Discover),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Visa => "visa",
+            Self::Mastercard => "mastercard",
+            Self::Amex => "amex",
+            Self::Discover => "discover",
+            Self::Verve => "verve",
+        }
+    }
+}
+
+fn luhn_valid(digits: &str) -> bool {
+    let sum: u32 = digits.bytes().rev().enumerate()
+        .map(|(i, b)| {
+            let d = u32::from(b - b'0');
+            if i % 2 == 1 { if d * 2 > 9 { d * 2 - 9 } else { d * 2 } } else { d }
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_card_number() {
+        let number = CardNumber::parse("4242 4242 4242 4242").unwrap();
+        assert_eq!((number.last_four(), number.brand()), ("4242", Some(CardBrand::Visa)));
+        assert_eq!(format!("{:?}", number), "CardNumber(****4242)");
+        for (pan, brand) in [("5555555555554444", CardBrand::Mastercard), ("2223003122003222", CardBrand::Mastercard),
+                             ("378282246310005", CardBrand::Amex), ("6011111111111117", CardBrand::Discover),
+                             ("5060990580000217499", CardBrand::Verve)] {
+            assert_eq!(CardNumber::parse(pan).unwrap().brand(), Some(brand), "{}", pan);
+        }
+
+        // Errors don't repeat the number back
+        assert_eq!(CardNumber::parse("4242424242424241"), Err("card number is not valid".to_string()));
+        assert!(CardNumber::parse("4242").is_err());
+        assert!(CardNumber::parse("4242x42424242424").is_err());
+        let rejected = serde_json::from_str::<CardNumber>(r#""4242424242424241""#).unwrap_err();
+        assert!(!rejected.to_string().contains("4242424242424241"), "{}", rejected);
+    }
+
+    #[test]
+    fn test_card_details_debug_is_masked() {
+        let card: CardDetails = serde_json::from_value(serde_json::json!({
+            "number": "4242424242424242", "exp_month": 12, "exp_year": 2030, "cvc": "123"
+        })).unwrap();
+        assert_eq!(card.number.expose(), "4242424242424242");
+        let debug = format!("{:?}", card);
+        assert!(!debug.contains("4242424242424242") && !debug.contains("123"), "{}", debug);
+        assert!(serde_json::from_value::<CardDetails>(serde_json::json!({
+            "number": "4242424242424242", "exp_month": 12, "exp_year": 2030, "cvc": "12"
+        })).is_err());
+    }
+}
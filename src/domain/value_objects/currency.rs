@@ -0,0 +1,96 @@
+//! ISO 4217 currency table
+//!
+//! Minor-unit exponents for active ISO 4217 codes. Anything not listed here is
+//! treated as unknown.
+
+use crate::domain::aggregates::PaymentError;
+
+const CURRENCIES: &[(&str, u32)] = &[
+    ("AED", 2), ("AFN", 2), ("ALL", 2), ("AMD", 2), ("ANG", 2), ("AOA", 2), ("ARS", 2), ("AUD", 2),
+    ("AWG", 2), ("AZN", 2), ("BAM", 2), ("BBD", 2), ("BDT", 2), ("BGN", 2), ("BHD", 3), ("BIF", 0),
+    ("BMD", 2), ("BND", 2), ("BOB", 2), ("BRL", 2), ("BSD", 2), ("BTN", 2), ("BWP", 2), ("BYN", 2),
+    ("BZD", 2), ("CAD", 2), ("CDF", 2), ("CHF", 2), ("CLF", 4), ("CLP", 0), ("CNY", 2), ("COP", 2),
+    ("CRC", 2), ("CUP", 2), ("CVE", 2), ("CZK", 2), ("DJF", 0), ("DKK", 2), ("DOP", 2), ("DZD", 2),
+    ("EGP", 2), ("ERN", 2), ("ETB", 2), ("EUR", 2), ("FJD", 2), ("FKP", 2), ("GBP", 2), ("GEL", 2),
+    ("GHS", 2), ("GIP", 2), ("GMD", 2), ("GNF", 0), ("GTQ", 2), ("GYD", 2), ("HKD", 2), ("HNL", 2),
+    ("HTG", 2), ("HUF", 2), ("IDR", 2), ("ILS", 2), ("INR", 2), ("IQD", 3), ("IRR", 2), ("ISK", 0),
+    ("JMD", 2), ("JOD", 3), ("JPY", 0), ("KES", 2), ("KGS", 2), ("KHR", 2), ("KMF", 0), ("KPW", 2),
+    ("KRW", 0), ("KWD", 3), ("KYD", 2), ("KZT", 2), ("LAK", 2), ("LBP", 2), ("LKR", 2), ("LRD", 2),
+    ("LSL", 2), ("LYD", 3), ("MAD", 2), ("MDL", 2), ("MGA", 2), ("MKD", 2), ("MMK", 2), ("MNT", 2),
+    ("MOP", 2), ("MRU", 2), ("MUR", 2), ("MVR", 2), ("MWK", 2), ("MXN", 2), ("MYR", 2), ("MZN", 2),
+    ("NAD", 2), ("NGN", 2), ("NIO", 2), ("NOK", 2), ("NPR", 2), ("NZD", 2), ("OMR", 3), ("PAB", 2),
+    ("PEN", 2), ("PGK", 2), ("PHP", 2), ("PKR", 2), ("PLN", 2), ("PYG", 0), ("QAR", 2), ("RON", 2),
+    ("RSD", 2), ("RUB", 2), ("RWF", 0), ("SAR", 2), ("SBD", 2), ("SCR", 2), ("SDG", 2), ("SEK", 2),
+    ("SGD", 2), ("SHP", 2), ("SLE", 2), ("SOS", 2), ("SRD", 2), ("SSP", 2), ("STN", 2), ("SVC", 2),
+    ("SYP", 2), ("SZL", 2), ("THB", 2), ("TJS", 2), ("TMT", 2), ("TND", 3), ("TOP", 2), ("TRY", 2),
+    ("TTD", 2), ("TWD", 2), ("TZS", 2), ("UAH", 2), ("UGX", 0), ("USD", 2), ("UYI", 0), ("UYU", 2),
+    ("UYW", 4), ("UZS", 2), ("VES", 2), ("VND", 0), ("VUV", 0), ("WST", 2), ("XAF", 0), ("XCD", 2),
+    ("XOF", 0), ("XPF", 0), ("YER", 2), ("ZAR", 2), ("ZMW", 2), ("ZWL", 2),
+];
+
+/// Number of minor-unit decimal places for an ISO 4217 code, e.g. 2 for USD, 0 for JPY.
+pub fn minor_units(code: &str) -> Option<u32> {
+    CURRENCIES.binary_search_by(|(c, _)| c.cmp(&code)).ok().map(|i| CURRENCIES[i].1)
+}
+
+pub fn is_known(code: &str) -> bool { minor_units(code).is_some() }
+
+const SYMBOLS: &[(&str, &str)] = &[
+    ("AUD", "A$"), ("CAD", "CA$"), ("CNY", "CN¥"), ("EUR", "€"), ("GBP", "£"), ("GHS", "GH₵"),
+    ("INR", "₹"), ("JPY", "¥"), ("KES", "KSh"), ("KRW", "₩"), ("NGN", "₦"), ("USD", "$"), ("ZAR", "R"),
+];
+
+/// Display symbol for the currencies we show with one, e.g. `$` for USD. Other codes are
+/// displayed with the code itself.
+pub fn symbol(code: &str) -> Option<&'static str> {
+    SYMBOLS.binary_search_by(|(c, _)| c.cmp(&code)).ok().map(|i| SYMBOLS[i].1)
+}
+
+/// Every code in the table, in alphabetical order.
+pub fn codes() -> impl Iterator<Item = &'static str> { CURRENCIES.iter().map(|(c, _)| *c) }
+
+/// How to treat codes missing from the ISO table. By default they are rejected; test
+/// deployments can allow-list specific codes and give them a fallback exponent.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CurrencyPolicy {
+    pub fallback_exponent: Option<u32>,
+    pub allowed_unknown: Vec<String>,
+}
+
+impl CurrencyPolicy {
+    pub fn minor_units(&self, code: &str) -> Result<u32, PaymentError> {
+        if let Some(exponent) = minor_units(code) { return Ok(exponent); }
+        match self.fallback_exponent {
+            Some(exponent) if self.allowed_unknown.iter().any(|c| c == code) => Ok(exponent),
+            _ => Err(PaymentError::InvalidCurrency(code.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_minor_units() {
+        assert!(CURRENCIES.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(SYMBOLS.windows(2).all(|w| w[0].0 < w[1].0) && SYMBOLS.iter().all(|(c, _)| is_known(c)));
+        assert_eq!(minor_units("USD"), Some(2));
+        assert_eq!(minor_units("JPY"), Some(0));
+        assert_eq!(minor_units("KWD"), Some(3));
+        assert_eq!(minor_units("BHD"), Some(3));
+        assert_eq!(minor_units("usd"), None);
+        assert_eq!(minor_units("XYZ"), None);
+    }
+
+    #[test]
+    fn test_policy_fallback() {
+        let strict = CurrencyPolicy::default();
+        assert_eq!(strict.minor_units("JPY"), Ok(0));
+        assert_eq!(strict.minor_units("ZZT"), Err(PaymentError::InvalidCurrency("ZZT".into())));
+
+        let lenient = CurrencyPolicy { fallback_exponent: Some(2), allowed_unknown: vec!["ZZT".into()] };
+        assert_eq!(lenient.minor_units("ZZT"), Ok(2));
+        assert_eq!(lenient.minor_units("KWD"), Ok(3));
+        assert!(lenient.minor_units("ZZQ").is_err());
+    }
+}
@@ -0,0 +1,111 @@
+//! `Decimal` as a JSON string, for `#[serde(with = "decimal_str")]`
+//!
+//! Most JSON clients read numbers as doubles, so an amount sent as `1234.5678901234` can
+//! come back changed. Amounts go out as strings carrying every digit they have. Coming in,
+//! a string is read exactly; a bare number is still accepted, and is exact as far as the
+//! double it arrived as.
+//!
+//! This is spelled out per field rather than left to `rust_decimal`'s default, which
+//! changes to numbers if any crate in the build turns on its `serde-float` feature.
+
+use std::fmt;
+use std::str::FromStr;
+use rust_decimal::Decimal;
+use serde::{de, Deserializer, Serializer};
+
+pub fn serialize<S: Serializer>(value: &Decimal, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
+    deserializer.deserialize_any(DecimalVisitor)
+}
+
+/// The same for `Option<Decimal>`; `null` is `None`. Pair it with `#[serde(default)]` on
+/// request fields that may be left out.
+pub mod option {
+    use super::*;
+    use serde::Deserialize;
+
+    pub fn serialize<S: Serializer>(value: &Option<Decimal>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.serialize_some(&value.to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Decimal>, D::Error> {
+        #[derive(Deserialize)]
+        struct Exact(#[serde(with = "super")] Decimal);
+        Ok(Option::<Exact>::deserialize(deserializer)?.map(|Exact(value)| value))
+    }
+}
+
+struct DecimalVisitor;
+
+impl de::Visitor<'_> for DecimalVisitor {
+    type Value = Decimal;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str("a decimal number as a string, e.g. \"1234.56\"") }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Decimal, E> {
+        let value = value.trim();
+        Decimal::from_str(value).or_else(|_| Decimal::from_scientific(value))
+            .map_err(|_| E::invalid_value(de::Unexpected::Str(value), &self))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Decimal, E> { Ok(Decimal::from(value)) }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Decimal, E> { Ok(Decimal::from(value)) }
+
+    /// Goes through the shortest text that reads back as the same double, so `0.1` is 0.1
+    /// rather than the binary value nearest it.
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Decimal, E> {
+        if !value.is_finite() { return Err(E::invalid_value(de::Unexpected::Float(value), &self)); }
+        self.visit_str(&value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+    use crate::domain::value_objects::Money;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Quote {
+        #[serde(with = "super")]
+        amount: Decimal,
+        #[serde(default, with = "super::option")]
+        rate: Option<Decimal>,
+    }
+
+    #[test]
+    fn test_round_trips_every_digit() {
+        // 28 significant digits: more than a double holds
+        let precise = Decimal::from_str("1234.567890123456789012345678").unwrap();
+        let money = Money::new(precise, "USD");
+        let encoded = serde_json::to_value(&money).unwrap();
+        assert_eq!(encoded, json!({ "amount": "1234.567890123456789012345678", "currency": "USD" }));
+        assert_eq!(serde_json::from_value::<Money>(encoded).unwrap(), money);
+
+        // Trailing zeros are kept, so 10.50 doesn't come back as 10.5
+        let quote = Quote { amount: Decimal::new(1050, 2), rate: Some(Decimal::from_str("0.000000000000000000000000001").unwrap()) };
+        let text = serde_json::to_string(&quote).unwrap();
+        assert_eq!(text, r#"{"amount":"10.50","rate":"0.000000000000000000000000001"}"#);
+        assert_eq!(serde_json::from_str::<Quote>(&text).unwrap(), quote);
+    }
+
+    #[test]
+    fn test_reads_strings_and_numbers() {
+        let read = |value: serde_json::Value| serde_json::from_value::<Quote>(value);
+        assert_eq!(read(json!({ "amount": 1500 })).unwrap(), Quote { amount: Decimal::from(1500), rate: None });
+        assert_eq!(read(json!({ "amount": 0.1, "rate": null })).unwrap().amount, Decimal::new(1, 1));
+        assert_eq!(read(json!({ "amount": " 2.5e3 " })).unwrap().amount, Decimal::from(2500));
+        assert_eq!(read(json!({ "amount": "-0.01", "rate": "1.5" })).unwrap().rate, Some(Decimal::new(15, 1)));
+        for bad in [json!({ "amount": "12,50" }), json!({ "amount": "" }), json!({ "amount": true }), json!({ "amount": "1", "rate": [] })] {
+            assert!(read(bad.clone()).is_err(), "{}", bad);
+        }
+    }
+}
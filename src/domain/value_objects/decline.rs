@@ -0,0 +1,85 @@
+//! Why a provider refused or failed a charge
+
+use serde::{Deserialize, Serialize};
+
+/// Issuer or gateway decline reasons, normalized across providers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeclineCode { InsufficientFunds, ExpiredCard, IncorrectCvc, InvalidCard, LimitExceeded, DoNotHonor, SuspectedFraud, Generic }
+
+impl DeclineCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InsufficientFunds => "insufficient_funds", Self::ExpiredCard => "expired_card", Self::IncorrectCvc => "incorrect_cvc",
+            Self::InvalidCard => "invalid_card", Self::LimitExceeded => "limit_exceeded", Self::DoNotHonor => "do_not_honor",
+            Self::SuspectedFraud => "suspected_fraud", Self::Generic => "generic_decline",
+        }
+    }
+
+    /// Maps Stripe-style decline codes and ISO 8583 response codes; anything
+    /// unrecognized is a generic decline.
+    pub fn from_provider_code(code: &str) -> Self {
+        match code.trim().to_ascii_lowercase().as_str() {
+            "insufficient_funds" | "51" => Self::InsufficientFunds,
+            "expired_card" | "54" => Self::ExpiredCard,
+            "incorrect_cvc" | "invalid_cvc" | "82" | "n7" => Self::IncorrectCvc,
+            "invalid_card" | "incorrect_number" | "invalid_number" | "14" => Self::InvalidCard,
+            "card_velocity_exceeded" | "withdrawal_count_limit_exceeded" | "61" | "65" => Self::LimitExceeded,
+            "do_not_honor" | "05" => Self::DoNotHonor,
+            "fraudulent" | "stolen_card" | "lost_card" | "pickup_card" | "41" | "43" | "59" => Self::SuspectedFraud,
+            _ => Self::Generic,
+        }
+    }
+}
+
+/// Failures on the provider's side, as opposed to the customer's card.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderErrorKind {
+    Timeout,
+    Unavailable,
+    RateLimited,
+    /// Our credentials were rejected.
+    Authentication,
+    /// The provider refused the request as malformed.
+    InvalidRequest,
+    InvalidResponse,
+}
+
+impl ProviderErrorKind {
+    pub fn is_transient(&self) -> bool { matches!(self, Self::Timeout | Self::Unavailable | Self::RateLimited) }
+
+    /// Classifies a non-2xx provider HTTP status.
+    pub fn from_http_status(status: u16) -> Self {
+        match status {
+            401 | 403 => Self::Authentication,
+            408 | 504 => Self::Timeout,
+            429 => Self::RateLimited,
+            500..=599 => Self::Unavailable,
+            _ => Self::InvalidRequest,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decline_codes() {
+        assert_eq!(DeclineCode::from_provider_code("insufficient_funds"), DeclineCode::InsufficientFunds);
+        assert_eq!(DeclineCode::from_provider_code("51"), DeclineCode::InsufficientFunds);
+        assert_eq!(DeclineCode::from_provider_code("N7"), DeclineCode::IncorrectCvc);
+        assert_eq!(DeclineCode::from_provider_code("something_new"), DeclineCode::Generic);
+        assert_eq!(serde_json::to_value(DeclineCode::ExpiredCard).unwrap(), "expired_card");
+    }
+
+    #[test]
+    fn test_provider_error_kinds() {
+        assert_eq!(ProviderErrorKind::from_http_status(503), ProviderErrorKind::Unavailable);
+        assert_eq!(ProviderErrorKind::from_http_status(401), ProviderErrorKind::Authentication);
+        assert_eq!(ProviderErrorKind::from_http_status(422), ProviderErrorKind::InvalidRequest);
+        assert!(ProviderErrorKind::Timeout.is_transient());
+        assert!(!ProviderErrorKind::Authentication.is_transient());
+    }
+}
@@ -0,0 +1,65 @@
+//! Card statement descriptors
+//!
+//! Networks show at most 22 characters on the cardholder's statement. A charge's
+//! descriptor is the merchant's static prefix plus an optional per-charge suffix,
+//! rendered as `PREFIX* SUFFIX`.
+
+use crate::domain::aggregates::PaymentError;
+
+pub const MAX_LEN: usize = 22;
+/// Characters networks reject or render unpredictably.
+const FORBIDDEN: &[char] = &['<', '>', '\\', '\'', '"', '*'];
+
+fn check_charset(part: &str, field: &str) -> Result<(), PaymentError> {
+    if let Some(c) = part.chars().find(|c| !c.is_ascii() || c.is_ascii_control() || FORBIDDEN.contains(c)) {
+        return Err(PaymentError::InvalidDescriptor(format!("{} contains disallowed character {:?}", field, c)));
+    }
+    Ok(())
+}
+
+/// Builds the descriptor for one charge. Without a suffix the prefix is used alone.
+pub fn statement_descriptor(prefix: &str, suffix: Option<&str>) -> Result<String, PaymentError> {
+    let prefix = prefix.trim();
+    check_charset(prefix, "statement descriptor prefix")?;
+    if !prefix.chars().any(|c| c.is_ascii_alphabetic()) {
+        return Err(PaymentError::InvalidDescriptor("statement descriptor prefix must contain a letter".into()));
+    }
+    let combined = match suffix.map(str::trim) {
+        None => prefix.to_string(),
+        Some("") => return Err(PaymentError::InvalidDescriptor("statement descriptor suffix must not be empty".into())),
+        Some(suffix) => {
+            check_charset(suffix, "statement descriptor suffix")?;
+            format!("{}* {}", prefix, suffix)
+        }
+    };
+    if combined.len() > MAX_LEN {
+        return Err(PaymentError::InvalidDescriptor(format!("statement descriptor '{}' exceeds {} characters", combined, MAX_LEN)));
+    }
+    Ok(combined.to_ascii_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combined_descriptor() {
+        assert_eq!(statement_descriptor("OpenSASE", Some("Order 1234")), Ok("OPENSASE* ORDER 1234".into()));
+        assert_eq!(statement_descriptor("OpenSASE", None), Ok("OPENSASE".into()));
+    }
+
+    #[test]
+    fn test_rejects_too_long() {
+        assert_eq!(statement_descriptor("OpenSASE", Some("Order 123456")), Ok("OPENSASE* ORDER 123456".into()));
+        assert!(matches!(statement_descriptor("OpenSASE", Some("Order 1234567")), Err(PaymentError::InvalidDescriptor(_))));
+    }
+
+    #[test]
+    fn test_rejects_invalid_characters() {
+        for suffix in ["Order<1>", "it's", "a*b", "caf\u{e9}", "tab\there"] {
+            assert!(matches!(statement_descriptor("OpenSASE", Some(suffix)), Err(PaymentError::InvalidDescriptor(_))), "{suffix}");
+        }
+        assert!(statement_descriptor("1234", None).is_err());
+        assert!(statement_descriptor("OpenSASE", Some("  ")).is_err());
+    }
+}
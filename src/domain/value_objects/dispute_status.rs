@@ -0,0 +1,67 @@
+//! Lifecycle of a dispute (chargeback)
+//!
+//! A dispute opens when the cardholder's bank challenges a charge. Evidence may be sent,
+//! and resent, until it closes as won (the charge stands) or lost (the funds go back).
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use crate::domain::aggregates::PaymentError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeStatus { Open, EvidenceSubmitted, Won, Lost }
+
+impl DisputeStatus {
+    pub const ALL: [Self; 4] = [Self::Open, Self::EvidenceSubmitted, Self::Won, Self::Lost];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::EvidenceSubmitted => "evidence_submitted",
+            Self::Won => "won",
+            Self::Lost => "lost",
+        }
+    }
+
+    pub fn is_closed(&self) -> bool { matches!(self, Self::Won | Self::Lost) }
+
+    /// A dispute can close with or without evidence, and nothing leaves a closed one.
+    pub fn can_transition_to(&self, next: Self) -> bool {
+        !self.is_closed() && next != Self::Open
+    }
+
+    pub fn transition_to(&self, next: Self) -> Result<Self, PaymentError> {
+        if self.can_transition_to(next) { return Ok(next); }
+        Err(PaymentError::InvalidTransition { from: self.as_str().to_string(), to: next.as_str().to_string() })
+    }
+}
+
+impl fmt::Display for DisputeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str(self.as_str()) }
+}
+
+impl FromStr for DisputeStatus {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL.into_iter().find(|status| status.as_str() == s).ok_or_else(|| format!("unknown dispute status '{}'", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use DisputeStatus::*;
+
+    #[test]
+    fn test_lifecycle() {
+        assert!(Open.can_transition_to(EvidenceSubmitted) && EvidenceSubmitted.can_transition_to(EvidenceSubmitted));
+        assert!(EvidenceSubmitted.can_transition_to(Won) && EvidenceSubmitted.can_transition_to(Lost) && Open.can_transition_to(Lost));
+        for closed in [Won, Lost] {
+            assert!(DisputeStatus::ALL.iter().all(|next| !closed.can_transition_to(*next)), "{}", closed);
+        }
+        assert!(!EvidenceSubmitted.can_transition_to(Open));
+        assert_eq!(Won.transition_to(Lost), Err(PaymentError::InvalidTransition { from: "won".into(), to: "lost".into() }));
+        assert_eq!("evidence_submitted".parse(), Ok(EvidenceSubmitted));
+    }
+}
@@ -0,0 +1,36 @@
+//! Exchange rates for converting `Money` between currencies
+//!
+//! A rate is quoted per direction: USD→NGN and NGN→USD are separate entries, since a
+//! provider's buy and sell rates differ and inverting one would invent a rate nobody quoted.
+
+use std::collections::HashMap;
+use rust_decimal::Decimal;
+use crate::domain::aggregates::PaymentError;
+
+pub trait ExchangeRateProvider: Send + Sync {
+    /// Units of `to` per unit of `from`. Fails with `PaymentError::UnknownExchangeRate`
+    /// when the pair isn't quoted.
+    fn rate(&self, from: &str, to: &str) -> Result<Decimal, PaymentError>;
+}
+
+/// A fixed table of rates, for tests and for rates loaded once from configuration.
+#[derive(Clone, Debug, Default)]
+pub struct StaticRates {
+    rates: HashMap<(String, String), Decimal>,
+}
+
+impl StaticRates {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn with_rate(mut self, from: &str, to: &str, rate: Decimal) -> Self {
+        self.rates.insert((from.to_string(), to.to_string()), rate);
+        self
+    }
+}
+
+impl ExchangeRateProvider for StaticRates {
+    fn rate(&self, from: &str, to: &str) -> Result<Decimal, PaymentError> {
+        self.rates.get(&(from.to_string(), to.to_string())).copied()
+            .ok_or_else(|| PaymentError::UnknownExchangeRate { from: from.to_string(), to: to.to_string() })
+    }
+}
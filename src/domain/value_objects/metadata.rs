@@ -0,0 +1,87 @@
+//! Merchant-supplied key/value metadata
+//!
+//! Limits match what providers accept so metadata can be forwarded unchanged.
+
+use std::collections::HashMap;
+
+pub const MAX_KEYS: usize = 50;
+pub const MAX_KEY_LEN: usize = 40;
+pub const MAX_VALUE_LEN: usize = 500;
+/// Cap on the whole object as JSON, below what `MAX_KEYS` full-length values would take.
+pub const MAX_SERIALIZED_LEN: usize = 8 * 1024;
+
+pub type Metadata = HashMap<String, String>;
+
+pub fn validate(metadata: &Metadata) -> Result<(), String> {
+    if metadata.len() > MAX_KEYS { return Err(format!("metadata may have at most {} keys", MAX_KEYS)); }
+    for (key, value) in metadata {
+        if key.is_empty() || key.len() > MAX_KEY_LEN { return Err(format!("metadata key '{}' must be 1-{} characters", key, MAX_KEY_LEN)); }
+        if value.len() > MAX_VALUE_LEN { return Err(format!("metadata value for '{}' exceeds {} characters", key, MAX_VALUE_LEN)); }
+    }
+    let size = serde_json::to_string(metadata).map(|json| json.len()).unwrap_or(usize::MAX);
+    if size > MAX_SERIALIZED_LEN { return Err(format!("metadata exceeds {} bytes", MAX_SERIALIZED_LEN)); }
+    Ok(())
+}
+
+/// Reads metadata sent as arbitrary JSON: a flat object of string values within the
+/// limits above. Nested objects, arrays and non-string values are rejected.
+pub fn from_json(value: &serde_json::Value) -> Result<Metadata, String> {
+    let object = value.as_object().ok_or("metadata must be a JSON object")?;
+    let metadata = object.iter()
+        .map(|(key, value)| match value {
+            serde_json::Value::String(s) => Ok((key.clone(), s.clone())),
+            _ => Err(format!("metadata value for '{}' must be a string", key)),
+        })
+        .collect::<Result<Metadata, String>>()?;
+    validate(&metadata)?;
+    Ok(metadata)
+}
+
+/// Applies `patch` on top of `current`: keys are added or overwritten, and an empty
+/// value removes the key.
+pub fn merge(current: &Metadata, patch: &Metadata) -> Result<Metadata, String> {
+    let mut merged = current.clone();
+    for (key, value) in patch {
+        if value.is_empty() { merged.remove(key); } else { merged.insert(key.clone(), value.clone()); }
+    }
+    validate(&merged)?;
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(pairs: &[(&str, &str)]) -> Metadata { pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect() }
+
+    #[test]
+    fn test_validate_limits() {
+        assert!(validate(&meta(&[("contract_id", "C-42")])).is_ok());
+        assert!(validate(&meta(&[("", "x")])).is_err());
+        assert!(validate(&meta(&[(&"k".repeat(MAX_KEY_LEN + 1), "x")])).is_err());
+        assert!(validate(&meta(&[("k", &"v".repeat(MAX_VALUE_LEN + 1))])).is_err());
+        let many: Metadata = (0..=MAX_KEYS).map(|i| (format!("k{}", i), "v".into())).collect();
+        assert!(validate(&many).is_err());
+        // Each value fits, but together they're too large
+        let large: Metadata = (0..20).map(|i| (format!("k{}", i), "v".repeat(MAX_VALUE_LEN))).collect();
+        assert_eq!(validate(&large), Err(format!("metadata exceeds {} bytes", MAX_SERIALIZED_LEN)));
+    }
+
+    #[test]
+    fn test_from_json() {
+        use serde_json::json;
+        assert_eq!(from_json(&json!({ "order_id": "O-1" })), Ok(meta(&[("order_id", "O-1")])));
+        assert_eq!(from_json(&json!({ "order": { "id": "O-1" } })), Err("metadata value for 'order' must be a string".to_string()));
+        assert!(from_json(&json!({ "tags": ["a"] })).is_err());
+        assert!(from_json(&json!({ "count": 3 })).is_err());
+        assert!(from_json(&json!(["order_id"])).is_err());
+        assert!(from_json(&json!({ "note": "v".repeat(MAX_VALUE_LEN + 1) })).is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let current = meta(&[("contract_id", "C-42"), ("region", "eu")]);
+        let merged = merge(&current, &meta(&[("region", "us"), ("contract_id", ""), ("tier", "gold")])).unwrap();
+        assert_eq!(merged, meta(&[("region", "us"), ("tier", "gold")]));
+    }
+}
@@ -1,18 +1,41 @@
 //! Payment value objects
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use crate::domain::aggregates::PaymentError;
+
+pub mod amount;
+pub mod billing;
+pub mod card;
+pub mod currency;
+pub mod decimal_str;
+pub mod decline;
+pub mod descriptor;
+pub mod dispute_status;
+pub mod exchange_rate;
+pub mod metadata;
+pub mod refund_reason;
+pub mod transaction_status;
+#[cfg(test)]
+mod money_properties;
+pub use amount::Amount;
+pub use billing::{Address, BillingDetails};
+pub use decline::{DeclineCode, ProviderErrorKind};
+pub use dispute_status::DisputeStatus;
+pub use exchange_rate::{ExchangeRateProvider, StaticRates};
+pub use refund_reason::RefundReason;
+pub use transaction_status::TransactionStatus;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct PaymentId(String);
 impl PaymentId {
-    pub fn new() -> Self { Self(format!("pay_{}", uuid::Uuid::new_v4().to_string().replace("-", "")[..24].to_string())) }
+    pub fn new() -> Self { Self(format!("pay_{}", &uuid::Uuid::new_v4().simple().to_string()[..24])) }
     pub fn from_string(s: impl Into<String>) -> Self { Self(s.into()) }
     pub fn as_str(&self) -> &str { &self.0 }
 }
 impl Default for PaymentId { fn default() -> Self { Self::new() } }
 impl fmt::Display for PaymentId { fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{}", self.0) } }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PaymentMethod {
     pub method_type: PaymentMethodType,
     pub last_four: Option<String>,
@@ -21,19 +44,327 @@ pub struct PaymentMethod {
     pub exp_year: Option<u16>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PaymentMethodType { Card, BankTransfer, Wallet, Crypto }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Money { pub amount: rust_decimal::Decimal, pub currency: String }
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Money {
+    #[serde(with = "decimal_str")]
+    pub amount: rust_decimal::Decimal,
+    pub currency: String,
+}
 impl Money {
+    /// Builds an amount without checking `currency`. It never panics; use it for codes that
+    /// are already validated, or allow-listed by a `CurrencyPolicy`. Input from clients
+    /// goes through `try_new`.
     pub fn new(amount: rust_decimal::Decimal, currency: &str) -> Self { Self { amount, currency: currency.to_string() } }
+
+    /// Builds an amount in an ISO 4217 currency. The code must be exactly as listed: three
+    /// uppercase letters, so `"usd"` and `"US"` are rejected rather than passed on.
+    pub fn try_new(amount: rust_decimal::Decimal, currency: &str) -> Result<Self, PaymentError> {
+        if !currency::is_known(currency) { return Err(PaymentError::InvalidCurrency(currency.to_string())); }
+        Ok(Self::new(amount, currency))
+    }
     pub fn usd(amount: rust_decimal::Decimal) -> Self { Self::new(amount, "USD") }
+    pub fn zero(currency: &str) -> Self { Self::new(rust_decimal::Decimal::ZERO, currency) }
+
+    /// Subtracts `other`, clamping at zero instead of going negative.
+    pub fn saturating_sub(&self, other: &Money) -> Result<Money, PaymentError> {
+        self.ensure_same_currency(other)?;
+        Ok(Money::new((self.amount - other.amount).max(rust_decimal::Decimal::ZERO), &self.currency))
+    }
+
+    pub fn checked_add(&self, other: &Money) -> Result<Money, PaymentError> {
+        self.ensure_same_currency(other)?;
+        let amount = self.amount.checked_add(other.amount).ok_or_else(|| PaymentError::InvalidAmount("addition overflows".into()))?;
+        Ok(Money::new(amount, &self.currency))
+    }
+
+    pub fn checked_sub(&self, other: &Money) -> Result<Money, PaymentError> {
+        self.ensure_same_currency(other)?;
+        let amount = self.amount.checked_sub(other.amount).ok_or_else(|| PaymentError::InvalidAmount("subtraction overflows".into()))?;
+        Ok(Money::new(amount, &self.currency))
+    }
+
+    /// Builds an amount from an integer count of minor units, e.g. 1050 USD cents -> 10.50.
+    pub fn from_minor_units(minor: i64, currency: &str) -> Result<Money, PaymentError> {
+        let exponent = Self::exponent(currency)?;
+        Ok(Money::new(rust_decimal::Decimal::new(minor, exponent), currency))
+    }
+
+    /// Integer count of minor units. Fails if the amount carries more precision than the
+    /// currency allows.
+    pub fn to_minor_units(&self) -> Result<i64, PaymentError> {
+        let exponent = Self::exponent(&self.currency)?;
+        if self.amount.round_dp(exponent) != self.amount {
+            return Err(PaymentError::InvalidAmount(format!("{} has more than {} decimal places for {}", self.amount, exponent, self.currency)));
+        }
+        let mut scaled = self.amount;
+        scaled.rescale(exponent);
+        i64::try_from(scaled.mantissa()).map_err(|_| PaymentError::InvalidAmount(format!("{} is out of range", self.amount)))
+    }
+
+    /// Rounds half-to-even to the currency's minor units. Unknown currencies are left as is.
+    pub fn round(&self) -> Money {
+        match currency::minor_units(&self.currency) {
+            Some(exponent) => Money::new(self.amount.round_dp_with_strategy(exponent, rust_decimal::RoundingStrategy::MidpointNearestEven), &self.currency),
+            None => self.clone(),
+        }
+    }
+
+    /// Display form with the currency symbol, rounded to the currency's minor units and
+    /// grouped in thousands: `$1,234.56`, `¥1,235`, `-€12.50`. Currencies without a symbol
+    /// fall back to `format_with_code`.
+    pub fn format(&self) -> String {
+        match currency::symbol(&self.currency) {
+            Some(symbol) => {
+                let (sign, digits) = self.grouped();
+                format!("{}{}{}", sign, symbol, digits)
+            }
+            None => self.format_with_code(),
+        }
+    }
+
+    /// Display form with the ISO code after the number: `1,234.56 USD`, `-12.50 EUR`.
+    pub fn format_with_code(&self) -> String {
+        let (sign, digits) = self.grouped();
+        format!("{}{} {}", sign, digits, self.currency)
+    }
+
+    /// The sign and the grouped absolute value. Unknown currencies keep their own scale.
+    fn grouped(&self) -> (&'static str, String) {
+        let mut amount = self.round().amount;
+        if let Some(exponent) = currency::minor_units(&self.currency) { amount.rescale(exponent); }
+        let sign = if amount.is_sign_negative() && !amount.is_zero() { "-" } else { "" };
+        let plain = amount.abs().to_string();
+        let (whole, fraction) = match plain.split_once('.') {
+            Some((whole, fraction)) => (whole, Some(fraction)),
+            None => (plain.as_str(), None),
+        };
+        let mut out = String::with_capacity(plain.len() + whole.len() / 3);
+        for (i, digit) in whole.chars().enumerate() {
+            if i > 0 && (whole.len() - i) % 3 == 0 { out.push(','); }
+            out.push(digit);
+        }
+        if let Some(fraction) = fraction {
+            out.push('.');
+            out.push_str(fraction);
+        }
+        (sign, out)
+    }
+
+    /// Converts into `to` at `rates`' rate, rounded half-to-even to `to`'s minor units, so
+    /// e.g. USD→JPY drops the decimals. Converting to the same currency is a no-op.
+    pub fn convert(&self, to: &str, rates: &dyn ExchangeRateProvider) -> Result<Money, PaymentError> {
+        if to == self.currency { return Ok(self.clone()); }
+        if !currency::is_known(to) { return Err(PaymentError::InvalidCurrency(to.to_string())); }
+        let rate = rates.rate(&self.currency, to)?;
+        if rate <= rust_decimal::Decimal::ZERO {
+            return Err(PaymentError::InvalidAmount(format!("exchange rate {} for {}->{} must be positive", rate, self.currency, to)));
+        }
+        let amount = self.amount.checked_mul(rate).ok_or_else(|| PaymentError::InvalidAmount("conversion overflows".into()))?;
+        Ok(Money::new(amount, to).round())
+    }
+
+    /// Splits the amount in proportion to `ratios` without losing a minor unit: leftover
+    /// units go one each to the earliest shares.
+    pub fn allocate(&self, ratios: &[u32]) -> Result<Vec<Money>, PaymentError> {
+        let total: u64 = ratios.iter().map(|&r| u64::from(r)).sum();
+        if total == 0 { return Err(PaymentError::InvalidAmount("allocation ratios must not all be zero".into())); }
+        let minor = i128::from(self.to_minor_units()?);
+        let mut shares: Vec<i128> = ratios.iter().map(|&r| minor * i128::from(r) / i128::from(total)).collect();
+        let mut remainder = minor - shares.iter().sum::<i128>();
+        let step = remainder.signum();
+        for (share, _) in shares.iter_mut().zip(ratios).filter(|(_, &r)| r > 0) {
+            if remainder == 0 { break; }
+            *share += step;
+            remainder -= step;
+        }
+        shares.into_iter().map(|s| Money::from_minor_units(s as i64, &self.currency)).collect()
+    }
+
+    /// Orders two amounts in the same currency. Amounts in different currencies have no
+    /// order and give `CurrencyMismatch`, however the numbers compare.
+    pub fn cmp_checked(&self, other: &Money) -> Result<std::cmp::Ordering, PaymentError> {
+        self.ensure_same_currency(other)?;
+        Ok(self.amount.cmp(&other.amount))
+    }
+
+    pub fn is_positive(&self) -> bool { self.amount > rust_decimal::Decimal::ZERO }
+    pub fn is_zero(&self) -> bool { self.amount.is_zero() }
+    pub fn is_negative(&self) -> bool { self.amount < rust_decimal::Decimal::ZERO }
+
+    fn exponent(code: &str) -> Result<u32, PaymentError> {
+        currency::minor_units(code).ok_or_else(|| PaymentError::InvalidCurrency(code.to_string()))
+    }
+
+    fn ensure_same_currency(&self, other: &Money) -> Result<(), PaymentError> {
+        if self.currency == other.currency { return Ok(()); }
+        Err(PaymentError::CurrencyMismatch { expected: self.currency.clone(), actual: other.currency.clone() })
+    }
+}
+
+/// `a + b` is `a.checked_add(&b)`: currencies must match and the sum must not overflow.
+impl std::ops::Add for Money {
+    type Output = Result<Money, PaymentError>;
+    fn add(self, rhs: Money) -> Self::Output { self.checked_add(&rhs) }
+}
+impl std::ops::Add for &Money {
+    type Output = Result<Money, PaymentError>;
+    fn add(self, rhs: &Money) -> Self::Output { self.checked_add(rhs) }
+}
+/// `a - b` is `a.checked_sub(&b)`. The result may be negative; see `saturating_sub` to clamp.
+impl std::ops::Sub for Money {
+    type Output = Result<Money, PaymentError>;
+    fn sub(self, rhs: Money) -> Self::Output { self.checked_sub(&rhs) }
+}
+impl std::ops::Sub for &Money {
+    type Output = Result<Money, PaymentError>;
+    fn sub(self, rhs: &Money) -> Self::Output { self.checked_sub(rhs) }
 }
 
+/// `a < b` is `a.cmp_checked(&b)`: amounts in different currencies are unordered, so every
+/// comparison between them is false.
+impl PartialOrd for Money {
+    fn partial_cmp(&self, other: &Money) -> Option<std::cmp::Ordering> { self.cmp_checked(other).ok() }
+}
+
+/// A percentage such as `1.5` for 1.5%. Never negative.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Percentage(rust_decimal::Decimal);
+impl Percentage {
+    pub fn new(percent: rust_decimal::Decimal) -> Option<Self> { (percent >= rust_decimal::Decimal::ZERO).then_some(Self(percent)) }
+    pub fn value(&self) -> rust_decimal::Decimal { self.0 }
+    pub fn as_fraction(&self) -> rust_decimal::Decimal { self.0 / rust_decimal::Decimal::ONE_HUNDRED }
+}
+impl fmt::Display for Percentage { fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{}%", self.0) } }
+
 #[cfg(test)]
 mod tests {
     use super::*;
     #[test]
     fn test_payment_id() { let id = PaymentId::new(); assert!(id.as_str().starts_with("pay_")); }
+
+    #[test]
+    fn test_saturating_sub() {
+        let ten = Money::usd(rust_decimal::Decimal::new(1000, 2));
+        let four = Money::usd(rust_decimal::Decimal::new(400, 2));
+        assert_eq!(ten.saturating_sub(&four).unwrap(), Money::usd(rust_decimal::Decimal::new(600, 2)));
+        assert_eq!(four.saturating_sub(&ten).unwrap(), Money::zero("USD"));
+        assert_eq!(ten.saturating_sub(&ten).unwrap(), Money::zero("USD"));
+        let eur = Money::new(rust_decimal::Decimal::ONE, "EUR");
+        assert_eq!(ten.saturating_sub(&eur), Err(PaymentError::CurrencyMismatch { expected: "USD".into(), actual: "EUR".into() }));
+    }
+
+    #[test]
+    fn test_add_and_sub() {
+        let ten = Money::usd(rust_decimal::Decimal::new(1000, 2));
+        let four = Money::usd(rust_decimal::Decimal::new(400, 2));
+        assert_eq!(ten.clone() + four.clone(), Ok(Money::usd(rust_decimal::Decimal::new(1400, 2))));
+        assert_eq!(&four - &ten, Ok(Money::usd(rust_decimal::Decimal::new(-600, 2))));
+        let eur = Money::new(rust_decimal::Decimal::ONE, "EUR");
+        assert_eq!(&ten + &eur, Err(PaymentError::CurrencyMismatch { expected: "USD".into(), actual: "EUR".into() }));
+        assert_eq!(ten - eur, Err(PaymentError::CurrencyMismatch { expected: "USD".into(), actual: "EUR".into() }));
+
+        let max = Money::usd(rust_decimal::Decimal::MAX);
+        assert!(matches!(&max + &Money::usd(rust_decimal::Decimal::ONE), Err(PaymentError::InvalidAmount(_))));
+        assert!(matches!(&Money::usd(rust_decimal::Decimal::MIN) - &Money::usd(rust_decimal::Decimal::ONE), Err(PaymentError::InvalidAmount(_))));
+        assert_eq!(&max - &Money::usd(rust_decimal::Decimal::ONE), Ok(Money::usd(rust_decimal::Decimal::MAX - rust_decimal::Decimal::ONE)));
+    }
+
+    #[test]
+    fn test_compare() {
+        use std::cmp::Ordering;
+        let usd = |cents| Money::usd(rust_decimal::Decimal::new(cents, 2));
+        assert_eq!(usd(400).cmp_checked(&usd(1000)), Ok(Ordering::Less));
+        assert_eq!(usd(1000).cmp_checked(&usd(400)), Ok(Ordering::Greater));
+        // Scale doesn't matter: 10.00 and 10.0 are the same amount
+        assert_eq!(usd(1000).cmp_checked(&Money::usd(rust_decimal::Decimal::new(100, 1))), Ok(Ordering::Equal));
+        assert!(usd(400) < usd(1000) && usd(1000) >= usd(1000));
+
+        let eur = Money::new(rust_decimal::Decimal::new(1000, 2), "EUR");
+        assert_eq!(usd(1000).cmp_checked(&eur), Err(PaymentError::CurrencyMismatch { expected: "USD".into(), actual: "EUR".into() }));
+        assert_eq!(usd(1000).partial_cmp(&eur), None);
+        let ten = usd(1000);
+        assert_eq!([ten < eur, ten > eur, ten <= eur, ten >= eur, ten == eur], [false; 5]);
+
+        assert!(usd(1).is_positive() && !usd(1).is_zero() && !usd(1).is_negative());
+        assert!(usd(-1).is_negative() && !usd(-1).is_positive());
+        assert!(Money::zero("USD").is_zero() && !Money::zero("USD").is_positive() && !Money::zero("USD").is_negative());
+    }
+
+    #[test]
+    fn test_minor_units_precision() {
+        assert_eq!(Money::usd(rust_decimal::Decimal::new(10501, 3)).to_minor_units().map_err(|_| ()), Err(()));
+        assert_eq!(Money::usd(rust_decimal::Decimal::new(10500, 3)).to_minor_units(), Ok(1050));
+        assert_eq!(Money::from_minor_units(500, "JPY").unwrap().amount, rust_decimal::Decimal::from(500));
+        assert_eq!(Money::from_minor_units(1, "ZZT"), Err(PaymentError::InvalidCurrency("ZZT".into())));
+        assert!(Money::usd(rust_decimal::Decimal::ONE).allocate(&[0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        let money = |amount: i64, scale: u32, currency: &str| Money::new(rust_decimal::Decimal::new(amount, scale), currency);
+        assert_eq!(money(123456, 2, "USD").format(), "$1,234.56");
+        assert_eq!(money(123456, 2, "USD").format_with_code(), "1,234.56 USD");
+        assert_eq!(money(5, 0, "USD").format(), "$5.00");
+        assert_eq!(money(123456, 0, "USD").format(), "$123,456.00");
+        assert_eq!(money(12345, 1, "JPY").format(), "¥1,234");
+        assert_eq!(money(12355, 1, "JPY").format(), "¥1,236");
+        assert_eq!(money(1234567, 0, "JPY").format_with_code(), "1,234,567 JPY");
+        assert_eq!(money(-1250, 2, "EUR").format(), "-€12.50");
+        assert_eq!(money(-123456789, 2, "EUR").format_with_code(), "-1,234,567.89 EUR");
+        assert_eq!(money(-1, 3, "EUR").format(), "€0.00");
+        assert_eq!(money(1500, 3, "KWD").format(), "1.500 KWD");
+    }
+
+    #[test]
+    fn test_round_half_even() {
+        let round = |mantissa, scale, currency| Money::new(rust_decimal::Decimal::new(mantissa, scale), currency).round();
+        // Exactly half a cent goes to the even cent, either way
+        assert_eq!(round(1005, 3, "USD").amount, rust_decimal::Decimal::new(100, 2));
+        assert_eq!(round(1015, 3, "USD").amount, rust_decimal::Decimal::new(102, 2));
+        assert_eq!(round(-1005, 3, "USD").amount, rust_decimal::Decimal::new(-100, 2));
+        // Anything past the half rounds away from it
+        assert_eq!(round(10051, 4, "USD").amount, rust_decimal::Decimal::new(101, 2));
+        assert_eq!(round(25, 1, "JPY").amount, rust_decimal::Decimal::from(2));
+        assert_eq!(round(10005, 4, "KWD").amount, rust_decimal::Decimal::new(1000, 3));
+        // Already at the currency's precision, or an unknown currency: unchanged
+        assert_eq!(round(100, 2, "USD"), Money::new(rust_decimal::Decimal::new(100, 2), "USD"));
+        assert_eq!(round(1005, 3, "ZZT").amount, rust_decimal::Decimal::new(1005, 3));
+    }
+
+    #[test]
+    fn test_try_new_validates_currency() {
+        let amount = rust_decimal::Decimal::new(1050, 2);
+        assert_eq!(Money::try_new(amount, "USD"), Ok(Money::new(amount, "USD")));
+        assert_eq!(Money::try_new(amount, "BHD").unwrap().currency, "BHD");
+        for bad in ["usd", "Usd", "US", "USDX", "ZZT", "", " USD"] {
+            assert_eq!(Money::try_new(amount, bad), Err(PaymentError::InvalidCurrency(bad.into())), "{:?}", bad);
+        }
+        // The unchecked constructor still accepts anything
+        assert_eq!(Money::new(amount, "usd").currency, "usd");
+    }
+
+    #[test]
+    fn test_convert() {
+        let rates = StaticRates::new()
+            .with_rate("USD", "JPY", rust_decimal::Decimal::new(14937, 2))
+            .with_rate("USD", "NGN", rust_decimal::Decimal::new(1_550_125, 3))
+            .with_rate("EUR", "USD", rust_decimal::Decimal::ZERO);
+        let usd = |cents| Money::usd(rust_decimal::Decimal::new(cents, 2));
+        // 10.01 * 149.37 = 1495.1937: yen have no minor units
+        assert_eq!(usd(1001).convert("JPY", &rates), Ok(Money::new(rust_decimal::Decimal::from(1495), "JPY")));
+        // 10.01 * 1550.125 = 15516.75125, rounded to kobo
+        assert_eq!(usd(1001).convert("NGN", &rates), Ok(Money::new(rust_decimal::Decimal::new(1_551_675, 2), "NGN")));
+        // 0.10 * 149.37 = 14.937, and a half rounds to even
+        assert_eq!(usd(10).convert("JPY", &rates).unwrap().amount, rust_decimal::Decimal::from(15));
+        assert_eq!(Money::usd(rust_decimal::Decimal::new(25, 1)).convert("JPY", &StaticRates::new().with_rate("USD", "JPY", rust_decimal::Decimal::ONE)).unwrap().amount, rust_decimal::Decimal::from(2));
+        assert_eq!(usd(1001).convert("USD", &StaticRates::new()), Ok(usd(1001)));
+
+        assert_eq!(usd(100).convert("GBP", &rates), Err(PaymentError::UnknownExchangeRate { from: "USD".into(), to: "GBP".into() }));
+        assert_eq!(Money::new(rust_decimal::Decimal::ONE, "JPY").convert("USD", &rates), Err(PaymentError::UnknownExchangeRate { from: "JPY".into(), to: "USD".into() }));
+        assert_eq!(usd(100).convert("ZZT", &rates), Err(PaymentError::InvalidCurrency("ZZT".into())));
+        assert!(matches!(Money::new(rust_decimal::Decimal::ONE, "EUR").convert("USD", &rates), Err(PaymentError::InvalidAmount(_))));
+    }
 }
@@ -0,0 +1,65 @@
+//! Property tests for `Money` arithmetic
+//!
+//! Generated across every known currency. A failing case is shrunk to a minimal one and
+//! saved under `proptest-regressions/`, so it is replayed first on the next run.
+
+use super::{currency, Money};
+use proptest::prelude::*;
+
+/// Keeps generated values well inside `i64` so sums of two cannot overflow.
+const MAX_MINOR: i64 = 1_000_000_000_000_000;
+
+fn any_currency() -> impl Strategy<Value = &'static str> {
+    prop::sample::select(currency::codes().collect::<Vec<_>>())
+}
+
+fn any_minor() -> impl Strategy<Value = i64> { -MAX_MINOR..=MAX_MINOR }
+
+fn any_money() -> impl Strategy<Value = Money> {
+    (any_minor(), any_currency()).prop_map(|(minor, code)| Money::from_minor_units(minor, code).unwrap())
+}
+
+/// Between 1 and 12 ratios, the first non-zero so there is always something to split by.
+fn any_ratios() -> impl Strategy<Value = Vec<u32>> {
+    (1..=100u32, prop::collection::vec(0..=100u32, 0..12)).prop_map(|(first, rest)| {
+        std::iter::once(first).chain(rest).collect()
+    })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(2_000))]
+
+    #[test]
+    fn minor_units_round_trip(minor in any_minor(), code in any_currency()) {
+        let money = Money::from_minor_units(minor, code).unwrap();
+        prop_assert_eq!(money.to_minor_units(), Ok(minor));
+        prop_assert_eq!(Money::from_minor_units(money.to_minor_units().unwrap(), code).unwrap(), money);
+    }
+
+    #[test]
+    fn allocate_sums_to_input(money in any_money(), ratios in any_ratios()) {
+        let shares = money.allocate(&ratios).unwrap();
+        prop_assert_eq!(shares.len(), ratios.len());
+        let total = shares.iter().try_fold(Money::zero(&money.currency), |acc, s| acc.checked_add(s)).unwrap();
+        prop_assert_eq!(total.to_minor_units(), money.to_minor_units());
+        for (share, ratio) in shares.iter().zip(&ratios) {
+            prop_assert!(share.to_minor_units().is_ok());
+            if *ratio == 0 { prop_assert_eq!(share.to_minor_units(), Ok(0)); }
+        }
+    }
+
+    #[test]
+    fn add_and_sub_are_inverse(a in any_money(), b_minor in any_minor()) {
+        let b = Money::from_minor_units(b_minor, &a.currency).unwrap();
+        let sum = a.checked_add(&b).unwrap();
+        prop_assert_eq!(sum.checked_sub(&b).unwrap().to_minor_units(), a.to_minor_units());
+        prop_assert_eq!(a.checked_sub(&b).unwrap().checked_add(&b).unwrap().to_minor_units(), a.to_minor_units());
+    }
+
+    #[test]
+    fn rounding_preserves_minor_units(money in any_money()) {
+        let rounded = money.round();
+        prop_assert_eq!(rounded.to_minor_units(), money.to_minor_units());
+        prop_assert_eq!(rounded.round(), rounded);
+    }
+}
@@ -0,0 +1,92 @@
+//! Why a refund was issued
+//!
+//! Stored and serialized as one canonical string: `duplicate`, `fraudulent`,
+//! `requested_by_customer`, or the free text of `Other`. The named variants are what
+//! provider reason codes are mapped from.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Longest free-text reason accepted, in characters.
+pub const MAX_OTHER_LEN: usize = 500;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum RefundReason {
+    Duplicate,
+    Fraudulent,
+    RequestedByCustomer,
+    /// Anything else, as trimmed free text. Never one of the named reasons' strings.
+    Other(String),
+}
+
+impl RefundReason {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Duplicate => "duplicate",
+            Self::Fraudulent => "fraudulent",
+            Self::RequestedByCustomer => "requested_by_customer",
+            Self::Other(text) => text,
+        }
+    }
+}
+
+impl fmt::Display for RefundReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str(self.as_str()) }
+}
+
+/// Named reasons match case-insensitively; any other non-empty text becomes `Other`.
+impl FromStr for RefundReason {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let text = s.trim();
+        match text.to_ascii_lowercase().as_str() {
+            "" => Err("refund reason must not be empty".to_string()),
+            "duplicate" => Ok(Self::Duplicate),
+            "fraudulent" => Ok(Self::Fraudulent),
+            "requested_by_customer" => Ok(Self::RequestedByCustomer),
+            _ if text.chars().count() > MAX_OTHER_LEN => Err(format!("refund reason is longer than {} characters", MAX_OTHER_LEN)),
+            _ => Ok(Self::Other(text.to_string())),
+        }
+    }
+}
+
+impl TryFrom<String> for RefundReason {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, Self::Error> { s.parse() }
+}
+
+impl From<RefundReason> for String {
+    fn from(reason: RefundReason) -> Self {
+        match reason {
+            RefundReason::Other(text) => text,
+            named => named.as_str().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialization() {
+        let cases = [
+            (RefundReason::Duplicate, "duplicate"),
+            (RefundReason::Fraudulent, "fraudulent"),
+            (RefundReason::RequestedByCustomer, "requested_by_customer"),
+            (RefundReason::Other("item arrived damaged".into()), "item arrived damaged"),
+        ];
+        for (reason, canonical) in cases {
+            assert_eq!(serde_json::to_value(&reason).unwrap(), canonical);
+            assert_eq!(serde_json::from_value::<RefundReason>(canonical.into()).unwrap(), reason);
+            assert_eq!(reason.to_string(), canonical);
+        }
+        assert_eq!(serde_json::from_str::<RefundReason>(r#"" Duplicate ""#).unwrap(), RefundReason::Duplicate);
+        assert_eq!(serde_json::from_str::<RefundReason>(r#""  late delivery ""#).unwrap(), RefundReason::Other("late delivery".into()));
+        assert!(serde_json::from_str::<RefundReason>(r#""   ""#).is_err());
+        assert!("x".repeat(MAX_OTHER_LEN + 1).parse::<RefundReason>().is_err());
+        assert!(serde_json::from_str::<RefundReason>("42").is_err());
+    }
+}
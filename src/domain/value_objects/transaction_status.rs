@@ -0,0 +1,142 @@
+//! Lifecycle of a stored transaction
+//!
+//! `transactions.status` holds one of these as snake_case text. Every status change
+//! goes through `can_transition_to`, so the graph below is the only place legal jumps
+//! are defined.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use crate::domain::aggregates::PaymentError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionStatus {
+    /// Waiting for its `scheduled_at`; nothing has been sent to a provider yet.
+    Scheduled,
+    Pending,
+    /// The customer must complete an extra step, e.g. 3DS.
+    RequiresAction,
+    Processing,
+    /// The provider holds the funds for a later capture; nothing has been taken yet.
+    Authorized,
+    Succeeded,
+    Failed,
+    Cancelled,
+    PartiallyRefunded,
+    Refunded,
+    /// The cardholder disputed the charge; it is held until the dispute closes.
+    Disputed,
+    /// A dispute was lost and the funds went back to the cardholder.
+    ChargedBack,
+}
+
+impl TransactionStatus {
+    pub const ALL: [Self; 12] = [
+        Self::Scheduled, Self::Pending, Self::RequiresAction, Self::Processing, Self::Authorized, Self::Succeeded,
+        Self::Failed, Self::Cancelled, Self::PartiallyRefunded, Self::Refunded,
+        Self::Disputed, Self::ChargedBack,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Scheduled => "scheduled",
+            Self::Pending => "pending",
+            Self::RequiresAction => "requires_action",
+            Self::Processing => "processing",
+            Self::Authorized => "authorized",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+            Self::PartiallyRefunded => "partially_refunded",
+            Self::Refunded => "refunded",
+            Self::Disputed => "disputed",
+            Self::ChargedBack => "charged_back",
+        }
+    }
+
+    /// Whether a transaction in this status may move to `next`. A failed payment can go
+    /// back to pending to be retried; a further partial refund keeps `PartiallyRefunded`.
+    /// A won dispute returns the charge to the status it had when the dispute opened.
+    /// A scheduled payment becomes pending when it falls due, or fails if it can no longer
+    /// be initiated. An authorization is either captured, which succeeds the charge, or
+    /// cancelled by a void or by expiring.
+    pub fn can_transition_to(&self, next: Self) -> bool {
+        use TransactionStatus::*;
+        matches!(
+            (self, next),
+            (Scheduled, Pending | Failed | Cancelled)
+                | (Pending, RequiresAction | Processing | Authorized | Succeeded | Failed | Cancelled)
+                | (RequiresAction, Processing | Authorized | Succeeded | Failed | Cancelled)
+                | (Processing, Authorized | Succeeded | Failed)
+                | (Authorized, Succeeded | Cancelled)
+                | (Failed, Pending)
+                | (Succeeded | PartiallyRefunded, PartiallyRefunded | Refunded | Disputed)
+                | (Disputed, Succeeded | PartiallyRefunded | ChargedBack)
+        )
+    }
+
+    pub fn transition_to(&self, next: Self) -> Result<Self, PaymentError> {
+        if self.can_transition_to(next) { return Ok(next); }
+        Err(PaymentError::InvalidTransition { from: self.as_str().to_string(), to: next.as_str().to_string() })
+    }
+
+    /// Every status that may move to `self`, for guarding an update in SQL.
+    pub fn predecessors(&self) -> Vec<Self> {
+        Self::ALL.into_iter().filter(|s| s.can_transition_to(*self)).collect()
+    }
+}
+
+impl fmt::Display for TransactionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str(self.as_str()) }
+}
+
+impl FromStr for TransactionStatus {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL.into_iter().find(|status| status.as_str() == s).ok_or_else(|| format!("unknown transaction status '{}'", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TransactionStatus::*;
+
+    #[test]
+    fn test_transition_matrix() {
+        let allowed = [
+            (Scheduled, Pending), (Scheduled, Failed), (Scheduled, Cancelled),
+            (Pending, RequiresAction), (Pending, Processing), (Pending, Authorized), (Pending, Succeeded), (Pending, Failed), (Pending, Cancelled),
+            (RequiresAction, Processing), (RequiresAction, Authorized), (RequiresAction, Succeeded), (RequiresAction, Failed), (RequiresAction, Cancelled),
+            (Processing, Authorized), (Processing, Succeeded), (Processing, Failed),
+            (Authorized, Succeeded), (Authorized, Cancelled),
+            (Failed, Pending),
+            (Succeeded, PartiallyRefunded), (Succeeded, Refunded), (Succeeded, Disputed),
+            (PartiallyRefunded, PartiallyRefunded), (PartiallyRefunded, Refunded), (PartiallyRefunded, Disputed),
+            (Disputed, Succeeded), (Disputed, PartiallyRefunded), (Disputed, ChargedBack),
+        ];
+        for from in TransactionStatus::ALL {
+            for to in TransactionStatus::ALL {
+                let expected = allowed.contains(&(from, to));
+                assert_eq!(from.can_transition_to(to), expected, "{} -> {}", from, to);
+                assert_eq!(from.transition_to(to).is_ok(), expected, "{} -> {}", from, to);
+            }
+        }
+        assert_eq!(Refunded.transition_to(Pending), Err(PaymentError::InvalidTransition { from: "refunded".into(), to: "pending".into() }));
+        assert_eq!(Succeeded.predecessors(), vec![Pending, RequiresAction, Processing, Authorized, Disputed]);
+        assert!(Cancelled.predecessors().iter().all(|s| matches!(s, Scheduled | Pending | RequiresAction | Authorized)));
+        assert_eq!(Authorized.predecessors(), vec![Pending, RequiresAction, Processing]);
+        assert_eq!(Pending.predecessors(), vec![Scheduled, Failed]);
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        for status in TransactionStatus::ALL {
+            assert_eq!(status.to_string().parse::<TransactionStatus>(), Ok(status));
+            assert_eq!(serde_json::to_value(status).unwrap(), status.as_str());
+        }
+        assert!("Succeeded".parse::<TransactionStatus>().is_err());
+        assert!("settled".parse::<TransactionStatus>().is_err());
+    }
+}
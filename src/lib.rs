@@ -2,8 +2,18 @@
 //!
 //! Self-hosted payment gateway, Stripe alternative.
 
+pub mod archive;
+pub mod checkout;
+pub mod crypto;
 pub mod domain;
+pub mod providers;
+pub mod rate_limit;
+pub mod webhooks;
+
+#[cfg(test)]
+mod test_support;
 
 pub use domain::aggregates::{Payment, Subscription, PaymentError, SubscriptionError};
-pub use domain::value_objects::{PaymentId, PaymentMethod};
-pub use domain::events::{DomainEvent, PaymentEvent, SubscriptionEvent};
+pub use domain::value_objects::{Amount, Money, PaymentId, PaymentMethod, Percentage};
+pub use domain::services::{accrue_late_fee, LateFeePolicy};
+pub use domain::events::{DisputeEvent, DomainEvent, PaymentEvent, PaymentMethodEvent, SubscriptionEvent};
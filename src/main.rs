@@ -2,23 +2,50 @@
 
 use anyhow::Result;
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    response::IntoResponse,
+    body::Bytes,
+    extract::{ConnectInfo, Path, Query, Request, State},
+    http::{HeaderMap, Method, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
     routing::{get, post},
-    Json, Router,
+    Extension, Json, Router,
 };
 use chrono::{DateTime, Utc};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
 use sqlx::postgres::PgPoolOptions;
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
 use tower_http::cors::CorsLayer;
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use uuid::Uuid;
 use validator::Validate;
 
+use sase_payments::providers::{classify, fails_over, flutterwave, paystack, webhook_event, FlutterwaveGateway, parse_provider_currencies, FailureClass, redact_raw_response, CaptureSubmission, ChargeRequest, ChargeResult, NextAction, PaymentGateway, PaystackGateway, ProviderCapabilities, ProviderRouter, RefundOutcome, RefundSubmission, RetryPolicy, StubGateway, Verification, VerifiedStatus, WebhookAllowlist, WebhookEvent};
+use sase_payments::domain::aggregates::{BillingCycle, DunningOutcome, DunningPolicy, Invoice, InvoiceError, InvoiceLine, InvoiceRecord, InvoiceStatus, Payment, PaymentPlan, PaymentRecord, Subscription as SubscriptionAggregate, SubscriptionRecord, SubscriptionStatus};
+use sase_payments::domain::repositories::{InvoiceRepository, PaymentRepository, RepositoryError};
+use sase_payments::domain::value_objects::amount::{minor_to_decimal, parse_amount_limits, AmountLimits, DEFAULT_CURRENCY};
+use sase_payments::domain::value_objects::card::CardDetails;
+use sase_payments::domain::value_objects::currency::CurrencyPolicy;
+use sase_payments::domain::value_objects::decimal_str;
+use sase_payments::domain::value_objects::descriptor::statement_descriptor;
+use sase_payments::domain::value_objects::metadata::{self, Metadata};
+use sase_payments::domain::services::ledger::{self, LedgerEntry};
+use sase_payments::domain::services::{card_expiry, ensure_sufficient, expires_on, is_expired, CardExpiry, FxConversion, LateFeePolicy, SubscriptionMetrics, TaxCalculator, TaxRates, TransferPreview};
+use sase_payments::archive::{self, ArchiveStore, LocalDirStore};
+use sase_payments::checkout;
+use sase_payments::rate_limit::{RateLimit, RateLimiter};
+use sase_payments::webhooks;
+use sase_payments::domain::value_objects::{BillingDetails, DisputeStatus, PaymentMethod as PaymentMethodDetails, Percentage, RefundReason, TransactionStatus};
+use sase_payments::domain::events::publisher::{self, EventPublisher, PublishError};
+use sase_payments::{Amount, DisputeEvent, DomainEvent, Money, PaymentError, PaymentEvent, PaymentId, PaymentMethodEvent, SubscriptionEvent};
+
 // =============================================================================
 // Domain Models
 // =============================================================================
@@ -27,35 +54,126 @@ use validator::Validate;
 pub struct Transaction {
     pub id: Uuid,
     pub reference: String,
+    #[serde(with = "decimal_str")]
     pub amount: Decimal,
     pub currency: String,
     pub status: String,
     pub transaction_type: String,
     pub customer_id: Option<Uuid>,
+    #[serde(skip_serializing)]
+    pub merchant_id: Option<Uuid>,
     pub customer_email: Option<String>,
     pub payment_method: Option<String>,
     pub provider: Option<String>,
     pub provider_reference: Option<String>,
+    /// What the customer must do next, while the charge is in `requires_action`.
+    pub next_action: Option<sqlx::types::Json<NextAction>>,
+    /// When a scheduled payment is, or was, due to be charged.
+    pub scheduled_at: Option<DateTime<Utc>>,
+    /// Made with `capture: false`: authorized first, and taken by a separate capture.
+    #[serde(default)]
+    pub manual_capture: bool,
+    /// What was held at authorization; `amount` drops to what was taken when less is captured.
+    #[serde(default, with = "decimal_str::option")]
+    pub authorized_amount: Option<Decimal>,
+    /// When an uncaptured authorization is cancelled.
+    pub authorization_expires_at: Option<DateTime<Utc>>,
     pub metadata: serde_json::Value,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub completed_at: Option<DateTime<Utc>>,
+    pub settled_at: Option<DateTime<Utc>>,
+    pub settlement_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Wallet {
     pub id: Uuid,
+    #[serde(skip_serializing)]
+    pub merchant_id: Option<Uuid>,
     pub customer_id: Uuid,
-    pub balance: Decimal,
+    /// Used by top-ups and transfers that don't name a currency.
     pub currency: String,
     pub status: String,
+    pub allow_overdraft: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct WalletBalance {
+    pub wallet_id: Uuid,
+    pub currency: String,
+    /// The ledger balance, including funds under a hold.
+    #[serde(with = "decimal_str")]
+    pub balance: Decimal,
+    #[serde(with = "decimal_str")]
+    pub held: Decimal,
+    /// What can be spent: `balance` less `held`.
+    #[serde(with = "decimal_str")]
+    pub available: Decimal,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Funds reserved out of a wallet's available balance until captured or released.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct WalletHold {
+    pub id: Uuid,
+    pub wallet_id: Uuid,
+    pub currency: String,
+    #[serde(with = "decimal_str")]
+    pub amount: Decimal,
+    pub status: String,
+    pub reference: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+/// A wallet with its balance in every currency it holds.
+#[derive(Debug, Serialize)]
+pub struct WalletView {
+    #[serde(flatten)]
+    pub wallet: Wallet,
+    pub balances: Vec<WalletBalance>,
+}
+
+/// One leg of a ledger posting.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct LedgerEntryRecord {
+    pub id: Uuid,
+    pub account: String,
+    pub direction: String,
+    #[serde(with = "decimal_str")]
+    pub amount: Decimal,
+    pub currency: String,
+    pub reference: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct WalletTransaction {
+    pub id: Uuid,
+    pub wallet_id: Uuid,
+    #[serde(with = "decimal_str")]
+    pub amount: Decimal,
+    pub currency: String,
+    #[serde(with = "decimal_str")]
+    pub balance_after: Decimal,
+    pub transaction_type: String,
+    pub reference: Option<String>,
+    pub description: Option<String>,
+    #[serde(with = "decimal_str")]
+    pub reversed_amount: Decimal,
+    pub reversed_at: Option<DateTime<Utc>>,
+    pub reverses_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct PaymentMethod {
     pub id: Uuid,
+    #[serde(skip_serializing)]
+    pub merchant_id: Option<Uuid>,
     pub customer_id: Uuid,
     pub method_type: String,
     pub provider: String,
@@ -63,6 +181,11 @@ pub struct PaymentMethod {
     pub last_four: Option<String>,
     pub brand: Option<String>,
     pub is_default: bool,
+    pub exp_month: Option<i16>,
+    pub exp_year: Option<i16>,
+    pub expiry_notified_at: Option<DateTime<Utc>>,
+    pub expired_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
 }
 
@@ -70,10 +193,83 @@ pub struct PaymentMethod {
 pub struct Refund {
     pub id: Uuid,
     pub transaction_id: Uuid,
+    #[serde(with = "decimal_str")]
     pub amount: Decimal,
     pub reason: Option<String>,
     pub status: String,
     pub created_at: DateTime<Utc>,
+    pub processed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Dispute {
+    pub id: Uuid,
+    pub transaction_id: Uuid,
+    pub provider_dispute_id: Option<String>,
+    pub reason: String,
+    pub status: String,
+    #[serde(with = "decimal_str")]
+    pub amount: Decimal,
+    pub evidence: Option<serde_json::Value>,
+    pub evidence_due_by: Option<DateTime<Utc>>,
+    /// The charge's status before the dispute, put back if it is won.
+    pub transaction_status: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub closed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Subscription {
+    pub id: Uuid,
+    #[serde(skip_serializing)]
+    pub merchant_id: Option<Uuid>,
+    pub customer_id: Uuid,
+    pub plan_id: String,
+    #[serde(with = "decimal_str")]
+    pub amount: Decimal,
+    pub currency: String,
+    pub billing_cycle: String,
+    pub status: String,
+    pub current_period_start: chrono::NaiveDate,
+    pub current_period_end: chrono::NaiveDate,
+    pub cancel_at_period_end: bool,
+    pub metadata: sqlx::types::Json<Metadata>,
+    #[serde(with = "decimal_str")]
+    pub total_paid: Decimal,
+    pub renewal_count: i32,
+    pub consecutive_failures: i32,
+    pub last_payment_failed_at: Option<DateTime<Utc>>,
+    pub payment_method_id: Option<Uuid>,
+    /// Where the customer is billed, for tax; `None` bills without tax.
+    pub billing_details: Option<sqlx::types::Json<BillingDetails>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Metered usage on a subscription; `billed_reference` is the renewal that charged it.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct UsageRecord {
+    pub id: Uuid,
+    pub subscription_id: Uuid,
+    pub quantity: i64,
+    #[serde(with = "decimal_str")]
+    pub unit_amount: Decimal,
+    #[serde(with = "decimal_str")]
+    pub amount: Decimal,
+    pub billed_reference: Option<String>,
+    pub recorded_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct WebhookEndpoint {
+    pub id: Uuid,
+    #[serde(skip_serializing)]
+    pub merchant_id: Option<Uuid>,
+    pub url: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
 }
 
 // =============================================================================
@@ -83,17 +279,108 @@ pub struct Refund {
 #[derive(Clone)]
 pub struct AppState {
     pub db: sqlx::PgPool,
-    pub nats: Option<async_nats::Client>,
+    /// Where domain events are published; `None` when no NATS server is configured.
+    pub nats: Option<Arc<dyn EventPublisher>>,
+    pub http: reqwest::Client,
+    /// Used when a payment doesn't name a provider.
+    pub gateway: Arc<dyn PaymentGateway>,
+    /// Further providers a payment can select by name.
+    pub gateways: Arc<Vec<Arc<dyn PaymentGateway>>>,
     pub config: Arc<Config>,
+    /// Buckets for `Config::payment_rate_limit`; `None` when payments aren't limited.
+    pub payment_limiter: Option<Arc<RateLimiter>>,
 }
 
-#[derive(Debug)]
+impl AppState {
+    /// The gateway registered as `name`, or the default one when no name is given.
+    fn gateway_named(&self, name: Option<&str>) -> Option<Arc<dyn PaymentGateway>> {
+        match name {
+            None => Some(self.gateway.clone()),
+            Some(name) => std::iter::once(&self.gateway).chain(self.gateways.iter()).find(|g| g.name() == name).cloned(),
+        }
+    }
+
+    /// The gateways the routing table picks for a payment, best first: its configured
+    /// providers that accept the currency, or the default gateway when no rule matches.
+    fn routed_gateways(&self, currency: &str, minor_units: i64) -> Result<Vec<Arc<dyn PaymentGateway>>, (StatusCode, String)> {
+        let usable = |name: &str| {
+            self.gateway_named(Some(name)).is_some()
+                && self.config.provider_capabilities.get(name).is_none_or(|c| c.supports_currency(currency))
+        };
+        match self.config.provider_routes.select(currency, minor_units, usable) {
+            None => Ok(vec![self.gateway.clone()]),
+            Some(Ok(names)) => names.iter()
+                .map(|name| self.gateway_named(Some(name)).ok_or_else(|| (StatusCode::INTERNAL_SERVER_ERROR, format!("Provider '{}' disappeared", name))))
+                .collect(),
+            Some(Err(tried)) => Err((StatusCode::UNPROCESSABLE_ENTITY, format!(
+                "No configured provider takes this {} payment (tried {})", currency, tried.join(", ")
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Config {
     pub port: u16,
     pub database_url: String,
+    pub db_max_connections: u32,
+    /// How long a request waits for a free connection before failing.
+    pub db_acquire_timeout_secs: u64,
+    /// Idle connections above the pool's minimum are closed after this long.
+    pub db_idle_timeout_secs: u64,
     pub nats_url: Option<String>,
     pub paystack_secret: Option<String>,
     pub flutterwave_secret: Option<String>,
+    /// The secret hash set on the Flutterwave dashboard, which its webhooks carry.
+    pub flutterwave_secret_hash: Option<String>,
+    pub admin_token: Option<String>,
+    /// The limit for merchants that don't set one in `merchant_settings`.
+    pub max_refunds_per_transaction: i64,
+    pub test_mode: bool,
+    /// For merchants without a `merchant_settings` value of their own.
+    pub require_idempotency_key: bool,
+    pub card_expiry_notice_days: i64,
+    pub card_expiry_scan_interval_secs: u64,
+    pub renewal_interval_secs: u64,
+    pub scheduled_payment_interval_secs: u64,
+    pub currency_policy: CurrencyPolicy,
+    /// Per-currency minimum and maximum charge, e.g. the providers' floors.
+    pub amount_limits: AmountLimits,
+    /// Keyed by gateway name; providers without an entry accept any currency.
+    pub provider_capabilities: HashMap<String, ProviderCapabilities>,
+    /// Picks the provider when a payment doesn't name one.
+    pub provider_routes: ProviderRouter,
+    /// Static statement descriptor prefix; per-charge suffixes are appended to it.
+    pub statement_descriptor: Option<String>,
+    /// Provider webhook source ranges; empty (the default) disables the check.
+    pub webhook_allowlist: WebhookAllowlist,
+    /// Status for card declines: 402 by default, some clients expect 422.
+    pub decline_status: StatusCode,
+    /// Terminal transactions older than this many days are archived; unset disables archival.
+    pub archive_retention_days: Option<i64>,
+    pub archive_dir: String,
+    /// Delete archived rows from the hot tables after export.
+    pub archive_move_rows: bool,
+    pub archive_batch_size: i64,
+    pub archive_interval_secs: u64,
+    pub outbox_poll_interval_secs: u64,
+    pub webhook_delivery_interval_secs: u64,
+    /// Backoff for idempotent provider calls such as verify.
+    pub provider_retry: RetryPolicy,
+    /// Per-client limit on the payment routes; `None` disables it.
+    pub payment_rate_limit: Option<RateLimit>,
+    /// How long in-flight requests and worker runs get to finish after SIGTERM.
+    pub shutdown_grace_secs: u64,
+    /// Signs checkout links; without it none are issued.
+    pub checkout_signing_secret: Option<String>,
+    pub checkout_link_ttl_secs: i64,
+    /// How long a `capture: false` authorization may wait to be captured.
+    pub authorization_ttl_secs: i64,
+    pub authorization_expiry_interval_secs: u64,
+    /// Tax on renewal invoices by the customer's billing address.
+    pub tax_rates: TaxRates,
+    /// How declined subscription renewals are retried, and what happens when they run out.
+    pub dunning: DunningPolicy,
 }
 
 impl Config {
@@ -101,35 +388,282 @@ impl Config {
         Ok(Config {
             port: std::env::var("PORT").ok().and_then(|p| p.parse().ok()).unwrap_or(8084),
             database_url: std::env::var("DATABASE_URL").expect("DATABASE_URL required"),
+            db_max_connections: std::env::var("DB_MAX_CONNECTIONS").ok().and_then(|v| v.parse().ok()).unwrap_or(10),
+            db_acquire_timeout_secs: std::env::var("DB_ACQUIRE_TIMEOUT_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(30),
+            db_idle_timeout_secs: std::env::var("DB_IDLE_TIMEOUT_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(600),
             nats_url: std::env::var("NATS_URL").ok(),
             paystack_secret: std::env::var("PAYSTACK_SECRET_KEY").ok(),
             flutterwave_secret: std::env::var("FLUTTERWAVE_SECRET_KEY").ok(),
+            flutterwave_secret_hash: std::env::var("FLUTTERWAVE_SECRET_HASH").ok(),
+            admin_token: std::env::var("ADMIN_API_TOKEN").ok(),
+            max_refunds_per_transaction: std::env::var("MAX_REFUNDS_PER_TRANSACTION").ok().and_then(|v| v.parse().ok()).unwrap_or(10),
+            test_mode: std::env::var("TEST_MODE").map(|v| v == "true" || v == "1").unwrap_or(false),
+            require_idempotency_key: std::env::var("REQUIRE_IDEMPOTENCY_KEY").map(|v| v == "true" || v == "1").unwrap_or(false),
+            card_expiry_notice_days: std::env::var("CARD_EXPIRY_NOTICE_DAYS").ok().and_then(|v| v.parse().ok()).unwrap_or(30),
+            card_expiry_scan_interval_secs: std::env::var("CARD_EXPIRY_SCAN_INTERVAL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(3600),
+            renewal_interval_secs: std::env::var("RENEWAL_INTERVAL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(3600),
+            scheduled_payment_interval_secs: std::env::var("SCHEDULED_PAYMENT_INTERVAL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(60),
+            currency_policy: CurrencyPolicy {
+                fallback_exponent: std::env::var("UNKNOWN_CURRENCY_EXPONENT").ok().and_then(|v| v.parse().ok()),
+                allowed_unknown: std::env::var("ALLOWED_UNKNOWN_CURRENCIES")
+                    .map(|v| v.split(',').map(|c| c.trim().to_string()).filter(|c| !c.is_empty()).collect())
+                    .unwrap_or_default(),
+            },
+            amount_limits: parse_amount_limits(&std::env::var("AMOUNT_LIMITS").unwrap_or_default()).map_err(anyhow::Error::msg)?,
+            provider_capabilities: std::env::var("PROVIDER_CURRENCIES").map(|v| parse_provider_currencies(&v)).unwrap_or_default(),
+            provider_routes: ProviderRouter::parse(&std::env::var("PROVIDER_ROUTES").unwrap_or_default()).map_err(anyhow::Error::msg)?,
+            statement_descriptor: std::env::var("STATEMENT_DESCRIPTOR").ok(),
+            webhook_allowlist: WebhookAllowlist::parse(
+                &std::env::var("WEBHOOK_IP_ALLOWLIST").unwrap_or_default(),
+                &std::env::var("TRUSTED_PROXIES").unwrap_or_default(),
+            ).map_err(anyhow::Error::msg)?,
+            decline_status: std::env::var("DECLINE_HTTP_STATUS").ok()
+                .and_then(|v| v.parse().ok())
+                .and_then(|v| StatusCode::from_u16(v).ok())
+                .filter(StatusCode::is_client_error)
+                .unwrap_or(StatusCode::PAYMENT_REQUIRED),
+            archive_retention_days: std::env::var("ARCHIVE_RETENTION_DAYS").ok().and_then(|v| v.parse().ok()),
+            archive_dir: std::env::var("ARCHIVE_DIR").unwrap_or_else(|_| "./archive".to_string()),
+            archive_move_rows: std::env::var("ARCHIVE_MOVE_ROWS").map(|v| v == "true" || v == "1").unwrap_or(false),
+            archive_batch_size: std::env::var("ARCHIVE_BATCH_SIZE").ok().and_then(|v| v.parse().ok()).unwrap_or(1000),
+            archive_interval_secs: std::env::var("ARCHIVE_INTERVAL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(86400),
+            outbox_poll_interval_secs: std::env::var("OUTBOX_POLL_INTERVAL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(5),
+            webhook_delivery_interval_secs: std::env::var("WEBHOOK_DELIVERY_INTERVAL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(10),
+            provider_retry: {
+                let default = RetryPolicy::default();
+                let millis = |name: &str, fallback: std::time::Duration| std::env::var(name).ok().and_then(|v| v.parse().ok()).map(std::time::Duration::from_millis).unwrap_or(fallback);
+                RetryPolicy {
+                    max_retries: std::env::var("PROVIDER_MAX_RETRIES").ok().and_then(|v| v.parse().ok()).unwrap_or(default.max_retries),
+                    base_delay: millis("PROVIDER_RETRY_BASE_MS", default.base_delay),
+                    max_delay: millis("PROVIDER_RETRY_MAX_MS", default.max_delay),
+                }
+            },
+            // 20 requests at once, then one every 2 seconds; PAYMENT_RATE_LIMIT_BURST=0 turns it off
+            payment_rate_limit: Some(RateLimit {
+                burst: std::env::var("PAYMENT_RATE_LIMIT_BURST").ok().and_then(|v| v.parse().ok()).unwrap_or(20),
+                per_second: std::env::var("PAYMENT_RATE_LIMIT_PER_SEC").ok().and_then(|v| v.parse().ok()).unwrap_or(0.5),
+            }).filter(|limit| limit.burst > 0),
+            // Inside Kubernetes' default 30 second termination grace period
+            shutdown_grace_secs: std::env::var("SHUTDOWN_GRACE_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(25),
+            checkout_signing_secret: std::env::var("CHECKOUT_SIGNING_SECRET").ok().filter(|s| !s.is_empty()),
+            checkout_link_ttl_secs: std::env::var("CHECKOUT_LINK_TTL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(3600),
+            // Card networks let most authorizations lapse after about a week
+            authorization_ttl_secs: std::env::var("AUTHORIZATION_TTL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(7 * 24 * 3600),
+            authorization_expiry_interval_secs: std::env::var("AUTHORIZATION_EXPIRY_INTERVAL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(300),
+            tax_rates: TaxRates::parse(&std::env::var("TAX_RATES").unwrap_or_default()).map_err(anyhow::Error::msg)?,
+            dunning: {
+                let default = DunningPolicy::default();
+                DunningPolicy {
+                    max_attempts: std::env::var("DUNNING_MAX_ATTEMPTS").ok().and_then(|v| v.parse().ok()).filter(|n| *n > 0).unwrap_or(default.max_attempts),
+                    outcome: match std::env::var("DUNNING_OUTCOME") {
+                        Ok(v) => DunningOutcome::parse(&v).ok_or_else(|| anyhow::anyhow!("DUNNING_OUTCOME must be 'unpaid' or 'cancelled', not '{}'", v))?,
+                        Err(_) => default.outcome,
+                    },
+                    retry_after_days: std::env::var("DUNNING_RETRY_AFTER_DAYS").ok().and_then(|v| v.parse().ok()).unwrap_or(default.retry_after_days),
+                }
+            },
         })
     }
+
+    /// The connection pool as configured, before it connects.
+    fn pool_options(&self) -> PgPoolOptions {
+        PgPoolOptions::new()
+            .max_connections(self.db_max_connections)
+            .acquire_timeout(Duration::from_secs(self.db_acquire_timeout_secs))
+            .idle_timeout(Duration::from_secs(self.db_idle_timeout_secs))
+    }
 }
 
 // =============================================================================
 // Request/Response DTOs
 // =============================================================================
 
-#[derive(Debug, Deserialize, Validate)]
+#[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct InitiatePaymentRequest {
-    #[validate(range(min = 1))]
-    pub amount: i64,
-    pub currency: Option<String>,
+    /// Client-supplied reference; generated when absent. Must be unique.
+    pub reference: Option<String>,
+    /// Minor units plus currency (default NGN).
+    #[serde(flatten)]
+    pub amount: Amount,
     #[validate(email)]
     pub email: String,
     pub customer_id: Option<Uuid>,
+    /// `wallet` pays from the customer's wallet balance instead of a provider.
     pub payment_method: Option<String>,
+    /// A stored payment method to charge.
+    pub payment_method_id: Option<Uuid>,
     pub callback_url: Option<String>,
     pub metadata: Option<serde_json::Value>,
+    /// Per-charge text (e.g. an order number) appended to the statement descriptor.
+    pub statement_descriptor_suffix: Option<String>,
+    /// `paystack` (default) or `flutterwave`.
+    pub provider: Option<String>,
+    /// Charge at this time instead of now. Must be in the future.
+    pub scheduled_at: Option<DateTime<Utc>>,
+    /// `false` only authorizes the amount, for `POST /transactions/:id/capture` to take
+    /// later. Defaults to true.
+    pub capture: Option<bool>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct InitiatePaymentResponse {
     pub reference: String,
     pub authorization_url: Option<String>,
+    /// Set when the customer must complete an extra step (e.g. 3DS) rather than a checkout.
+    pub next_action: Option<NextAction>,
+    pub status: String,
+    /// Signed, expiring token for `GET /checkout/:token`, issued with a checkout when
+    /// checkout links are configured.
+    pub checkout_token: Option<String>,
+    pub checkout_expires_at: Option<DateTime<Utc>>,
+}
+
+/// What a checkout page needs to show for a payment.
+#[derive(Debug, Serialize)]
+pub struct CheckoutContext {
+    pub reference: String,
+    #[serde(with = "decimal_str")]
+    pub amount: Decimal,
+    pub currency: String,
     pub status: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Card details are masked in `Debug` and can't be serialized back out.
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreatePaymentMethodRequest {
+    pub customer_id: Uuid,
+    /// The cardholder's, which some providers file the card under.
+    #[validate(email)]
+    pub email: String,
+    pub card: CardDetails,
+    /// The provider that vaults the card; the default gateway otherwise.
+    pub provider: Option<String>,
+    /// Whether the new card becomes the customer's default. Defaults to true; a
+    /// customer's first card is always the default.
+    pub is_default: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateSubscriptionRequest {
+    pub customer_id: Uuid,
+    pub plan_id: String,
+    #[serde(flatten)]
+    pub amount: Amount,
+    /// `monthly` (default), `yearly` or `weekly`.
+    pub billing_cycle: Option<String>,
+    /// One of the customer's stored payment methods to renew with.
+    pub payment_method_id: Option<Uuid>,
+    pub billing_details: Option<BillingDetails>,
+    #[serde(default)]
+    pub metadata: Metadata,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreatePaymentPlanRequest {
+    /// The plan's total, in minor units plus currency.
+    #[serde(flatten)]
+    pub amount: Amount,
+    /// How many installments to split the total into.
+    pub installments: u32,
+    /// Time between installments: `monthly` (default), `yearly` or `weekly`.
+    pub interval: Option<String>,
+    /// When the first installment is due; defaults to today.
+    pub first_due_on: Option<chrono::NaiveDate>,
+    #[validate(email)]
+    pub email: String,
+    pub customer_id: Option<Uuid>,
+    /// A stored payment method to charge each installment to.
+    pub payment_method_id: Option<Uuid>,
+    pub callback_url: Option<String>,
+    /// Copied onto every installment's charge, with `payment_plan_id` and `installment`.
+    pub metadata: Option<serde_json::Value>,
+    pub provider: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PaymentPlanView {
+    pub id: Uuid,
+    #[serde(with = "decimal_str")]
+    pub total: Decimal,
+    pub currency: String,
+    pub billing_cycle: String,
+    pub installments: Vec<PlanInstallment>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct PlanInstallment {
+    pub number: i32,
+    #[serde(with = "decimal_str")]
+    pub amount: Decimal,
+    pub due_on: chrono::NaiveDate,
+    /// The installment's charge.
+    pub reference: String,
+    pub status: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateSubscriptionRequest {
+    /// Merged into the stored metadata; an empty value removes the key.
+    pub metadata: Option<Metadata>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecordUsageRequest {
+    pub quantity: i64,
+    /// Minor units of the subscription's currency, per unit.
+    pub unit_amount: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SettlementRequest {
+    /// The provider's settlement/batch identifier.
+    pub settlement_id: String,
+    pub transaction_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PayoutParams {
+    pub currency: Option<String>,
+}
+
+/// What the next payout would cover: settled-eligible transactions not yet marked settled.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct PayoutSummary {
+    pub currency: String,
+    pub transaction_count: i64,
+    #[serde(with = "decimal_str")]
+    pub gross: Decimal,
+    #[serde(with = "decimal_str")]
+    pub refunded: Decimal,
+    #[serde(with = "decimal_str")]
+    pub net: Decimal,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SettlementReportParams {
+    pub date: chrono::NaiveDate,
+}
+
+/// Settleable transactions completed on `date` (UTC), totalled per currency and provider.
+#[derive(Debug, Serialize)]
+pub struct SettlementReport {
+    pub date: chrono::NaiveDate,
+    pub totals: Vec<SettlementTotal>,
+}
+
+#[derive(Debug, PartialEq, Serialize, sqlx::FromRow)]
+pub struct SettlementTotal {
+    pub currency: String,
+    pub provider: Option<String>,
+    pub transaction_count: i64,
+    #[serde(with = "decimal_str")]
+    pub gross: Decimal,
+    #[serde(with = "decimal_str")]
+    pub refunded: Decimal,
+    #[serde(with = "decimal_str")]
+    pub net: Decimal,
 }
 
 #[derive(Debug, Deserialize)]
@@ -137,12 +671,73 @@ pub struct VerifyPaymentRequest {
     pub reference: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ConfirmPaymentRequest {
+    pub reference: String,
+}
+
+/// Send `{}` to capture the whole authorization.
 #[derive(Debug, Deserialize, Validate)]
+pub struct CaptureRequest {
+    /// Minor units to take, at most the authorized amount.
+    #[validate(range(min = 1))]
+    pub amount: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct RefundRequest {
     pub transaction_id: Uuid,
     #[validate(range(min = 1))]
     pub amount: Option<i64>,
-    pub reason: Option<String>,
+    pub reason: Option<RefundReason>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct OpenDisputeRequest {
+    pub transaction_id: Uuid,
+    #[validate(length(min = 1, max = 255))]
+    pub reason: String,
+    /// In minor units; the whole charge when omitted.
+    #[validate(range(min = 1))]
+    pub amount: Option<i64>,
+    pub evidence_due_by: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubmitEvidenceRequest {
+    pub evidence: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisputeOutcome { Won, Lost }
+
+impl DisputeOutcome {
+    pub fn status(&self) -> DisputeStatus {
+        match self { Self::Won => DisputeStatus::Won, Self::Lost => DisputeStatus::Lost }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CloseDisputeRequest {
+    pub outcome: DisputeOutcome,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct BulkRefundRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub refunds: Vec<RefundRequest>,
+}
+
+/// The outcome of one item of a bulk refund, in request order: the refund, or the status
+/// and error `POST /refunds` would have returned for it.
+#[derive(Debug, Serialize)]
+pub struct BulkRefundResult {
+    pub transaction_id: Uuid,
+    pub success: bool,
+    pub status: u16,
+    pub refund: Option<Refund>,
+    pub error: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Validate)]
@@ -153,22 +748,98 @@ pub struct WalletTopupRequest {
     pub currency: Option<String>,
 }
 
+#[derive(Debug, Deserialize, Validate)]
+pub struct WalletHoldRequest {
+    #[validate(range(min = 1))]
+    pub amount: i64,
+    pub currency: Option<String>,
+    /// What the funds are held for, e.g. the pending payment's reference.
+    #[validate(length(max = 100))]
+    pub reference: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WalletTopupResponse {
+    #[serde(flatten)]
+    pub wallet: WalletView,
+    pub topup_id: Uuid,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ReverseTopupRequest {
+    /// Minor units to reverse; defaults to the unreversed remainder of the top-up.
+    #[validate(range(min = 1))]
+    pub amount: Option<i64>,
+    pub reason: Option<String>,
+}
+
 #[derive(Debug, Deserialize, Validate)]
 pub struct TransferRequest {
     pub from_wallet_id: Uuid,
     pub to_wallet_id: Uuid,
     #[validate(range(min = 1))]
     pub amount: i64,
+    /// The source balance to move; defaults to the source wallet's currency.
+    pub currency: Option<String>,
+    /// The balance to credit; defaults to `currency`. A different currency needs `fx_rate`.
+    pub to_currency: Option<String>,
+    #[serde(default, with = "decimal_str::option")]
+    pub fx_rate: Option<Decimal>,
     pub description: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct CreateWebhookEndpointRequest {
+    pub url: String,
+}
+
+/// Returned only on registration; this is the one time the secret is shown.
+#[derive(Debug, Serialize)]
+pub struct CreatedWebhookEndpoint {
+    #[serde(flatten)]
+    pub endpoint: WebhookEndpoint,
+    pub secret: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ListParams {
     pub page: Option<u32>,
     pub per_page: Option<u32>,
+    /// A `next_cursor` from a previous page; takes precedence over `page`.
+    pub cursor: Option<String>,
+    pub status: Option<String>,
+    pub from_date: Option<DateTime<Utc>>,
+    pub to_date: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExpiringParams {
+    /// Defaults to the configured card expiry notice window.
+    pub within_days: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DeletePaymentMethodParams {
+    /// Pause the active subscriptions that renew with the method instead of refusing.
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefundListParams {
+    pub transaction_id: Option<Uuid>,
     pub status: Option<String>,
     pub from_date: Option<DateTime<Utc>>,
     pub to_date: Option<DateTime<Utc>>,
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LedgerListParams {
+    pub currency: Option<String>,
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
 }
 
 #[derive(Debug, Serialize)]
@@ -177,6 +848,9 @@ pub struct PaginatedResponse<T> {
     pub total: i64,
     pub page: u32,
     pub per_page: u32,
+    /// Pass as `cursor` to fetch the rows after this page; absent on the last page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
 }
 
 // =============================================================================
@@ -194,298 +868,7939 @@ async fn main() -> Result<()> {
         .init();
 
     tracing::info!("Starting OpenSASE Payments...");
+    prometheus();
 
     let config = Config::from_env()?;
     let config = Arc::new(config);
 
-    let db = PgPoolOptions::new()
-        .max_connections(10)
-        .connect(&config.database_url)
-        .await?;
+    let db = config.pool_options().connect(&config.database_url).await?;
 
     sqlx::migrate!("./migrations").run(&db).await?;
 
     let nats = if let Some(ref url) = config.nats_url {
-        async_nats::connect(url).await.ok()
+        async_nats::connect(url).await.ok().map(|client| Arc::new(NatsPublisher(client)) as Arc<dyn EventPublisher>)
     } else {
         None
     };
 
-    let state = AppState { db, nats, config: config.clone() };
+    let http = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(30))
+        .build()?;
+
+    // Without a secret key, payments get a stub checkout URL and Paystack is never called
+    let gateway: Arc<dyn PaymentGateway> = match &config.paystack_secret {
+        Some(secret) => Arc::new(PaystackGateway::new(http.clone(), secret).with_retry(config.provider_retry)),
+        None => Arc::new(StubGateway),
+    };
+    let mut gateways: Vec<Arc<dyn PaymentGateway>> = Vec::new();
+    if let Some(secret) = &config.flutterwave_secret {
+        gateways.push(Arc::new(FlutterwaveGateway::new(http.clone(), secret).with_retry(config.provider_retry)));
+    }
+
+    let payment_limiter = config.payment_rate_limit.map(|limit| Arc::new(RateLimiter::new(limit)));
+    let state = AppState { db, nats, http, gateway, gateways: Arc::new(gateways), config: config.clone(), payment_limiter };
+    let (shutdown_tx, shutdown) = watch::channel(false);
+    let mut workers = tokio::task::JoinSet::new();
+    workers.spawn(run_card_expiry_worker(state.clone(), shutdown.clone()));
+    workers.spawn(run_renewal_worker(state.clone(), shutdown.clone()));
+    workers.spawn(run_scheduled_payment_worker(state.clone(), shutdown.clone()));
+    workers.spawn(run_authorization_expiry_worker(state.clone(), shutdown.clone()));
+    workers.spawn(run_webhook_delivery_worker(state.clone(), shutdown.clone()));
+    if let Some(bus) = state.nats.clone() {
+        workers.spawn(run_outbox_worker(state.clone(), bus, shutdown.clone()));
+    }
+    if state.config.archive_retention_days.is_some() {
+        let store: Arc<dyn ArchiveStore> = Arc::new(LocalDirStore::new(&state.config.archive_dir));
+        workers.spawn(run_archival_worker(state.clone(), store, shutdown.clone()));
+    }
     let app = build_router(state);
 
     let addr = format!("0.0.0.0:{}", config.port);
     tracing::info!("🚀 OpenSASE Payments listening on {}", addr);
 
     let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        tracing::info!("Shutting down; draining in-flight requests and workers");
+        let _ = shutdown_tx.send(true);
+    });
+    let grace = Duration::from_secs(config.shutdown_grace_secs);
+    let (served, ()) = tokio::join!(
+        serve_until_shutdown(listener, app, shutdown.clone(), grace),
+        drain_workers(workers, shutdown, grace),
+    );
+    served?;
+    tracing::info!("Shutdown complete");
 
     Ok(())
 }
 
-fn build_router(state: AppState) -> Router {
-    Router::new()
-        .route("/health", get(health))
-        .nest("/api/v1", api_routes())
-        .layer(TraceLayer::new_for_http())
-        .layer(CorsLayer::permissive())
-        .with_state(state)
+// =============================================================================
+// Shutdown
+// =============================================================================
+
+/// Resolves on SIGTERM (what Kubernetes sends) or ctrl-c.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::warn!("Could not listen for ctrl-c: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => { sigterm.recv().await; }
+            Err(e) => {
+                tracing::warn!("Could not listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
 }
 
-fn api_routes() -> Router<AppState> {
-    Router::new()
-        .route("/payments/initiate", post(initiate_payment))
-        .route("/payments/verify", post(verify_payment))
-        .route("/payments/webhook", post(webhook_handler))
-        .route("/transactions", get(list_transactions))
-        .route("/transactions/:id", get(get_transaction))
-        .route("/refunds", post(create_refund).get(list_refunds))
-        .route("/wallets", post(create_wallet).get(list_wallets))
-        .route("/wallets/:id", get(get_wallet))
-        .route("/wallets/:id/topup", post(topup_wallet))
-        .route("/transfers", post(create_transfer))
+async fn shutdown_requested(mut shutdown: watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|&stop| stop).await;
 }
 
-async fn health() -> impl IntoResponse {
-    Json(serde_json::json!({
-        "status": "healthy",
-        "service": "opensase-payments",
-        "version": env!("CARGO_PKG_VERSION")
-    }))
+/// Serves `app` until shutdown is requested, then stops accepting connections and waits
+/// up to `grace` for requests already being handled. Requests still running after that
+/// are dropped.
+async fn serve_until_shutdown(listener: tokio::net::TcpListener, app: Router, shutdown: watch::Receiver<bool>, grace: Duration) -> std::io::Result<()> {
+    use std::future::IntoFuture;
+    let server = axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_requested(shutdown.clone()))
+        .into_future();
+    tokio::pin!(server);
+    tokio::select! {
+        served = &mut server => return served,
+        _ = shutdown_requested(shutdown) => {}
+    }
+    match tokio::time::timeout(grace, server).await {
+        Ok(served) => served,
+        Err(_) => {
+            tracing::warn!("Requests still in flight after {:?}; dropping them", grace);
+            Ok(())
+        }
+    }
 }
 
-// =============================================================================
-// Payment Handlers
+/// Once shutdown is requested, waits up to `grace` for the workers to finish their
+/// current run. Any still running are aborted when the set is dropped.
+async fn drain_workers(mut workers: tokio::task::JoinSet<()>, shutdown: watch::Receiver<bool>, grace: Duration) {
+    shutdown_requested(shutdown).await;
+    let drained = tokio::time::timeout(grace, async { while workers.join_next().await.is_some() {} }).await;
+    if drained.is_err() {
+        tracing::warn!("{} background workers still running after {:?}; stopping them", workers.len(), grace);
+    }
+}
+
+// =============================================================================
+// Background Workers
 // =============================================================================
 
-async fn initiate_payment(
-    State(state): State<AppState>,
-    Json(req): Json<InitiatePaymentRequest>,
-) -> Result<Json<InitiatePaymentResponse>, (StatusCode, String)> {
-    req.validate().map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+/// Waits for a worker's next run. Returns false once shutdown is requested, so a run in
+/// progress completes but no new one starts.
+async fn next_run(interval: &mut tokio::time::Interval, shutdown: &mut watch::Receiver<bool>) -> bool {
+    tokio::select! {
+        _ = interval.tick() => {}
+        _ = shutdown.wait_for(|&stop| stop) => return false,
+    }
+    !*shutdown.borrow()
+}
 
-    let reference = format!("TXN-{}", Uuid::now_v7());
-    let id = Uuid::now_v7();
-    let amount = Decimal::new(req.amount, 2);
+async fn run_card_expiry_worker(state: AppState, mut shutdown: watch::Receiver<bool>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(state.config.card_expiry_scan_interval_secs));
+    while next_run(&mut interval, &mut shutdown).await {
+        match scan_card_expiry(&state, Utc::now().date_naive()).await {
+            Ok(0) => {}
+            Ok(notified) => tracing::info!("Notified {} expiring payment methods", notified),
+            Err(e) => tracing::warn!("Card expiry scan failed: {}", e),
+        }
+    }
+}
 
-    sqlx::query(
-        r#"INSERT INTO transactions (id, reference, amount, currency, status, transaction_type, customer_email, metadata, created_at, updated_at)
-           VALUES ($1, $2, $3, $4, 'pending', 'payment', $5, $6, NOW(), NOW())"#
+/// Flags expired cards and emits one `payment_method.expiring` event per card entering the
+/// notice window, through the outbox. Returns the number of expiring notifications sent.
+async fn scan_card_expiry(state: &AppState, today: chrono::NaiveDate) -> Result<usize, sqlx::Error> {
+    let methods = sqlx::query_as::<_, PaymentMethod>(
+        r#"SELECT * FROM payment_methods
+           WHERE method_type = 'card' AND exp_month IS NOT NULL AND exp_year IS NOT NULL AND expired_at IS NULL AND deleted_at IS NULL"#
     )
-    .bind(id)
-    .bind(&reference)
-    .bind(amount)
-    .bind(req.currency.as_deref().unwrap_or("NGN"))
-    .bind(&req.email)
-    .bind(req.metadata.unwrap_or(serde_json::json!({})))
-    .execute(&state.db)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    .fetch_all(&state.db)
+    .await?;
 
-    // In production, integrate with Paystack/Flutterwave here
-    let authorization_url = Some(format!("https://checkout.paystack.com/{}", reference));
+    let mut notified = 0;
+    for method in methods {
+        let (Some(month), Some(year)) = (method.exp_month, method.exp_year) else { continue };
+        match card_expiry(month as u32, year as i32, today, state.config.card_expiry_notice_days) {
+            Some(CardExpiry::Expired) | None => {
+                sqlx::query("UPDATE payment_methods SET expired_at = NOW() WHERE id = $1")
+                    .bind(method.id)
+                    .execute(&state.db)
+                    .await?;
+            }
+            Some(CardExpiry::ExpiringSoon) => {
+                // Claim the notification with its outbox event so concurrent scans fire it once
+                let mut tx = state.db.begin().await?;
+                let claimed = sqlx::query("UPDATE payment_methods SET expiry_notified_at = NOW() WHERE id = $1 AND expiry_notified_at IS NULL")
+                    .bind(method.id)
+                    .execute(&mut *tx)
+                    .await?;
+                if claimed.rows_affected() == 0 { continue; }
+                let event = PaymentMethodEvent::Expiring {
+                    payment_method_id: method.id.to_string(),
+                    customer_id: method.customer_id.to_string(),
+                    exp_month: month as u32,
+                    exp_year: year as i32,
+                };
+                insert_outbox(&mut tx, method.merchant_id, &DomainEvent::PaymentMethod(event)).await?;
+                tx.commit().await?;
+                notified += 1;
+            }
+            Some(CardExpiry::Valid) => {}
+        }
+    }
+    Ok(notified)
+}
 
-    Ok(Json(InitiatePaymentResponse {
-        reference,
-        authorization_url,
-        status: "pending".to_string(),
-    }))
+async fn run_scheduled_payment_worker(state: AppState, mut shutdown: watch::Receiver<bool>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(state.config.scheduled_payment_interval_secs));
+    while next_run(&mut interval, &mut shutdown).await {
+        match start_due_scheduled_payments(&state, Utc::now()).await {
+            Ok(0) => {}
+            Ok(started) => tracing::info!("Started {} scheduled payments", started),
+            Err(e) => tracing::warn!("Scheduled payment scan failed: {}", e),
+        }
+    }
 }
 
-async fn verify_payment(
-    State(state): State<AppState>,
-    Json(req): Json<VerifyPaymentRequest>,
-) -> Result<Json<Transaction>, (StatusCode, String)> {
-    let txn = sqlx::query_as::<_, Transaction>(
-        "SELECT * FROM transactions WHERE reference = $1"
+/// Scheduled payments started per scan; the rest wait for the next one.
+const SCHEDULED_PAYMENT_BATCH_SIZE: i64 = 100;
+
+/// Starts every scheduled payment due by `now` through the same path as an immediate one.
+/// Returns how many were sent to a provider, whatever the provider made of them.
+async fn start_due_scheduled_payments(state: &AppState, now: DateTime<Utc>) -> Result<usize, sqlx::Error> {
+    let due: Vec<(Uuid, String, Uuid, serde_json::Value)> = sqlx::query_as(
+        r#"SELECT id, reference, merchant_id, scheduled_request FROM transactions
+           WHERE status = $1 AND scheduled_at <= $2 ORDER BY scheduled_at, id LIMIT $3"#
     )
-    .bind(&req.reference)
-    .fetch_optional(&state.db)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-    .ok_or((StatusCode::NOT_FOUND, "Transaction not found".to_string()))?;
+    .bind(TransactionStatus::Scheduled.as_str())
+    .bind(now)
+    .bind(SCHEDULED_PAYMENT_BATCH_SIZE)
+    .fetch_all(&state.db)
+    .await?;
 
-    Ok(Json(txn))
+    let mut started = 0;
+    for (id, reference, merchant_id, request) in due {
+        match start_scheduled_payment(state, id, &reference, Merchant::new(merchant_id), request).await {
+            Ok(true) => started += 1,
+            Ok(false) => {}
+            Err(e) => tracing::warn!(reference = %reference, "Scheduled payment failed: {}", e.message),
+        }
+    }
+    Ok(started)
 }
 
-async fn webhook_handler(
-    State(_state): State<AppState>,
-    Json(payload): Json<serde_json::Value>,
-) -> impl IntoResponse {
-    tracing::info!("Webhook received: {:?}", payload);
-    StatusCode::OK
+/// Replays the stored request for scheduled payment `id`. One that no longer passes
+/// validation, e.g. because its provider was removed, fails rather than waiting forever.
+/// Returns whether it reached a provider.
+async fn start_scheduled_payment(
+    state: &AppState,
+    id: Uuid,
+    reference: &str,
+    merchant: Merchant,
+    request: serde_json::Value,
+) -> Result<bool, ApiError> {
+    let prepared = match serde_json::from_value::<InitiatePaymentRequest>(request) {
+        Ok(req) => prepare_payment(state, merchant, &req).await.map(|payment| (req, payment)),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, format!("stored request is unreadable: {}", e)).into()),
+    };
+    let (req, payment) = match prepared {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            fail_scheduled_payment(&state.db, id, reference, &e.message).await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            return Err(e);
+        }
+    };
+    match start_payment(state, merchant, &req, payment, Some(id)).await {
+        Ok(_) => Ok(true),
+        // Another scan got to it first
+        Err(e) if e.status == StatusCode::CONFLICT => Ok(false),
+        Err(e) => Err(e),
+    }
 }
 
-async fn list_transactions(
-    State(state): State<AppState>,
-    Query(params): Query<ListParams>,
-) -> Result<Json<PaginatedResponse<Transaction>>, (StatusCode, String)> {
-    let page = params.page.unwrap_or(1).max(1);
-    let per_page = params.per_page.unwrap_or(20).min(100);
-    let offset = ((page - 1) * per_page) as i64;
+async fn fail_scheduled_payment(db: &sqlx::PgPool, id: Uuid, reference: &str, reason: &str) -> Result<(), sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let failed: Option<Option<Uuid>> = sqlx::query_scalar(
+        "UPDATE transactions SET status = $1, updated_at = NOW() WHERE id = $2 AND status = $3 RETURNING merchant_id"
+    )
+    .bind(TransactionStatus::Failed.as_str())
+    .bind(id)
+    .bind(TransactionStatus::Scheduled.as_str())
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(merchant_id) = failed else { return tx.commit().await };
+    let event = DomainEvent::Payment(PaymentEvent::Failed { payment_id: PaymentId::from_string(reference), reason: reason.to_string() });
+    insert_outbox(&mut tx, merchant_id, &event).await?;
+    tx.commit().await?;
+    count_payment_event(&event);
+    Ok(())
+}
 
-    let transactions = sqlx::query_as::<_, Transaction>(
-        "SELECT * FROM transactions ORDER BY created_at DESC LIMIT $1 OFFSET $2"
+async fn run_authorization_expiry_worker(state: AppState, mut shutdown: watch::Receiver<bool>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(state.config.authorization_expiry_interval_secs));
+    while next_run(&mut interval, &mut shutdown).await {
+        match expire_authorizations(&state, Utc::now()).await {
+            Ok(0) => {}
+            Ok(cancelled) => tracing::info!("Cancelled {} expired authorizations", cancelled),
+            Err(e) => tracing::warn!("Authorization expiry scan failed: {}", e),
+        }
+    }
+}
+
+/// Authorizations expired per scan; the rest wait for the next one.
+const AUTHORIZATION_EXPIRY_BATCH_SIZE: i64 = 100;
+
+/// Cancels authorizations left uncaptured past their expiry. Each is voided with its
+/// provider so the customer's funds are freed now; a void that fails is only logged,
+/// since the provider's hold lapses by itself. Returns how many were cancelled.
+async fn expire_authorizations(state: &AppState, now: DateTime<Utc>) -> Result<usize, sqlx::Error> {
+    let expired = sqlx::query_as::<_, Transaction>(
+        "SELECT * FROM transactions WHERE status = $1 AND authorization_expires_at <= $2 ORDER BY authorization_expires_at, id LIMIT $3"
     )
-    .bind(per_page as i64)
-    .bind(offset)
+    .bind(TransactionStatus::Authorized.as_str())
+    .bind(now)
+    .bind(AUTHORIZATION_EXPIRY_BATCH_SIZE)
     .fetch_all(&state.db)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    .await?;
 
-    let total: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM transactions")
-        .fetch_one(&state.db)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let mut cancelled = 0;
+    for txn in expired {
+        if let (Some(provider_reference), Some(gateway)) = (&txn.provider_reference, state.gateway_named(txn.provider.as_deref())) {
+            if let Err(e) = timed(gateway.name(), "void", gateway.void(provider_reference)).await {
+                tracing::warn!(reference = %txn.reference, "Couldn't void expired authorization: {}", e);
+            }
+        }
+        // Captures refuse expired authorizations, so one can't be taken in the meantime
+        let mut tx = state.db.begin().await?;
+        let result = sqlx::query("UPDATE transactions SET status = $1, updated_at = NOW() WHERE id = $2 AND status = $3")
+            .bind(TransactionStatus::Cancelled.as_str())
+            .bind(txn.id)
+            .bind(TransactionStatus::Authorized.as_str())
+            .execute(&mut *tx)
+            .await?;
+        if result.rows_affected() == 0 { continue; }
+        let event = DomainEvent::Payment(PaymentEvent::Cancelled { payment_id: PaymentId::from_string(&txn.reference) });
+        insert_outbox(&mut tx, txn.merchant_id, &event).await?;
+        tx.commit().await?;
+        cancelled += 1;
+    }
+    Ok(cancelled)
+}
 
-    Ok(Json(PaginatedResponse { data: transactions, total: total.0, page, per_page }))
+async fn run_renewal_worker(state: AppState, mut shutdown: watch::Receiver<bool>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(state.config.renewal_interval_secs));
+    while next_run(&mut interval, &mut shutdown).await {
+        let today = Utc::now().date_naive();
+        match renew_due_subscriptions(&state, today).await {
+            Ok(0) => {}
+            Ok(billed) => tracing::info!("Billed {} due subscriptions", billed),
+            Err(e) => tracing::warn!("Subscription renewal scan failed: {}", e),
+        }
+        match accrue_late_fees(&state, today).await {
+            Ok(0) => {}
+            Ok(accrued) => tracing::info!("Accrued late fees on {} overdue invoices", accrued),
+            Err(e) => tracing::warn!("Late fee scan failed: {}", e),
+        }
+    }
 }
 
-async fn get_transaction(
-    State(state): State<AppState>,
-    Path(id): Path<Uuid>,
-) -> Result<Json<Transaction>, (StatusCode, String)> {
-    let txn = sqlx::query_as::<_, Transaction>("SELECT * FROM transactions WHERE id = $1")
-        .bind(id)
-        .fetch_optional(&state.db)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-        .ok_or((StatusCode::NOT_FOUND, "Transaction not found".to_string()))?;
+/// Due subscriptions billed per scan; the rest wait for the next one.
+const RENEWAL_BATCH_SIZE: i64 = 100;
 
-    Ok(Json(txn))
+/// Bills every active subscription whose period ended by `today` and hasn't been charged
+/// for it yet, and retries the declined charge of every past-due one whose next dunning
+/// attempt has come. Returns how many were renewed, failed or cancelled.
+async fn renew_due_subscriptions(state: &AppState, today: chrono::NaiveDate) -> Result<usize, sqlx::Error> {
+    let retry_failed_by = today - chrono::Duration::days(i64::from(state.config.dunning.retry_after_days));
+    let due = sqlx::query_as::<_, Subscription>(
+        r#"SELECT * FROM subscriptions s
+           WHERE s.current_period_end <= $1
+             AND ((s.status = 'active'
+                   AND NOT EXISTS (SELECT 1 FROM transactions t WHERE t.reference = 'SUB-' || REPLACE(s.id::text, '-', '') || '-' || TO_CHAR(s.current_period_end, 'YYYYMMDD')))
+               OR (s.status = 'past_due' AND (s.last_payment_failed_at AT TIME ZONE 'UTC')::date <= $2
+                   AND EXISTS (SELECT 1 FROM transactions t WHERE t.reference = 'SUB-' || REPLACE(s.id::text, '-', '') || '-' || TO_CHAR(s.current_period_end, 'YYYYMMDD') AND t.status = 'failed')))
+           ORDER BY s.current_period_end, s.id LIMIT $3"#
+    )
+    .bind(today)
+    .bind(retry_failed_by)
+    .bind(RENEWAL_BATCH_SIZE)
+    .fetch_all(&state.db)
+    .await?;
+
+    let mut billed = 0;
+    for row in due {
+        let id = row.id;
+        match renew_subscription(state, row, today).await {
+            Ok(true) => billed += 1,
+            Ok(false) => {}
+            Err(e) => tracing::warn!(subscription_id = %id, "Renewal failed: {}", e.message),
+        }
+    }
+    Ok(billed)
 }
 
-async fn create_refund(
-    State(state): State<AppState>,
-    Json(req): Json<RefundRequest>,
-) -> Result<(StatusCode, Json<Refund>), (StatusCode, String)> {
-    let id = Uuid::now_v7();
-    let amount = Decimal::new(req.amount.unwrap_or(0), 2);
+/// One charge per subscription and period, so a scan that overlaps another, or a charge
+/// still waiting on the customer, is never billed twice.
+fn renewal_reference(subscription_id: Uuid, period_end: chrono::NaiveDate) -> String {
+    format!("SUB-{}-{}", subscription_id.simple(), period_end.format("%Y%m%d"))
+}
 
-    let refund = sqlx::query_as::<_, Refund>(
-        r#"INSERT INTO refunds (id, transaction_id, amount, reason, status, created_at)
-           VALUES ($1, $2, $3, $4, 'pending', NOW()) RETURNING *"#
+/// Charges the subscription's next period through the default gateway, then renews it on
+/// success or records the failed attempt under the dunning policy. An active subscription
+/// is invoiced afresh; a past-due one has its declined charge for the same invoice tried
+/// again. A charge the customer still has to complete (a checkout or 3DS) is left to the
+/// provider's webhook. Returns whether the subscription changed.
+async fn renew_subscription(state: &AppState, row: Subscription, today: chrono::NaiveDate) -> Result<bool, ApiError> {
+    let (id, period_end) = (row.id, row.current_period_end);
+    let mut subscription = SubscriptionAggregate::from_record(
+        SubscriptionRecord::try_from(&row).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?,
+        state.config.dunning,
+    );
+    if !subscription.is_due(today) { return Ok(false); }
+    let billed_as = subscription.status().clone();
+    if subscription.cancel_at_period_end() {
+        subscription.cancel(false);
+        return save_renewal(&state.db, &mut subscription, id, &billed_as, period_end).await;
+    }
+
+    let reference = renewal_reference(id, period_end);
+    let opened = match billed_as {
+        SubscriptionStatus::PastDue => reopen_renewal_charge(&state.db, &reference).await?,
+        _ => open_renewal_charge(state, &row, &subscription, reference).await?,
+    };
+    let Some((transaction_id, charge)) = opened else { return Ok(false) };
+    let amount = charge.amount.clone();
+    match submit_charge(state, std::slice::from_ref(&state.gateway), transaction_id, charge).await {
+        Ok(response) if response.status == TransactionStatus::Succeeded.as_str() => {
+            subscription.pay_renewal(&amount).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            save_renewal(&state.db, &mut subscription, id, &billed_as, period_end).await
+        }
+        Ok(_) => Ok(false),
+        Err(e) => {
+            // Only a charge the provider refused counts as a failed attempt
+            let status: String = sqlx::query_scalar("SELECT status FROM transactions WHERE id = $1")
+                .bind(transaction_id)
+                .fetch_one(&state.db)
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            if status != TransactionStatus::Failed.as_str() { return Err(e); }
+            let attempt = subscription.metrics().consecutive_failures + 1;
+            subscription.mark_payment_failed(attempt).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            save_renewal(&state.db, &mut subscription, id, &billed_as, period_end).await
+        }
+    }
+}
+
+/// Invoices the period, its flat amount plus any unbilled usage, and stores the pending
+/// charge for the invoice's total under `reference`. `None` when another scan got there first.
+async fn open_renewal_charge(
+    state: &AppState,
+    row: &Subscription,
+    subscription: &SubscriptionAggregate,
+    reference: String,
+) -> Result<Option<(Uuid, ChargeRequest)>, ApiError> {
+    let email: Option<String> = sqlx::query_scalar(
+        "SELECT customer_email FROM transactions WHERE customer_id = $1 AND merchant_id = $2 AND customer_email IS NOT NULL ORDER BY created_at DESC LIMIT 1"
     )
-    .bind(id)
-    .bind(req.transaction_id)
-    .bind(amount)
-    .bind(&req.reason)
-    .fetch_one(&state.db)
+    .bind(row.customer_id)
+    .bind(row.merchant_id)
+    .fetch_optional(&state.db)
     .await
     .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let metadata = serde_json::json!({ "subscription_id": row.id });
+    let transaction_id = Uuid::now_v7();
+    let provider_key = format!("chg_{}", Uuid::new_v4().simple());
 
-    Ok((StatusCode::CREATED, Json(refund)))
+    let mut tx = state.db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    // The renewal claims all unbilled usage, so the next period starts from nothing
+    let usage: Vec<(i64, Decimal)> = sqlx::query_as(
+        r#"WITH billed AS (
+               UPDATE usage_records SET billed_reference = $1 WHERE subscription_id = $2 AND billed_reference IS NULL RETURNING id, quantity, unit_amount
+           )
+           SELECT quantity, unit_amount FROM billed ORDER BY id"#
+    )
+    .bind(&reference)
+    .bind(row.id)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let billing = row.billing_details.clone().map(|b| b.0).unwrap_or_default();
+    let invoice = renewal_invoice(subscription, &reference, &usage, &state.config.tax_rates, &billing)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let amount = invoice.total().map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let claimed = sqlx::query(
+        r#"INSERT INTO transactions (id, reference, amount, currency, status, transaction_type, customer_id, customer_email, metadata,
+                                     provider_idempotency_key, merchant_id, created_at, updated_at)
+           VALUES ($1, $2, $3, $4, 'pending', 'payment', $5, $6, $7, $8, $9, NOW(), NOW())
+           ON CONFLICT (reference) DO NOTHING"#
+    )
+    .bind(transaction_id)
+    .bind(&reference)
+    .bind(amount.amount)
+    .bind(&amount.currency)
+    .bind(row.customer_id)
+    .bind(&email)
+    .bind(&metadata)
+    .bind(&provider_key)
+    .bind(row.merchant_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if claimed.rows_affected() == 0 { return Ok(None); }
+    PgInvoiceRepository::save_in(&mut tx, &invoice).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let created = DomainEvent::Payment(PaymentEvent::Created { payment_id: PaymentId::from_string(&reference), amount: amount.amount });
+    insert_outbox(&mut tx, row.merchant_id, &created).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    count_payment_event(&created);
+
+    Ok(Some((transaction_id, ChargeRequest {
+        reference,
+        amount,
+        email: email.unwrap_or_default(),
+        callback_url: None,
+        metadata,
+        statement_descriptor: state.config.statement_descriptor.as_deref().and_then(|prefix| statement_descriptor(prefix, None).ok()),
+        idempotency_key: provider_key,
+        capture: true,
+    })))
 }
 
-async fn list_refunds(
-    State(state): State<AppState>,
-) -> Result<Json<Vec<Refund>>, (StatusCode, String)> {
-    let refunds = sqlx::query_as::<_, Refund>("SELECT * FROM refunds ORDER BY created_at DESC")
-        .fetch_all(&state.db)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+/// Puts the period's declined charge back to pending for a dunning retry, under a new
+/// provider idempotency key: reusing the old one would only replay the decline. `None`
+/// when it is no longer failed, e.g. the customer paid it meanwhile.
+async fn reopen_renewal_charge(db: &sqlx::PgPool, reference: &str) -> Result<Option<(Uuid, ChargeRequest)>, ApiError> {
+    let row = sqlx::query_as::<_, RetryableCharge>(
+        r#"UPDATE transactions SET status = $2, provider_idempotency_key = $3, updated_at = NOW()
+           WHERE reference = $1 AND status = $4
+           RETURNING id, reference, amount, currency, customer_email, metadata, provider_idempotency_key, callback_url, statement_descriptor, provider, manual_capture"#
+    )
+    .bind(reference)
+    .bind(TransactionStatus::Pending.as_str())
+    .bind(format!("chg_{}", Uuid::new_v4().simple()))
+    .bind(TransactionStatus::Failed.as_str())
+    .fetch_optional(db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(row.map(|row| (row.id, ChargeRequest {
+        reference: row.reference,
+        amount: Money::new(row.amount, &row.currency),
+        email: row.customer_email.unwrap_or_default(),
+        callback_url: row.callback_url,
+        metadata: row.metadata,
+        statement_descriptor: row.statement_descriptor,
+        idempotency_key: row.provider_idempotency_key.unwrap_or_default(),
+        capture: true,
+    })))
+}
 
-    Ok(Json(refunds))
+/// Overdue invoices brought up to date per scan; the rest wait for the next one.
+const LATE_FEE_BATCH_SIZE: i64 = 100;
+
+#[derive(sqlx::FromRow)]
+struct OverdueInvoice {
+    id: String,
+    total: Decimal,
+    currency: String,
+    finalized_at: DateTime<Utc>,
+    daily_rate: Decimal,
+    max_fee: Decimal,
 }
 
-// =============================================================================
-// Wallet Handlers
-// =============================================================================
+/// Brings the late fee on each overdue invoice up to `today` under its merchant's policy
+/// for the currency. An invoice is overdue from when it was finalized while its charge
+/// has failed; merchants without a policy charge no fees. Returns how many were updated.
+async fn accrue_late_fees(state: &AppState, today: chrono::NaiveDate) -> Result<usize, sqlx::Error> {
+    let overdue = sqlx::query_as::<_, OverdueInvoice>(
+        r#"SELECT i.id, i.total, i.currency, i.finalized_at, p.daily_rate, p.max_fee
+           FROM invoices i
+           JOIN transactions t ON t.reference = i.reference
+           JOIN late_fee_policies p ON p.merchant_id = i.merchant_id AND p.currency = i.currency
+           WHERE i.status = 'finalized' AND i.finalized_at IS NOT NULL AND t.status = 'failed'
+             AND i.late_fee < p.max_fee AND (i.late_fee_accrued_on IS NULL OR i.late_fee_accrued_on < $1)
+           ORDER BY i.finalized_at, i.id LIMIT $2"#
+    )
+    .bind(today)
+    .bind(LATE_FEE_BATCH_SIZE)
+    .fetch_all(&state.db)
+    .await?;
 
-async fn create_wallet(
-    State(state): State<AppState>,
-    Json(req): Json<serde_json::Value>,
-) -> Result<(StatusCode, Json<Wallet>), (StatusCode, String)> {
-    let customer_id = req["customer_id"].as_str()
-        .and_then(|s| Uuid::parse_str(s).ok())
-        .ok_or((StatusCode::BAD_REQUEST, "customer_id required".to_string()))?;
+    let mut accrued = 0;
+    for invoice in overdue {
+        let days = (today - invoice.finalized_at.date_naive()).num_days().max(0) as u32;
+        let fee = Percentage::new(invoice.daily_rate)
+            .ok_or_else(|| PaymentError::InvalidAmount("late fee rate must not be negative".into()))
+            .and_then(|rate| LateFeePolicy::new(rate, Money::new(invoice.max_fee, &invoice.currency)).fee_for(&Money::new(invoice.total, &invoice.currency), days));
+        let fee = match fee {
+            Ok(fee) => fee,
+            Err(e) => {
+                tracing::warn!(invoice_id = %invoice.id, "Late fee not accrued: {}", e);
+                continue;
+            }
+        };
+        // A fee only grows, so a scan that raced ahead of this one is left alone
+        let updated = sqlx::query("UPDATE invoices SET late_fee = $1, late_fee_accrued_on = $2 WHERE id = $3 AND late_fee <= $1")
+            .bind(fee.amount)
+            .bind(today)
+            .bind(&invoice.id)
+            .execute(&state.db)
+            .await?;
+        accrued += updated.rows_affected() as usize;
+    }
+    Ok(accrued)
+}
 
-    let id = Uuid::now_v7();
+/// The finalized invoice for a renewal: the plan's flat amount, a line per usage record
+/// as `(quantity, unit_amount)`, and tax for where the customer is billed.
+fn renewal_invoice(
+    subscription: &SubscriptionAggregate,
+    reference: &str,
+    usage: &[(i64, Decimal)],
+    tax: &dyn TaxCalculator,
+    billing: &BillingDetails,
+) -> Result<Invoice, InvoiceError> {
+    let currency = &subscription.amount().currency;
+    let mut invoice = Invoice::create(subscription.id(), reference, currency);
+    invoice.add_line(subscription.plan_id(), 1, subscription.amount().clone())?;
+    for (quantity, unit_amount) in usage {
+        invoice.add_line("Usage", *quantity, Money::new(*unit_amount, currency))?;
+    }
+    invoice.finalize(tax, billing)?;
+    Ok(invoice)
+}
 
-    let wallet = sqlx::query_as::<_, Wallet>(
-        r#"INSERT INTO wallets (id, customer_id, balance, currency, status, created_at, updated_at)
-           VALUES ($1, $2, 0, 'NGN', 'active', NOW(), NOW()) RETURNING *"#
+/// Writes the subscription's new state and queues its events, provided it is still the
+/// subscription for `period_end`, in the `billed_as` status, that was billed.
+async fn save_renewal(db: &sqlx::PgPool, subscription: &mut SubscriptionAggregate, id: Uuid, billed_as: &SubscriptionStatus, period_end: chrono::NaiveDate) -> Result<bool, ApiError> {
+    let mut tx = db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let saved = save_subscription_in(&mut tx, subscription, id, billed_as, period_end).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if !saved {
+        tracing::warn!(subscription_id = %id, "Subscription changed while its renewal was billed");
+        return Ok(false);
+    }
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(true)
+}
+
+/// `save_renewal` inside the caller's transaction. Returns false, writing nothing, when
+/// the subscription is no longer in `billed_as` for `period_end`.
+async fn save_subscription_in(
+    conn: &mut sqlx::PgConnection,
+    subscription: &mut SubscriptionAggregate,
+    id: Uuid,
+    billed_as: &SubscriptionStatus,
+    period_end: chrono::NaiveDate,
+) -> Result<bool, sqlx::Error> {
+    let record = subscription.to_record();
+    let updated: Option<Option<Uuid>> = sqlx::query_scalar(
+        r#"UPDATE subscriptions
+           SET status = $1, current_period_start = $2, current_period_end = $3, total_paid = $4, renewal_count = $5,
+               consecutive_failures = $6, last_payment_failed_at = $7, updated_at = NOW()
+           WHERE id = $8 AND status = $9 AND current_period_end = $10
+           RETURNING merchant_id"#
     )
+    .bind(record.status.as_str())
+    .bind(record.current_period_start)
+    .bind(record.current_period_end)
+    .bind(record.total_paid.amount)
+    .bind(record.renewals as i32)
+    .bind(record.consecutive_failures as i32)
+    .bind(record.last_payment_failed_at)
     .bind(id)
-    .bind(customer_id)
-    .fetch_one(&state.db)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    .bind(billed_as.as_str())
+    .bind(period_end)
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some(merchant_id) = updated else { return Ok(false) };
+    for event in subscription.take_events() {
+        insert_outbox(&mut *conn, merchant_id, &event).await?;
+    }
+    Ok(true)
+}
 
-    Ok((StatusCode::CREATED, Json(wallet)))
+async fn run_archival_worker(state: AppState, store: Arc<dyn ArchiveStore>, mut shutdown: watch::Receiver<bool>) {
+    let Some(retention_days) = state.config.archive_retention_days else { return };
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(state.config.archive_interval_secs));
+    while next_run(&mut interval, &mut shutdown).await {
+        let cutoff = Utc::now() - chrono::Duration::days(retention_days);
+        match archive_transactions(&state.db, store.as_ref(), cutoff, state.config.archive_batch_size, state.config.archive_move_rows).await {
+            Ok(0) => {}
+            Ok(archived) => tracing::info!("Archived {} transactions older than {}", archived, cutoff),
+            Err(e) => tracing::warn!("Transaction archival failed: {}", e),
+        }
+    }
 }
 
-async fn list_wallets(
-    State(state): State<AppState>,
-) -> Result<Json<Vec<Wallet>>, (StatusCode, String)> {
-    let wallets = sqlx::query_as::<_, Wallet>("SELECT * FROM wallets ORDER BY created_at DESC")
-        .fetch_all(&state.db)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+/// One exported transaction with everything that hangs off it.
+#[derive(Debug, Serialize, Deserialize)]
+struct ArchivedTransaction {
+    #[serde(flatten)]
+    transaction: Transaction,
+    refunds: Vec<Refund>,
+    #[serde(default)]
+    disputes: Vec<Dispute>,
+    ledger_entries: Vec<WalletTransaction>,
+}
+
+/// Exports terminal transactions created before `cutoff` in batches of gzipped NDJSON.
+/// Each batch is uploaded before it is recorded in `transactions_archive`, and its key
+/// is derived from its contents, so an interrupted run resumes where it stopped and
+/// archived rows are never exported twice. Returns the number of transactions archived.
+async fn archive_transactions(
+    db: &sqlx::PgPool,
+    store: &dyn ArchiveStore,
+    cutoff: DateTime<Utc>,
+    batch_size: i64,
+    move_rows: bool,
+) -> Result<usize> {
+    let mut archived = 0;
+    loop {
+        let batch = sqlx::query_as::<_, Transaction>(
+            r#"SELECT * FROM transactions t
+               WHERE t.created_at < $1 AND t.status = ANY($2)
+                 AND NOT EXISTS (SELECT 1 FROM transactions_archive a WHERE a.id = t.id)
+               ORDER BY t.created_at, t.id
+               LIMIT $3"#
+        )
+        .bind(cutoff)
+        .bind(archive::TERMINAL_STATUSES)
+        .bind(batch_size)
+        .fetch_all(db)
+        .await?;
+        let Some(first) = batch.first() else { break };
+
+        let ids: Vec<Uuid> = batch.iter().map(|t| t.id).collect();
+        let references: Vec<&str> = batch.iter().map(|t| t.reference.as_str()).collect();
+        let refunds = sqlx::query_as::<_, Refund>("SELECT * FROM refunds WHERE transaction_id = ANY($1) ORDER BY created_at")
+            .bind(&ids)
+            .fetch_all(db)
+            .await?;
+        let disputes = sqlx::query_as::<_, Dispute>("SELECT * FROM disputes WHERE transaction_id = ANY($1) ORDER BY created_at")
+            .bind(&ids)
+            .fetch_all(db)
+            .await?;
+        let ledger = sqlx::query_as::<_, WalletTransaction>("SELECT * FROM wallet_transactions WHERE reference = ANY($1) ORDER BY created_at")
+            .bind(&references)
+            .fetch_all(db)
+            .await?;
 
-    Ok(Json(wallets))
+        let key = archive::batch_key(
+            &first.created_at.format("%Y/%m/%d").to_string(),
+            &ids.iter().map(Uuid::to_string).collect::<Vec<_>>(),
+            ".ndjson.gz",
+        );
+        let records: Vec<ArchivedTransaction> = batch.iter().map(|t| ArchivedTransaction {
+            transaction: t.clone(),
+            refunds: refunds.iter().filter(|r| r.transaction_id == t.id).cloned().collect(),
+            disputes: disputes.iter().filter(|d| d.transaction_id == t.id).cloned().collect(),
+            ledger_entries: ledger.iter().filter(|l| l.reference.as_deref() == Some(t.reference.as_str())).cloned().collect(),
+        }).collect();
+
+        if !store.exists(&key).await? {
+            store.put(&key, gzip(&archive::to_ndjson(&records)?)?).await?;
+        }
+
+        let mut tx = db.begin().await?;
+        for record in &records {
+            let txn = &record.transaction;
+            sqlx::query(
+                r#"INSERT INTO transactions_archive (id, reference, export_key, record, transaction_created_at)
+                   VALUES ($1, $2, $3, $4, $5) ON CONFLICT (id) DO NOTHING"#
+            )
+            .bind(txn.id)
+            .bind(&txn.reference)
+            .bind(&key)
+            .bind(if move_rows { Some(serde_json::to_value(record)?) } else { None })
+            .bind(txn.created_at)
+            .execute(&mut *tx)
+            .await?;
+        }
+        if move_rows {
+            sqlx::query("DELETE FROM refunds WHERE transaction_id = ANY($1)").bind(&ids).execute(&mut *tx).await?;
+            sqlx::query("DELETE FROM disputes WHERE transaction_id = ANY($1)").bind(&ids).execute(&mut *tx).await?;
+            sqlx::query("DELETE FROM transactions WHERE id = ANY($1)").bind(&ids).execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        archived += records.len();
+    }
+    Ok(archived)
 }
 
-async fn get_wallet(
-    State(state): State<AppState>,
-    Path(id): Path<Uuid>,
-) -> Result<Json<Wallet>, (StatusCode, String)> {
-    let wallet = sqlx::query_as::<_, Wallet>("SELECT * FROM wallets WHERE id = $1")
-        .bind(id)
-        .fetch_optional(&state.db)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-        .ok_or((StatusCode::NOT_FOUND, "Wallet not found".to_string()))?;
+fn gzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
 
-    Ok(Json(wallet))
+struct NatsPublisher(async_nats::Client);
+
+#[async_trait::async_trait]
+impl EventPublisher for NatsPublisher {
+    async fn publish_bytes(&self, subject: String, payload: Vec<u8>) -> Result<(), PublishError> {
+        self.0.publish(subject, payload.into()).await.map_err(|e| PublishError::Transport(e.to_string()))
+    }
+
+    async fn ping(&self) -> Result<(), PublishError> {
+        match self.0.connection_state() {
+            async_nats::connection::State::Connected => Ok(()),
+            state => Err(PublishError::Transport(format!("NATS connection is {:?}", state))),
+        }
+    }
 }
 
-async fn topup_wallet(
-    State(state): State<AppState>,
-    Path(id): Path<Uuid>,
-    Json(req): Json<WalletTopupRequest>,
-) -> Result<Json<Wallet>, (StatusCode, String)> {
-    let amount = Decimal::new(req.amount, 2);
+/// Queues `event` in the outbox as part of `tx`, so it is published if and only if the
+/// change it describes commits. `merchant_id` is the merchant it happened to, whose
+/// endpoints get it as a webhook.
+async fn insert_outbox(tx: &mut sqlx::PgConnection, merchant_id: Option<Uuid>, event: &DomainEvent) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO event_outbox (id, subject, payload, merchant_id, created_at) VALUES ($1, $2, $3, $4, NOW())")
+        .bind(Uuid::now_v7())
+        .bind(publisher::subject(event))
+        .bind(sqlx::types::Json(event))
+        .bind(merchant_id)
+        .execute(&mut *tx)
+        .await?;
+    let Some(merchant_id) = merchant_id else { return Ok(()) };
+    let mut data = serde_json::to_value(event).unwrap_or_default();
+    if let Some(fields) = data.as_object_mut() { fields.remove("type"); }
+    queue_merchant_event(tx, merchant_id, event.event_name(), data).await
+}
 
-    let wallet = sqlx::query_as::<_, Wallet>(
-        "UPDATE wallets SET balance = balance + $1, updated_at = NOW() WHERE id = $2 RETURNING *"
+#[derive(sqlx::FromRow)]
+struct OutboxRow {
+    id: Uuid,
+    subject: String,
+    payload: serde_json::Value,
+}
+
+async fn run_outbox_worker(state: AppState, bus: Arc<dyn EventPublisher>, mut shutdown: watch::Receiver<bool>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(state.config.outbox_poll_interval_secs));
+    while next_run(&mut interval, &mut shutdown).await {
+        match publish_outbox(&state.db, bus.as_ref()).await {
+            Ok(0) => {}
+            Ok(published) => tracing::debug!("Published {} outbox events", published),
+            Err(e) => tracing::warn!("Outbox publishing failed: {}", e),
+        }
+    }
+}
+
+/// Publishes unsent outbox rows oldest first and marks them sent. A failed publish is
+/// recorded on its row and ends the batch, so events go out in order and the row is
+/// retried on the next run. Returns the number published.
+async fn publish_outbox(db: &sqlx::PgPool, bus: &dyn EventPublisher) -> Result<usize, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    // Rows locked by a concurrent worker are left to it
+    let rows = sqlx::query_as::<_, OutboxRow>(
+        "SELECT id, subject, payload FROM event_outbox WHERE published_at IS NULL ORDER BY created_at, id LIMIT 100 FOR UPDATE SKIP LOCKED"
     )
-    .bind(amount)
-    .bind(id)
-    .fetch_optional(&state.db)
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-    .ok_or((StatusCode::NOT_FOUND, "Wallet not found".to_string()))?;
+    .fetch_all(&mut *tx)
+    .await?;
 
-    Ok(Json(wallet))
+    let mut published = 0;
+    for row in rows {
+        let payload = row.payload.to_string().into_bytes();
+        match bus.publish_bytes(row.subject.clone(), payload).await {
+            Ok(()) => {
+                sqlx::query("UPDATE event_outbox SET published_at = NOW(), attempts = attempts + 1 WHERE id = $1")
+                    .bind(row.id)
+                    .execute(&mut *tx)
+                    .await?;
+                published += 1;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to publish {}: {}", row.subject, e);
+                sqlx::query("UPDATE event_outbox SET attempts = attempts + 1, last_error = $1 WHERE id = $2")
+                    .bind(e.to_string())
+                    .bind(row.id)
+                    .execute(&mut *tx)
+                    .await?;
+                break;
+            }
+        }
+    }
+    tx.commit().await?;
+    Ok(published)
 }
 
-async fn create_transfer(
-    State(state): State<AppState>,
-    Json(req): Json<TransferRequest>,
-) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
-    let amount = Decimal::new(req.amount, 2);
+/// Queues `event_type` for each of the merchant's webhook endpoints, as part of `tx`.
+/// The delivery worker sends it; each endpoint gets the same event id on every attempt.
+async fn queue_merchant_event(tx: &mut sqlx::PgConnection, merchant_id: Uuid, event_type: &str, data: serde_json::Value) -> Result<(), sqlx::Error> {
+    let event = serde_json::json!({
+        "id": format!("evt_{}", Uuid::now_v7().simple()),
+        "type": event_type,
+        "created_at": Utc::now(),
+        "data": data,
+    });
+    let endpoints: Vec<Uuid> = sqlx::query_scalar("SELECT id FROM webhook_endpoints WHERE merchant_id = $1")
+        .bind(merchant_id)
+        .fetch_all(&mut *tx)
+        .await?;
+    for endpoint_id in endpoints {
+        sqlx::query(
+            r#"INSERT INTO webhook_deliveries (id, endpoint_id, event_type, payload, status, next_attempt_at, created_at)
+               VALUES ($1, $2, $3, $4, 'pending', NOW(), NOW())"#
+        )
+        .bind(Uuid::now_v7())
+        .bind(endpoint_id)
+        .bind(event_type)
+        .bind(&event)
+        .execute(&mut *tx)
+        .await?;
+    }
+    Ok(())
+}
 
-    // Debit source wallet
-    sqlx::query("UPDATE wallets SET balance = balance - $1 WHERE id = $2 AND balance >= $1")
-        .bind(amount)
-        .bind(req.from_wallet_id)
-        .execute(&state.db)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+#[derive(sqlx::FromRow)]
+struct DueDelivery {
+    id: Uuid,
+    event_type: String,
+    payload: serde_json::Value,
+    attempts: i32,
+    url: String,
+    secret: String,
+}
 
-    // Credit destination wallet
-    sqlx::query("UPDATE wallets SET balance = balance + $1 WHERE id = $2")
-        .bind(amount)
-        .bind(req.to_wallet_id)
-        .execute(&state.db)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+async fn run_webhook_delivery_worker(state: AppState, mut shutdown: watch::Receiver<bool>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(state.config.webhook_delivery_interval_secs));
+    while next_run(&mut interval, &mut shutdown).await {
+        match deliver_due_webhooks(&state).await {
+            Ok(0) => {}
+            Ok(delivered) => tracing::debug!("Delivered {} merchant webhooks", delivered),
+            Err(e) => tracing::warn!("Webhook delivery failed: {}", e),
+        }
+    }
+}
 
-    Ok(Json(serde_json::json!({
-        "status": "completed",
-        "amount": req.amount,
-        "from": req.from_wallet_id,
-        "to": req.to_wallet_id
-    })))
+/// How long a claimed delivery is left to the worker sending it, well past the HTTP
+/// client's 30s timeout. One still pending after that, say because the worker died
+/// mid-send, is picked up again.
+const WEBHOOK_DELIVERY_LEASE_SECS: f64 = 300.0;
+
+/// Sends queued webhooks that are due and records each attempt. Due deliveries are
+/// claimed for [`WEBHOOK_DELIVERY_LEASE_SECS`] and sent concurrently, outside any
+/// transaction, so a slow endpoint neither holds row locks nor holds up the others.
+/// A failed delivery is rescheduled with backoff, or marked failed once out of
+/// attempts. Returns the number delivered.
+async fn deliver_due_webhooks(state: &AppState) -> Result<usize, sqlx::Error> {
+    // Rows locked by a concurrent claim are left to it; the lease is committed before sending
+    let due = sqlx::query_as::<_, DueDelivery>(
+        r#"WITH due AS (
+               SELECT id FROM webhook_deliveries
+               WHERE status = 'pending' AND next_attempt_at <= NOW()
+               ORDER BY next_attempt_at, id LIMIT 50
+               FOR UPDATE SKIP LOCKED
+           ), claimed AS (
+               UPDATE webhook_deliveries d SET next_attempt_at = NOW() + make_interval(secs => $1)
+               FROM due WHERE d.id = due.id
+               RETURNING d.id, d.endpoint_id, d.event_type, d.payload, d.attempts
+           )
+           SELECT c.id, c.event_type, c.payload, c.attempts, e.url, e.secret
+           FROM claimed c JOIN webhook_endpoints e ON e.id = c.endpoint_id"#
+    )
+    .bind(WEBHOOK_DELIVERY_LEASE_SECS)
+    .fetch_all(&state.db)
+    .await?;
+
+    let mut sending = tokio::task::JoinSet::new();
+    for delivery in due {
+        let state = state.clone();
+        sending.spawn(async move {
+            let result = webhooks::deliver(&state.http, &delivery.url, &delivery.secret, &delivery.payload).await;
+            let success = result.success;
+            // Left to the lease when this fails, so the delivery is retried rather than lost
+            if let Err(e) = record_webhook_attempt(&state.db, &delivery, &result).await {
+                tracing::warn!("Failed to record webhook delivery {}: {}", delivery.id, e);
+                return false;
+            }
+            success
+        });
+    }
+    let mut delivered = 0;
+    while let Some(outcome) = sending.join_next().await {
+        match outcome {
+            Ok(true) => delivered += 1,
+            Ok(false) => {}
+            Err(e) => tracing::warn!("Webhook delivery task failed: {}", e),
+        }
+    }
+    Ok(delivered)
+}
+
+/// Records one delivery attempt and its outcome: delivered, rescheduled, or failed.
+async fn record_webhook_attempt(db: &sqlx::PgPool, delivery: &DueDelivery, result: &webhooks::DeliveryResult) -> Result<(), sqlx::Error> {
+    let mut tx = db.begin().await?;
+    sqlx::query(
+        "INSERT INTO webhook_delivery_attempts (id, delivery_id, status_code, error, duration_ms, attempted_at) VALUES ($1, $2, $3, $4, $5, NOW())"
+    )
+    .bind(Uuid::now_v7())
+    .bind(delivery.id)
+    .bind(result.status_code.map(|code| code as i16))
+    .bind(&result.error)
+    .bind(result.duration_ms as i64)
+    .execute(&mut *tx)
+    .await?;
+
+    let attempts = delivery.attempts.max(0) as u32 + 1;
+    if result.success {
+        sqlx::query("UPDATE webhook_deliveries SET status = 'delivered', attempts = $1, last_error = NULL, delivered_at = NOW() WHERE id = $2")
+            .bind(attempts as i32)
+            .bind(delivery.id)
+            .execute(&mut *tx)
+            .await?;
+        return tx.commit().await;
+    }
+    let retry_in = webhooks::retry_delay(attempts);
+    tracing::warn!("Webhook delivery of {} to {} failed (attempt {}): {:?}", delivery.event_type, delivery.url, attempts, result.error);
+    sqlx::query(
+        r#"UPDATE webhook_deliveries
+           SET status = $1, attempts = $2, last_error = $3, next_attempt_at = NOW() + make_interval(secs => $4)
+           WHERE id = $5"#
+    )
+    .bind(if retry_in.is_some() { "pending" } else { "failed" })
+    .bind(attempts as i32)
+    .bind(&result.error)
+    .bind(retry_in.map_or(0.0, |delay| delay.as_secs_f64()))
+    .bind(delivery.id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await
+}
+
+fn build_router(state: AppState) -> Router {
+    Router::new()
+        .route("/health", get(health))
+        .route("/health/ready", get(readiness))
+        .route("/metrics", get(metrics))
+        .nest("/api/v1", api_routes(&state))
+        .layer(TraceLayer::new_for_http())
+        .layer(middleware::from_fn(request_id))
+        .layer(CorsLayer::permissive())
+        .with_state(state)
+}
+
+fn api_routes(state: &AppState) -> Router<AppState> {
+    Router::new()
+        .merge(payment_routes(state))
+        .route("/transactions", get(list_transactions))
+        .route("/transactions/:id", get(get_transaction))
+        .route("/transactions/:id/void", post(void_transaction))
+        .route("/transactions/:id/capture", post(capture_transaction))
+        .route("/customers/:customer_id/transactions", get(list_customer_transactions))
+        .route("/refunds", post(create_refund).get(list_refunds))
+        .route("/refunds/bulk", post(create_bulk_refunds))
+        .route("/refunds/:id", get(get_refund))
+        .route("/disputes", post(create_dispute))
+        .route("/disputes/:id", get(get_dispute))
+        .route("/disputes/:id/evidence", post(submit_dispute_evidence))
+        .route("/disputes/:id/close", post(close_dispute))
+        .route("/payment-methods", post(create_payment_method))
+        .route("/payment-methods/expiring", get(list_expiring_payment_methods))
+        .route("/payment-methods/:id", axum::routing::delete(delete_payment_method))
+        .route("/payment-methods/:id/default", post(set_default_payment_method))
+        .route("/customers/:customer_id/payment-methods", get(list_customer_payment_methods))
+        .route("/wallets", post(create_wallet).get(list_wallets))
+        .route("/wallets/:id", get(get_wallet))
+        .route("/wallets/:id/topup", post(topup_wallet))
+        .route("/wallets/:id/ledger", get(get_wallet_ledger))
+        .route("/wallets/:id/topups/:topup_id/reverse", post(reverse_topup))
+        .route("/wallets/:id/holds", post(create_wallet_hold))
+        .route("/wallets/:id/holds/:hold_id/capture", post(capture_wallet_hold))
+        .route("/wallets/:id/holds/:hold_id/release", post(release_wallet_hold))
+        .route("/transfers", post(create_transfer))
+        .route("/transfers/preview", post(preview_transfer))
+        .route("/payment-plans", post(create_payment_plan))
+        .route("/payment-plans/:id", get(get_payment_plan))
+        .route("/subscriptions", post(create_subscription))
+        .route("/subscriptions/:id", get(get_subscription).patch(update_subscription))
+        .route("/subscriptions/:id/metrics", get(get_subscription_metrics))
+        .route("/subscriptions/:id/usage", post(record_usage))
+        .route("/webhook-endpoints", post(create_webhook_endpoint).get(list_webhook_endpoints))
+        .route("/webhook-endpoints/:id", axum::routing::delete(delete_webhook_endpoint))
+        .route("/webhook-endpoints/:id/test", post(test_webhook_endpoint))
+        .route_layer(middleware::from_fn_with_state(state.clone(), idempotency_key_guard))
+        .route_layer(middleware::from_fn_with_state(state.clone(), authenticate_merchant))
+        // Authenticated by provider signature, checkout token and admin token instead of an API key
+        .route("/payments/webhook", post(webhook_handler))
+        .route("/payments/webhook/flutterwave", post(flutterwave_webhook_handler))
+        .route("/checkout/:token", get(get_checkout))
+        .route("/admin/transactions/:id/debug", get(get_transaction_debug))
+        .route("/admin/settlements", post(create_settlement))
+        .route("/admin/payouts/pending", get(get_pending_payout))
+        .route("/reports/settlements", get(get_settlement_report))
+}
+
+/// Client-facing payment routes, rate limited per client. The provider webhook is left out:
+/// providers retry anything refused, and they are checked by signature instead.
+fn payment_routes(state: &AppState) -> Router<AppState> {
+    Router::new()
+        .route("/payments/initiate", post(initiate_payment))
+        .route("/payments/verify", post(verify_payment))
+        .route("/payments/confirm", post(confirm_payment))
+        .route("/payments/:reference/retry", post(retry_payment))
+        .route_layer(middleware::from_fn_with_state(state.clone(), payment_rate_limit))
+}
+
+/// Refuses a client's payment requests with 429 and `Retry-After` once its bucket is empty.
+/// Clients are told apart by API key, falling back to their address.
+async fn payment_rate_limit(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let Some(limiter) = &state.payment_limiter else { return next.run(req).await };
+    if let Err(wait) = limiter.check(&rate_limit_key(&state, &req)) {
+        let retry_after = wait.as_secs_f64().ceil().clamp(1.0, 86_400.0) as u64;
+        let error = ApiError::from((StatusCode::TOO_MANY_REQUESTS, "Too many payment requests".to_string()));
+        return ([(axum::http::header::RETRY_AFTER, retry_after.to_string())], error).into_response();
+    }
+    next.run(req).await
+}
+
+/// `key:<hash>` for requests with an API key, else `ip:<client>`. Keys are hashed so the
+/// limiter never holds a usable credential.
+fn rate_limit_key(state: &AppState, req: &Request) -> String {
+    if let Some(key) = bearer_token(req.headers()) {
+        return format!("key:{}", sase_payments::crypto::sha256_hex(key.as_bytes()));
+    }
+    match req.extensions().get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(addr)) => format!("ip:{}", webhook_client_ip(&state.config.webhook_allowlist, addr.ip(), req.headers())),
+        None => "ip:unknown".to_string(),
+    }
+}
+
+/// The merchant whose API key authenticated the request, with its settings, put in
+/// request extensions by `authenticate_merchant`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, sqlx::FromRow)]
+pub struct Merchant {
+    pub id: Uuid,
+    /// Overrides `Config::require_idempotency_key` when set.
+    pub require_idempotency_key: Option<bool>,
+    /// Overrides `Config::max_refunds_per_transaction` when set.
+    pub max_refunds_per_transaction: Option<i64>,
+}
+
+impl Merchant {
+    /// A merchant with no settings of its own, so the deployment's apply.
+    pub fn new(id: Uuid) -> Self { Self { id, require_idempotency_key: None, max_refunds_per_transaction: None } }
+
+    pub fn requires_idempotency_key(&self, config: &Config) -> bool {
+        self.require_idempotency_key.unwrap_or(config.require_idempotency_key)
+    }
+
+    pub fn max_refunds_per_transaction(&self, config: &Config) -> i64 {
+        self.max_refunds_per_transaction.unwrap_or(config.max_refunds_per_transaction)
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers.get(axum::http::header::AUTHORIZATION).and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+}
+
+/// Resolves `Authorization: Bearer <key>` to the merchant of an unrevoked key, or answers 401.
+async fn authenticate_merchant(State(state): State<AppState>, mut req: Request, next: Next) -> Response {
+    let Some(key) = bearer_token(req.headers()) else { return unauthorized("API key required") };
+    let merchant: Result<Option<Merchant>, _> = sqlx::query_as(
+        r#"SELECT k.merchant_id AS id, s.require_idempotency_key, s.max_refunds_per_transaction
+           FROM api_keys k LEFT JOIN merchant_settings s ON s.merchant_id = k.merchant_id
+           WHERE k.key_hash = $1 AND k.revoked_at IS NULL"#
+    )
+    .bind(sase_payments::crypto::sha256_hex(key.as_bytes()))
+    .fetch_optional(&state.db)
+    .await;
+    match merchant {
+        Ok(Some(merchant)) => {
+            req.extensions_mut().insert(merchant);
+            next.run(req).await
+        }
+        Ok(None) => unauthorized("Invalid or revoked API key"),
+        Err(e) => ApiError::from((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())).into_response(),
+    }
+}
+
+fn unauthorized(message: &str) -> Response {
+    let error = ApiError::from((StatusCode::UNAUTHORIZED, message.to_string()));
+    ([(axum::http::header::WWW_AUTHENTICATE, "Bearer")], error).into_response()
+}
+
+/// The `X-Request-Id` of the request being handled, as a request extension.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+/// Error bodies larger than this are passed through without a `request_id`.
+const MAX_TAGGED_ERROR_BODY: usize = 64 * 1024;
+
+/// Keeps the client's `X-Request-Id` if it is 1-128 characters of letters, digits, `-`,
+/// `_`, `.` or `:`, and makes one up otherwise. The id is put in a request extension and
+/// on a span around everything below this layer, and echoed in the response header and
+/// in JSON error bodies.
+async fn request_id(mut req: Request, next: Next) -> Response {
+    let supplied = req.headers().get(REQUEST_ID_HEADER).and_then(|v| v.to_str().ok())
+        .filter(|id| (1..=128).contains(&id.len()) && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':')));
+    let id = supplied.map_or_else(|| Uuid::now_v7().to_string(), str::to_string);
+    req.extensions_mut().insert(RequestId(id.clone()));
+
+    let span = tracing::info_span!("request", request_id = %id);
+    let response = tracing::Instrument::instrument(next.run(req), span).await;
+    let mut response = tag_error_body(response, &id).await;
+    if let Ok(value) = id.parse() {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// Adds `request_id` to a JSON object error body, leaving any other response untouched.
+async fn tag_error_body(response: Response, id: &str) -> Response {
+    let is_json = response.headers().get(axum::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if !(response.status().is_client_error() || response.status().is_server_error()) || !is_json {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_TAGGED_ERROR_BODY).await else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Error response too large").into_response();
+    };
+    let bytes = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(serde_json::Value::Object(mut body)) => {
+            body.insert("request_id".to_string(), id.into());
+            parts.headers.remove(axum::http::header::CONTENT_LENGTH);
+            serde_json::to_vec(&body).map(Bytes::from).unwrap_or(bytes)
+        }
+        _ => bytes,
+    };
+    Response::from_parts(parts, axum::body::Body::from(bytes))
+}
+
+/// POST routes that create or move money.
+fn is_money_moving(method: &Method, path: &str) -> bool {
+    if method != Method::POST { return false; }
+    let segments: Vec<&str> = path.trim_start_matches("/api/v1/").split('/').collect();
+    matches!(
+        segments.as_slice(),
+        ["payments", "initiate"] | ["payments", _, "retry"] | ["refunds"] | ["transfers"] | ["wallets", _, "topup"] | ["wallets", _, "topups", _, "reverse"]
+    )
+}
+
+/// Rejects money-moving requests without an `Idempotency-Key` header when the merchant, or
+/// failing a setting of its own the deployment, requires one. Runs after `authenticate_merchant`.
+async fn idempotency_key_guard(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let required = req.extensions().get::<Merchant>()
+        .map_or(state.config.require_idempotency_key, |merchant| merchant.requires_idempotency_key(&state.config));
+    // Nested under /api/v1, which the request's own URI no longer carries
+    let path = req.extensions().get::<axum::extract::OriginalUri>().map_or(req.uri().path(), |uri| uri.path());
+    if required && is_money_moving(req.method(), path) && !req.headers().contains_key("idempotency-key") {
+        return (StatusCode::BAD_REQUEST, "Idempotency-Key header is required").into_response();
+    }
+    next.run(req).await
+}
+
+async fn health() -> impl IntoResponse {
+    Json(serde_json::json!({
+        "status": "healthy",
+        "service": "opensase-payments",
+        "version": env!("CARGO_PKG_VERSION")
+    }))
+}
+
+const PROVIDER_CALL_SECONDS: &str = "provider_call_duration_seconds";
+const PROVIDER_CALL_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// The process-wide Prometheus recorder, installed on first use. `main` installs it at
+/// startup so nothing recorded before the first scrape is lost.
+fn prometheus() -> &'static PrometheusHandle {
+    static HANDLE: std::sync::OnceLock<PrometheusHandle> = std::sync::OnceLock::new();
+    HANDLE.get_or_init(|| {
+        let handle = PrometheusBuilder::new()
+            .set_buckets_for_metric(Matcher::Full(PROVIDER_CALL_SECONDS.to_string()), PROVIDER_CALL_BUCKETS)
+            .expect("bucket list is not empty")
+            .install_recorder()
+            .expect("no other metrics recorder is installed");
+        metrics::describe_counter!("payments_initiated_total", "Payments created, whatever became of them.");
+        metrics::describe_counter!("payments_succeeded_total", "Payments that succeeded.");
+        metrics::describe_counter!("payments_failed_total", "Payments the provider declined or refused.");
+        metrics::describe_counter!("refunds_succeeded_total", "Refunds the provider completed.");
+        metrics::describe_counter!("refunds_failed_total", "Refunds the provider rejected.");
+        metrics::describe_counter!("refunded_amount_minor_total", "Refunded amounts by currency, in minor units.");
+        metrics::describe_histogram!(PROVIDER_CALL_SECONDS, metrics::Unit::Seconds, "Time spent waiting on provider calls, by provider and operation.");
+        handle
+    })
+}
+
+/// Runs a provider call, recording how long it took in `provider_call_duration_seconds`.
+async fn timed<T>(provider: &'static str, operation: &'static str, call: impl std::future::Future<Output = T>) -> T {
+    let started = std::time::Instant::now();
+    let result = call.await;
+    metrics::histogram!(PROVIDER_CALL_SECONDS, "provider" => provider, "operation" => operation).record(started.elapsed().as_secs_f64());
+    result
+}
+
+/// Payment counters follow the payment events, so each path that creates or settles a
+/// charge is counted once. Called only after the transaction that queued the event has
+/// committed, so a rolled-back attempt is never counted.
+fn count_payment_event(event: &DomainEvent) {
+    let name = match event {
+        DomainEvent::Payment(PaymentEvent::Created { .. }) => "payments_initiated_total",
+        DomainEvent::Payment(PaymentEvent::Succeeded { .. }) => "payments_succeeded_total",
+        DomainEvent::Payment(PaymentEvent::Failed { .. }) => "payments_failed_total",
+        _ => return,
+    };
+    metrics::counter!(name).increment(1);
+}
+
+/// Everything the recorder holds, in the Prometheus text format, followed by this pool's
+/// gauges. The pool's are read at each scrape rather than recorded, so they always
+/// describe the pool serving it. A pool whose size sits at `DB_MAX_CONNECTIONS` with
+/// nothing idle is making requests wait for a connection.
+async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let mut body = prometheus().render();
+    body.push_str(&format!(
+        "# HELP db_pool_size Open database connections, idle or in use.\n# TYPE db_pool_size gauge\ndb_pool_size {}\n\
+         # HELP db_pool_idle Open database connections not in use.\n# TYPE db_pool_idle gauge\ndb_pool_idle {}\n",
+        state.db.size(),
+        state.db.num_idle(),
+    ));
+    ([(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+/// How long a readiness check may take before its dependency counts as down.
+const READINESS_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Readiness probe: 200 when Postgres answers and NATS, if configured, is connected;
+/// otherwise 503. `/health` stays a liveness probe that touches nothing.
+async fn readiness(State(state): State<AppState>) -> Response {
+    let database = match tokio::time::timeout(READINESS_TIMEOUT, sqlx::query("SELECT 1").execute(&state.db)).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("timed out".to_string()),
+    };
+    let nats = match (&state.config.nats_url, &state.nats) {
+        (None, _) => None,
+        (Some(_), None) => Some(Err("not connected".to_string())),
+        (Some(_), Some(bus)) => Some(match tokio::time::timeout(READINESS_TIMEOUT, bus.ping()).await {
+            Ok(pinged) => pinged.map_err(|e| e.to_string()),
+            Err(_) => Err("timed out".to_string()),
+        }),
+    };
+
+    let check = |result: &Result<(), String>| match result {
+        Ok(()) => serde_json::json!({ "status": "up" }),
+        Err(e) => serde_json::json!({ "status": "down", "error": e }),
+    };
+    let mut checks = serde_json::Map::new();
+    checks.insert("database".into(), check(&database));
+    checks.insert("nats".into(), nats.as_ref().map(check).unwrap_or_else(|| serde_json::json!({ "status": "not_configured" })));
+    let ready = database.is_ok() && !matches!(nats, Some(Err(_)));
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(serde_json::json!({ "status": if ready { "ready" } else { "unavailable" }, "checks": checks }))).into_response()
+}
+
+// =============================================================================
+// Payment Handlers
+// =============================================================================
+
+async fn initiate_payment(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    headers: HeaderMap,
+    Json(req): Json<InitiatePaymentRequest>,
+) -> Result<Json<InitiatePaymentResponse>, ApiError> {
+    req.validate().map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    let Some(key) = idempotency_key(&headers)? else {
+        return create_payment(&state, merchant, &req).await.map(|(_, response)| Json(response));
+    };
+    let request_hash = request_hash(&req)?;
+    if let Some(replay) = claim_idempotency_key(&state.db, merchant, &key, &request_hash).await? {
+        return Ok(Json(replay));
+    }
+    match create_payment(&state, merchant, &req).await {
+        Ok((id, response)) => {
+            sqlx::query("UPDATE idempotency_keys SET response = $1, transaction_id = $2 WHERE merchant_id = $3 AND key = $4")
+                .bind(serde_json::to_value(&response).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?)
+                .bind(id)
+                .bind(merchant.id)
+                .bind(&key)
+                .execute(&state.db)
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            Ok(Json(response))
+        }
+        Err(e) => {
+            // Nothing to replay: release the key so the client can retry with it
+            sqlx::query("DELETE FROM idempotency_keys WHERE merchant_id = $1 AND key = $2 AND response IS NULL")
+                .bind(merchant.id)
+                .bind(&key)
+                .execute(&state.db)
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            Err(e)
+        }
+    }
+}
+
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, (StatusCode, String)> {
+    let Some(value) = headers.get("idempotency-key") else { return Ok(None) };
+    let key = value.to_str().map(str::trim).unwrap_or_default();
+    if key.is_empty() || key.len() > 255 {
+        return Err((StatusCode::BAD_REQUEST, "Idempotency-Key must be 1-255 visible ASCII characters".to_string()));
+    }
+    Ok(Some(key.to_string()))
+}
+
+/// SHA-256 of the request as canonical JSON (object keys sorted), so formatting and key
+/// order don't make a retry look like a different request.
+fn request_hash(req: &impl Serialize) -> Result<String, (StatusCode, String)> {
+    let canonical = serde_json::to_value(req).and_then(|v| serde_json::to_vec(&v))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(sase_payments::crypto::sha256_hex(&canonical))
+}
+
+/// Records the merchant's `key` for a new request, or returns the stored response when it
+/// is a replay. A key seen with a different body, or whose first request hasn't finished,
+/// is a 409.
+async fn claim_idempotency_key(db: &sqlx::PgPool, merchant: Merchant, key: &str, request_hash: &str) -> Result<Option<InitiatePaymentResponse>, (StatusCode, String)> {
+    let claimed = sqlx::query("INSERT INTO idempotency_keys (merchant_id, key, request_hash, created_at) VALUES ($1, $2, $3, NOW()) ON CONFLICT (merchant_id, key) DO NOTHING")
+        .bind(merchant.id)
+        .bind(key)
+        .bind(request_hash)
+        .execute(db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if claimed.rows_affected() == 1 { return Ok(None); }
+
+    let (stored_hash, response): (String, Option<serde_json::Value>) =
+        sqlx::query_as("SELECT request_hash, response FROM idempotency_keys WHERE merchant_id = $1 AND key = $2")
+            .bind(merchant.id)
+            .bind(key)
+            .fetch_one(db)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if stored_hash != request_hash {
+        return Err((StatusCode::CONFLICT, "Idempotency-Key was already used with a different request body".to_string()));
+    }
+    let response = response.ok_or((StatusCode::CONFLICT, "A request with this Idempotency-Key is still in progress".to_string()))?;
+    serde_json::from_value(response).map(Some).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// The request's metadata once it passes the limits in `metadata`; stored as sent.
+fn payment_metadata(req: &InitiatePaymentRequest) -> Result<serde_json::Value, ApiError> {
+    match &req.metadata {
+        Some(value) => {
+            metadata::from_json(value).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            Ok(value.clone())
+        }
+        None => Ok(serde_json::json!({})),
+    }
+}
+
+/// Inserts the merchant's transaction and charges it, or stores it as `scheduled` when
+/// the request names a `scheduled_at`. Returns the new transaction id with the response.
+async fn create_payment(state: &AppState, merchant: Merchant, req: &InitiatePaymentRequest) -> Result<(Uuid, InitiatePaymentResponse), ApiError> {
+    if let Some(at) = req.scheduled_at {
+        if at <= Utc::now() {
+            return Err((StatusCode::BAD_REQUEST, "scheduled_at must be in the future".to_string()).into());
+        }
+        if req.payment_method.as_deref() == Some(WALLET_PAYMENT_METHOD) {
+            return Err((StatusCode::BAD_REQUEST, "Wallet payments can't be scheduled".to_string()).into());
+        }
+    }
+    if req.payment_method.as_deref() == Some(WALLET_PAYMENT_METHOD) {
+        if req.capture == Some(false) {
+            return Err((StatusCode::BAD_REQUEST, "Wallet payments are always captured; use a wallet hold instead".to_string()).into());
+        }
+        return create_wallet_payment(state, merchant, req).await;
+    }
+    let payment = prepare_payment(state, merchant, req).await?;
+    match req.scheduled_at {
+        Some(at) => {
+            let mut conn = state.db.acquire().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            schedule_payment(&mut conn, merchant, req, payment, at).await
+        }
+        None => start_payment(state, merchant, req, payment, None).await,
+    }
+}
+
+/// A provider charge that passed validation: everything `start_payment` needs besides
+/// the request itself.
+struct PreparedPayment {
+    reference: String,
+    money: Money,
+    gateways: Vec<Arc<dyn PaymentGateway>>,
+    descriptor: Option<String>,
+    metadata: serde_json::Value,
+}
+
+/// Checks a provider charge against the limits, currency and routing config, and picks
+/// the gateways to try.
+async fn prepare_payment(state: &AppState, merchant: Merchant, req: &InitiatePaymentRequest) -> Result<PreparedPayment, ApiError> {
+    if let Some(method_id) = req.payment_method_id {
+        ensure_payment_method_usable(&state.db, merchant, method_id).await?;
+    }
+
+    let reference = payment_reference(req)?;
+    req.amount.ensure_positive()?;
+    req.amount.ensure_within(&state.config.amount_limits)?;
+    let money = req.amount.to_money_in(&state.config.currency_policy)?;
+    // A provider named on the request overrides the routing table, and gets no fallback
+    let mut gateways = match req.provider.as_deref() {
+        Some(name) => vec![state.gateway_named(Some(name))
+            .ok_or_else(|| (StatusCode::BAD_REQUEST, format!("Unknown or unconfigured provider '{}'", name)))?],
+        None => state.routed_gateways(&money.currency, req.amount.minor_units)?,
+    };
+    // A provider that can't hold funds would take them at once
+    if req.capture == Some(false) {
+        gateways.retain(|g| g.supports_manual_capture());
+        if gateways.is_empty() {
+            return Err((StatusCode::BAD_REQUEST, "No provider for this payment supports capture: false".to_string()).into());
+        }
+    }
+    // Routed fallbacks were already filtered on currency; this covers the first choice
+    if let Some(capabilities) = state.config.provider_capabilities.get(gateways[0].name()) {
+        capabilities.ensure_currency(gateways[0].name(), &money.currency)?;
+    }
+    let descriptor = match (&state.config.statement_descriptor, &req.statement_descriptor_suffix) {
+        (Some(prefix), suffix) => Some(statement_descriptor(prefix, suffix.as_deref())?),
+        (None, Some(_)) => return Err((StatusCode::BAD_REQUEST, "statement_descriptor_suffix requires a configured statement descriptor".to_string()).into()),
+        (None, None) => None,
+    };
+    let metadata = payment_metadata(req)?;
+    Ok(PreparedPayment { reference, money, gateways, descriptor, metadata })
+}
+
+/// Stores the payment as `scheduled`, with the request it came from, for the scheduler
+/// to start at `at`.
+async fn schedule_payment(
+    conn: &mut sqlx::PgConnection,
+    merchant: Merchant,
+    req: &InitiatePaymentRequest,
+    payment: PreparedPayment,
+    at: DateTime<Utc>,
+) -> Result<(Uuid, InitiatePaymentResponse), ApiError> {
+    // Replayed with the reference already fixed, so the charge keeps the one returned here
+    let mut request = serde_json::to_value(req).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    request["reference"] = serde_json::json!(payment.reference);
+    request["scheduled_at"] = serde_json::Value::Null;
+    let id = Uuid::now_v7();
+    sqlx::query(
+        r#"INSERT INTO transactions (id, reference, amount, currency, status, transaction_type, customer_email, metadata, callback_url,
+                                     statement_descriptor, merchant_id, scheduled_at, scheduled_request, created_at, updated_at)
+           VALUES ($1, $2, $3, $4, $5, 'payment', $6, $7, $8, $9, $10, $11, $12, NOW(), NOW())"#
+    )
+    .bind(id)
+    .bind(&payment.reference)
+    .bind(payment.money.amount)
+    .bind(&payment.money.currency)
+    .bind(TransactionStatus::Scheduled.as_str())
+    .bind(&req.email)
+    .bind(&payment.metadata)
+    .bind(&req.callback_url)
+    .bind(&payment.descriptor)
+    .bind(merchant.id)
+    .bind(at)
+    .bind(&request)
+    .execute(conn)
+    .await
+    .map_err(|e| match e.as_database_error() {
+        Some(db) if db.is_unique_violation() => (StatusCode::CONFLICT, format!("Reference '{}' already exists", payment.reference)),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    })?;
+
+    Ok((id, InitiatePaymentResponse {
+        reference: payment.reference,
+        authorization_url: None,
+        next_action: None,
+        status: TransactionStatus::Scheduled.to_string(),
+        checkout_token: None,
+        checkout_expires_at: None,
+    }))
+}
+
+/// Opens the pending transaction and charges it. `scheduled` is the id of a scheduled
+/// payment that has fallen due, which is moved to pending instead of inserting a new row.
+async fn start_payment(
+    state: &AppState,
+    merchant: Merchant,
+    req: &InitiatePaymentRequest,
+    payment: PreparedPayment,
+    scheduled: Option<Uuid>,
+) -> Result<(Uuid, InitiatePaymentResponse), ApiError> {
+    let PreparedPayment { reference, money, gateways, descriptor, metadata } = payment;
+    let id = scheduled.unwrap_or_else(Uuid::now_v7);
+    let provider_key = format!("chg_{}", Uuid::new_v4().simple());
+    let manual_capture = req.capture == Some(false);
+
+    let mut tx = state.db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if scheduled.is_some() {
+        let started = sqlx::query(
+            "UPDATE transactions SET status = $1, provider_idempotency_key = $2, statement_descriptor = $3, manual_capture = $6, updated_at = NOW() WHERE id = $4 AND status = $5"
+        )
+        .bind(TransactionStatus::Pending.as_str())
+        .bind(&provider_key)
+        .bind(&descriptor)
+        .bind(id)
+        .bind(TransactionStatus::Scheduled.as_str())
+        .bind(manual_capture)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        if started.rows_affected() == 0 {
+            return Err((StatusCode::CONFLICT, format!("Scheduled payment '{}' was already started", reference)).into());
+        }
+    } else {
+        sqlx::query(
+            r#"INSERT INTO transactions (id, reference, amount, currency, status, transaction_type, customer_email, metadata,
+                                         provider_idempotency_key, callback_url, statement_descriptor, merchant_id, manual_capture, created_at, updated_at)
+               VALUES ($1, $2, $3, $4, 'pending', 'payment', $5, $6, $7, $8, $9, $10, $11, NOW(), NOW())"#
+        )
+        .bind(id)
+        .bind(&reference)
+        .bind(money.amount)
+        .bind(&money.currency)
+        .bind(&req.email)
+        .bind(&metadata)
+        .bind(&provider_key)
+        .bind(&req.callback_url)
+        .bind(&descriptor)
+        .bind(merchant.id)
+        .bind(manual_capture)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| match e.as_database_error() {
+            Some(db) if db.is_unique_violation() => (StatusCode::CONFLICT, format!("Reference '{}' already exists", reference)),
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        })?;
+    }
+    let created = DomainEvent::Payment(PaymentEvent::Created { payment_id: PaymentId::from_string(&reference), amount: money.amount });
+    insert_outbox(&mut tx, Some(merchant.id), &created).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    count_payment_event(&created);
+
+    let charge = ChargeRequest {
+        reference,
+        amount: money,
+        email: req.email.clone(),
+        callback_url: req.callback_url.clone(),
+        metadata,
+        statement_descriptor: descriptor,
+        idempotency_key: provider_key,
+        capture: !manual_capture,
+    };
+
+    submit_charge(state, &gateways, id, charge).await.map(|response| (id, response))
+}
+
+/// `payment_method` for a charge paid from the customer's wallet balance rather than a provider.
+const WALLET_PAYMENT_METHOD: &str = "wallet";
+
+fn payment_reference(req: &InitiatePaymentRequest) -> Result<String, (StatusCode, String)> {
+    match &req.reference {
+        Some(reference) => validate_client_reference(reference),
+        None => Ok(format!("TXN-{}", Uuid::now_v7())),
+    }
+}
+
+/// Pays from the customer's wallet balance in the charge currency. The debit, the already
+/// succeeded transaction and its ledger postings commit together; without enough balance
+/// nothing is written and the request fails with 402.
+async fn create_wallet_payment(state: &AppState, merchant: Merchant, req: &InitiatePaymentRequest) -> Result<(Uuid, InitiatePaymentResponse), ApiError> {
+    let Some(customer_id) = req.customer_id else {
+        return Err((StatusCode::BAD_REQUEST, "customer_id is required for wallet payments".to_string()).into());
+    };
+    if req.provider.is_some() || req.payment_method_id.is_some() {
+        return Err((StatusCode::BAD_REQUEST, "Wallet payments don't take a provider or payment_method_id".to_string()).into());
+    }
+    let reference = payment_reference(req)?;
+    req.amount.ensure_positive()?;
+    req.amount.ensure_within(&state.config.amount_limits)?;
+    let money = req.amount.to_money_in(&state.config.currency_policy)?;
+    let metadata = payment_metadata(req)?;
+
+    let mut tx = state.db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let (wallet_id,): (Uuid,) = sqlx::query_as(
+        "SELECT id FROM wallets WHERE customer_id = $1 AND merchant_id = $2 AND status = 'active' ORDER BY created_at, id LIMIT 1 FOR UPDATE"
+    )
+    .bind(customer_id)
+    .bind(merchant.id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .ok_or((StatusCode::NOT_FOUND, "Wallet not found".to_string()))?;
+
+    let debited: Option<(Decimal,)> = sqlx::query_as(
+        r#"UPDATE wallet_balances SET balance = balance - $1, updated_at = NOW()
+           WHERE wallet_id = $2 AND currency = $3 AND balance - held >= $1 RETURNING balance"#
+    )
+    .bind(money.amount)
+    .bind(wallet_id)
+    .bind(&money.currency)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let Some((balance_after,)) = debited else {
+        let available = fetch_available(&mut *tx, wallet_id, &money.currency).await?;
+        ensure_sufficient(&Money::new(available, &money.currency), &money)?;
+        return Err(PaymentError::InsufficientFunds("balance changed during payment".into()).into());
+    };
+
+    let id = Uuid::now_v7();
+    sqlx::query(
+        r#"INSERT INTO transactions (id, reference, amount, currency, status, transaction_type, payment_method, provider,
+                                     customer_id, customer_email, metadata, merchant_id, created_at, updated_at, completed_at)
+           VALUES ($1, $2, $3, $4, 'succeeded', 'payment', $5, $5, $6, $7, $8, $9, NOW(), NOW(), NOW())"#
+    )
+    .bind(id)
+    .bind(&reference)
+    .bind(money.amount)
+    .bind(&money.currency)
+    .bind(WALLET_PAYMENT_METHOD)
+    .bind(customer_id)
+    .bind(&req.email)
+    .bind(&metadata)
+    .bind(merchant.id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| match e.as_database_error() {
+        Some(db) if db.is_unique_violation() => (StatusCode::CONFLICT, format!("Reference '{}' already exists", reference)),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    })?;
+    sqlx::query(
+        r#"INSERT INTO wallet_transactions (id, wallet_id, amount, currency, balance_after, transaction_type, description, created_at)
+           VALUES ($1, $2, $3, $4, $5, 'payment', $6, NOW())"#
+    )
+    .bind(Uuid::now_v7())
+    .bind(wallet_id)
+    .bind(-money.amount)
+    .bind(&money.currency)
+    .bind(balance_after)
+    .bind(&reference)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let entries = ledger::posting(&ledger::wallet_account(wallet_id), ledger::MERCHANT_REVENUE, &money);
+    record_ledger(&mut tx, &reference, &entries).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let payment_id = PaymentId::from_string(&reference);
+    let events = [PaymentEvent::Created { payment_id: payment_id.clone(), amount: money.amount }, PaymentEvent::Succeeded { payment_id }].map(DomainEvent::Payment);
+    for event in &events {
+        insert_outbox(&mut tx, Some(merchant.id), event).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    }
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    events.iter().for_each(count_payment_event);
+
+    Ok((id, InitiatePaymentResponse {
+        reference,
+        authorization_url: None,
+        next_action: None,
+        status: TransactionStatus::Succeeded.to_string(),
+        checkout_token: None,
+        checkout_expires_at: None,
+    }))
+}
+
+/// Sends a charge to the first of `gateways` and records the outcome on transaction `id`.
+/// A provider that is down hands the charge to the next one; the provider that answers is
+/// the one recorded. Provider idempotency keys are per provider, so each may see the same key.
+async fn submit_charge(state: &AppState, gateways: &[Arc<dyn PaymentGateway>], id: Uuid, charge: ChargeRequest) -> Result<InitiatePaymentResponse, ApiError> {
+    let mut remaining = gateways.iter();
+    let mut gateway = remaining.next().ok_or((StatusCode::INTERNAL_SERVER_ERROR, "No provider to charge".to_string()))?;
+    let response = loop {
+        let e = match timed(gateway.name(), "charge", gateway.charge(&charge)).await {
+            Ok(response) => break response,
+            Err(e) => e,
+        };
+        if fails_over(&e) {
+            if let Some(next) = remaining.next() {
+                tracing::warn!(reference = %charge.reference, from = gateway.name(), to = next.name(), "Provider unavailable, failing over: {}", e);
+                gateway = next;
+                continue;
+            }
+        }
+        let failed = sqlx::query("UPDATE transactions SET status = $1, provider = $2, updated_at = NOW() WHERE id = $3 AND status = ANY($4)")
+            .bind(TransactionStatus::Failed.as_str())
+            .bind(gateway.name())
+            .bind(id)
+            .bind(sources_of(TransactionStatus::Failed))
+            .execute(&state.db)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        if failed.rows_affected() == 0 {
+            tracing::warn!(reference = %charge.reference, "Charge failed but the transaction had already moved on");
+        } else {
+            // Refused outright: there is no payment.failed event to count it by
+            metrics::counter!("payments_failed_total").increment(1);
+        }
+        tracing::warn!(reference = %charge.reference, "Charge failed: {}", e);
+        return Err(charge_failure_response(&state.config, &e));
+    };
+
+    let result = &response.result;
+    let (status, authorization_url, next_action) = match result {
+        ChargeResult::Checkout { authorization_url, .. } => (TransactionStatus::Pending, Some(authorization_url.clone()), None),
+        ChargeResult::RequiresAction { next_action, .. } => (TransactionStatus::RequiresAction, None, Some(next_action.clone())),
+        ChargeResult::Succeeded { .. } => (TransactionStatus::Succeeded, None, None),
+        ChargeResult::Authorized { .. } => (TransactionStatus::Authorized, None, None),
+    };
+    if !charge.capture && status == TransactionStatus::Succeeded {
+        tracing::warn!(reference = %charge.reference, provider = gateway.name(), "Provider captured a charge sent for authorization only");
+    }
+    // The charge was made from `pending`; a checkout simply leaves it there
+    if status != TransactionStatus::Pending {
+        TransactionStatus::Pending.transition_to(status)?;
+    }
+
+    let mut tx = state.db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let updated: Option<Option<Uuid>> = sqlx::query_scalar(
+        r#"UPDATE transactions
+           SET status = $1, provider = $2, provider_reference = $3, updated_at = NOW(),
+               completed_at = CASE WHEN $1 = 'succeeded' THEN NOW() ELSE completed_at END,
+               avs_result = $4, cvv_result = $5, network_response_code = $6,
+               provider_raw_response = COALESCE($7, provider_raw_response), next_action = $9,
+               authorized_amount = CASE WHEN $1 = 'authorized' THEN amount ELSE authorized_amount END,
+               authorization_expires_at = CASE WHEN $1 = 'authorized' THEN $10 ELSE authorization_expires_at END
+           WHERE id = $8 AND status = 'pending'
+           RETURNING merchant_id"#
+    )
+    .bind(status.as_str())
+    .bind(gateway.name())
+    .bind(result.provider_reference())
+    .bind(response.checks.avs_result.map(|r| r.as_str()))
+    .bind(response.checks.cvv_result.map(|r| r.as_str()))
+    .bind(&response.checks.network_response_code)
+    .bind(response.raw_response.as_ref().map(redact_raw_response))
+    .bind(id)
+    .bind(next_action.as_ref().map(sqlx::types::Json))
+    .bind(Utc::now() + chrono::Duration::seconds(state.config.authorization_ttl_secs))
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let Some(merchant_id) = updated else {
+        return Err((StatusCode::CONFLICT, format!("Transaction '{}' changed status while the charge was in flight", charge.reference)).into());
+    };
+    let succeeded = (status == TransactionStatus::Succeeded)
+        .then(|| DomainEvent::Payment(PaymentEvent::Succeeded { payment_id: PaymentId::from_string(&charge.reference) }));
+    if let Some(succeeded) = &succeeded {
+        insert_outbox(&mut tx, merchant_id, succeeded).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    }
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    succeeded.iter().for_each(count_payment_event);
+
+    let checkout = authorization_url.as_ref().and_then(|_| checkout_link(&state.config, &charge.reference, Utc::now()));
+    let (checkout_token, checkout_expires_at) = checkout.unzip();
+    Ok(InitiatePaymentResponse {
+        reference: charge.reference,
+        authorization_url,
+        next_action,
+        status: status.to_string(),
+        checkout_token,
+        checkout_expires_at,
+    })
+}
+
+/// A checkout token for `reference` good for the configured time from `now`.
+fn checkout_link(config: &Config, reference: &str, now: DateTime<Utc>) -> Option<(String, DateTime<Utc>)> {
+    let secret = config.checkout_signing_secret.as_ref()?;
+    let expires_at = now + chrono::Duration::seconds(config.checkout_link_ttl_secs);
+    Some((checkout::sign(secret.as_bytes(), reference, expires_at.timestamp()), expires_at))
+}
+
+/// Public: the token itself is the credential. A tampered or malformed token is a 400 and
+/// an expired one a 410, so a checkout page can offer to start again.
+async fn get_checkout(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> Result<Json<CheckoutContext>, (StatusCode, String)> {
+    let secret = state.config.checkout_signing_secret.as_ref()
+        .ok_or((StatusCode::NOT_FOUND, "Checkout links are not enabled".to_string()))?;
+    let (reference, expires_at) = checkout::verify(secret.as_bytes(), &token, Utc::now().timestamp()).map_err(|e| match e {
+        checkout::TokenError::Expired => (StatusCode::GONE, e.to_string()),
+        checkout::TokenError::Malformed | checkout::TokenError::BadSignature => (StatusCode::BAD_REQUEST, e.to_string()),
+    })?;
+    let txn = sqlx::query_as::<_, Transaction>("SELECT * FROM transactions WHERE reference = $1")
+        .bind(&reference)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Payment not found".to_string()))?;
+    Ok(Json(CheckoutContext {
+        reference: txn.reference,
+        amount: txn.amount,
+        currency: txn.currency,
+        status: txn.status,
+        expires_at: DateTime::from_timestamp(expires_at, 0).unwrap_or_default(),
+    }))
+}
+
+/// Maps a gateway error to a status and a stable code. Provider detail stays in the logs.
+fn charge_failure_response(config: &Config, error: &PaymentError) -> ApiError {
+    let failure = classify(error);
+    let status = match failure.class {
+        FailureClass::Declined => config.decline_status,
+        FailureClass::CustomerActionable => StatusCode::UNPROCESSABLE_ENTITY,
+        FailureClass::Transient => StatusCode::SERVICE_UNAVAILABLE,
+        FailureClass::Internal => StatusCode::BAD_GATEWAY,
+    };
+    ApiError { status, code: failure.code.to_string(), message: failure.message.to_string(), decline_code: failure.decline_code }
+}
+
+#[derive(sqlx::FromRow)]
+struct RetryableCharge {
+    id: Uuid,
+    reference: String,
+    amount: Decimal,
+    currency: String,
+    customer_email: Option<String>,
+    metadata: serde_json::Value,
+    provider_idempotency_key: Option<String>,
+    callback_url: Option<String>,
+    statement_descriptor: Option<String>,
+    provider: Option<String>,
+    manual_capture: bool,
+}
+
+/// Resends a failed charge. The original provider idempotency key is reused, so if the
+/// first attempt did reach the provider it is not charged twice.
+async fn retry_payment(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(reference): Path<String>,
+) -> Result<Json<InitiatePaymentResponse>, ApiError> {
+    let row = sqlx::query_as::<_, RetryableCharge>(
+        r#"UPDATE transactions SET status = $2, updated_at = NOW()
+           WHERE reference = $1 AND status = ANY($3) AND transaction_type = 'payment' AND merchant_id = $4
+           RETURNING id, reference, amount, currency, customer_email, metadata, provider_idempotency_key, callback_url, statement_descriptor, provider, manual_capture"#
+    )
+    .bind(&reference)
+    .bind(TransactionStatus::Pending.as_str())
+    // A scheduled payment is started by the scheduler, not retried early
+    .bind(sources_of(TransactionStatus::Pending).into_iter().filter(|s| *s != TransactionStatus::Scheduled.as_str()).collect::<Vec<_>>())
+    .bind(merchant.id)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .ok_or((StatusCode::CONFLICT, format!("No failed payment with reference '{}' to retry", reference)))?;
+    // The retry goes to the provider that holds the idempotency key
+    let gateway = state.gateway_named(row.provider.as_deref())
+        .ok_or_else(|| (StatusCode::UNPROCESSABLE_ENTITY, format!("Provider '{}' is no longer configured", row.provider.as_deref().unwrap_or_default())))?;
+
+    let charge = ChargeRequest {
+        reference: row.reference,
+        amount: Money::new(row.amount, &row.currency),
+        email: row.customer_email.unwrap_or_default(),
+        callback_url: row.callback_url,
+        metadata: row.metadata,
+        statement_descriptor: row.statement_descriptor,
+        // Rows from before keys were stored get one now; later retries reuse it.
+        idempotency_key: row.provider_idempotency_key.unwrap_or_else(|| format!("chg_{}", row.id.simple())),
+        capture: !row.manual_capture,
+    };
+    sqlx::query("UPDATE transactions SET provider_idempotency_key = $1 WHERE id = $2 AND provider_idempotency_key IS NULL")
+        .bind(&charge.idempotency_key)
+        .bind(row.id)
+        .execute(&state.db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    submit_charge(&state, std::slice::from_ref(&gateway), row.id, charge).await.map(Json)
+}
+
+/// Client references are 4-100 characters of letters, digits, `-`, `_` or `.`.
+fn validate_client_reference(reference: &str) -> Result<String, (StatusCode, String)> {
+    let valid = (4..=100).contains(&reference.len())
+        && reference.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err((StatusCode::BAD_REQUEST, "reference must be 4-100 characters of letters, digits, '-', '_' or '.'".to_string()));
+    }
+    Ok(reference.to_string())
+}
+
+async fn verify_payment(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Json(req): Json<VerifyPaymentRequest>,
+) -> Result<Json<Transaction>, ApiError> {
+    let txn = sqlx::query_as::<_, Transaction>(
+        "SELECT * FROM transactions WHERE reference = $1 AND merchant_id = $2"
+    )
+    .bind(&req.reference)
+    .bind(merchant.id)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .ok_or_else(|| PaymentError::PaymentNotFound(req.reference.clone()))?;
+
+    // Only open charges made through a configured gateway can change on the provider's side
+    let open = matches!(txn.status.parse(), Ok(TransactionStatus::Pending | TransactionStatus::RequiresAction | TransactionStatus::Processing));
+    let gateway = txn.provider.as_deref().and_then(|name| state.gateway_named(Some(name)));
+    let Some(gateway) = gateway.filter(|_| open) else { return Ok(Json(txn)) };
+    let Some(verification) = timed(gateway.name(), "verify", gateway.verify(&txn.reference)).await.map_err(|e| charge_failure_response(&state.config, &e))? else {
+        return Ok(Json(txn));
+    };
+    apply_verification(&state, &txn, &verification).await?;
+    Ok(Json(reload_transaction(&state.db, txn.id).await?))
+}
+
+/// Completes a charge left in `requires_action` once the customer has authenticated
+/// (e.g. passed 3DS). The provider says where it stands now: settled, still waiting on
+/// the customer with a fresh `next_action`, or authenticated and processing, in which
+/// case its webhook settles it.
+async fn confirm_payment(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Json(req): Json<ConfirmPaymentRequest>,
+) -> Result<Json<Transaction>, ApiError> {
+    let txn = sqlx::query_as::<_, Transaction>(
+        "SELECT * FROM transactions WHERE reference = $1 AND merchant_id = $2"
+    )
+    .bind(&req.reference)
+    .bind(merchant.id)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .ok_or_else(|| PaymentError::PaymentNotFound(req.reference.clone()))?;
+    if txn.status != TransactionStatus::RequiresAction.as_str() {
+        return Err((StatusCode::CONFLICT, format!("Transaction '{}' is {}, not awaiting customer action", txn.reference, txn.status)).into());
+    }
+
+    let provider = txn.provider.as_deref().unwrap_or_default();
+    let gateway = state.gateway_named(Some(provider))
+        .ok_or_else(|| (StatusCode::UNPROCESSABLE_ENTITY, format!("Provider '{}' is not configured", provider)))?;
+    let verification = timed(gateway.name(), "verify", gateway.verify(&txn.reference)).await.map_err(|e| charge_failure_response(&state.config, &e))?
+        .ok_or_else(|| (StatusCode::UNPROCESSABLE_ENTITY, format!("Provider '{}' can't confirm charges", provider)))?;
+    if verification.status == VerifiedStatus::Pending {
+        sqlx::query("UPDATE transactions SET status = $1, next_action = NULL, updated_at = NOW() WHERE id = $2 AND status = ANY($3)")
+            .bind(TransactionStatus::Processing.as_str())
+            .bind(txn.id)
+            .bind(sources_of(TransactionStatus::Processing))
+            .execute(&state.db)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    } else {
+        apply_verification(&state, &txn, &verification).await?;
+    }
+    Ok(Json(reload_transaction(&state.db, txn.id).await?))
+}
+
+/// Moves an open charge to where the provider says it stands. One that still needs the
+/// customer to authenticate keeps the provider's latest `next_action`.
+async fn apply_verification(state: &AppState, txn: &Transaction, verification: &Verification) -> Result<(), (StatusCode, String)> {
+    if let VerifiedStatus::RequiresAction(next_action) = &verification.status {
+        sqlx::query("UPDATE transactions SET status = $1, next_action = $2, updated_at = NOW() WHERE id = $3 AND status = ANY($4)")
+            .bind(TransactionStatus::RequiresAction.as_str())
+            .bind(sqlx::types::Json(next_action))
+            .bind(txn.id)
+            .bind(vec![TransactionStatus::Pending.as_str(), TransactionStatus::RequiresAction.as_str()])
+            .execute(&state.db)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        return Ok(());
+    }
+    let Some(outcome) = verification.status.transaction_status() else { return Ok(()) };
+
+    let settled = settle_charge(&state.db, &txn.reference, outcome).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if settled {
+        if let Some(raw) = &verification.raw_response {
+            sqlx::query("UPDATE transactions SET provider_raw_response = $1 WHERE id = $2")
+                .bind(redact_raw_response(raw))
+                .bind(txn.id)
+                .execute(&state.db)
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            if outcome != TransactionStatus::Cancelled { apply_subscription_charge(state, raw, outcome).await; }
+        }
+    }
+    Ok(())
+}
+
+async fn reload_transaction(db: &sqlx::PgPool, id: Uuid) -> Result<Transaction, (StatusCode, String)> {
+    sqlx::query_as::<_, Transaction>("SELECT * FROM transactions WHERE id = $1")
+        .bind(id)
+        .fetch_one(db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// Webhook bodies larger than this are refused with a 413 before they are read in full.
+const MAX_WEBHOOK_BODY_BYTES: usize = 256 * 1024;
+
+/// A webhook's body, exactly as sent, for the signature check. Only its size is checked
+/// here, as a 413; it is parsed once the sender is authenticated.
+struct WebhookBody(Bytes);
+
+#[async_trait::async_trait]
+impl<S: Send + Sync> axum::extract::FromRequest<S> for WebhookBody {
+    type Rejection = StatusCode;
+
+    async fn from_request(req: Request, _state: &S) -> Result<Self, Self::Rejection> {
+        let declared = req.headers().get(axum::http::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<usize>().ok());
+        if declared.is_some_and(|len| len > MAX_WEBHOOK_BODY_BYTES) {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        // A body without a declared length fails here once it passes the limit
+        let raw = axum::body::to_bytes(req.into_body(), MAX_WEBHOOK_BODY_BYTES).await
+            .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE)?;
+        Ok(Self(raw))
+    }
+}
+
+/// Paystack's webhooks, signed with an HMAC-SHA512 of the body keyed by the secret key.
+async fn webhook_handler(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    WebhookBody(raw): WebhookBody,
+) -> impl IntoResponse {
+    if let Err(status) = check_webhook_source(&state, "paystack", connect_info, &headers) { return status; }
+
+    // The signature covers the exact bytes sent, so it is checked before any parsing
+    let Some(secret) = &state.config.paystack_secret else {
+        tracing::warn!("Rejecting webhook: PAYSTACK_SECRET_KEY is not configured, so it cannot be authenticated");
+        return StatusCode::UNAUTHORIZED;
+    };
+    let signature = headers.get(paystack::SIGNATURE_HEADER).and_then(|v| v.to_str().ok()).unwrap_or_default();
+    if !paystack::verify_webhook_signature(secret, &raw, signature) {
+        tracing::warn!("Rejecting webhook with missing or invalid signature");
+        return StatusCode::UNAUTHORIZED;
+    }
+    dispatch_webhook(&state, "paystack", raw).await
+}
+
+/// Flutterwave's webhooks, which carry the dashboard's secret hash in `verif-hash`.
+async fn flutterwave_webhook_handler(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    WebhookBody(raw): WebhookBody,
+) -> impl IntoResponse {
+    if let Err(status) = check_webhook_source(&state, "flutterwave", connect_info, &headers) { return status; }
+
+    let Some(secret_hash) = &state.config.flutterwave_secret_hash else {
+        tracing::warn!("Rejecting Flutterwave webhook: FLUTTERWAVE_SECRET_HASH is not configured, so it cannot be authenticated");
+        return StatusCode::UNAUTHORIZED;
+    };
+    let received = headers.get(flutterwave::SIGNATURE_HEADER).and_then(|v| v.to_str().ok()).unwrap_or_default();
+    if !flutterwave::verify_webhook_hash(secret_hash, received) {
+        tracing::warn!("Rejecting Flutterwave webhook with missing or invalid verif-hash");
+        return StatusCode::UNAUTHORIZED;
+    }
+    dispatch_webhook(&state, "flutterwave", raw).await
+}
+
+/// Refuses a webhook claiming to be from `provider` that comes from outside the
+/// provider's allowlisted ranges, when it has any.
+fn check_webhook_source(state: &AppState, provider: &str, connect_info: Option<ConnectInfo<SocketAddr>>, headers: &HeaderMap) -> Result<(), StatusCode> {
+    if !state.config.webhook_allowlist.is_enabled(provider) { return Ok(()); }
+    let Some(peer) = connect_info.map(|ConnectInfo(addr)| addr.ip()) else {
+        tracing::warn!("Rejecting {} webhook: peer address unavailable", provider);
+        return Err(StatusCode::FORBIDDEN);
+    };
+    let client = webhook_client_ip(&state.config.webhook_allowlist, peer, headers);
+    if !state.config.webhook_allowlist.allows(provider, client) {
+        tracing::warn!(%client, "Rejecting {} webhook from disallowed source", provider);
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(())
+}
+
+/// Applies an authenticated webhook from `sender`, whose format the body is in. A body
+/// that isn't JSON is a 400.
+async fn dispatch_webhook(state: &AppState, sender: &str, raw: Bytes) -> StatusCode {
+    let Ok(payload) = serde_json::from_slice::<serde_json::Value>(&raw) else {
+        tracing::warn!("Rejecting {} webhook: body is not JSON", sender);
+        return StatusCode::BAD_REQUEST;
+    };
+    let event = webhook_event::parse(sender, &payload);
+    tracing::info!(?event, "Webhook received");
+
+    if let WebhookEvent::Unknown { event } = &event {
+        // Acknowledged all the same, so the provider stops retrying it
+        tracing::info!("Ignoring unhandled webhook {:?}", event);
+        return StatusCode::OK;
+    }
+    let event_id = webhook_event::event_id(&payload, &raw);
+    match apply_webhook(&state.db, state.config.dunning, sender, &event_id, &payload, event).await {
+        Ok(true) => StatusCode::OK,
+        Ok(false) => {
+            tracing::info!(%event_id, "Webhook already processed");
+            StatusCode::OK
+        }
+        // Nothing was applied or recorded, so the provider's retry gets a clean attempt
+        Err(e) => {
+            tracing::warn!(%event_id, "Failed to apply webhook: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Applies a webhook's effect at most once per event id. The id is recorded in the same
+/// transaction as the effect; returns false, changing nothing, when it already was.
+async fn apply_webhook(db: &sqlx::PgPool, dunning: DunningPolicy, provider: &str, event_id: &str, payload: &serde_json::Value, event: WebhookEvent) -> Result<bool, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let recorded = sqlx::query(
+        r#"INSERT INTO processed_webhook_events (provider, event_id, event_type, processed_at)
+           VALUES ($1, $2, $3, NOW()) ON CONFLICT DO NOTHING"#
+    )
+    .bind(provider)
+    .bind(event_id)
+    .bind(payload["event"].as_str().unwrap_or_default())
+    .execute(&mut *tx)
+    .await?;
+    if recorded.rows_affected() == 0 { return Ok(false); }
+
+    if let Some(reference) = event.reference() {
+        store_provider_response(&mut *tx, reference, payload).await?;
+    }
+    // Counted once the webhook's changes have committed
+    let mut settled_charge = None;
+    let mut settled_refund = None;
+    match event {
+        WebhookEvent::ChargeSucceeded { reference, amount, subscription_id } => {
+            if on_charge_outcome(&mut tx, &reference, TransactionStatus::Succeeded, subscription_id, amount, dunning).await? {
+                settled_charge = settled_charge_event(&reference, TransactionStatus::Succeeded);
+            }
+        }
+        WebhookEvent::ChargeFailed { reference, subscription_id } => {
+            if on_charge_outcome(&mut tx, &reference, TransactionStatus::Failed, subscription_id, None, dunning).await? {
+                settled_charge = settled_charge_event(&reference, TransactionStatus::Failed);
+            }
+        }
+        WebhookEvent::RefundProcessed { reference, amount } => settled_refund = on_refund_processed(&mut tx, &reference, amount).await?,
+        WebhookEvent::SubscriptionRenewed { subscription_id, amount } => {
+            record_subscription_charge(&mut tx, subscription_id, TransactionStatus::Succeeded, amount, dunning).await?
+        }
+        WebhookEvent::DisputeOpened { reference, dispute_id, amount, reason, evidence_due_by } => {
+            on_dispute_opened(&mut tx, &reference, &dispute_id, amount, reason.as_deref(), evidence_due_by).await?
+        }
+        WebhookEvent::DisputeResolved { dispute_id, won } => on_dispute_resolved(&mut tx, &dispute_id, won).await?,
+        WebhookEvent::Unknown { .. } => {}
+    }
+    tx.commit().await?;
+    settled_charge.iter().for_each(count_payment_event);
+    if let Some(amount) = &settled_refund { count_settled_refund(RefundOutcome::Succeeded, amount); }
+    Ok(true)
+}
+
+/// Settles the charge and, only if that changed it, feeds the subscription it paid for.
+/// Returns whether it was settled.
+async fn on_charge_outcome(
+    conn: &mut sqlx::PgConnection,
+    reference: &str,
+    outcome: TransactionStatus,
+    subscription_id: Option<Uuid>,
+    amount: Option<Money>,
+    dunning: DunningPolicy,
+) -> Result<bool, sqlx::Error> {
+    if !settle_charge_in(&mut *conn, reference, outcome).await? { return Ok(false); }
+    if let Some(subscription_id) = subscription_id {
+        record_subscription_charge(conn, subscription_id, outcome, amount, dunning).await?;
+    }
+    Ok(true)
+}
+
+/// Opens the provider's dispute on the charge, for the whole charge when it doesn't say how much.
+async fn on_dispute_opened(
+    conn: &mut sqlx::PgConnection,
+    reference: &str,
+    dispute_id: &str,
+    amount: Option<Money>,
+    reason: Option<&str>,
+    evidence_due_by: Option<DateTime<Utc>>,
+) -> Result<(), sqlx::Error> {
+    let Some(txn) = sqlx::query_as::<_, Transaction>("SELECT * FROM transactions WHERE reference = $1 FOR UPDATE")
+        .bind(reference)
+        .fetch_optional(&mut *conn)
+        .await? else {
+        tracing::warn!("Dispute {} is for unknown charge {}", dispute_id, reference);
+        return Ok(());
+    };
+    let amount = amount.map(|m| m.amount).filter(|a| *a > Decimal::ZERO && *a <= txn.amount).unwrap_or(txn.amount);
+    if open_dispute_in(conn, &txn, Some(dispute_id), reason.unwrap_or("general"), amount, evidence_due_by).await?.is_none() {
+        tracing::warn!("Dispute {} ignored: charge {} is {}", dispute_id, reference, txn.status);
+    }
+    Ok(())
+}
+
+/// Closes the provider's dispute, unless it is already closed or was never opened here.
+async fn on_dispute_resolved(conn: &mut sqlx::PgConnection, dispute_id: &str, won: bool) -> Result<(), sqlx::Error> {
+    let dispute = sqlx::query_as::<_, Dispute>("SELECT * FROM disputes WHERE provider_dispute_id = $1 FOR UPDATE")
+        .bind(dispute_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+    let status = dispute.as_ref().and_then(|d| d.status.parse::<DisputeStatus>().ok());
+    match (dispute, status) {
+        (Some(dispute), Some(status)) if !status.is_closed() => {
+            close_dispute_in(conn, &dispute, if won { DisputeOutcome::Won } else { DisputeOutcome::Lost }).await?;
+        }
+        _ => tracing::info!("No open dispute {} to resolve", dispute_id),
+    }
+    Ok(())
+}
+
+/// Settles the oldest pending refund of the charge as succeeded, matching the amount when
+/// the provider sends one. Returns the amount settled, if any.
+async fn on_refund_processed(conn: &mut sqlx::PgConnection, reference: &str, amount: Option<Money>) -> Result<Option<Money>, sqlx::Error> {
+    let pending: Option<Uuid> = sqlx::query_scalar(
+        r#"SELECT r.id FROM refunds r JOIN transactions t ON t.id = r.transaction_id
+           WHERE t.reference = $1 AND r.status = 'pending' AND ($2::DECIMAL IS NULL OR r.amount = $2)
+           ORDER BY r.created_at LIMIT 1
+           FOR UPDATE OF r"#
+    )
+    .bind(reference)
+    .bind(amount.map(|m| m.amount))
+    .fetch_optional(&mut *conn)
+    .await?;
+    match pending {
+        Some(refund_id) => settle_refund_in(conn, refund_id, RefundOutcome::Succeeded).await,
+        None => {
+            tracing::info!("No pending refund of {} matches the provider's refund", reference);
+            Ok(None)
+        }
+    }
+}
+
+fn webhook_client_ip(allowlist: &WebhookAllowlist, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    allowlist.client_ip(peer, header("forwarded"), header("x-forwarded-for"))
+}
+
+/// Moves an open charge to the final status the provider reported. Returns
+/// false when the transaction was already resolved, so replayed webhooks are no-ops.
+async fn settle_charge(db: &sqlx::PgPool, reference: &str, outcome: TransactionStatus) -> Result<bool, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let settled = settle_charge_in(&mut tx, reference, outcome).await?;
+    tx.commit().await?;
+    if settled { settled_charge_event(reference, outcome).iter().for_each(count_payment_event); }
+    Ok(settled)
+}
+
+/// `settle_charge` inside the caller's transaction.
+async fn settle_charge_in(conn: &mut sqlx::PgConnection, reference: &str, outcome: TransactionStatus) -> Result<bool, sqlx::Error> {
+    let settled: Option<Option<Uuid>> = sqlx::query_scalar(
+        r#"UPDATE transactions SET status = $1, updated_at = NOW(), next_action = NULL,
+                  completed_at = CASE WHEN $1 = 'succeeded' THEN NOW() ELSE completed_at END
+           WHERE reference = $2 AND status = ANY($3)
+           RETURNING merchant_id"#
+    )
+    .bind(outcome.as_str())
+    .bind(reference)
+    // A disputed charge only leaves `disputed` when its dispute closes, a scheduled one
+    // hasn't reached a provider to be settled by, and an authorized one is captured or voided
+    .bind(sources_of(outcome).into_iter()
+        .filter(|s| ![TransactionStatus::Disputed.as_str(), TransactionStatus::Scheduled.as_str(), TransactionStatus::Authorized.as_str()].contains(s))
+        .collect::<Vec<_>>())
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some(merchant_id) = settled else { return Ok(false) };
+
+    if let Some(event) = settled_charge_event(reference, outcome) {
+        insert_outbox(conn, merchant_id, &event).await?;
+    }
+    Ok(true)
+}
+
+/// The payment event for a charge settled as `outcome`, if it has one.
+fn settled_charge_event(reference: &str, outcome: TransactionStatus) -> Option<DomainEvent> {
+    let payment_id = PaymentId::from_string(reference);
+    let event = match outcome {
+        TransactionStatus::Succeeded => PaymentEvent::Succeeded { payment_id },
+        TransactionStatus::Failed => PaymentEvent::Failed { payment_id, reason: "declined by provider".to_string() },
+        _ => return None,
+    };
+    Some(DomainEvent::Payment(event))
+}
+
+/// The statuses that may legally move to `next`, for `status = ANY(..)` update guards.
+fn sources_of(next: TransactionStatus) -> Vec<&'static str> {
+    next.predecessors().iter().map(TransactionStatus::as_str).collect()
+}
+
+/// Charges tagged with `metadata.subscription_id` feed that subscription's lifetime metrics.
+async fn apply_subscription_charge(state: &AppState, payload: &serde_json::Value, outcome: TransactionStatus) {
+    let data = &payload["data"];
+    let Some(subscription_id) = data["metadata"]["subscription_id"].as_str().and_then(|s| Uuid::parse_str(s).ok()) else { return };
+    let currency = data["currency"].as_str().unwrap_or(DEFAULT_CURRENCY);
+    let paid = data["amount"].as_i64().and_then(|minor| Money::from_minor_units(minor, currency).ok());
+    let recorded = async {
+        let mut tx = state.db.begin().await?;
+        record_subscription_charge(&mut tx, subscription_id, outcome, paid, state.config.dunning).await?;
+        tx.commit().await
+    };
+    if let Err(e) = recorded.await {
+        tracing::warn!("Failed to update subscription {} metrics: {}", subscription_id, e);
+    }
+}
+
+/// Applies a charge the provider settled for the subscription through the aggregate: a
+/// successful one pays and renews its period, a failed one counts as a dunning attempt.
+/// A subscription that can no longer be billed, e.g. one already cancelled, is left as is.
+async fn record_subscription_charge(
+    conn: &mut sqlx::PgConnection,
+    subscription_id: Uuid,
+    outcome: TransactionStatus,
+    paid: Option<Money>,
+    dunning: DunningPolicy,
+) -> Result<(), sqlx::Error> {
+    let Some(row) = sqlx::query_as::<_, Subscription>("SELECT * FROM subscriptions WHERE id = $1 FOR UPDATE")
+        .bind(subscription_id)
+        .fetch_optional(&mut *conn)
+        .await? else {
+        tracing::warn!("Charge is for unknown subscription {}", subscription_id);
+        return Ok(());
+    };
+    let record = match SubscriptionRecord::try_from(&row) {
+        Ok(record) => record,
+        Err(e) => {
+            tracing::warn!("Subscription {} charge ignored: {}", subscription_id, e);
+            return Ok(());
+        }
+    };
+    let mut subscription = SubscriptionAggregate::from_record(record, dunning);
+    let billed_as = subscription.status().clone();
+    let applied = if outcome == TransactionStatus::Succeeded {
+        let Some(paid) = paid else {
+            tracing::warn!("Subscription {} charge has no usable amount", subscription_id);
+            return Ok(());
+        };
+        subscription.pay_renewal(&paid)
+    } else {
+        let attempt = subscription.metrics().consecutive_failures + 1;
+        subscription.mark_payment_failed(attempt)
+    };
+    if let Err(e) = applied {
+        tracing::info!("Subscription {} charge ignored: {}", subscription_id, e);
+        return Ok(());
+    }
+    save_subscription_in(conn, &mut subscription, subscription_id, &billed_as, row.current_period_end).await?;
+    Ok(())
+}
+
+/// Persists a provider's raw response against the transaction, redacted and size-capped.
+async fn store_provider_response<'e>(db: impl sqlx::PgExecutor<'e>, reference: &str, raw: &serde_json::Value) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE transactions SET provider_raw_response = $1, updated_at = NOW() WHERE reference = $2")
+        .bind(redact_raw_response(raw))
+        .bind(reference)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+async fn list_transactions(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Query(params): Query<ListParams>,
+) -> Result<Json<PaginatedResponse<Transaction>>, (StatusCode, String)> {
+    page_transactions(&state.db, merchant, None, &params).await.map(Json)
+}
+
+/// Lists one customer's transactions, by customer id or, for transactions recorded
+/// without one, by `customer_email` (matched case-insensitively).
+async fn list_customer_transactions(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(customer): Path<String>,
+    Query(params): Query<ListParams>,
+) -> Result<Json<PaginatedResponse<Transaction>>, (StatusCode, String)> {
+    let customer = CustomerKey::parse(&customer)?;
+    page_transactions(&state.db, merchant, Some(&customer), &params).await.map(Json)
+}
+
+enum CustomerKey {
+    Id(Uuid),
+    Email(String),
+}
+
+impl CustomerKey {
+    fn parse(raw: &str) -> Result<Self, (StatusCode, String)> {
+        if let Ok(id) = Uuid::parse_str(raw) { return Ok(Self::Id(id)); }
+        if raw.contains('@') { return Ok(Self::Email(raw.to_lowercase())); }
+        Err((StatusCode::BAD_REQUEST, "Customer must be a customer id or email".to_string()))
+    }
+}
+
+async fn page_transactions(db: &sqlx::PgPool, merchant: Merchant, customer: Option<&CustomerKey>, params: &ListParams) -> Result<PaginatedResponse<Transaction>, (StatusCode, String)> {
+    let page = params.page.unwrap_or(1).max(1);
+    let per_page = params.per_page.unwrap_or(20).clamp(1, 100);
+    let cursor = params.cursor.as_deref().map(decode_cursor).transpose()?;
+
+    // Keyset pagination when a cursor is given, offset otherwise. One extra row tells
+    // whether there is a next page.
+    let mut query = sqlx::QueryBuilder::new("SELECT * FROM transactions WHERE merchant_id = ");
+    query.push_bind(merchant.id);
+    push_customer_filter(&mut query, customer);
+    push_transaction_filters(&mut query, params);
+    if let Some((created_at, id)) = cursor {
+        query.push(" AND (created_at, id) < (").push_bind(created_at).push(", ").push_bind(id).push(")");
+    }
+    query.push(" ORDER BY created_at DESC, id DESC LIMIT ").push_bind(per_page as i64 + 1);
+    if cursor.is_none() {
+        query.push(" OFFSET ").push_bind(((page - 1) * per_page) as i64);
+    }
+    let mut transactions = query.build_query_as::<Transaction>()
+        .fetch_all(db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let next_cursor = if transactions.len() > per_page as usize {
+        transactions.truncate(per_page as usize);
+        transactions.last().map(|t| encode_cursor(t.created_at, t.id))
+    } else {
+        None
+    };
+
+    let mut count = sqlx::QueryBuilder::new("SELECT COUNT(*) FROM transactions WHERE merchant_id = ");
+    count.push_bind(merchant.id);
+    push_customer_filter(&mut count, customer);
+    push_transaction_filters(&mut count, params);
+    let total: (i64,) = count.build_query_as()
+        .fetch_one(db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(PaginatedResponse { data: transactions, total: total.0, page, per_page, next_cursor })
+}
+
+/// `<created_at in microseconds>_<id>`: the sort key of the last row on a page.
+fn encode_cursor(created_at: DateTime<Utc>, id: Uuid) -> String {
+    format!("{}_{}", created_at.timestamp_micros(), id.simple())
+}
+
+fn decode_cursor(cursor: &str) -> Result<(DateTime<Utc>, Uuid), (StatusCode, String)> {
+    let invalid = || (StatusCode::BAD_REQUEST, "Invalid cursor".to_string());
+    let (micros, id) = cursor.split_once('_').ok_or_else(invalid)?;
+    let created_at = micros.parse().ok().and_then(DateTime::from_timestamp_micros).ok_or_else(invalid)?;
+    let id = Uuid::parse_str(id).map_err(|_| invalid())?;
+    Ok((created_at, id))
+}
+
+fn push_customer_filter(query: &mut sqlx::QueryBuilder<'_, sqlx::Postgres>, customer: Option<&CustomerKey>) {
+    match customer {
+        Some(CustomerKey::Id(id)) => { query.push(" AND customer_id = ").push_bind(*id); }
+        Some(CustomerKey::Email(email)) => { query.push(" AND LOWER(customer_email) = ").push_bind(email.clone()); }
+        None => {}
+    }
+}
+
+fn push_transaction_filters(query: &mut sqlx::QueryBuilder<'_, sqlx::Postgres>, params: &ListParams) {
+    if let Some(status) = &params.status {
+        query.push(" AND status = ").push_bind(status.clone());
+    }
+    if let Some(from) = params.from_date {
+        query.push(" AND created_at >= ").push_bind(from);
+    }
+    if let Some(to) = params.to_date {
+        query.push(" AND created_at <= ").push_bind(to);
+    }
+}
+
+async fn get_transaction(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Transaction>, ApiError> {
+    let txn = sqlx::query_as::<_, Transaction>("SELECT * FROM transactions WHERE id = $1 AND merchant_id = $2")
+        .bind(id)
+        .bind(merchant.id)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or_else(|| PaymentError::PaymentNotFound(id.to_string()))?;
+
+    Ok(Json(txn))
+}
+
+/// Cancels a charge the customer hasn't paid yet, or an authorization that wasn't
+/// captured. The provider is asked to void it first when it has an id for it, and a
+/// refusal leaves the charge as it was. Money that has already moved comes back through
+/// a refund instead.
+async fn void_transaction(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Transaction>, ApiError> {
+    let mut tx = state.db.begin().await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    // Held for the provider call, so a webhook can't settle the charge mid-void
+    let txn = sqlx::query_as::<_, Transaction>("SELECT * FROM transactions WHERE id = $1 AND merchant_id = $2 FOR UPDATE")
+        .bind(id)
+        .bind(merchant.id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or_else(|| PaymentError::PaymentNotFound(id.to_string()))?;
+
+    let current: TransactionStatus = txn.status.parse().map_err(|e: String| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    match current {
+        TransactionStatus::Pending | TransactionStatus::RequiresAction | TransactionStatus::Authorized => {}
+        TransactionStatus::Succeeded => return Err((StatusCode::CONFLICT, "Transaction has succeeded and can't be voided; refund it instead".to_string()).into()),
+        other => return Err(PaymentError::InvalidTransition { from: other.as_str().into(), to: TransactionStatus::Cancelled.as_str().into() }.into()),
+    }
+
+    if let Some(provider_reference) = &txn.provider_reference {
+        if let Some(gateway) = state.gateway_named(txn.provider.as_deref()) {
+            timed(gateway.name(), "void", gateway.void(provider_reference)).await.map_err(|e| {
+                tracing::warn!(transaction_id = %txn.id, "Provider refused void: {}", e);
+                charge_failure_response(&state.config, &e)
+            })?;
+        }
+    }
+
+    let voided = sqlx::query_as::<_, Transaction>(
+        "UPDATE transactions SET status = 'cancelled', next_action = NULL, updated_at = NOW() WHERE id = $1 RETURNING *"
+    )
+    .bind(txn.id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let cancelled = DomainEvent::Payment(PaymentEvent::Cancelled { payment_id: PaymentId::from_string(&voided.reference) });
+    insert_outbox(&mut tx, voided.merchant_id, &cancelled).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(voided))
+}
+
+/// Takes all or part of an authorization, once. The provider releases whatever isn't
+/// captured, and the charge's `amount` becomes what was taken, so refunds and disputes
+/// count against that.
+async fn capture_transaction(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<CaptureRequest>,
+) -> Result<Json<Transaction>, ApiError> {
+    req.validate().map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let mut tx = state.db.begin().await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    // Held for the provider call, so a second capture waits and then finds it succeeded
+    let txn = sqlx::query_as::<_, Transaction>("SELECT * FROM transactions WHERE id = $1 AND merchant_id = $2 FOR UPDATE")
+        .bind(id)
+        .bind(merchant.id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or_else(|| PaymentError::PaymentNotFound(id.to_string()))?;
+
+    if txn.status != TransactionStatus::Authorized.as_str() {
+        return Err((StatusCode::CONFLICT, format!("Transaction is {}; only an authorized one can be captured", txn.status)).into());
+    }
+    if txn.authorization_expires_at.is_some_and(|at| at <= Utc::now()) {
+        return Err((StatusCode::CONFLICT, "Authorization has expired".to_string()).into());
+    }
+    let amount = req.amount.map(|a| minor_to_decimal(a, &txn.currency)).transpose()
+        .map_err(payment_error_status)?
+        .unwrap_or(txn.amount);
+    if amount > txn.amount {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Can't capture {} of an authorization for {}", amount, txn.amount)).into());
+    }
+
+    let gateway = state.gateway_named(txn.provider.as_deref())
+        .ok_or_else(|| (StatusCode::UNPROCESSABLE_ENTITY, format!("Provider '{}' is no longer configured", txn.provider.as_deref().unwrap_or_default())))?;
+    let submission = CaptureSubmission {
+        charge_reference: txn.reference.clone(),
+        provider_reference: txn.provider_reference.clone(),
+        amount: Money::new(amount, &txn.currency),
+    };
+    timed(gateway.name(), "capture", gateway.capture(&submission)).await.map_err(|e| {
+        tracing::warn!(transaction_id = %txn.id, "Provider refused capture: {}", e);
+        charge_failure_response(&state.config, &e)
+    })?;
+
+    let captured = sqlx::query_as::<_, Transaction>(
+        "UPDATE transactions SET status = 'succeeded', amount = $2, completed_at = NOW(), updated_at = NOW() WHERE id = $1 RETURNING *"
+    )
+    .bind(txn.id)
+    .bind(amount)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let succeeded = DomainEvent::Payment(PaymentEvent::Succeeded { payment_id: PaymentId::from_string(&txn.reference) });
+    insert_outbox(&mut tx, txn.merchant_id, &succeeded).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    count_payment_event(&succeeded);
+    Ok(Json(captured))
+}
+
+async fn create_refund(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Json(req): Json<RefundRequest>,
+) -> Result<(StatusCode, Json<Refund>), (StatusCode, String)> {
+    let refund = refund_transaction(&state, merchant, &req).await?;
+    Ok((StatusCode::CREATED, Json(refund)))
+}
+
+/// Refunds each item in turn, each in its own transaction, so one refused item doesn't
+/// undo or stop the others. Items for the same charge count against its ceiling in order.
+async fn create_bulk_refunds(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Json(req): Json<BulkRefundRequest>,
+) -> Result<Json<Vec<BulkRefundResult>>, (StatusCode, String)> {
+    req.validate().map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let mut results = Vec::with_capacity(req.refunds.len());
+    for item in &req.refunds {
+        let result = match refund_transaction(&state, merchant, item).await {
+            Ok(refund) => BulkRefundResult { transaction_id: item.transaction_id, success: true, status: StatusCode::CREATED.as_u16(), refund: Some(refund), error: None },
+            Err((status, error)) => BulkRefundResult { transaction_id: item.transaction_id, success: false, status: status.as_u16(), refund: None, error: Some(error) },
+        };
+        results.push(result);
+    }
+    Ok(Json(results))
+}
+
+/// Records a refund against one of the merchant's charges and sends it to the provider.
+async fn refund_transaction(state: &AppState, merchant: Merchant, req: &RefundRequest) -> Result<Refund, (StatusCode, String)> {
+    req.validate().map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    let mut tx = state.db.begin().await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    // Lock the transaction row so concurrent refunds serialize on the ceiling check. Another
+    // merchant's transaction is reported as missing, so ids can't be probed.
+    let txn = sqlx::query_as::<_, Transaction>("SELECT * FROM transactions WHERE id = $1 AND merchant_id = $2 FOR UPDATE")
+        .bind(req.transaction_id)
+        .bind(merchant.id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Transaction not found".to_string()))?;
+
+    let current: TransactionStatus = txn.status.parse().map_err(|e: String| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    // Only a charge that can still move to refunded has anything to refund
+    current.transition_to(TransactionStatus::Refunded).map_err(payment_error_status)?;
+
+    // Pending refunds count alongside succeeded ones: they have been submitted and may still go through
+    let (refunded, refund_count): (Decimal, i64) = sqlx::query_as(
+        "SELECT COALESCE(SUM(amount), 0), COUNT(*) FROM refunds WHERE transaction_id = $1 AND status <> 'failed'"
+    )
+    .bind(txn.id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let max_refunds = merchant.max_refunds_per_transaction(&state.config);
+    if refund_count >= max_refunds {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Transaction already has the maximum of {} refunds", max_refunds)));
+    }
+
+    let refundable = txn.amount - refunded;
+    let amount = req.amount.map(|a| minor_to_decimal(a, &txn.currency)).transpose()
+        .map_err(payment_error_status)?
+        .unwrap_or(refundable);
+    if amount <= Decimal::ZERO {
+        return Err(payment_error_status(PaymentError::RefundFailed("refund amount must be positive".into())));
+    }
+    if refunded + amount > txn.amount {
+        return Err(payment_error_status(PaymentError::RefundFailed(format!(
+            "{} already refunded; {} more would exceed the charge of {}", refunded, amount, txn.amount
+        ))));
+    }
+
+    let refund = sqlx::query_as::<_, Refund>(
+        r#"INSERT INTO refunds (id, transaction_id, amount, reason, status, created_at)
+           VALUES ($1, $2, $3, $4, 'pending', NOW()) RETURNING *"#
+    )
+    .bind(Uuid::now_v7())
+    .bind(txn.id)
+    .bind(amount)
+    .bind(req.reason.as_ref().map(RefundReason::as_str))
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    // The pending row already holds its share of the ceiling, so the provider call runs unlocked
+    process_refund(state, refund, &txn).await.map_err(|e| (e.status, e.message))
+}
+
+/// Sends a pending refund to the provider that took the charge and records the outcome.
+/// It stays pending when the provider is still processing it, can't be asked, or didn't
+/// answer, since the refund may still go through; the provider's webhook settles it then.
+async fn process_refund(state: &AppState, refund: Refund, txn: &Transaction) -> Result<Refund, ApiError> {
+    let Some(gateway) = state.gateway_named(txn.provider.as_deref()) else {
+        tracing::info!(refund_id = %refund.id, "No configured provider for {:?}; refund left pending", txn.provider);
+        return Ok(refund);
+    };
+    let submission = RefundSubmission {
+        charge_reference: txn.reference.clone(),
+        provider_reference: txn.provider_reference.clone(),
+        amount: Money::new(refund.amount, &txn.currency),
+        reason: refund.reason.clone(),
+    };
+    let outcome = match timed(gateway.name(), "refund", gateway.refund(&submission)).await {
+        Ok(Some(outcome @ (RefundOutcome::Succeeded | RefundOutcome::Failed))) => outcome,
+        Ok(Some(RefundOutcome::Pending) | None) => return Ok(refund),
+        Err(e) if classify(&e).class == FailureClass::Transient => {
+            tracing::warn!(refund_id = %refund.id, "Refund outcome unknown, left pending: {}", e);
+            return Ok(refund);
+        }
+        Err(e) => {
+            tracing::warn!(refund_id = %refund.id, "Provider rejected refund: {}", e);
+            settle_refund(&state.db, refund.id, RefundOutcome::Failed).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            return Err(charge_failure_response(&state.config, &e));
+        }
+    };
+    settle_refund(&state.db, refund.id, outcome).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    sqlx::query_as::<_, Refund>("SELECT * FROM refunds WHERE id = $1")
+        .bind(refund.id)
+        .fetch_one(&state.db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into())
+}
+
+async fn settle_refund(db: &sqlx::PgPool, refund_id: Uuid, outcome: RefundOutcome) -> Result<bool, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let settled = settle_refund_in(&mut tx, refund_id, outcome).await?;
+    tx.commit().await?;
+    if let Some(amount) = &settled { count_settled_refund(outcome, amount); }
+    Ok(settled.is_some())
+}
+
+/// Counts a refund settled as `outcome`, once the settlement has committed.
+fn count_settled_refund(outcome: RefundOutcome, amount: &Money) {
+    if outcome == RefundOutcome::Failed {
+        metrics::counter!("refunds_failed_total").increment(1);
+        return;
+    }
+    metrics::counter!("refunds_succeeded_total").increment(1);
+    if let Some(minor) = amount.to_minor_units().ok().and_then(|m| u64::try_from(m).ok()) {
+        metrics::counter!("refunded_amount_minor_total", "currency" => amount.currency.clone()).increment(minor);
+    }
+}
+
+/// Moves a pending refund to `outcome`. A succeeded refund moves the charge to
+/// `partially_refunded` or `refunded`, posts it to the ledger and emits
+/// `payment.refunded`. Returns the refund's amount, or `None` when it was already settled.
+async fn settle_refund_in(conn: &mut sqlx::PgConnection, refund_id: Uuid, outcome: RefundOutcome) -> Result<Option<Money>, sqlx::Error> {
+    let status = match outcome {
+        RefundOutcome::Succeeded => "succeeded",
+        RefundOutcome::Failed => "failed",
+        RefundOutcome::Pending => return Ok(None),
+    };
+    let settled: Option<(Uuid, Decimal, String)> = sqlx::query_as(
+        r#"UPDATE refunds r SET status = $1, processed_at = NOW() FROM transactions t
+           WHERE r.id = $2 AND r.status = 'pending' AND t.id = r.transaction_id
+           RETURNING r.transaction_id, r.amount, t.currency"#
+    )
+    .bind(status)
+    .bind(refund_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some((transaction_id, amount, currency)) = settled else { return Ok(None) };
+    let settled = Money::new(amount, &currency);
+    if outcome == RefundOutcome::Failed { return Ok(Some(settled)); }
+
+    let txn = sqlx::query_as::<_, Transaction>("SELECT * FROM transactions WHERE id = $1 FOR UPDATE")
+        .bind(transaction_id)
+        .fetch_one(&mut *conn)
+        .await?;
+    let refunded: Decimal = sqlx::query_scalar("SELECT COALESCE(SUM(amount), 0) FROM refunds WHERE transaction_id = $1 AND status = 'succeeded'")
+        .bind(transaction_id)
+        .fetch_one(&mut *conn)
+        .await?;
+    let next = if refunded >= txn.amount { TransactionStatus::Refunded } else { TransactionStatus::PartiallyRefunded };
+    match txn.status.parse::<TransactionStatus>() {
+        Ok(current) if current == next => {}
+        Ok(current) if current.transition_to(next).is_ok() => {
+            sqlx::query("UPDATE transactions SET status = $1, updated_at = NOW() WHERE id = $2")
+                .bind(next.as_str())
+                .bind(transaction_id)
+                .execute(&mut *conn)
+                .await?;
+        }
+        _ => tracing::warn!(refund_id = %refund_id, "Refund succeeded but transaction {} can't move from {} to {}", txn.reference, txn.status, next),
+    }
+
+    let entries = ledger::posting(ledger::MERCHANT_REVENUE, ledger::REFUNDS_PAYABLE, &Money::new(amount, &txn.currency));
+    record_ledger(&mut *conn, &refund_id.to_string(), &entries).await?;
+    // The column keeps four places; the event carries the currency's own
+    let amount = Money::new(amount, &txn.currency).round().amount;
+    let refunded_event = DomainEvent::Payment(PaymentEvent::Refunded { payment_id: PaymentId::from_string(&txn.reference), amount });
+    insert_outbox(&mut *conn, txn.merchant_id, &refunded_event).await?;
+    Ok(Some(settled))
+}
+
+/// One refund of the merchant's transactions.
+async fn get_refund(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Refund>, (StatusCode, String)> {
+    sqlx::query_as::<_, Refund>(
+        "SELECT r.* FROM refunds r JOIN transactions t ON t.id = r.transaction_id WHERE r.id = $1 AND t.merchant_id = $2"
+    )
+    .bind(id)
+    .bind(merchant.id)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .map(Json)
+    .ok_or((StatusCode::NOT_FOUND, "Refund not found".to_string()))
+}
+
+/// Lists refunds of the merchant's transactions.
+async fn list_refunds(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Query(params): Query<RefundListParams>,
+) -> Result<Json<PaginatedResponse<Refund>>, (StatusCode, String)> {
+    let page = params.page.unwrap_or(1).max(1);
+    let per_page = params.per_page.unwrap_or(20).min(100);
+    let offset = ((page - 1) * per_page) as i64;
+
+    let mut query = sqlx::QueryBuilder::new("SELECT * FROM refunds WHERE TRUE");
+    push_refund_filters(&mut query, merchant, &params);
+    query.push(" ORDER BY created_at DESC LIMIT ").push_bind(per_page as i64).push(" OFFSET ").push_bind(offset);
+    let refunds = query.build_query_as::<Refund>()
+        .fetch_all(&state.db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let mut count = sqlx::QueryBuilder::new("SELECT COUNT(*) FROM refunds WHERE TRUE");
+    push_refund_filters(&mut count, merchant, &params);
+    let total: (i64,) = count.build_query_as()
+        .fetch_one(&state.db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(PaginatedResponse { data: refunds, total: total.0, page, per_page, next_cursor: None }))
+}
+
+fn push_refund_filters(query: &mut sqlx::QueryBuilder<'_, sqlx::Postgres>, merchant: Merchant, params: &RefundListParams) {
+    query.push(" AND transaction_id IN (SELECT id FROM transactions WHERE merchant_id = ").push_bind(merchant.id).push(")");
+    if let Some(transaction_id) = params.transaction_id {
+        query.push(" AND transaction_id = ").push_bind(transaction_id);
+    }
+    if let Some(status) = &params.status {
+        query.push(" AND status = ").push_bind(status.clone());
+    }
+    if let Some(from) = params.from_date {
+        query.push(" AND created_at >= ").push_bind(from);
+    }
+    if let Some(to) = params.to_date {
+        query.push(" AND created_at <= ").push_bind(to);
+    }
+}
+
+/// Opens a dispute against one of the merchant's charges, for disputes the provider
+/// doesn't report by webhook.
+async fn create_dispute(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Json(req): Json<OpenDisputeRequest>,
+) -> Result<(StatusCode, Json<Dispute>), (StatusCode, String)> {
+    req.validate().map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    let mut tx = state.db.begin().await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let txn = sqlx::query_as::<_, Transaction>("SELECT * FROM transactions WHERE id = $1 AND merchant_id = $2 FOR UPDATE")
+        .bind(req.transaction_id)
+        .bind(merchant.id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Transaction not found".to_string()))?;
+
+    let current: TransactionStatus = txn.status.parse().map_err(|e: String| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    current.transition_to(TransactionStatus::Disputed).map_err(payment_error_status)?;
+    let amount = req.amount.map(|a| minor_to_decimal(a, &txn.currency)).transpose()
+        .map_err(payment_error_status)?
+        .unwrap_or(txn.amount);
+    if amount > txn.amount {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Dispute of {} exceeds the charge of {}", amount, txn.amount)));
+    }
+
+    let dispute = open_dispute_in(&mut tx, &txn, None, &req.reason, amount, req.evidence_due_by).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::CONFLICT, "Transaction can't be disputed".to_string()))?;
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok((StatusCode::CREATED, Json(dispute)))
+}
+
+async fn get_dispute(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Dispute>, (StatusCode, String)> {
+    sqlx::query_as::<_, Dispute>(
+        "SELECT d.* FROM disputes d JOIN transactions t ON t.id = d.transaction_id WHERE d.id = $1 AND t.merchant_id = $2"
+    )
+    .bind(id)
+    .bind(merchant.id)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .map(Json)
+    .ok_or((StatusCode::NOT_FOUND, "Dispute not found".to_string()))
+}
+
+/// Records the evidence sent to the provider. Evidence can be resent until the dispute
+/// closes; the latest replaces what was there.
+async fn submit_dispute_evidence(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<SubmitEvidenceRequest>,
+) -> Result<Json<Dispute>, (StatusCode, String)> {
+    let mut tx = state.db.begin().await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let dispute = lock_dispute(&mut tx, id, merchant).await?;
+    let current: DisputeStatus = dispute.status.parse().map_err(|e: String| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let next = current.transition_to(DisputeStatus::EvidenceSubmitted).map_err(payment_error_status)?;
+
+    let dispute = sqlx::query_as::<_, Dispute>(
+        "UPDATE disputes SET status = $2, evidence = $3, updated_at = NOW() WHERE id = $1 RETURNING *"
+    )
+    .bind(id)
+    .bind(next.as_str())
+    .bind(&req.evidence)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let reference: String = sqlx::query_scalar("SELECT reference FROM transactions WHERE id = $1")
+        .bind(dispute.transaction_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let event = DisputeEvent::EvidenceSubmitted { dispute_id: id.to_string(), payment_id: PaymentId::from_string(&reference) };
+    insert_outbox(&mut tx, Some(merchant.id), &DomainEvent::Dispute(event)).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(dispute))
+}
+
+/// Closes the dispute with the provider's decision, for disputes not resolved by webhook.
+async fn close_dispute(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<CloseDisputeRequest>,
+) -> Result<Json<Dispute>, (StatusCode, String)> {
+    let mut tx = state.db.begin().await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let dispute = lock_dispute(&mut tx, id, merchant).await?;
+    let current: DisputeStatus = dispute.status.parse().map_err(|e: String| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    current.transition_to(req.outcome.status()).map_err(payment_error_status)?;
+
+    let dispute = close_dispute_in(&mut tx, &dispute, req.outcome).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(dispute))
+}
+
+/// Locks one of the merchant's disputes; another merchant's is reported as missing.
+async fn lock_dispute(conn: &mut sqlx::PgConnection, id: Uuid, merchant: Merchant) -> Result<Dispute, (StatusCode, String)> {
+    sqlx::query_as::<_, Dispute>(
+        "SELECT d.* FROM disputes d JOIN transactions t ON t.id = d.transaction_id WHERE d.id = $1 AND t.merchant_id = $2 FOR UPDATE OF d"
+    )
+    .bind(id)
+    .bind(merchant.id)
+    .fetch_optional(conn)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .ok_or((StatusCode::NOT_FOUND, "Dispute not found".to_string()))
+}
+
+/// Opens a dispute on `txn`, which the caller has locked, and moves the charge to
+/// `disputed`. `None` when the charge can't be disputed: it never succeeded, was fully
+/// refunded, or already has a dispute in progress.
+async fn open_dispute_in(
+    conn: &mut sqlx::PgConnection,
+    txn: &Transaction,
+    provider_dispute_id: Option<&str>,
+    reason: &str,
+    amount: Decimal,
+    evidence_due_by: Option<DateTime<Utc>>,
+) -> Result<Option<Dispute>, sqlx::Error> {
+    let moved = sqlx::query("UPDATE transactions SET status = $2, updated_at = NOW() WHERE id = $1 AND status = ANY($3)")
+        .bind(txn.id)
+        .bind(TransactionStatus::Disputed.as_str())
+        .bind(sources_of(TransactionStatus::Disputed))
+        .execute(&mut *conn)
+        .await?;
+    if moved.rows_affected() != 1 { return Ok(None); }
+
+    let dispute = sqlx::query_as::<_, Dispute>(
+        r#"INSERT INTO disputes (id, transaction_id, provider_dispute_id, reason, status, amount, evidence_due_by, transaction_status)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING *"#
+    )
+    .bind(Uuid::now_v7())
+    .bind(txn.id)
+    .bind(provider_dispute_id)
+    .bind(reason)
+    .bind(DisputeStatus::Open.as_str())
+    .bind(amount)
+    .bind(evidence_due_by)
+    .bind(&txn.status)
+    .fetch_one(&mut *conn)
+    .await?;
+    let event = DisputeEvent::Opened {
+        dispute_id: dispute.id.to_string(), payment_id: PaymentId::from_string(&txn.reference), amount, reason: reason.to_string(),
+    };
+    insert_outbox(conn, txn.merchant_id, &DomainEvent::Dispute(event)).await?;
+    Ok(Some(dispute))
+}
+
+/// Closes a dispute the caller has locked and checked is still in progress. A won dispute
+/// puts the charge back as it was; a lost one leaves it charged back.
+async fn close_dispute_in(conn: &mut sqlx::PgConnection, dispute: &Dispute, outcome: DisputeOutcome) -> Result<Dispute, sqlx::Error> {
+    let closed = sqlx::query_as::<_, Dispute>(
+        "UPDATE disputes SET status = $2, updated_at = NOW(), closed_at = NOW() WHERE id = $1 RETURNING *"
+    )
+    .bind(dispute.id)
+    .bind(outcome.status().as_str())
+    .fetch_one(&mut *conn)
+    .await?;
+    let transaction_status = match outcome {
+        DisputeOutcome::Won => dispute.transaction_status.as_str(),
+        DisputeOutcome::Lost => TransactionStatus::ChargedBack.as_str(),
+    };
+    let (reference, merchant_id): (String, Option<Uuid>) = sqlx::query_as(
+        "UPDATE transactions SET status = $2, updated_at = NOW() WHERE id = $1 AND status = $3 RETURNING reference, merchant_id"
+    )
+    .bind(dispute.transaction_id)
+    .bind(transaction_status)
+    .bind(TransactionStatus::Disputed.as_str())
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let dispute_id = dispute.id.to_string();
+    let payment_id = PaymentId::from_string(&reference);
+    let event = match outcome {
+        DisputeOutcome::Won => DisputeEvent::Won { dispute_id, payment_id },
+        DisputeOutcome::Lost => DisputeEvent::Lost { dispute_id, payment_id, amount: dispute.amount },
+    };
+    insert_outbox(conn, merchant_id, &DomainEvent::Dispute(event)).await?;
+    Ok(closed)
+}
+
+/// Saves a card as a provider token. The number and CVC go to the provider and nowhere
+/// else: the row keeps the token, last four digits, brand and expiry.
+async fn create_payment_method(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Json(req): Json<CreatePaymentMethodRequest>,
+) -> Result<(StatusCode, Json<PaymentMethod>), (StatusCode, String)> {
+    req.validate().map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let card = &req.card;
+    if is_expired(card.exp_month.into(), card.exp_year.into(), Utc::now().date_naive()) {
+        return Err((StatusCode::BAD_REQUEST, "Card expiry is invalid or in the past".to_string()));
+    }
+    let gateway = state.gateway_named(req.provider.as_deref())
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, format!("Unknown or unconfigured provider '{}'", req.provider.as_deref().unwrap_or_default())))?;
+    let token = match timed(gateway.name(), "tokenize", gateway.tokenize(card, &req.email)).await {
+        Ok(Some(token)) => token,
+        Ok(None) => return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Provider '{}' doesn't save cards", gateway.name()))),
+        Err(e) => {
+            tracing::warn!(customer_id = %req.customer_id, "Card tokenization failed: {}", e);
+            let e = charge_failure_response(&state.config, &e);
+            return Err((e.status, e.message));
+        }
+    };
+
+    let mut tx = state.db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let method = sqlx::query_as::<_, PaymentMethod>(
+        r#"INSERT INTO payment_methods (id, customer_id, method_type, provider, token, last_four, brand, exp_month, exp_year, is_default, merchant_id, created_at)
+           VALUES ($1, $2, 'card', $3, $4, $5, $6, $7, $8, FALSE, $9, NOW()) RETURNING *"#
+    )
+    .bind(Uuid::now_v7())
+    .bind(req.customer_id)
+    .bind(gateway.name())
+    .bind(&token)
+    .bind(card.number.last_four())
+    .bind(card.number.brand().map(|b| b.as_str()))
+    .bind(i16::from(card.exp_month))
+    .bind(card.exp_year as i16)
+    .bind(merchant.id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let has_default: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM payment_methods WHERE customer_id = $1 AND merchant_id = $2 AND is_default)")
+        .bind(req.customer_id)
+        .bind(merchant.id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let method = if req.is_default.unwrap_or(true) || !has_default {
+        make_default(&mut tx, &method).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    } else {
+        method
+    };
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok((StatusCode::CREATED, Json(method)))
+}
+
+/// Makes `method` its customer's only default among the merchant's methods. The customer's
+/// methods are locked first, so two concurrent changes can't both clear the old default and
+/// then collide.
+async fn make_default(conn: &mut sqlx::PgConnection, method: &PaymentMethod) -> Result<PaymentMethod, sqlx::Error> {
+    sqlx::query("SELECT id FROM payment_methods WHERE customer_id = $1 AND merchant_id = $2 FOR UPDATE")
+        .bind(method.customer_id)
+        .bind(method.merchant_id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("UPDATE payment_methods SET is_default = FALSE WHERE customer_id = $1 AND merchant_id = $2 AND is_default AND id <> $3")
+        .bind(method.customer_id)
+        .bind(method.merchant_id)
+        .bind(method.id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query_as::<_, PaymentMethod>("UPDATE payment_methods SET is_default = TRUE WHERE id = $1 RETURNING *")
+        .bind(method.id)
+        .fetch_one(&mut *conn)
+        .await
+}
+
+async fn set_default_payment_method(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<PaymentMethod>, (StatusCode, String)> {
+    let method = ensure_payment_method_usable(&state.db, merchant, id).await?;
+    let mut tx = state.db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let method = make_default(&mut tx, &method).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(method))
+}
+
+/// The customer's payment methods, the default first and then newest first.
+async fn list_customer_payment_methods(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(customer_id): Path<Uuid>,
+) -> Result<Json<Vec<PaymentMethod>>, (StatusCode, String)> {
+    let methods = sqlx::query_as::<_, PaymentMethod>(
+        "SELECT * FROM payment_methods WHERE customer_id = $1 AND merchant_id = $2 AND deleted_at IS NULL ORDER BY is_default DESC, created_at DESC, id DESC"
+    )
+    .bind(customer_id)
+    .bind(merchant.id)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(methods))
+}
+
+/// Cards that are still usable but lapse within `within_days`, soonest first. A card is
+/// good through the last day of its expiry month.
+async fn list_expiring_payment_methods(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Query(params): Query<ExpiringParams>,
+) -> Result<Json<Vec<PaymentMethod>>, (StatusCode, String)> {
+    use chrono::Datelike;
+    let within_days = params.within_days.unwrap_or(state.config.card_expiry_notice_days);
+    if !(0..=3660).contains(&within_days) {
+        return Err((StatusCode::BAD_REQUEST, "within_days must be between 0 and 3660".to_string()));
+    }
+    let today = Utc::now().date_naive();
+    let horizon = today + chrono::Duration::days(within_days);
+    // Narrowed by year here; the month boundary is left to the domain rule
+    let candidates = sqlx::query_as::<_, PaymentMethod>(
+        r#"SELECT * FROM payment_methods
+           WHERE merchant_id = $1 AND method_type = 'card' AND exp_year BETWEEN $2 AND $3
+             AND exp_month IS NOT NULL AND expired_at IS NULL AND deleted_at IS NULL"#
+    )
+    .bind(merchant.id)
+    .bind(today.year() as i16)
+    .bind(horizon.year() as i16)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let mut expiring: Vec<(chrono::NaiveDate, PaymentMethod)> = candidates.into_iter()
+        .filter_map(|method| {
+            let (month, year) = (method.exp_month? as u32, method.exp_year? as i32);
+            match card_expiry(month, year, today, within_days)? {
+                CardExpiry::ExpiringSoon => Some((expires_on(month, year)?, method)),
+                CardExpiry::Valid | CardExpiry::Expired => None,
+            }
+        })
+        .collect();
+    expiring.sort_by_key(|(lapses, method)| (*lapses, method.id));
+    Ok(Json(expiring.into_iter().map(|(_, method)| method).collect()))
+}
+
+/// Subscription states that still renew, and so still need their payment method.
+const RENEWING_SUBSCRIPTION_STATUSES: &[&str] = &["active", "trialing", "past_due", "unpaid"];
+
+/// Soft-deletes a payment method. Refused with 409 while a renewing subscription uses it,
+/// unless `force` pauses those subscriptions in the same transaction. A deleted default
+/// hands over to the customer's newest remaining method.
+async fn delete_payment_method(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<DeletePaymentMethodParams>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let statuses: Vec<String> = RENEWING_SUBSCRIPTION_STATUSES.iter().map(|s| s.to_string()).collect();
+    let mut tx = state.db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let method = sqlx::query_as::<_, PaymentMethod>("SELECT * FROM payment_methods WHERE id = $1 AND merchant_id = $2 AND deleted_at IS NULL FOR UPDATE")
+        .bind(id)
+        .bind(merchant.id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Payment method not found".to_string()))?;
+
+    let subscriptions: Vec<Uuid> = sqlx::query_scalar(
+        "SELECT id FROM subscriptions WHERE payment_method_id = $1 AND status = ANY($2) ORDER BY id FOR UPDATE"
+    )
+    .bind(id)
+    .bind(&statuses)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if !subscriptions.is_empty() {
+        if !params.force {
+            return Err((StatusCode::CONFLICT, format!(
+                "Payment method is used by {} active subscription(s); pass force=true to pause them", subscriptions.len()
+            )));
+        }
+        sqlx::query("UPDATE subscriptions SET status = 'paused', updated_at = NOW() WHERE id = ANY($1)")
+            .bind(&subscriptions)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        tracing::info!(payment_method_id = %id, paused = subscriptions.len(), "Paused subscriptions of a deleted payment method");
+    }
+
+    sqlx::query("UPDATE payment_methods SET deleted_at = NOW(), is_default = FALSE WHERE id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if method.is_default {
+        let next = sqlx::query_as::<_, PaymentMethod>(
+            "SELECT * FROM payment_methods WHERE customer_id = $1 AND merchant_id = $2 AND deleted_at IS NULL ORDER BY created_at DESC, id DESC LIMIT 1"
+        )
+        .bind(method.customer_id)
+        .bind(merchant.id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        if let Some(next) = next {
+            make_default(&mut tx, &next).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        }
+    }
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Fails fast on stored cards that have been deleted, flagged or are past their expiry month.
+async fn ensure_payment_method_usable(db: &sqlx::PgPool, merchant: Merchant, id: Uuid) -> Result<PaymentMethod, (StatusCode, String)> {
+    let method = sqlx::query_as::<_, PaymentMethod>("SELECT * FROM payment_methods WHERE id = $1 AND merchant_id = $2 AND deleted_at IS NULL")
+        .bind(id)
+        .bind(merchant.id)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Payment method not found".to_string()))?;
+
+    let lapsed = match (method.exp_month, method.exp_year) {
+        (Some(month), Some(year)) => is_expired(month as u32, year as i32, Utc::now().date_naive()),
+        _ => false,
+    };
+    if method.expired_at.is_some() || lapsed {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "Payment method has expired".to_string()));
+    }
+    Ok(method)
+}
+
+// =============================================================================
+// Payment Plan Handlers
+// =============================================================================
+
+/// Splits the request's total into installments and schedules one charge per
+/// installment. The first is due on `first_due_on`, or straight away when that is today.
+async fn create_payment_plan(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Json(req): Json<CreatePaymentPlanRequest>,
+) -> Result<(StatusCode, Json<PaymentPlanView>), ApiError> {
+    req.validate().map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let cycle = match req.interval.as_deref() {
+        None => BillingCycle::default(),
+        Some(cycle) => BillingCycle::parse(cycle).ok_or((StatusCode::BAD_REQUEST, format!("Unknown billing cycle '{}'", cycle)))?,
+    };
+    req.amount.ensure_positive()?;
+    let total = req.amount.to_money_in(&state.config.currency_policy)?;
+    let now = Utc::now();
+    let first_due = req.first_due_on.unwrap_or(now.date_naive());
+    if first_due < now.date_naive() {
+        return Err((StatusCode::BAD_REQUEST, "first_due_on must not be in the past".to_string()).into());
+    }
+    let plan = PaymentPlan::create(total, req.installments, cycle, first_due).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let plan_id = Uuid::parse_str(plan.id()).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    // Every installment is checked, like any other charge, before any of them is stored
+    let mut scheduled = Vec::with_capacity(plan.installments().len());
+    for installment in plan.installments() {
+        let mut metadata = req.metadata.clone().unwrap_or_else(|| serde_json::json!({}));
+        if let Some(fields) = metadata.as_object_mut() {
+            fields.insert("payment_plan_id".into(), serde_json::json!(plan_id));
+            // Merchant metadata only holds strings
+            fields.insert("installment".into(), serde_json::json!(installment.number.to_string()));
+        }
+        let charge = InitiatePaymentRequest {
+            reference: Some(installment_reference(plan_id, installment.number)),
+            amount: Amount::new(installment.amount.to_minor_units()?, &installment.amount.currency),
+            email: req.email.clone(),
+            customer_id: req.customer_id,
+            payment_method: None,
+            payment_method_id: req.payment_method_id,
+            callback_url: req.callback_url.clone(),
+            metadata: Some(metadata),
+            statement_descriptor_suffix: None,
+            provider: req.provider.clone(),
+            scheduled_at: None,
+            capture: None,
+        };
+        let payment = prepare_payment(&state, merchant, &charge).await?;
+        let due_at = installment.due_on.and_time(chrono::NaiveTime::MIN).and_utc().max(now);
+        scheduled.push((installment, charge, payment, due_at));
+    }
+
+    let mut tx = state.db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    sqlx::query(
+        r#"INSERT INTO payment_plans (id, merchant_id, customer_id, customer_email, total, currency, billing_cycle, created_at)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#
+    )
+    .bind(plan_id)
+    .bind(merchant.id)
+    .bind(req.customer_id)
+    .bind(&req.email)
+    .bind(plan.total().amount)
+    .bind(&plan.total().currency)
+    .bind(plan.cycle().as_str())
+    .bind(plan.created_at())
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    for (installment, charge, payment, due_at) in scheduled {
+        let reference = payment.reference.clone();
+        schedule_payment(&mut tx, merchant, &charge, payment, due_at).await?;
+        sqlx::query("INSERT INTO payment_plan_installments (plan_id, number, amount, due_on, reference) VALUES ($1, $2, $3, $4, $5)")
+            .bind(plan_id)
+            .bind(installment.number as i32)
+            .bind(installment.amount.amount)
+            .bind(installment.due_on)
+            .bind(&reference)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    }
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok((StatusCode::CREATED, Json(fetch_payment_plan(&state.db, plan_id, merchant).await?)))
+}
+
+async fn get_payment_plan(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<PaymentPlanView>, (StatusCode, String)> {
+    Ok(Json(fetch_payment_plan(&state.db, id, merchant).await?))
+}
+
+fn installment_reference(plan_id: Uuid, number: u32) -> String {
+    format!("PLAN-{}-{}", plan_id.simple(), number)
+}
+
+/// The plan with each installment's charge status. A status is missing once the charge
+/// has been archived.
+async fn fetch_payment_plan(db: &sqlx::PgPool, id: Uuid, merchant: Merchant) -> Result<PaymentPlanView, (StatusCode, String)> {
+    let (id, total, currency, billing_cycle, created_at): (Uuid, Decimal, String, String, DateTime<Utc>) = sqlx::query_as(
+        "SELECT id, total, currency, billing_cycle, created_at FROM payment_plans WHERE id = $1 AND merchant_id = $2"
+    )
+    .bind(id)
+    .bind(merchant.id)
+    .fetch_optional(db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .ok_or((StatusCode::NOT_FOUND, "Payment plan not found".to_string()))?;
+    let installments = sqlx::query_as::<_, PlanInstallment>(
+        r#"SELECT i.number, i.amount, i.due_on, i.reference, t.status
+           FROM payment_plan_installments i LEFT JOIN transactions t ON t.reference = i.reference
+           WHERE i.plan_id = $1 ORDER BY i.number"#
+    )
+    .bind(id)
+    .fetch_all(db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(PaymentPlanView { id, total, currency, billing_cycle, installments, created_at })
+}
+
+// =============================================================================
+// Subscription Handlers
+// =============================================================================
+
+async fn create_subscription(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Json(req): Json<CreateSubscriptionRequest>,
+) -> Result<(StatusCode, Json<Subscription>), (StatusCode, String)> {
+    req.validate().map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let cycle = match req.billing_cycle.as_deref() {
+        None => BillingCycle::default(),
+        Some(cycle) => BillingCycle::parse(cycle).ok_or((StatusCode::BAD_REQUEST, format!("Unknown billing cycle '{}'", cycle)))?,
+    };
+    req.amount.ensure_positive().map_err(payment_error_status)?;
+    let amount = req.amount.to_money_in(&state.config.currency_policy).map_err(payment_error_status)?;
+    if let Some(method_id) = req.payment_method_id {
+        let method = ensure_payment_method_usable(&state.db, merchant, method_id).await?;
+        if method.customer_id != req.customer_id {
+            return Err((StatusCode::BAD_REQUEST, "Payment method belongs to another customer".to_string()));
+        }
+    }
+    if let Some(billing) = &req.billing_details {
+        billing.validate().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    }
+    let subscription = SubscriptionAggregate::create(req.customer_id.to_string(), req.plan_id.clone(), amount, cycle)
+        .with_metadata(req.metadata)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    let row = sqlx::query_as::<_, Subscription>(
+        r#"INSERT INTO subscriptions (id, customer_id, plan_id, amount, currency, billing_cycle, status,
+                                      current_period_start, current_period_end, metadata, payment_method_id, billing_details, merchant_id, created_at, updated_at)
+           VALUES ($1, $2, $3, $4, $5, $6, 'active', $7, $8, $9, $10, $11, $12, NOW(), NOW()) RETURNING *"#
+    )
+    .bind(Uuid::now_v7())
+    .bind(req.customer_id)
+    .bind(subscription.plan_id())
+    .bind(subscription.amount().amount)
+    .bind(&subscription.amount().currency)
+    .bind(subscription.billing_cycle().as_str())
+    .bind(subscription.current_period_start())
+    .bind(subscription.current_period_end())
+    .bind(sqlx::types::Json(subscription.metadata()))
+    .bind(req.payment_method_id)
+    .bind(req.billing_details.as_ref().map(sqlx::types::Json))
+    .bind(merchant.id)
+    .fetch_one(&state.db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok((StatusCode::CREATED, Json(row)))
+}
+
+async fn get_subscription(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Subscription>, (StatusCode, String)> {
+    sqlx::query_as::<_, Subscription>("SELECT * FROM subscriptions WHERE id = $1 AND merchant_id = $2")
+        .bind(id)
+        .bind(merchant.id)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "Subscription not found".to_string()))
+}
+
+async fn update_subscription(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<UpdateSubscriptionRequest>,
+) -> Result<Json<Subscription>, (StatusCode, String)> {
+    let mut tx = state.db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let current = sqlx::query_as::<_, Subscription>("SELECT * FROM subscriptions WHERE id = $1 AND merchant_id = $2 FOR UPDATE")
+        .bind(id)
+        .bind(merchant.id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Subscription not found".to_string()))?;
+
+    let merged = match &req.metadata {
+        Some(patch) => metadata::merge(&current.metadata, patch).map_err(|e| (StatusCode::BAD_REQUEST, e))?,
+        None => current.metadata.0.clone(),
+    };
+
+    let updated = sqlx::query_as::<_, Subscription>(
+        "UPDATE subscriptions SET metadata = $1, updated_at = NOW() WHERE id = $2 RETURNING *"
+    )
+    .bind(sqlx::types::Json(&merged))
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(updated))
+}
+
+/// Adds usage to the subscription's next renewal, which charges it on top of the flat
+/// amount. Only a subscription that still renews takes usage.
+async fn record_usage(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<RecordUsageRequest>,
+) -> Result<(StatusCode, Json<UsageRecord>), (StatusCode, String)> {
+    if req.quantity <= 0 { return Err((StatusCode::BAD_REQUEST, "quantity must be positive".to_string())); }
+    if req.unit_amount < 0 { return Err((StatusCode::BAD_REQUEST, "unit_amount can't be negative".to_string())); }
+
+    let mut tx = state.db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let (status, currency): (String, String) = sqlx::query_as("SELECT status, currency FROM subscriptions WHERE id = $1 AND merchant_id = $2 FOR SHARE")
+        .bind(id)
+        .bind(merchant.id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Subscription not found".to_string()))?;
+    if !RENEWING_SUBSCRIPTION_STATUSES.contains(&status.as_str()) {
+        return Err((StatusCode::CONFLICT, format!("A {} subscription doesn't take usage", status)));
+    }
+    let unit_amount = minor_to_decimal(req.unit_amount, &currency).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let amount = unit_amount.checked_mul(Decimal::from(req.quantity))
+        .ok_or((StatusCode::BAD_REQUEST, "Usage amount is too large".to_string()))?;
+
+    let record = sqlx::query_as::<_, UsageRecord>(
+        r#"INSERT INTO usage_records (id, subscription_id, quantity, unit_amount, amount, recorded_at)
+           VALUES ($1, $2, $3, $4, $5, NOW()) RETURNING *"#
+    )
+    .bind(Uuid::now_v7())
+    .bind(id)
+    .bind(req.quantity)
+    .bind(unit_amount)
+    .bind(amount)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let recorded = DomainEvent::Subscription(SubscriptionEvent::UsageRecorded { subscription_id: id.to_string(), quantity: req.quantity, amount });
+    insert_outbox(&mut tx, Some(merchant.id), &recorded).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok((StatusCode::CREATED, Json(record)))
+}
+
+async fn get_subscription_metrics(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<SubscriptionMetrics>, (StatusCode, String)> {
+    let Json(sub) = get_subscription(State(state.clone()), Extension(merchant), Path(id)).await?;
+    let record = SubscriptionRecord::try_from(&sub).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(SubscriptionAggregate::from_record(record, state.config.dunning).metrics()))
+}
+
+// =============================================================================
+// Webhook Endpoint Handlers
+// =============================================================================
+
+async fn create_webhook_endpoint(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Json(req): Json<CreateWebhookEndpointRequest>,
+) -> Result<(StatusCode, Json<CreatedWebhookEndpoint>), (StatusCode, String)> {
+    let url = webhooks::validate_endpoint_url(&req.url, state.config.test_mode)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    let endpoint = sqlx::query_as::<_, WebhookEndpoint>(
+        "INSERT INTO webhook_endpoints (id, url, secret, merchant_id, created_at) VALUES ($1, $2, $3, $4, NOW()) RETURNING *"
+    )
+    .bind(Uuid::now_v7())
+    .bind(url.as_str())
+    .bind(webhooks::generate_secret())
+    .bind(merchant.id)
+    .fetch_one(&state.db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let secret = endpoint.secret.clone();
+    Ok((StatusCode::CREATED, Json(CreatedWebhookEndpoint { endpoint, secret })))
+}
+
+async fn list_webhook_endpoints(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+) -> Result<Json<Vec<WebhookEndpoint>>, (StatusCode, String)> {
+    let endpoints = sqlx::query_as::<_, WebhookEndpoint>("SELECT * FROM webhook_endpoints WHERE merchant_id = $1 ORDER BY created_at DESC")
+        .bind(merchant.id)
+        .fetch_all(&state.db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(endpoints))
+}
+
+async fn delete_webhook_endpoint(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let result = sqlx::query("DELETE FROM webhook_endpoints WHERE id = $1 AND merchant_id = $2")
+        .bind(id)
+        .bind(merchant.id)
+        .execute(&state.db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Webhook endpoint not found".to_string()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn test_webhook_endpoint(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<webhooks::DeliveryResult>, (StatusCode, String)> {
+    let endpoint = sqlx::query_as::<_, WebhookEndpoint>("SELECT * FROM webhook_endpoints WHERE id = $1 AND merchant_id = $2")
+        .bind(id)
+        .bind(merchant.id)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Webhook endpoint not found".to_string()))?;
+
+    let event = serde_json::json!({
+        "id": format!("evt_{}", Uuid::now_v7().simple()),
+        "type": "webhook_endpoint.test",
+        "created_at": Utc::now(),
+        "data": { "webhook_endpoint_id": endpoint.id }
+    });
+
+    Ok(Json(webhooks::deliver(&state.http, &endpoint.url, &endpoint.secret, &event).await))
+}
+
+// =============================================================================
+// Admin Handlers
+// =============================================================================
+
+#[derive(sqlx::FromRow)]
+struct TransactionDebugRow {
+    reference: String,
+    provider: Option<String>,
+    provider_reference: Option<String>,
+    provider_raw_response: Option<serde_json::Value>,
+    avs_result: Option<String>,
+    cvv_result: Option<String>,
+    network_response_code: Option<String>,
+}
+
+fn require_admin(state: &AppState, headers: &HeaderMap) -> Result<(), (StatusCode, String)> {
+    let supplied = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    match (&state.config.admin_token, supplied) {
+        (Some(expected), Some(supplied)) if sase_payments::crypto::constant_time_eq(expected.as_bytes(), supplied.as_bytes()) => Ok(()),
+        _ => Err((StatusCode::FORBIDDEN, "Admin access required".to_string())),
+    }
+}
+
+async fn get_transaction_debug(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    require_admin(&state, &headers)?;
+
+    let row: TransactionDebugRow = sqlx::query_as(
+        r#"SELECT reference, provider, provider_reference, provider_raw_response, avs_result, cvv_result, network_response_code
+           FROM transactions WHERE id = $1"#
+    )
+    .bind(id)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .ok_or((StatusCode::NOT_FOUND, "Transaction not found".to_string()))?;
+
+    Ok(Json(serde_json::json!({
+        "id": id,
+        "reference": row.reference,
+        "provider": row.provider,
+        "provider_reference": row.provider_reference,
+        "provider_raw_response": row.provider_raw_response,
+        "avs_result": row.avs_result,
+        "cvv_result": row.cvv_result,
+        "network_response_code": row.network_response_code,
+    })))
+}
+
+// =============================================================================
+// Settlement Handlers
+// =============================================================================
+
+/// Statuses whose funds the provider settles to us.
+const SETTLEABLE_STATUSES: &[&str] = &["succeeded", "partially_refunded", "refunded"];
+
+/// Marks transactions as settled under `settlement_id`. Rows that are already settled or
+/// not in a settleable status are skipped, so replaying the same settlement is a no-op.
+/// Returns how many rows were newly settled.
+async fn mark_settled(db: &sqlx::PgPool, ids: &[Uuid], settlement_id: &str) -> Result<u64, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let result = sqlx::query(
+        r#"UPDATE transactions SET settled_at = NOW(), settlement_id = $1, updated_at = NOW()
+           WHERE id = ANY($2) AND settled_at IS NULL AND status = ANY($3)"#
+    )
+    .bind(settlement_id)
+    .bind(ids)
+    .bind(SETTLEABLE_STATUSES)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result.rows_affected())
+}
+
+/// Sums unsettled, settleable transactions net of non-failed refunds.
+async fn compute_pending_payout(db: &sqlx::PgPool, currency: &str) -> Result<PayoutSummary, sqlx::Error> {
+    sqlx::query_as::<_, PayoutSummary>(
+        r#"SELECT $1::VARCHAR AS currency,
+                  COUNT(*) AS transaction_count,
+                  COALESCE(SUM(t.amount), 0) AS gross,
+                  COALESCE(SUM(r.refunded), 0) AS refunded,
+                  COALESCE(SUM(t.amount - COALESCE(r.refunded, 0)), 0) AS net
+           FROM transactions t
+           LEFT JOIN (SELECT transaction_id, SUM(amount) AS refunded FROM refunds WHERE status <> 'failed' GROUP BY transaction_id) r
+             ON r.transaction_id = t.id
+           WHERE t.currency = $1 AND t.settled_at IS NULL AND t.status = ANY($2)"#
+    )
+    .bind(currency)
+    .bind(SETTLEABLE_STATUSES)
+    .fetch_one(db)
+    .await
+}
+
+async fn create_settlement(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<SettlementRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    require_admin(&state, &headers)?;
+    if req.settlement_id.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "settlement_id required".to_string()));
+    }
+
+    let settled = mark_settled(&state.db, &req.transaction_ids, &req.settlement_id).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(serde_json::json!({
+        "settlement_id": req.settlement_id,
+        "requested": req.transaction_ids.len(),
+        "settled": settled,
+    })))
+}
+
+async fn get_pending_payout(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<PayoutParams>,
+) -> Result<Json<PayoutSummary>, (StatusCode, String)> {
+    require_admin(&state, &headers)?;
+    let currency = params.currency.as_deref().unwrap_or(DEFAULT_CURRENCY);
+    compute_pending_payout(&state.db, currency).await
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+async fn get_settlement_report(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<SettlementReportParams>,
+) -> Result<Json<SettlementReport>, (StatusCode, String)> {
+    require_admin(&state, &headers)?;
+    let totals = compute_settlement_totals(&state.db, params.date).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(SettlementReport { date: params.date, totals }))
+}
+
+/// Sums settleable transactions completed on `date`, net of their non-failed refunds,
+/// grouped by currency and provider. Transactions without a completion time count on
+/// the day they were created.
+async fn compute_settlement_totals(db: &sqlx::PgPool, date: chrono::NaiveDate) -> Result<Vec<SettlementTotal>, sqlx::Error> {
+    let start = date.and_time(chrono::NaiveTime::MIN).and_utc();
+    sqlx::query_as::<_, SettlementTotal>(
+        r#"SELECT t.currency, t.provider,
+                  COUNT(*) AS transaction_count,
+                  SUM(t.amount) AS gross,
+                  COALESCE(SUM(r.refunded), 0) AS refunded,
+                  SUM(t.amount - COALESCE(r.refunded, 0)) AS net
+           FROM transactions t
+           LEFT JOIN (SELECT transaction_id, SUM(amount) AS refunded FROM refunds WHERE status <> 'failed' GROUP BY transaction_id) r
+             ON r.transaction_id = t.id
+           WHERE t.status = ANY($1) AND COALESCE(t.completed_at, t.created_at) >= $2 AND COALESCE(t.completed_at, t.created_at) < $3
+           GROUP BY t.currency, t.provider
+           ORDER BY t.currency, t.provider NULLS LAST"#
+    )
+    .bind(SETTLEABLE_STATUSES)
+    .bind(start)
+    .bind(start + chrono::Duration::days(1))
+    .fetch_all(db)
+    .await
+}
+
+// =============================================================================
+// Wallet Handlers
+// =============================================================================
+
+async fn create_wallet(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Json(req): Json<serde_json::Value>,
+) -> Result<(StatusCode, Json<WalletView>), (StatusCode, String)> {
+    let customer_id = req["customer_id"].as_str()
+        .and_then(|s| Uuid::parse_str(s).ok())
+        .ok_or((StatusCode::BAD_REQUEST, "customer_id required".to_string()))?;
+
+    let id = Uuid::now_v7();
+
+    let mut tx = state.db.begin().await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let wallet = sqlx::query_as::<_, Wallet>(
+        r#"INSERT INTO wallets (id, customer_id, currency, status, merchant_id, created_at, updated_at)
+           VALUES ($1, $2, 'NGN', 'active', $3, NOW(), NOW()) RETURNING *"#
+    )
+    .bind(id)
+    .bind(customer_id)
+    .bind(merchant.id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let balance = sqlx::query_as::<_, WalletBalance>(
+        "INSERT INTO wallet_balances (wallet_id, currency, balance, updated_at) VALUES ($1, $2, 0, NOW()) RETURNING *, balance - held AS available"
+    )
+    .bind(id)
+    .bind(&wallet.currency)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok((StatusCode::CREATED, Json(WalletView { wallet, balances: vec![balance] })))
+}
+
+async fn list_wallets(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+) -> Result<Json<Vec<WalletView>>, (StatusCode, String)> {
+    let wallets = sqlx::query_as::<_, Wallet>("SELECT * FROM wallets WHERE merchant_id = $1 ORDER BY created_at DESC")
+        .bind(merchant.id)
+        .fetch_all(&state.db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let ids: Vec<Uuid> = wallets.iter().map(|w| w.id).collect();
+    let mut balances: HashMap<Uuid, Vec<WalletBalance>> = HashMap::new();
+    for balance in fetch_balances(&state.db, &ids).await? {
+        balances.entry(balance.wallet_id).or_default().push(balance);
+    }
+
+    Ok(Json(wallets.into_iter().map(|wallet| {
+        let balances = balances.remove(&wallet.id).unwrap_or_default();
+        WalletView { wallet, balances }
+    }).collect()))
+}
+
+async fn get_wallet(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<WalletView>, (StatusCode, String)> {
+    Ok(Json(fetch_wallet_view(&state.db, merchant, id).await?))
+}
+
+async fn topup_wallet(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<WalletTopupRequest>,
+) -> Result<Json<WalletTopupResponse>, (StatusCode, String)> {
+    let wallet = fetch_wallet(&state.db, merchant, id).await?;
+    let currency = wallet_currency(req.currency.as_deref(), &wallet);
+    let amount = minor_to_decimal(req.amount, &currency).map_err(payment_error_status)?;
+
+    let mut tx = state.db.begin().await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let balance = credit_balance(&mut tx, id, &Money::new(amount, &currency)).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let topup_id = Uuid::now_v7();
+    sqlx::query(
+        r#"INSERT INTO wallet_transactions (id, wallet_id, amount, currency, balance_after, transaction_type, created_at)
+           VALUES ($1, $2, $3, $4, $5, 'topup', NOW())"#
+    )
+    .bind(topup_id)
+    .bind(id)
+    .bind(amount)
+    .bind(&currency)
+    .bind(balance)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let entries = ledger::posting(ledger::TOPUP_CLEARING, &ledger::wallet_account(id), &Money::new(amount, &currency));
+    record_ledger(&mut tx, &topup_id.to_string(), &entries).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let wallet = fetch_wallet_view(&state.db, merchant, id).await?;
+    Ok(Json(WalletTopupResponse { wallet, topup_id }))
+}
+
+async fn reverse_topup(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path((wallet_id, topup_id)): Path<(Uuid, Uuid)>,
+    Json(req): Json<ReverseTopupRequest>,
+) -> Result<Json<WalletTransaction>, (StatusCode, String)> {
+    req.validate().map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    let mut tx = state.db.begin().await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let wallet = sqlx::query_as::<_, Wallet>("SELECT * FROM wallets WHERE id = $1 AND merchant_id = $2 FOR UPDATE")
+        .bind(wallet_id)
+        .bind(merchant.id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Wallet not found".to_string()))?;
+
+    let topup = sqlx::query_as::<_, WalletTransaction>(
+        "SELECT * FROM wallet_transactions WHERE id = $1 AND wallet_id = $2 AND transaction_type = 'topup' FOR UPDATE"
+    )
+    .bind(topup_id)
+    .bind(wallet_id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .ok_or((StatusCode::NOT_FOUND, "Top-up not found".to_string()))?;
+
+    // The reversal comes out of the balance the top-up went into, less anything on hold
+    let balance = fetch_available(&mut *tx, wallet_id, &topup.currency).await?;
+
+    let amount = sase_payments::domain::services::topup_reversal_amount(
+        topup.amount,
+        topup.reversed_amount,
+        req.amount.map(|a| minor_to_decimal(a, &topup.currency)).transpose().map_err(payment_error_status)?,
+        &Money::new(balance, &topup.currency),
+        wallet.allow_overdraft,
+    )
+    .map_err(payment_error_status)?;
+
+    // Checked again as part of the debit, so the reversal can't dig into held funds
+    let balance_after: (Decimal,) = sqlx::query_as(
+        r#"UPDATE wallet_balances SET balance = balance - $1, updated_at = NOW()
+           WHERE wallet_id = $2 AND currency = $3 AND (balance - held >= $1 OR $4) RETURNING balance"#
+    )
+    .bind(amount)
+    .bind(wallet_id)
+    .bind(&topup.currency)
+    .bind(wallet.allow_overdraft)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .ok_or_else(|| payment_error_status(PaymentError::InsufficientFunds("balance changed during reversal".into())))?;
+
+    sqlx::query(
+        r#"UPDATE wallet_transactions
+           SET reversed_amount = reversed_amount + $1,
+               reversed_at = CASE WHEN reversed_amount + $1 >= amount THEN NOW() ELSE reversed_at END
+           WHERE id = $2"#
+    )
+    .bind(amount)
+    .bind(topup_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let reversal_id = Uuid::now_v7();
+    let reversal = sqlx::query_as::<_, WalletTransaction>(
+        r#"INSERT INTO wallet_transactions (id, wallet_id, amount, currency, balance_after, transaction_type, description, reverses_id, created_at)
+           VALUES ($1, $2, $3, $4, $5, 'topup_reversal', $6, $7, NOW()) RETURNING *"#
+    )
+    .bind(reversal_id)
+    .bind(wallet_id)
+    .bind(-amount)
+    .bind(&topup.currency)
+    .bind(balance_after.0)
+    .bind(&req.reason)
+    .bind(topup_id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let entries = ledger::posting(&ledger::wallet_account(wallet_id), ledger::TOPUP_CLEARING, &Money::new(amount, &topup.currency));
+    record_ledger(&mut tx, &reversal_id.to_string(), &entries).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(reversal))
+}
+
+async fn create_wallet_hold(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<WalletHoldRequest>,
+) -> Result<(StatusCode, Json<WalletHold>), (StatusCode, String)> {
+    req.validate().map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let wallet = fetch_wallet(&state.db, merchant, id).await?;
+    let currency = wallet_currency(req.currency.as_deref(), &wallet);
+    let amount = minor_to_decimal(req.amount, &currency).map_err(payment_error_status)?;
+
+    let mut tx = state.db.begin().await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let hold = reserve_in(&mut tx, id, &Money::new(amount, &currency), req.reference.as_deref()).await?;
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok((StatusCode::CREATED, Json(hold)))
+}
+
+async fn capture_wallet_hold(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path((wallet_id, hold_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<WalletHold>, (StatusCode, String)> {
+    let mut tx = state.db.begin().await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let hold = lock_hold(&mut tx, merchant, wallet_id, hold_id).await?;
+    let hold = capture_hold_in(&mut tx, &hold).await?;
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(hold))
+}
+
+async fn release_wallet_hold(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path((wallet_id, hold_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<WalletHold>, (StatusCode, String)> {
+    let mut tx = state.db.begin().await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let hold = lock_hold(&mut tx, merchant, wallet_id, hold_id).await?;
+    let hold = release_hold_in(&mut tx, &hold).await?;
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(hold))
+}
+
+/// Reserves `amount` out of the wallet's available balance. The balance itself, and so
+/// the ledger, is untouched until the hold is captured.
+async fn reserve_in(tx: &mut sqlx::PgConnection, wallet_id: Uuid, amount: &Money, reference: Option<&str>) -> Result<WalletHold, (StatusCode, String)> {
+    let reserved = sqlx::query(
+        "UPDATE wallet_balances SET held = held + $1, updated_at = NOW() WHERE wallet_id = $2 AND currency = $3 AND balance - held >= $1"
+    )
+    .bind(amount.amount)
+    .bind(wallet_id)
+    .bind(&amount.currency)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if reserved.rows_affected() == 0 {
+        let available = fetch_available(&mut *tx, wallet_id, &amount.currency).await?;
+        ensure_sufficient(&Money::new(available, &amount.currency), amount).map_err(payment_error_status)?;
+        return Err(payment_error_status(PaymentError::InsufficientFunds("balance changed during hold".into())));
+    }
+
+    sqlx::query_as::<_, WalletHold>(
+        r#"INSERT INTO wallet_holds (id, wallet_id, currency, amount, status, reference, created_at)
+           VALUES ($1, $2, $3, $4, 'held', $5, NOW()) RETURNING *"#
+    )
+    .bind(Uuid::now_v7())
+    .bind(wallet_id)
+    .bind(&amount.currency)
+    .bind(amount.amount)
+    .bind(reference)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+async fn lock_hold(conn: &mut sqlx::PgConnection, merchant: Merchant, wallet_id: Uuid, hold_id: Uuid) -> Result<WalletHold, (StatusCode, String)> {
+    sqlx::query_as::<_, WalletHold>(
+        "SELECT * FROM wallet_holds WHERE id = $1 AND wallet_id = $2 AND wallet_id IN (SELECT id FROM wallets WHERE merchant_id = $3) FOR UPDATE"
+    )
+    .bind(hold_id)
+    .bind(wallet_id)
+    .bind(merchant.id)
+    .fetch_optional(conn)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .ok_or((StatusCode::NOT_FOUND, "Hold not found".to_string()))
+}
+
+/// Debits the held funds from `hold`'s wallet, which the caller has locked, paying them
+/// to the merchant the same way a wallet payment does.
+async fn capture_hold_in(tx: &mut sqlx::PgConnection, hold: &WalletHold) -> Result<WalletHold, (StatusCode, String)> {
+    let hold = resolve_hold(&mut *tx, hold, "captured").await?;
+    let (balance_after,): (Decimal,) = sqlx::query_as(
+        r#"UPDATE wallet_balances SET balance = balance - $1, held = held - $1, updated_at = NOW()
+           WHERE wallet_id = $2 AND currency = $3 RETURNING balance"#
+    )
+    .bind(hold.amount)
+    .bind(hold.wallet_id)
+    .bind(&hold.currency)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    sqlx::query(
+        r#"INSERT INTO wallet_transactions (id, wallet_id, amount, currency, balance_after, transaction_type, reference, created_at)
+           VALUES ($1, $2, $3, $4, $5, 'hold_capture', $6, NOW())"#
+    )
+    .bind(Uuid::now_v7())
+    .bind(hold.wallet_id)
+    .bind(-hold.amount)
+    .bind(&hold.currency)
+    .bind(balance_after)
+    .bind(&hold.reference)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let entries = ledger::posting(&ledger::wallet_account(hold.wallet_id), ledger::MERCHANT_REVENUE, &Money::new(hold.amount, &hold.currency));
+    record_ledger(&mut *tx, &hold.id.to_string(), &entries).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(hold)
+}
+
+/// Returns the held funds to `hold`'s wallet's available balance.
+async fn release_hold_in(tx: &mut sqlx::PgConnection, hold: &WalletHold) -> Result<WalletHold, (StatusCode, String)> {
+    let hold = resolve_hold(&mut *tx, hold, "released").await?;
+    sqlx::query("UPDATE wallet_balances SET held = held - $1, updated_at = NOW() WHERE wallet_id = $2 AND currency = $3")
+        .bind(hold.amount)
+        .bind(hold.wallet_id)
+        .bind(&hold.currency)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(hold)
+}
+
+/// Moves a `held` hold to `status`; a hold is captured or released once.
+async fn resolve_hold(conn: &mut sqlx::PgConnection, hold: &WalletHold, status: &str) -> Result<WalletHold, (StatusCode, String)> {
+    sqlx::query_as::<_, WalletHold>(
+        "UPDATE wallet_holds SET status = $1, resolved_at = NOW() WHERE id = $2 AND status = 'held' RETURNING *"
+    )
+    .bind(status)
+    .bind(hold.id)
+    .fetch_optional(conn)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .ok_or_else(|| (StatusCode::CONFLICT, format!("Hold is already {}", hold.status)))
+}
+
+async fn preview_transfer(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Json(req): Json<TransferRequest>,
+) -> Result<Json<TransferPreview>, (StatusCode, String)> {
+    Ok(Json(load_transfer_preview(&state.db, merchant, &req).await?))
+}
+
+async fn create_transfer(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Json(req): Json<TransferRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let preview = load_transfer_preview(&state.db, merchant, &req).await?;
+
+    // Debit and credit commit together or not at all; dropping `tx` on any early return rolls back
+    let mut tx = state.db.begin().await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    // Lock both wallets in id order so opposing transfers can't deadlock
+    let locked: Vec<(Uuid,)> = sqlx::query_as("SELECT id FROM wallets WHERE id = ANY($1) AND merchant_id = $2 ORDER BY id FOR UPDATE")
+        .bind(vec![req.from_wallet_id, req.to_wallet_id])
+        .bind(merchant.id)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if [req.from_wallet_id, req.to_wallet_id].iter().any(|id| !locked.iter().any(|(locked_id,)| locked_id == id)) {
+        return Err((StatusCode::NOT_FOUND, "Wallet not found".to_string()));
+    }
+
+    // Debit source balance
+    let debited = sqlx::query(
+        "UPDATE wallet_balances SET balance = balance - $1, updated_at = NOW() WHERE wallet_id = $2 AND currency = $3 AND balance - held >= $1"
+    )
+    .bind(preview.total_debit.amount)
+    .bind(req.from_wallet_id)
+    .bind(&preview.total_debit.currency)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    if debited.rows_affected() == 0 {
+        // Balance changed since the preview; report the shortfall against the locked balance
+        let available = fetch_available(&mut *tx, req.from_wallet_id, &preview.total_debit.currency).await?;
+        ensure_sufficient(&Money::new(available, &preview.total_debit.currency), &preview.total_debit).map_err(payment_error_status)?;
+        return Err(payment_error_status(PaymentError::InsufficientFunds("balance changed during transfer".into())));
+    }
+
+    // Credit destination balance, in the converted currency for an FX transfer
+    credit_balance(&mut tx, req.to_wallet_id, &preview.credit).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let transfer_id = Uuid::now_v7();
+    record_ledger(&mut tx, &transfer_id.to_string(), &transfer_entries(req.from_wallet_id, req.to_wallet_id, &preview)).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(serde_json::json!({
+        "id": transfer_id,
+        "status": "completed",
+        "amount": req.amount,
+        "currency": preview.amount.currency,
+        "fee": preview.fee.amount.to_string(),
+        "credit": preview.credit,
+        "from": req.from_wallet_id,
+        "to": req.to_wallet_id
+    })))
+}
+
+/// The postings for a transfer: source to destination, through the FX account when the
+/// credit is in another currency, plus any fee.
+fn transfer_entries(from: Uuid, to: Uuid, preview: &TransferPreview) -> Vec<LedgerEntry> {
+    let (source, destination) = (ledger::wallet_account(from), ledger::wallet_account(to));
+    let mut entries = if preview.credit.currency == preview.amount.currency {
+        ledger::posting(&source, &destination, &preview.amount)
+    } else {
+        let fx = ledger::fx_account(&preview.amount.currency, &preview.credit.currency);
+        let mut entries = ledger::posting(&source, &fx, &preview.amount);
+        entries.extend(ledger::posting(&fx, &destination, &preview.credit));
+        entries
+    };
+    if !preview.fee.amount.is_zero() {
+        entries.extend(ledger::posting(&source, ledger::TRANSFER_FEES, &preview.fee));
+    }
+    entries
+}
+
+/// Computes the balance impact of a transfer from the wallets' current balances.
+/// Both the preview endpoint and the real transfer go through this.
+async fn load_transfer_preview(db: &sqlx::PgPool, merchant: Merchant, req: &TransferRequest) -> Result<TransferPreview, (StatusCode, String)> {
+    req.validate().map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    let source = fetch_wallet(db, merchant, req.from_wallet_id).await?;
+    fetch_wallet(db, merchant, req.to_wallet_id).await?;
+    let currency = wallet_currency(req.currency.as_deref(), &source);
+    let to_currency = req.to_currency.as_deref().map(|c| c.trim().to_ascii_uppercase()).unwrap_or_else(|| currency.clone());
+    let amount = Amount::new(req.amount, &currency).to_money().map_err(payment_error_status)?;
+    let source_balance = Money::new(fetch_available(db, req.from_wallet_id, &currency).await?, &currency);
+    let destination_balance = Money::new(fetch_balance(db, req.to_wallet_id, &to_currency).await?, &to_currency);
+
+    let preview = match (to_currency == currency, req.fx_rate) {
+        (true, None) => sase_payments::domain::services::preview_transfer(&source_balance, &destination_balance, &amount),
+        (false, Some(rate)) => {
+            let fx = FxConversion { to_currency, rate };
+            sase_payments::domain::services::preview_fx_transfer(&source_balance, &destination_balance, &amount, &fx)
+        }
+        (false, None) => Err(PaymentError::CurrencyMismatch { expected: currency, actual: to_currency }),
+        (true, Some(_)) => return Err((StatusCode::BAD_REQUEST, "fx_rate is only valid when to_currency differs from currency".to_string())),
+    };
+    preview.map_err(payment_error_status)
+}
+
+/// The requested currency, or the wallet's default when none is given.
+fn wallet_currency(requested: Option<&str>, wallet: &Wallet) -> String {
+    requested.map(|c| c.trim().to_ascii_uppercase()).unwrap_or_else(|| wallet.currency.clone())
+}
+
+/// The merchant's wallet `id`; another merchant's wallet is not found.
+async fn fetch_wallet(db: &sqlx::PgPool, merchant: Merchant, id: Uuid) -> Result<Wallet, (StatusCode, String)> {
+    sqlx::query_as::<_, Wallet>("SELECT * FROM wallets WHERE id = $1 AND merchant_id = $2")
+        .bind(id)
+        .bind(merchant.id)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Wallet not found".to_string()))
+}
+
+async fn fetch_wallet_view(db: &sqlx::PgPool, merchant: Merchant, id: Uuid) -> Result<WalletView, (StatusCode, String)> {
+    let wallet = fetch_wallet(db, merchant, id).await?;
+    let balances = fetch_balances(db, &[id]).await?;
+    Ok(WalletView { wallet, balances })
+}
+
+async fn fetch_balances(db: &sqlx::PgPool, wallet_ids: &[Uuid]) -> Result<Vec<WalletBalance>, (StatusCode, String)> {
+    sqlx::query_as::<_, WalletBalance>("SELECT *, balance - held AS available FROM wallet_balances WHERE wallet_id = ANY($1) ORDER BY currency")
+        .bind(wallet_ids)
+        .fetch_all(db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// A wallet's balance in `currency`; zero if it has never held any.
+async fn fetch_balance<'e>(db: impl sqlx::PgExecutor<'e>, wallet_id: Uuid, currency: &str) -> Result<Decimal, (StatusCode, String)> {
+    let balance: Option<(Decimal,)> = sqlx::query_as("SELECT balance FROM wallet_balances WHERE wallet_id = $1 AND currency = $2")
+        .bind(wallet_id)
+        .bind(currency)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(balance.map_or(Decimal::ZERO, |(b,)| b))
+}
+
+/// What a wallet can spend in `currency`: its balance less any holds on it.
+async fn fetch_available<'e>(db: impl sqlx::PgExecutor<'e>, wallet_id: Uuid, currency: &str) -> Result<Decimal, (StatusCode, String)> {
+    let available: Option<(Decimal,)> = sqlx::query_as("SELECT balance - held FROM wallet_balances WHERE wallet_id = $1 AND currency = $2")
+        .bind(wallet_id)
+        .bind(currency)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(available.map_or(Decimal::ZERO, |(a,)| a))
+}
+
+/// Writes `entries` under `reference` as part of `tx`. Entries come from
+/// `ledger::posting`, so they always balance.
+async fn record_ledger(tx: &mut sqlx::PgConnection, reference: &str, entries: &[LedgerEntry]) -> Result<(), sqlx::Error> {
+    debug_assert!(ledger::is_balanced(entries), "unbalanced ledger posting for {}", reference);
+    for entry in entries {
+        sqlx::query(
+            r#"INSERT INTO ledger_entries (id, account, direction, amount, currency, reference, created_at)
+               VALUES ($1, $2, $3, $4, $5, $6, NOW())"#
+        )
+        .bind(Uuid::now_v7())
+        .bind(&entry.account)
+        .bind(entry.direction.as_str())
+        .bind(entry.amount.amount)
+        .bind(&entry.amount.currency)
+        .bind(reference)
+        .execute(&mut *tx)
+        .await?;
+    }
+    Ok(())
+}
+
+async fn get_wallet_ledger(
+    State(state): State<AppState>,
+    Extension(merchant): Extension<Merchant>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<LedgerListParams>,
+) -> Result<Json<PaginatedResponse<LedgerEntryRecord>>, (StatusCode, String)> {
+    fetch_wallet(&state.db, merchant, id).await?;
+    let page = params.page.unwrap_or(1).max(1);
+    let per_page = params.per_page.unwrap_or(20).min(100);
+    let offset = ((page - 1) * per_page) as i64;
+    let account = ledger::wallet_account(id);
+    let currency = params.currency.as_deref().map(|c| c.trim().to_ascii_uppercase());
+
+    let entries = sqlx::query_as::<_, LedgerEntryRecord>(
+        r#"SELECT * FROM ledger_entries WHERE account = $1 AND ($2::TEXT IS NULL OR currency = $2)
+           ORDER BY created_at DESC, id DESC LIMIT $3 OFFSET $4"#
+    )
+    .bind(&account)
+    .bind(&currency)
+    .bind(per_page as i64)
+    .bind(offset)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let total: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM ledger_entries WHERE account = $1 AND ($2::TEXT IS NULL OR currency = $2)")
+        .bind(&account)
+        .bind(&currency)
+        .fetch_one(&state.db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(PaginatedResponse { data: entries, total: total.0, page, per_page, next_cursor: None }))
+}
+
+/// Adds `amount` to the wallet's balance in its currency, opening that balance if
+/// needed. Returns the new balance.
+async fn credit_balance(tx: &mut sqlx::PgConnection, wallet_id: Uuid, amount: &Money) -> Result<Decimal, sqlx::Error> {
+    let (balance,): (Decimal,) = sqlx::query_as(
+        r#"INSERT INTO wallet_balances (wallet_id, currency, balance, updated_at) VALUES ($1, $2, $3, NOW())
+           ON CONFLICT (wallet_id, currency) DO UPDATE SET balance = wallet_balances.balance + EXCLUDED.balance, updated_at = NOW()
+           RETURNING balance"#
+    )
+    .bind(wallet_id)
+    .bind(&amount.currency)
+    .bind(amount.amount)
+    .fetch_one(tx)
+    .await?;
+    Ok(balance)
+}
+
+/// Stores `Payment` aggregates in `payments`, with their events in the outbox.
+pub struct PgPaymentRepository {
+    db: sqlx::PgPool,
+}
+
+impl PgPaymentRepository {
+    pub fn new(db: sqlx::PgPool) -> Self { Self { db } }
+}
+
+#[derive(sqlx::FromRow)]
+struct PaymentRow {
+    id: String,
+    customer_id: String,
+    amount: Decimal,
+    currency: String,
+    status: String,
+    payment_method: Option<sqlx::types::Json<PaymentMethodDetails>>,
+    description: Option<String>,
+    metadata: sqlx::types::Json<HashMap<String, String>>,
+    refunded_amount: Decimal,
+    failure_reason: Option<String>,
+    created_at: DateTime<Utc>,
+    version: i64,
+}
+
+impl TryFrom<PaymentRow> for PaymentRecord {
+    type Error = RepositoryError;
+    fn try_from(row: PaymentRow) -> Result<Self, Self::Error> {
+        Ok(PaymentRecord {
+            status: row.status.parse().map_err(RepositoryError::Corrupt)?,
+            id: PaymentId::from_string(row.id),
+            customer_id: row.customer_id,
+            amount: Money::new(row.amount, &row.currency),
+            payment_method: row.payment_method.map(|m| m.0),
+            description: row.description,
+            metadata: row.metadata.0,
+            refunded_amount: row.refunded_amount,
+            failure_reason: row.failure_reason,
+            created_at: row.created_at,
+            version: u64::try_from(row.version).map_err(|_| RepositoryError::Corrupt(format!("negative version {}", row.version)))?,
+        })
+    }
+}
+
+impl TryFrom<&Subscription> for SubscriptionRecord {
+    type Error = RepositoryError;
+    fn try_from(row: &Subscription) -> Result<Self, Self::Error> {
+        let corrupt = |field: &str, value: &str| RepositoryError::Corrupt(format!("unknown {} '{}'", field, value));
+        Ok(SubscriptionRecord {
+            id: row.id.to_string(),
+            customer_id: row.customer_id.to_string(),
+            plan_id: row.plan_id.clone(),
+            status: SubscriptionStatus::parse(&row.status).ok_or_else(|| corrupt("status", &row.status))?,
+            current_period_start: row.current_period_start,
+            current_period_end: row.current_period_end,
+            billing_cycle: BillingCycle::parse(&row.billing_cycle).ok_or_else(|| corrupt("billing cycle", &row.billing_cycle))?,
+            amount: Money::new(row.amount, &row.currency),
+            cancel_at_period_end: row.cancel_at_period_end,
+            metadata: row.metadata.0.clone(),
+            total_paid: Money::new(row.total_paid, &row.currency),
+            renewals: row.renewal_count.max(0) as u32,
+            consecutive_failures: row.consecutive_failures.max(0) as u32,
+            last_payment_failed_at: row.last_payment_failed_at,
+            created_at: row.created_at,
+        })
+    }
+}
+
+fn storage_error(e: sqlx::Error) -> RepositoryError { RepositoryError::Storage(e.to_string()) }
+
+#[async_trait::async_trait]
+impl PaymentRepository for PgPaymentRepository {
+    async fn save(&self, payment: &mut Payment) -> Result<(), RepositoryError> {
+        let record = payment.to_record();
+        let mut tx = self.db.begin().await.map_err(storage_error)?;
+        let saved = sqlx::query(
+            r#"INSERT INTO payments (id, customer_id, amount, currency, status, payment_method, description, metadata, refunded_amount, failure_reason, created_at, version, updated_at)
+               VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12 + 1, NOW())
+               ON CONFLICT (id) DO UPDATE SET status = EXCLUDED.status, payment_method = EXCLUDED.payment_method, description = EXCLUDED.description,
+                   metadata = EXCLUDED.metadata, refunded_amount = EXCLUDED.refunded_amount, failure_reason = EXCLUDED.failure_reason,
+                   version = EXCLUDED.version, updated_at = NOW()
+               WHERE payments.version = $12"#
+        )
+        .bind(record.id.as_str())
+        .bind(&record.customer_id)
+        .bind(record.amount.amount)
+        .bind(&record.amount.currency)
+        .bind(record.status.as_str())
+        .bind(record.payment_method.as_ref().map(sqlx::types::Json))
+        .bind(&record.description)
+        .bind(sqlx::types::Json(&record.metadata))
+        .bind(record.refunded_amount)
+        .bind(&record.failure_reason)
+        .bind(record.created_at)
+        .bind(record.version as i64)
+        .execute(&mut *tx)
+        .await
+        .map_err(storage_error)?;
+        // No row written: it exists at another version than the one we loaded
+        if saved.rows_affected() == 0 { return Err(PaymentError::ConcurrencyConflict.into()); }
+        for event in payment.pending_events() {
+            insert_outbox(&mut tx, None, event).await.map_err(storage_error)?;
+        }
+        tx.commit().await.map_err(storage_error)?;
+        payment.pending_events().iter().for_each(count_payment_event);
+        payment.mark_saved();
+        Ok(())
+    }
+
+    async fn load(&self, id: &PaymentId) -> Result<Option<Payment>, RepositoryError> {
+        let row: Option<PaymentRow> = sqlx::query_as("SELECT * FROM payments WHERE id = $1")
+            .bind(id.as_str())
+            .fetch_optional(&self.db)
+            .await
+            .map_err(storage_error)?;
+        row.map(|row| PaymentRecord::try_from(row).map(Payment::from_record)).transpose()
+    }
+}
+
+/// Stores `Invoice` aggregates in `invoices` and `invoice_lines`.
+pub struct PgInvoiceRepository {
+    db: sqlx::PgPool,
+}
+
+impl PgInvoiceRepository {
+    pub fn new(db: sqlx::PgPool) -> Self { Self { db } }
+
+    /// The save, inside a transaction the caller already holds. A new invoice takes its
+    /// subscription's merchant.
+    async fn save_in(conn: &mut sqlx::PgConnection, invoice: &Invoice) -> Result<(), RepositoryError> {
+        let saved = sqlx::query(
+            r#"INSERT INTO invoices (id, subscription_id, reference, currency, subtotal, tax, total, status, created_at, finalized_at, merchant_id)
+               VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, (SELECT merchant_id FROM subscriptions WHERE id::text = $2))
+               ON CONFLICT (id) DO UPDATE SET subtotal = EXCLUDED.subtotal, tax = EXCLUDED.tax, total = EXCLUDED.total,
+                   status = EXCLUDED.status, finalized_at = EXCLUDED.finalized_at
+               WHERE invoices.status = 'draft'"#
+        )
+        .bind(invoice.id())
+        .bind(invoice.subscription_id())
+        .bind(invoice.reference())
+        .bind(invoice.currency())
+        .bind(invoice.subtotal()?.amount)
+        .bind(invoice.tax().amount)
+        .bind(invoice.total()?.amount)
+        .bind(invoice.status().as_str())
+        .bind(invoice.created_at())
+        .bind(invoice.finalized_at())
+        .execute(&mut *conn)
+        .await
+        .map_err(storage_error)?;
+        if saved.rows_affected() == 0 { return Err(InvoiceError::Finalized.into()); }
+        sqlx::query("DELETE FROM invoice_lines WHERE invoice_id = $1").bind(invoice.id()).execute(&mut *conn).await.map_err(storage_error)?;
+        for (position, line) in invoice.lines().iter().enumerate() {
+            sqlx::query(
+                "INSERT INTO invoice_lines (invoice_id, position, description, quantity, unit_amount, amount) VALUES ($1, $2, $3, $4, $5, $6)"
+            )
+            .bind(invoice.id())
+            .bind(position as i32)
+            .bind(&line.description)
+            .bind(line.quantity)
+            .bind(line.unit_amount.amount)
+            .bind(line.amount.amount)
+            .execute(&mut *conn)
+            .await
+            .map_err(storage_error)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct InvoiceRow {
+    id: String,
+    subscription_id: String,
+    reference: String,
+    currency: String,
+    tax: Decimal,
+    status: String,
+    created_at: DateTime<Utc>,
+    finalized_at: Option<DateTime<Utc>>,
+}
+
+#[async_trait::async_trait]
+impl InvoiceRepository for PgInvoiceRepository {
+    async fn save(&self, invoice: &Invoice) -> Result<(), RepositoryError> {
+        let mut tx = self.db.begin().await.map_err(storage_error)?;
+        Self::save_in(&mut tx, invoice).await?;
+        tx.commit().await.map_err(storage_error)
+    }
+
+    async fn load(&self, id: &str) -> Result<Option<Invoice>, RepositoryError> {
+        let row: Option<InvoiceRow> = sqlx::query_as(
+            "SELECT id, subscription_id, reference, currency, tax, status, created_at, finalized_at FROM invoices WHERE id = $1"
+        )
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await
+        .map_err(storage_error)?;
+        let Some(row) = row else { return Ok(None) };
+        let lines: Vec<(String, i64, Decimal, Decimal)> = sqlx::query_as(
+            "SELECT description, quantity, unit_amount, amount FROM invoice_lines WHERE invoice_id = $1 ORDER BY position"
+        )
+        .bind(id)
+        .fetch_all(&self.db)
+        .await
+        .map_err(storage_error)?;
+        Ok(Some(Invoice::from_record(InvoiceRecord {
+            status: InvoiceStatus::parse(&row.status).ok_or_else(|| RepositoryError::Corrupt(format!("unknown invoice status '{}'", row.status)))?,
+            tax: Money::new(row.tax, &row.currency),
+            lines: lines.into_iter().map(|(description, quantity, unit_amount, amount)| InvoiceLine {
+                description, quantity, unit_amount: Money::new(unit_amount, &row.currency), amount: Money::new(amount, &row.currency),
+            }).collect(),
+            id: row.id,
+            subscription_id: row.subscription_id,
+            reference: row.reference,
+            currency: row.currency,
+            created_at: row.created_at,
+            finalized_at: row.finalized_at,
+        })))
+    }
+}
+
+/// An error response: a status for its cause and a `{ "error": code, "message": ... }`
+/// body, plus `decline_code` for card declines.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: String,
+    pub message: String,
+    pub decline_code: Option<&'static str>,
+}
+
+impl ApiError {
+    pub fn body(&self) -> serde_json::Value {
+        let mut body = serde_json::json!({ "error": self.code, "message": self.message });
+        if let Some(decline_code) = self.decline_code { body["decline_code"] = decline_code.into(); }
+        body
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response { (self.status, Json(self.body())).into_response() }
+}
+
+impl From<PaymentError> for ApiError {
+    fn from(e: PaymentError) -> Self {
+        let status = match &e {
+            PaymentError::PaymentNotFound(_) => StatusCode::NOT_FOUND,
+            PaymentError::Declined(_) | PaymentError::InsufficientFunds(_) => StatusCode::PAYMENT_REQUIRED,
+            PaymentError::InvalidAmount(_) | PaymentError::InvalidCurrency(_) | PaymentError::InvalidDescriptor(_) => StatusCode::BAD_REQUEST,
+            PaymentError::CurrencyMismatch { .. } | PaymentError::UnsupportedCurrency { .. } | PaymentError::RefundFailed(_)
+            | PaymentError::UnknownExchangeRate { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            PaymentError::ProviderError { .. } => StatusCode::BAD_GATEWAY,
+            _ => StatusCode::CONFLICT,
+        };
+        let decline_code = match &e { PaymentError::Declined(code) => Some(code.as_str()), _ => None };
+        // Provider messages can carry hosts and raw responses; they stay in the logs
+        let message = match &e { PaymentError::ProviderError { .. } => classify(&e).message.to_string(), _ => e.to_string() };
+        ApiError { status, code: e.code().to_string(), message, decline_code }
+    }
+}
+
+/// Errors raised as a bare status and message take the status's name as their code.
+impl From<(StatusCode, String)> for ApiError {
+    fn from((status, message): (StatusCode, String)) -> Self {
+        let code = status.canonical_reason().unwrap_or("error").to_lowercase().replace([' ', '-'], "_");
+        ApiError { status, code, message, decline_code: None }
+    }
+}
+
+fn payment_error_status(e: PaymentError) -> (StatusCode, String) {
+    let e = ApiError::from(e);
+    (e.status, e.message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use std::io::Read;
+    use sase_payments::providers::{AvsResult, CardChecks, CvvResult, MockGateway};
+    use tower::ServiceExt;
+
+    fn test_state(db: sqlx::PgPool) -> AppState {
+        test_state_with_gateway(db, Arc::new(StubGateway))
+    }
+
+    const TEST_PAYSTACK_SECRET: &str = "sk_test_webhooks";
+    const TEST_FLUTTERWAVE_HASH: &str = "flw_test_hash";
+
+    /// A webhook body with a valid Paystack signature for the test secret.
+    fn signed_webhook(payload: &serde_json::Value) -> (HeaderMap, Bytes) {
+        let body = payload.to_string();
+        let mut headers = HeaderMap::new();
+        let signature = sase_payments::crypto::hmac_sha512_hex(TEST_PAYSTACK_SECRET.as_bytes(), body.as_bytes());
+        headers.insert(paystack::SIGNATURE_HEADER, signature.parse().unwrap());
+        (headers, Bytes::from(body))
+    }
+
+    fn test_state_with_gateway(db: sqlx::PgPool, gateway: Arc<dyn PaymentGateway>) -> AppState {
+        let config = Config {
+            port: 0,
+            database_url: String::new(),
+            db_max_connections: 10,
+            db_acquire_timeout_secs: 30,
+            db_idle_timeout_secs: 600,
+            nats_url: None,
+            paystack_secret: Some(TEST_PAYSTACK_SECRET.to_string()),
+            flutterwave_secret: None,
+            flutterwave_secret_hash: Some(TEST_FLUTTERWAVE_HASH.to_string()),
+            admin_token: Some("admin-secret".to_string()),
+            max_refunds_per_transaction: 2,
+            test_mode: false,
+            require_idempotency_key: false,
+            card_expiry_notice_days: 30,
+            card_expiry_scan_interval_secs: 3600,
+            renewal_interval_secs: 3600,
+            scheduled_payment_interval_secs: 60,
+            currency_policy: CurrencyPolicy::default(),
+            amount_limits: HashMap::new(),
+            provider_capabilities: HashMap::new(),
+            provider_routes: ProviderRouter::default(),
+            statement_descriptor: None,
+            webhook_allowlist: WebhookAllowlist::default(),
+            decline_status: StatusCode::PAYMENT_REQUIRED,
+            archive_retention_days: None,
+            archive_dir: String::new(),
+            archive_move_rows: false,
+            archive_batch_size: 1000,
+            archive_interval_secs: 86400,
+            outbox_poll_interval_secs: 5,
+            webhook_delivery_interval_secs: 10,
+            provider_retry: RetryPolicy::NONE,
+            payment_rate_limit: None,
+            shutdown_grace_secs: 25,
+            checkout_signing_secret: Some(TEST_CHECKOUT_SECRET.into()),
+            checkout_link_ttl_secs: 3600,
+            authorization_ttl_secs: 7 * 24 * 3600,
+            authorization_expiry_interval_secs: 300,
+            tax_rates: TaxRates::default(),
+            dunning: DunningPolicy::default(),
+        };
+        AppState { db, nats: None, http: reqwest::Client::new(), gateway, gateways: Arc::new(vec![]), config: Arc::new(config), payment_limiter: None }
+    }
+
+    const TEST_CHECKOUT_SECRET: &str = "cks_test";
+    const TEST_MERCHANT: Uuid = Uuid::from_u128(0x5a5e);
+    const TEST_API_KEY: &str = "sk_test_merchant";
+
+    fn merchant() -> Extension<Merchant> { Extension(Merchant::new(TEST_MERCHANT)) }
+
+    async fn seed_api_key(db: &sqlx::PgPool, merchant_id: Uuid, key: &str) -> Uuid {
+        let id = Uuid::now_v7();
+        sqlx::query("INSERT INTO api_keys (id, merchant_id, key_hash, prefix) VALUES ($1, $2, $3, $4)")
+            .bind(id)
+            .bind(merchant_id)
+            .bind(sase_payments::crypto::sha256_hex(key.as_bytes()))
+            .bind(&key[..key.len().min(8)])
+            .execute(db)
+            .await
+            .unwrap();
+        id
+    }
+
+    async fn seed_transaction(db: &sqlx::PgPool, amount: Decimal, status: &str) -> Uuid {
+        let id = Uuid::now_v7();
+        sqlx::query(
+            r#"INSERT INTO transactions (id, reference, amount, currency, status, transaction_type, merchant_id, created_at, updated_at)
+               VALUES ($1, $2, $3, 'NGN', $4, 'payment', $5, NOW(), NOW())"#
+        )
+        .bind(id)
+        .bind(format!("TXN-{}", id))
+        .bind(amount)
+        .bind(status)
+        .bind(TEST_MERCHANT)
+        .execute(db)
+        .await
+        .unwrap();
+        id
+    }
+
+    fn refund_request(transaction_id: Uuid, amount: i64) -> RefundRequest {
+        RefundRequest { transaction_id, amount: Some(amount), reason: None }
+    }
+
+    #[sqlx::test]
+    async fn test_refund_accounting(db: sqlx::PgPool) {
+        let mut state = test_state(db.clone());
+        state.config = Arc::new(Config { max_refunds_per_transaction: 10, ..Config::clone(&state.config) });
+        let status_of = |txn_id: Uuid| {
+            let db = db.clone();
+            async move { sqlx::query_as::<_, (String,)>("SELECT status FROM transactions WHERE id = $1").bind(txn_id).fetch_one(&db).await.unwrap().0 }
+        };
+
+        // Exactly the full amount in one go
+        let full = seed_transaction(&db, Decimal::new(10000, 2), "succeeded").await;
+        let request: RefundRequest = serde_json::from_value(serde_json::json!({ "transaction_id": full, "amount": 10000, "reason": "Requested_By_Customer" })).unwrap();
+        let (_, Json(refund)) = create_refund(State(state.clone()), merchant(), Json(request)).await.unwrap();
+        assert_eq!(refund.reason.as_deref(), Some("requested_by_customer"));
+        assert_eq!(status_of(full).await, "refunded");
+
+        // Partial refunds accumulate up to, but not past, the charge
+        let partial = seed_transaction(&db, Decimal::new(10000, 2), "succeeded").await;
+        let _ = create_refund(State(state.clone()), merchant(), Json(refund_request(partial, 3000))).await.unwrap();
+        assert_eq!(status_of(partial).await, "partially_refunded");
+        let over = create_refund(State(state.clone()), merchant(), Json(refund_request(partial, 7001))).await.unwrap_err();
+        assert_eq!(over.0, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(over.1.starts_with("Refund failed:"), "{}", over.1);
+        assert_eq!(status_of(partial).await, "partially_refunded");
+
+        // A failed refund frees its amount up again
+        sqlx::query("UPDATE refunds SET status = 'failed' WHERE transaction_id = $1").bind(partial).execute(&db).await.unwrap();
+        let _ = create_refund(State(state.clone()), merchant(), Json(refund_request(partial, 7000))).await.unwrap();
+        assert_eq!(status_of(partial).await, "partially_refunded");
+        let _ = create_refund(State(state.clone()), merchant(), Json(refund_request(partial, 3000))).await.unwrap();
+        assert_eq!(status_of(partial).await, "refunded");
+
+        // Once fully refunded, the transaction can't move again
+        let again = create_refund(State(state), merchant(), Json(refund_request(full, 1))).await.unwrap_err();
+        assert_eq!(again.0, StatusCode::CONFLICT);
+    }
+
+    #[sqlx::test]
+    async fn test_concurrent_refunds_cannot_exceed_amount(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let txn_id = seed_transaction(&db, Decimal::new(10000, 2), "succeeded").await;
+
+        let (a, b) = tokio::join!(
+            create_refund(State(state.clone()), merchant(), Json(refund_request(txn_id, 6000))),
+            create_refund(State(state.clone()), merchant(), Json(refund_request(txn_id, 6000))),
+        );
+
+        assert_eq!([a.is_ok(), b.is_ok()].iter().filter(|ok| **ok).count(), 1);
+        let rejected = a.err().or(b.err()).unwrap();
+        assert_eq!(rejected.0, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let refunded: (Decimal,) = sqlx::query_as("SELECT SUM(amount) FROM refunds WHERE transaction_id = $1")
+            .bind(txn_id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(refunded.0, Decimal::new(6000, 2));
+    }
+
+    #[sqlx::test]
+    async fn test_provider_response_stored_redacted_and_exposed_to_admin(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let txn_id = seed_transaction(&db, Decimal::new(10000, 2), "pending").await;
+        let reference = format!("TXN-{}", txn_id);
+        let raw = serde_json::json!({
+            "event": "charge.success",
+            "data": {
+                "reference": reference,
+                "gateway_response": "Approved",
+                "authorization": { "bin": "408408", "last4": "4081", "card_type": "visa" },
+                "customer": { "email": "ada@example.com" }
+            }
+        });
+
+        let (headers, body) = signed_webhook(&raw);
+        assert_eq!(webhook_handler(State(state.clone()), None, headers, WebhookBody(body)).await.into_response().status(), StatusCode::OK);
+
+        let mut headers = HeaderMap::new();
+        let denied = get_transaction_debug(State(state.clone()), headers.clone(), Path(txn_id)).await;
+        assert_eq!(denied.unwrap_err().0, StatusCode::FORBIDDEN);
+        headers.insert("x-admin-token", "admin-secreT".parse().unwrap());
+        let wrong = get_transaction_debug(State(state.clone()), headers.clone(), Path(txn_id)).await;
+        assert_eq!(wrong.unwrap_err().0, StatusCode::FORBIDDEN);
+
+        headers.insert("x-admin-token", "admin-secret".parse().unwrap());
+        let Json(debug) = get_transaction_debug(State(state), headers, Path(txn_id)).await.unwrap();
+        let stored = &debug["provider_raw_response"]["data"];
+        assert_eq!(stored["gateway_response"], "Approved");
+        assert_eq!(stored["authorization"]["last4"], "[REDACTED]");
+        assert_eq!(stored["customer"]["email"], "[REDACTED]");
+        assert!(!debug.to_string().contains("4081"));
+    }
+
+    #[sqlx::test]
+    async fn test_refund_count_limit(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let txn_id = seed_transaction(&db, Decimal::new(10000, 2), "succeeded").await;
+
+        for _ in 0..2 {
+            let _ = create_refund(State(state.clone()), merchant(), Json(refund_request(txn_id, 100))).await.unwrap();
+        }
+        let err = create_refund(State(state.clone()), merchant(), Json(refund_request(txn_id, 100))).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(err.1.contains("maximum of 2 refunds"));
+
+        // A merchant with a limit of its own gets that one instead
+        let generous = Merchant { max_refunds_per_transaction: Some(3), ..Merchant::new(Uuid::now_v7()) };
+        let theirs = seed_transaction(&db, Decimal::new(10000, 2), "succeeded").await;
+        sqlx::query("UPDATE transactions SET merchant_id = $1 WHERE id = $2").bind(generous.id).bind(theirs).execute(&db).await.unwrap();
+        for _ in 0..3 {
+            let _ = create_refund(State(state.clone()), Extension(generous), Json(refund_request(theirs, 100))).await.unwrap();
+        }
+        let err = create_refund(State(state), Extension(generous), Json(refund_request(theirs, 100))).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(err.1.contains("maximum of 3 refunds"));
+    }
+
+    #[sqlx::test]
+    async fn test_webhook_endpoint_registration(db: sqlx::PgPool) {
+        let state = test_state(db);
+
+        let rejected = create_webhook_endpoint(
+            State(state.clone()), merchant(),
+            Json(CreateWebhookEndpointRequest { url: "http://merchant.example/hooks".into() }),
+        ).await.unwrap_err();
+        assert_eq!(rejected.0, StatusCode::BAD_REQUEST);
+
+        let (status, Json(created)) = create_webhook_endpoint(
+            State(state.clone()), merchant(),
+            Json(CreateWebhookEndpointRequest { url: "https://merchant.example/hooks".into() }),
+        ).await.unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(created.secret.starts_with("whsec_"));
+
+        let Json(listed) = list_webhook_endpoints(State(state), merchant()).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert!(serde_json::to_value(&listed[0]).unwrap().get("secret").is_none());
+    }
+
+    async fn seed_wallet(db: &sqlx::PgPool, balance: Decimal) -> Uuid {
+        seed_wallet_in(db, balance, "NGN").await
+    }
+
+    async fn seed_wallet_in(db: &sqlx::PgPool, balance: Decimal, currency: &str) -> Uuid {
+        let id = Uuid::now_v7();
+        sqlx::query(
+            r#"INSERT INTO wallets (id, customer_id, currency, status, merchant_id, created_at, updated_at)
+               VALUES ($1, $2, $3, 'active', $4, NOW(), NOW())"#
+        )
+        .bind(id)
+        .bind(Uuid::now_v7())
+        .bind(currency)
+        .bind(TEST_MERCHANT)
+        .execute(db)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO wallet_balances (wallet_id, currency, balance) VALUES ($1, $2, $3)")
+            .bind(id)
+            .bind(currency)
+            .bind(balance)
+            .execute(db)
+            .await
+            .unwrap();
+        id
+    }
+
+    async fn balance_of(db: &sqlx::PgPool, wallet_id: Uuid, currency: &str) -> Decimal {
+        fetch_balance(db, wallet_id, currency).await.unwrap()
+    }
+
+    fn transfer_request(from_wallet_id: Uuid, to_wallet_id: Uuid, amount: i64) -> TransferRequest {
+        TransferRequest { from_wallet_id, to_wallet_id, amount, currency: None, to_currency: None, fx_rate: None, description: None }
+    }
+
+    #[sqlx::test]
+    async fn test_transfer_preview_matches_execution(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let from = seed_wallet(&db, Decimal::new(10000, 2)).await;
+        let to = seed_wallet(&db, Decimal::new(500, 2)).await;
+
+        let Json(preview) = preview_transfer(State(state.clone()), merchant(), Json(transfer_request(from, to, 2500))).await.unwrap();
+        let _ = create_transfer(State(state.clone()), merchant(), Json(transfer_request(from, to, 2500))).await.unwrap();
+
+        assert_eq!(balance_of(&db, from, "NGN").await, preview.source_balance_after.amount);
+        assert_eq!(balance_of(&db, to, "NGN").await, preview.destination_balance_after.amount);
+
+        let overdraw = preview_transfer(State(state.clone()), merchant(), Json(transfer_request(from, to, 1_000_000))).await.unwrap_err();
+        let executed = create_transfer(State(state), merchant(), Json(transfer_request(from, to, 1_000_000))).await.unwrap_err();
+        assert_eq!(overdraw, executed);
+        assert_eq!(overdraw.0, StatusCode::PAYMENT_REQUIRED);
+    }
+
+    #[sqlx::test]
+    async fn test_transfer_is_atomic(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let from = seed_wallet(&db, Decimal::new(10000, 2)).await;
+        let foreign = seed_wallet_in(&db, Decimal::ZERO, "GHS").await;
+
+        let to_cedis = TransferRequest { to_currency: Some("GHS".into()), ..transfer_request(from, foreign, 2500) };
+        let mismatch = create_transfer(State(state.clone()), merchant(), Json(to_cedis)).await.unwrap_err();
+        assert_eq!(mismatch.0, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(balance_of(&db, from, "NGN").await, Decimal::new(10000, 2));
+        assert_eq!(balance_of(&db, foreign, "GHS").await, Decimal::ZERO);
+
+        let missing = create_transfer(State(state), merchant(), Json(transfer_request(from, Uuid::now_v7(), 2500))).await.unwrap_err();
+        assert_eq!(missing.0, StatusCode::NOT_FOUND);
+        assert_eq!(balance_of(&db, from, "NGN").await, Decimal::new(10000, 2));
+    }
+
+    #[sqlx::test]
+    async fn test_wallet_payment(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let wallet = seed_wallet(&db, Decimal::new(10000, 2)).await;
+        let (customer_id,): (Uuid,) = sqlx::query_as("SELECT customer_id FROM wallets WHERE id = $1").bind(wallet).fetch_one(&db).await.unwrap();
+        let pay = |amount: i64| InitiatePaymentRequest { customer_id: Some(customer_id), payment_method: Some("wallet".into()), ..initiate_request(amount) };
+        let transactions = || {
+            let db = db.clone();
+            async move { sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM transactions").fetch_one(&db).await.unwrap().0 }
+        };
+
+        let Json(paid) = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(pay(6000))).await.unwrap();
+        assert_eq!(paid.status, "succeeded");
+        assert!(paid.authorization_url.is_none());
+        let txn = sqlx::query_as::<_, Transaction>("SELECT * FROM transactions WHERE reference = $1").bind(&paid.reference).fetch_one(&db).await.unwrap();
+        assert_eq!((txn.status.as_str(), txn.amount, txn.customer_id), ("succeeded", Decimal::new(6000, 2), Some(customer_id)));
+        assert!(txn.completed_at.is_some());
+        assert_eq!(balance_of(&db, wallet, "NGN").await, Decimal::new(4000, 2));
+        let postings: Vec<(String, String, Decimal)> = sqlx::query_as("SELECT account, direction, amount FROM ledger_entries WHERE reference = $1 ORDER BY direction DESC")
+            .bind(&paid.reference).fetch_all(&db).await.unwrap();
+        assert_eq!(postings, vec![
+            (format!("wallet:{}", wallet), "debit".to_string(), Decimal::new(6000, 2)),
+            ("revenue:payments".to_string(), "credit".to_string(), Decimal::new(6000, 2)),
+        ]);
+
+        // Short balance, or none in the currency: no transaction, no debit
+        let before = transactions().await;
+        let short = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(pay(4001))).await.unwrap_err();
+        assert_eq!((short.status, short.code.as_str()), (StatusCode::PAYMENT_REQUIRED, "insufficient_funds"));
+        let dollars = InitiatePaymentRequest { amount: Amount::new(100, "USD"), ..pay(100) };
+        assert_eq!(initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(dollars)).await.unwrap_err().status, StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(transactions().await, before);
+        assert_eq!(balance_of(&db, wallet, "NGN").await, Decimal::new(4000, 2));
+
+        let anonymous = InitiatePaymentRequest { customer_id: None, ..pay(100) };
+        assert_eq!(initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(anonymous)).await.unwrap_err().status, StatusCode::BAD_REQUEST);
+        let stranger = InitiatePaymentRequest { customer_id: Some(Uuid::now_v7()), ..pay(100) };
+        assert_eq!(initiate_payment(State(state), merchant(), HeaderMap::new(), Json(stranger)).await.unwrap_err().status, StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn test_merchants_only_reach_their_own_resources(db: sqlx::PgPool) {
+        use chrono::Datelike;
+        let state = test_state(db.clone());
+        let other = || Extension(Merchant::new(Uuid::now_v7()));
+        let wallet = seed_wallet(&db, Decimal::new(10000, 2)).await;
+        let (customer_id,): (Uuid,) = sqlx::query_as("SELECT customer_id FROM wallets WHERE id = $1").bind(wallet).fetch_one(&db).await.unwrap();
+        let card = seed_card(&db, 12, (Utc::now().year() + 1) as i16).await;
+        let (_, Json(subscription)) = create_subscription(State(state.clone()), merchant(), Json(CreateSubscriptionRequest {
+            customer_id,
+            plan_id: "PLAN_PRO".into(),
+            amount: Amount::new(4900, "NGN"),
+            billing_cycle: None,
+            payment_method_id: None,
+            billing_details: None,
+            metadata: Default::default(),
+        })).await.unwrap();
+        let (_, Json(endpoint)) = create_webhook_endpoint(
+            State(state.clone()), merchant(),
+            Json(CreateWebhookEndpointRequest { url: "https://merchant.example/hooks".into() }),
+        ).await.unwrap();
+
+        // Another merchant's ids are not found, and their lists are empty
+        assert!(list_wallets(State(state.clone()), other()).await.unwrap().0.is_empty());
+        assert_eq!(get_wallet(State(state.clone()), other(), Path(wallet)).await.unwrap_err().0, StatusCode::NOT_FOUND);
+        let topup = WalletTopupRequest { customer_id, amount: 5000, currency: None };
+        assert_eq!(topup_wallet(State(state.clone()), other(), Path(wallet), Json(topup)).await.unwrap_err().0, StatusCode::NOT_FOUND);
+        let peer = seed_wallet(&db, Decimal::ZERO).await;
+        assert_eq!(create_transfer(State(state.clone()), other(), Json(transfer_request(wallet, peer, 2500))).await.unwrap_err().0, StatusCode::NOT_FOUND);
+        assert_eq!(set_default_payment_method(State(state.clone()), other(), Path(card)).await.unwrap_err().0, StatusCode::NOT_FOUND);
+        let deleted = delete_payment_method(State(state.clone()), other(), Path(card), Query(DeletePaymentMethodParams { force: true })).await;
+        assert_eq!(deleted.unwrap_err().0, StatusCode::NOT_FOUND);
+        assert_eq!(get_subscription(State(state.clone()), other(), Path(subscription.id)).await.unwrap_err().0, StatusCode::NOT_FOUND);
+        let usage = RecordUsageRequest { quantity: 1, unit_amount: 100 };
+        assert_eq!(record_usage(State(state.clone()), other(), Path(subscription.id), Json(usage)).await.unwrap_err().0, StatusCode::NOT_FOUND);
+        assert!(list_webhook_endpoints(State(state.clone()), other()).await.unwrap().0.is_empty());
+        assert_eq!(test_webhook_endpoint(State(state.clone()), other(), Path(endpoint.endpoint.id)).await.unwrap_err().0, StatusCode::NOT_FOUND);
+        assert_eq!(delete_webhook_endpoint(State(state.clone()), other(), Path(endpoint.endpoint.id)).await.unwrap_err(), (StatusCode::NOT_FOUND, "Webhook endpoint not found".to_string()));
+
+        // Nor can another merchant charge this merchant's customer's wallet
+        let pay = InitiatePaymentRequest { customer_id: Some(customer_id), payment_method: Some("wallet".into()), ..initiate_request(100) };
+        assert_eq!(initiate_payment(State(state.clone()), other(), HeaderMap::new(), Json(pay)).await.unwrap_err().status, StatusCode::NOT_FOUND);
+        assert_eq!(balance_of(&db, wallet, "NGN").await, Decimal::new(10000, 2));
+        assert_eq!(list_wallets(State(state), merchant()).await.unwrap().0.len(), 2);
+    }
+
+    #[sqlx::test]
+    async fn test_wallet_balances_per_currency(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let alice = seed_wallet(&db, Decimal::new(10000, 2)).await;
+        let bob = seed_wallet(&db, Decimal::ZERO).await;
+
+        let dollars = WalletTopupRequest { customer_id: Uuid::now_v7(), amount: 5000, currency: Some("usd".into()) };
+        let _ = topup_wallet(State(state.clone()), merchant(), Path(alice), Json(dollars)).await.unwrap();
+        let Json(view) = get_wallet(State(state.clone()), merchant(), Path(alice)).await.unwrap();
+        let held: Vec<(String, Decimal)> = view.balances.iter().map(|b| (b.currency.clone(), b.balance)).collect();
+        assert_eq!(held, vec![("NGN".to_string(), Decimal::new(10000, 2)), ("USD".to_string(), Decimal::new(5000, 2))]);
+
+        // Moving dollars leaves both wallets' naira alone; Bob's dollar balance opens on first credit
+        let send_usd = TransferRequest { currency: Some("USD".into()), ..transfer_request(alice, bob, 2000) };
+        let _ = create_transfer(State(state.clone()), merchant(), Json(send_usd)).await.unwrap();
+        assert_eq!(balance_of(&db, alice, "USD").await, Decimal::new(3000, 2));
+        assert_eq!(balance_of(&db, bob, "USD").await, Decimal::new(2000, 2));
+        assert_eq!(balance_of(&db, alice, "NGN").await, Decimal::new(10000, 2));
+        assert_eq!(balance_of(&db, bob, "NGN").await, Decimal::ZERO);
+
+        // Dollars can only arrive as naira with an explicit rate
+        let usd_to_ngn = || TransferRequest { currency: Some("USD".into()), to_currency: Some("NGN".into()), ..transfer_request(alice, bob, 1000) };
+        let rejected = create_transfer(State(state.clone()), merchant(), Json(usd_to_ngn())).await.unwrap_err();
+        assert_eq!(rejected.0, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(balance_of(&db, alice, "USD").await, Decimal::new(3000, 2));
+        let converted = TransferRequest { fx_rate: Some(Decimal::new(1500, 0)), ..usd_to_ngn() };
+        let _ = create_transfer(State(state.clone()), merchant(), Json(converted)).await.unwrap();
+        assert_eq!(balance_of(&db, alice, "USD").await, Decimal::new(2000, 2));
+        assert_eq!(balance_of(&db, bob, "NGN").await, Decimal::new(15000, 0));
+
+        let pointless_rate = TransferRequest { fx_rate: Some(Decimal::ONE), ..transfer_request(alice, bob, 100) };
+        assert_eq!(create_transfer(State(state), merchant(), Json(pointless_rate)).await.unwrap_err().0, StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn test_wallet_hold_capture_and_release(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let alice = seed_wallet(&db, Decimal::new(10000, 2)).await;
+        let bob = seed_wallet(&db, Decimal::ZERO).await;
+        let hold = |amount| WalletHoldRequest { amount, currency: None, reference: Some("TXN-HOLD".into()) };
+        let naira = |view: &WalletView| (view.balances[0].balance, view.balances[0].available);
+
+        // Reserving lowers what can be spent but not the balance or the ledger
+        let (status, Json(held)) = create_wallet_hold(State(state.clone()), merchant(), Path(alice), Json(hold(6000))).await.unwrap();
+        assert_eq!((status, held.status.as_str(), held.amount), (StatusCode::CREATED, "held", Decimal::new(6000, 2)));
+        let Json(view) = get_wallet(State(state.clone()), merchant(), Path(alice)).await.unwrap();
+        assert_eq!(naira(&view), (Decimal::new(10000, 2), Decimal::new(4000, 2)));
+        let ledger_entries = |wallet| sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM ledger_entries WHERE account = $1")
+            .bind(ledger::wallet_account(wallet));
+        assert_eq!(ledger_entries(alice).fetch_one(&db).await.unwrap().0, 0);
+
+        // Held funds can't be reserved twice or transferred away
+        let rejected = create_wallet_hold(State(state.clone()), merchant(), Path(alice), Json(hold(5000))).await.unwrap_err();
+        assert_eq!(rejected.0, StatusCode::PAYMENT_REQUIRED);
+        assert!(create_transfer(State(state.clone()), merchant(), Json(transfer_request(alice, bob, 5000))).await.is_err());
+
+        // Capturing debits the balance and posts to the ledger, once
+        let Json(captured) = capture_wallet_hold(State(state.clone()), merchant(), Path((alice, held.id))).await.unwrap();
+        assert_eq!(captured.status, "captured");
+        assert!(captured.resolved_at.is_some());
+        let Json(view) = get_wallet(State(state.clone()), merchant(), Path(alice)).await.unwrap();
+        assert_eq!(naira(&view), (Decimal::new(4000, 2), Decimal::new(4000, 2)));
+        assert_eq!(ledger_entries(alice).fetch_one(&db).await.unwrap().0, 1);
+        for again in [capture_wallet_hold(State(state.clone()), merchant(), Path((alice, held.id))).await, release_wallet_hold(State(state.clone()), merchant(), Path((alice, held.id))).await] {
+            assert_eq!(again.unwrap_err().0, StatusCode::CONFLICT);
+        }
+
+        // Releasing frees the funds without touching the balance
+        let (_, Json(held)) = create_wallet_hold(State(state.clone()), merchant(), Path(alice), Json(hold(2500))).await.unwrap();
+        assert_eq!(release_wallet_hold(State(state.clone()), merchant(), Path((bob, held.id))).await.unwrap_err().0, StatusCode::NOT_FOUND);
+        let Json(released) = release_wallet_hold(State(state.clone()), merchant(), Path((alice, held.id))).await.unwrap();
+        assert_eq!(released.status, "released");
+        let Json(view) = get_wallet(State(state.clone()), merchant(), Path(alice)).await.unwrap();
+        assert_eq!(naira(&view), (Decimal::new(4000, 2), Decimal::new(4000, 2)));
+        assert_eq!(ledger_entries(alice).fetch_one(&db).await.unwrap().0, 1);
+        assert_eq!(capture_wallet_hold(State(state), merchant(), Path((alice, held.id))).await.unwrap_err().0, StatusCode::CONFLICT);
+    }
+
+    #[sqlx::test]
+    async fn test_ledger_reconciles_with_balances(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let alice = seed_wallet(&db, Decimal::ZERO).await;
+        let bob = seed_wallet(&db, Decimal::ZERO).await;
+        let topup = |amount, currency: &str| WalletTopupRequest { customer_id: Uuid::now_v7(), amount, currency: Some(currency.into()) };
+
+        let Json(naira) = topup_wallet(State(state.clone()), merchant(), Path(alice), Json(topup(50_000, "NGN"))).await.unwrap();
+        let _ = topup_wallet(State(state.clone()), merchant(), Path(bob), Json(topup(2_000, "USD"))).await.unwrap();
+        let _ = create_transfer(State(state.clone()), merchant(), Json(transfer_request(alice, bob, 15_000))).await.unwrap();
+        let reverse = ReverseTopupRequest { amount: Some(10_000), reason: None };
+        let _ = reverse_topup(State(state.clone()), merchant(), Path((alice, naira.topup_id)), Json(reverse)).await.unwrap();
+        let usd_to_ngn = TransferRequest { currency: Some("USD".into()), to_currency: Some("NGN".into()), fx_rate: Some(Decimal::new(15, 1)), ..transfer_request(bob, alice, 1_000) };
+        let _ = create_transfer(State(state.clone()), merchant(), Json(usd_to_ngn)).await.unwrap();
+        let txn_id = seed_transaction(&db, Decimal::new(10000, 2), "succeeded").await;
+        let _ = create_refund(State(state.clone()), merchant(), Json(refund_request(txn_id, 2500))).await.unwrap();
+
+        for (wallet, currency) in [(alice, "NGN"), (bob, "NGN"), (bob, "USD")] {
+            let Json(entries) = get_wallet_ledger(
+                State(state.clone()), merchant(),
+                Path(wallet),
+                Query(LedgerListParams { currency: Some(currency.into()), page: None, per_page: Some(100) }),
+            ).await.unwrap();
+            let net: Decimal = entries.data.iter()
+                .map(|e| if e.direction == "credit" { e.amount } else { -e.amount })
+                .sum();
+            assert_eq!(net, balance_of(&db, wallet, currency).await, "{} {}", wallet, currency);
+            assert_eq!(entries.total as usize, entries.data.len());
+        }
+        // 500 topped up, 150 sent, 100 reversed, 10 USD received at 1.5
+        assert_eq!(balance_of(&db, alice, "NGN").await, Decimal::new(26_500, 2));
+
+        // Every posting nets to zero in each currency it touches
+        let unbalanced: Vec<(String, String, Decimal)> = sqlx::query_as(
+            r#"SELECT reference, currency, SUM(CASE WHEN direction = 'debit' THEN amount ELSE -amount END) AS net
+               FROM ledger_entries GROUP BY reference, currency HAVING SUM(CASE WHEN direction = 'debit' THEN amount ELSE -amount END) <> 0"#
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        assert!(unbalanced.is_empty(), "{:?}", unbalanced);
+        let postings: (i64,) = sqlx::query_as("SELECT COUNT(DISTINCT reference) FROM ledger_entries").fetch_one(&db).await.unwrap();
+        assert_eq!(postings.0, 6);
+    }
+
+    #[sqlx::test]
+    async fn test_topup_reversal(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let wallet_id = seed_wallet(&db, Decimal::ZERO).await;
+        let topup = WalletTopupRequest { customer_id: Uuid::now_v7(), amount: 5000, currency: None };
+        let Json(topped_up) = topup_wallet(State(state.clone()), merchant(), Path(wallet_id), Json(topup)).await.unwrap();
+        assert_eq!(topped_up.wallet.balances.len(), 1);
+        assert_eq!(topped_up.wallet.balances[0].balance, Decimal::new(5000, 2));
+
+        let path = || Path((wallet_id, topped_up.topup_id));
+        let over = ReverseTopupRequest { amount: Some(5001), reason: None };
+        assert_eq!(reverse_topup(State(state.clone()), merchant(), path(), Json(over)).await.unwrap_err().0, StatusCode::BAD_REQUEST);
+
+        let partial = ReverseTopupRequest { amount: Some(2000), reason: Some("funding reversed".into()) };
+        let Json(reversal) = reverse_topup(State(state.clone()), merchant(), path(), Json(partial)).await.unwrap();
+        assert_eq!(reversal.amount, Decimal::new(-2000, 2));
+        assert_eq!(reversal.balance_after, Decimal::new(3000, 2));
+
+        let rest = ReverseTopupRequest { amount: None, reason: None };
+        let _ = reverse_topup(State(state.clone()), merchant(), path(), Json(rest)).await.unwrap();
+        assert_eq!(balance_of(&db, wallet_id, "NGN").await, Decimal::ZERO);
+
+        let again = ReverseTopupRequest { amount: None, reason: None };
+        assert_eq!(reverse_topup(State(state), merchant(), path(), Json(again)).await.unwrap_err().0, StatusCode::CONFLICT);
+    }
+
+    fn initiate_request(amount: i64) -> InitiatePaymentRequest {
+        InitiatePaymentRequest {
+            reference: None,
+            amount: Amount::new(amount, "NGN"),
+            email: "ada@example.com".into(),
+            customer_id: None,
+            payment_method: None,
+            payment_method_id: None,
+            callback_url: None,
+            metadata: None,
+            statement_descriptor_suffix: None,
+            provider: None,
+            scheduled_at: None,
+            capture: None,
+        }
+    }
+
+    struct FailingPublisher;
+
+    #[async_trait::async_trait]
+    impl EventPublisher for FailingPublisher {
+        async fn publish_bytes(&self, _subject: String, _payload: Vec<u8>) -> Result<(), PublishError> {
+            Err(PublishError::Transport("no servers available".into()))
+        }
+    }
+
+    fn published(bus: &publisher::RecordingPublisher) -> Vec<(String, serde_json::Value)> {
+        bus.messages().into_iter()
+            .map(|(subject, payload)| (subject, serde_json::from_slice(&payload).unwrap()))
+            .collect()
+    }
+
+    #[sqlx::test]
+    async fn test_domain_events_published(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let bus = publisher::RecordingPublisher::default();
+
+        let Json(created) = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap();
+        let txn_id = seed_transaction(&db, Decimal::new(10000, 2), "succeeded").await;
+        let _ = create_refund(State(state.clone()), merchant(), Json(refund_request(txn_id, 2500))).await.unwrap();
+        // A rejected refund commits nothing, so queues nothing
+        create_refund(State(state.clone()), merchant(), Json(refund_request(txn_id, 100_000))).await.unwrap_err();
+
+        assert_eq!(publish_outbox(&db, &bus).await.unwrap(), 2);
+        let messages = published(&bus);
+        assert_eq!(messages[0].0, "payments.payment.created");
+        assert_eq!(messages[0].1["payment_id"], created.reference);
+        assert_eq!(messages[1].0, "payments.payment.refunded");
+        assert_eq!(messages[1].1, serde_json::json!({ "type": "payment.refunded", "payment_id": format!("TXN-{}", txn_id), "amount": "25.00" }));
+        assert_eq!(publish_outbox(&db, &bus).await.unwrap(), 0);
+    }
+
+    #[sqlx::test]
+    async fn test_failed_publish_stays_in_outbox(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let txn_id = seed_transaction(&db, Decimal::new(10000, 2), "succeeded").await;
+        let _ = create_refund(State(state), merchant(), Json(refund_request(txn_id, 2500))).await.unwrap();
+
+        assert_eq!(publish_outbox(&db, &FailingPublisher).await.unwrap(), 0);
+        let (attempts, last_error, published_at): (i32, Option<String>, Option<DateTime<Utc>>) =
+            sqlx::query_as("SELECT attempts, last_error, published_at FROM event_outbox").fetch_one(&db).await.unwrap();
+        assert_eq!(attempts, 1);
+        assert_eq!(last_error.as_deref(), Some("Event publish failed: no servers available"));
+        assert!(published_at.is_none());
+
+        let bus = publisher::RecordingPublisher::default();
+        assert_eq!(publish_outbox(&db, &bus).await.unwrap(), 1);
+        assert_eq!(published(&bus)[0].0, "payments.payment.refunded");
+        let (attempts, published_at): (i32, Option<DateTime<Utc>>) =
+            sqlx::query_as("SELECT attempts, published_at FROM event_outbox").fetch_one(&db).await.unwrap();
+        assert_eq!(attempts, 2);
+        assert!(published_at.is_some());
+    }
+
+    #[sqlx::test]
+    async fn test_initiate_surfaces_next_action(db: sqlx::PgPool) {
+        let three_ds = NextAction::RedirectToUrl { url: "https://acs.example/3ds".into() };
+        let gateway = MockGateway::new(Ok(ChargeResult::RequiresAction {
+            next_action: three_ds.clone(),
+            provider_reference: Some("ch_3ds".into()),
+        }));
+        let state = test_state_with_gateway(db.clone(), Arc::new(gateway));
+        let Json(resp) = initiate_payment(State(state), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap();
+        assert_eq!(resp.status, "requires_action");
+        assert_eq!(resp.next_action, Some(three_ds));
+        assert!(resp.authorization_url.is_none());
+
+        let Json(normal) = initiate_payment(State(test_state(db)), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap();
+        assert_eq!(normal.status, "pending");
+        assert!(normal.next_action.is_none());
+        assert!(normal.authorization_url.is_some());
+    }
+
+    #[sqlx::test]
+    async fn test_list_refunds_filters(db: sqlx::PgPool) {
+        let gateway = Arc::new(MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: None })));
+        gateway.set_refund_outcome(Ok(Some(RefundOutcome::Pending)));
+        let state = test_state_with_gateway(db.clone(), gateway);
+        let first = seed_transaction(&db, Decimal::new(10000, 2), "succeeded").await;
+        let second = seed_transaction(&db, Decimal::new(10000, 2), "succeeded").await;
+        let _ = create_refund(State(state.clone()), merchant(), Json(refund_request(first, 1000))).await.unwrap();
+        let _ = create_refund(State(state.clone()), merchant(), Json(refund_request(first, 1000))).await.unwrap();
+        let (_, Json(other)) = create_refund(State(state.clone()), merchant(), Json(refund_request(second, 1000))).await.unwrap();
+        sqlx::query("UPDATE refunds SET status = 'succeeded' WHERE id = $1").bind(other.id).execute(&db).await.unwrap();
+
+        let params = |transaction_id, status: Option<&str>| RefundListParams {
+            transaction_id,
+            status: status.map(String::from),
+            from_date: None,
+            to_date: None,
+            page: None,
+            per_page: None,
+        };
+
+        let Json(by_txn) = list_refunds(State(state.clone()), merchant(), Query(params(Some(first), None))).await.unwrap();
+        assert_eq!(by_txn.total, 2);
+        assert!(by_txn.data.iter().all(|r| r.transaction_id == first));
+
+        let Json(succeeded) = list_refunds(State(state.clone()), merchant(), Query(params(None, Some("succeeded")))).await.unwrap();
+        assert_eq!(succeeded.total, 1);
+        assert_eq!(succeeded.data[0].id, other.id);
+
+        let Json(all) = list_refunds(State(state), merchant(), Query(params(None, None))).await.unwrap();
+        assert_eq!(all.total, 3);
+    }
+
+    #[test]
+    fn test_money_moving_routes() {
+        assert!(is_money_moving(&Method::POST, "/api/v1/payments/initiate"));
+        assert!(is_money_moving(&Method::POST, "/api/v1/wallets/abc/topup"));
+        assert!(is_money_moving(&Method::POST, "/api/v1/wallets/abc/topups/def/reverse"));
+        assert!(!is_money_moving(&Method::GET, "/api/v1/refunds"));
+        assert!(!is_money_moving(&Method::POST, "/api/v1/payments/verify"));
+        assert!(!is_money_moving(&Method::POST, "/api/v1/webhook-endpoints"));
+    }
+
+    fn initiate_http_request(api_key: &str, idempotency_key: Option<&str>) -> axum::http::Request<Body> {
+        let body = serde_json::json!({ "amount": 5000, "currency": "NGN", "email": "ada@example.com" });
+        let mut builder = axum::http::Request::post("/api/v1/payments/initiate")
+            .header("content-type", "application/json")
+            .header("authorization", format!("Bearer {}", api_key));
+        if let Some(key) = idempotency_key {
+            builder = builder.header("idempotency-key", key);
+        }
+        builder.body(Body::from(body.to_string())).unwrap()
+    }
+
+    #[sqlx::test]
+    async fn test_idempotency_key_requirement(db: sqlx::PgPool) {
+        const STRICT_KEY: &str = "sk_test_strict_merchant";
+        let strict = Uuid::now_v7();
+        seed_api_key(&db, TEST_MERCHANT, TEST_API_KEY).await;
+        seed_api_key(&db, strict, STRICT_KEY).await;
+        sqlx::query("INSERT INTO merchant_settings (merchant_id, require_idempotency_key) VALUES ($1, TRUE)").bind(strict).execute(&db).await.unwrap();
+        let app = build_router(test_state(db.clone()));
+
+        // Only the merchant that requires keys has keyless requests refused
+        let keyless = app.clone().oneshot(initiate_http_request(STRICT_KEY, None)).await.unwrap();
+        assert_eq!(keyless.status(), StatusCode::BAD_REQUEST);
+        let keyed = app.clone().oneshot(initiate_http_request(STRICT_KEY, Some("key-1"))).await.unwrap();
+        assert_eq!(keyed.status(), StatusCode::OK);
+        let keyless = app.oneshot(initiate_http_request(TEST_API_KEY, None)).await.unwrap();
+        assert_eq!(keyless.status(), StatusCode::OK);
+
+        // A deployment that requires keys does so for merchants without a setting, and a
+        // merchant can opt out
+        let mut strict_deployment = test_state(db.clone());
+        strict_deployment.config = Arc::new(Config { require_idempotency_key: true, ..Config::clone(&strict_deployment.config) });
+        let app = build_router(strict_deployment);
+        let keyless = app.clone().oneshot(initiate_http_request(TEST_API_KEY, None)).await.unwrap();
+        assert_eq!(keyless.status(), StatusCode::BAD_REQUEST);
+        sqlx::query("INSERT INTO merchant_settings (merchant_id, require_idempotency_key) VALUES ($1, FALSE)").bind(TEST_MERCHANT).execute(&db).await.unwrap();
+        let keyless = app.oneshot(initiate_http_request(TEST_API_KEY, None)).await.unwrap();
+        assert_eq!(keyless.status(), StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    async fn test_idempotency_key_replay(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let mut headers = HeaderMap::new();
+        headers.insert("idempotency-key", "order-42".parse().unwrap());
+
+        let Json(first) = initiate_payment(State(state.clone()), merchant(), headers.clone(), Json(initiate_request(5000))).await.unwrap();
+        let Json(replay) = initiate_payment(State(state.clone()), merchant(), headers.clone(), Json(initiate_request(5000))).await.unwrap();
+        assert_eq!(replay.reference, first.reference);
+        assert_eq!(replay.authorization_url, first.authorization_url);
+        let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM transactions").fetch_one(&db).await.unwrap();
+        assert_eq!(count.0, 1);
+
+        let err = initiate_payment(State(state.clone()), merchant(), headers, Json(initiate_request(6000))).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::CONFLICT);
+
+        // Without a key every request is a new payment
+        let _ = initiate_payment(State(state), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap();
+        let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM transactions").fetch_one(&db).await.unwrap();
+        assert_eq!(count.0, 2);
+    }
+
+    async fn seed_card(db: &sqlx::PgPool, exp_month: i16, exp_year: i16) -> Uuid {
+        let id = Uuid::now_v7();
+        sqlx::query(
+            r#"INSERT INTO payment_methods (id, customer_id, method_type, provider, token, last_four, exp_month, exp_year, merchant_id, created_at)
+               VALUES ($1, $2, 'card', 'paystack', 'AUTH_test', '4081', $3, $4, $5, NOW())"#
+        )
+        .bind(id)
+        .bind(Uuid::now_v7())
+        .bind(exp_month)
+        .bind(exp_year)
+        .bind(TEST_MERCHANT)
+        .execute(db)
+        .await
+        .unwrap();
+        id
+    }
+
+    #[sqlx::test]
+    async fn test_card_expiry_scan(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let today = chrono::NaiveDate::from_ymd_opt(2026, 11, 10).unwrap();
+        let expiring = seed_card(&db, 11, 2026).await;
+        let expired = seed_card(&db, 10, 2026).await;
+        let valid = seed_card(&db, 5, 2029).await;
+
+        assert_eq!(scan_card_expiry(&state, today).await.unwrap(), 1);
+        assert_eq!(scan_card_expiry(&state, today).await.unwrap(), 0);
+
+        let flagged: Vec<(Uuid, bool, bool)> = sqlx::query_as(
+            "SELECT id, expiry_notified_at IS NOT NULL, expired_at IS NOT NULL FROM payment_methods ORDER BY id"
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        assert!(flagged.contains(&(expiring, true, false)));
+        assert!(flagged.contains(&(expired, false, true)));
+        assert!(flagged.contains(&(valid, false, false)));
+
+        let charge = InitiatePaymentRequest { payment_method_id: Some(expired), ..initiate_request(5000) };
+        let err = initiate_payment(State(state), merchant(), HeaderMap::new(), Json(charge)).await.unwrap_err();
+        assert_eq!((err.status, err.message.as_str()), (StatusCode::UNPROCESSABLE_ENTITY, "Payment method has expired"));
+
+        // The warning also goes to NATS, once
+        let outbox: Vec<(String, serde_json::Value)> = sqlx::query_as("SELECT subject, payload FROM event_outbox").fetch_all(&db).await.unwrap();
+        assert_eq!(outbox.len(), 1);
+        assert_eq!(outbox[0].0, "payments.payment_method.expiring");
+        assert_eq!((outbox[0].1["payment_method_id"].as_str(), outbox[0].1["exp_month"].as_i64()), (Some(expiring.to_string().as_str()), Some(11)));
+    }
+
+    #[sqlx::test]
+    async fn test_list_expiring_payment_methods(db: sqlx::PgPool) {
+        use chrono::Datelike;
+        seed_api_key(&db, TEST_MERCHANT, TEST_API_KEY).await;
+        let app = build_router(test_state(db.clone()));
+        let today = Utc::now().date_naive();
+        let (month, year) = (today.month() as i16, today.year() as i16);
+        let this_month = seed_card(&db, month, year).await;
+        let next_year = seed_card(&db, month, year + 1).await;
+        let (last_month, last_month_year) = if month == 1 { (12, year - 1) } else { (month - 1, year) };
+        seed_card(&db, last_month, last_month_year).await;
+
+        // Good through the last day of the month, which is at most 31 days away
+        let (status, body) = send_json(&app, "GET", "/api/v1/payment-methods/expiring?within_days=31", serde_json::Value::Null, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        let ids: Vec<&str> = body.as_array().unwrap().iter().map(|m| m["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, vec![this_month.to_string()]);
+
+        let (_, body) = send_json(&app, "GET", "/api/v1/payment-methods/expiring?within_days=400", serde_json::Value::Null, &[]).await;
+        let ids: Vec<&str> = body.as_array().unwrap().iter().map(|m| m["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, vec![this_month.to_string(), next_year.to_string()]);
+
+        let (status, _) = send_json(&app, "GET", "/api/v1/payment-methods/expiring?within_days=-1", serde_json::Value::Null, &[]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn test_card_checks_exposed_in_debug_view(db: sqlx::PgPool) {
+        let gateway = MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: Some("ch_1".into()) }))
+            .with_checks(CardChecks {
+                avs_result: AvsResult::from_code("Z"),
+                cvv_result: CvvResult::from_stripe("pass"),
+                network_response_code: Some("00".into()),
+            })
+            .with_raw_response(serde_json::json!({ "status": "success", "authorization": { "last4": "4081" } }));
+        let state = test_state_with_gateway(db.clone(), Arc::new(gateway));
+        let Json(resp) = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap();
+
+        let (txn_id,): (Uuid,) = sqlx::query_as("SELECT id FROM transactions WHERE reference = $1")
+            .bind(&resp.reference)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", "admin-secret".parse().unwrap());
+        let Json(debug) = get_transaction_debug(State(state), headers, Path(txn_id)).await.unwrap();
+        assert_eq!(debug["avs_result"], "partial_match");
+        assert_eq!(debug["cvv_result"], "match");
+        assert_eq!(debug["network_response_code"], "00");
+        assert_eq!(debug["provider_raw_response"]["authorization"]["last4"], "[REDACTED]");
+    }
+
+    #[sqlx::test]
+    async fn test_unknown_currency_policy(db: sqlx::PgPool) {
+        let charge = || InitiatePaymentRequest { amount: Amount::new(5000, "ZZT"), ..initiate_request(5000) };
+
+        let err = initiate_payment(State(test_state(db.clone())), merchant(), HeaderMap::new(), Json(charge())).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+
+        let mut lenient = test_state(db);
+        lenient.config = Arc::new(Config {
+            currency_policy: CurrencyPolicy { fallback_exponent: Some(2), allowed_unknown: vec!["ZZT".into()] },
+            ..Config::clone(&lenient.config)
+        });
+        let Json(resp) = initiate_payment(State(lenient), merchant(), HeaderMap::new(), Json(charge())).await.unwrap();
+        assert_eq!(resp.status, "pending");
+    }
+
+    #[test]
+    fn test_amounts_serialize_as_strings() {
+        let amount = "98765432.123456789012345678";
+        let txn: Transaction = serde_json::from_value(serde_json::json!({
+            "id": Uuid::now_v7(), "reference": "TXN-1", "amount": amount, "currency": "NGN", "status": "succeeded",
+            "transaction_type": "payment", "metadata": {}, "created_at": Utc::now(), "updated_at": Utc::now()
+        })).unwrap();
+        assert_eq!(txn.amount.to_string(), amount);
+        let encoded = serde_json::to_value(&txn).unwrap();
+        assert_eq!(encoded["amount"], amount);
+        assert_eq!(serde_json::from_value::<Transaction>(encoded).unwrap().amount, txn.amount);
+
+        // Requests take a rate as a string or a plain number, or leave it out
+        let transfer = |fx_rate: serde_json::Value| serde_json::from_value::<TransferRequest>(serde_json::json!({
+            "from_wallet_id": Uuid::now_v7(), "to_wallet_id": Uuid::now_v7(), "amount": 100, "fx_rate": fx_rate
+        })).unwrap().fx_rate;
+        assert_eq!(transfer(serde_json::json!("1532.123456789")), Some("1532.123456789".parse().unwrap()));
+        assert_eq!(transfer(serde_json::json!(1500)), Some(Decimal::from(1500)));
+        assert_eq!(transfer(serde_json::Value::Null), None);
+        let without: TransferRequest = serde_json::from_value(serde_json::json!({ "from_wallet_id": Uuid::now_v7(), "to_wallet_id": Uuid::now_v7(), "amount": 100 })).unwrap();
+        assert_eq!(without.fx_rate, None);
+    }
+
+    #[test]
+    fn test_client_reference_format() {
+        assert!(validate_client_reference("order-1234_a.b").is_ok());
+        assert_eq!(validate_client_reference("abc").unwrap_err().0, StatusCode::BAD_REQUEST);
+        assert!(validate_client_reference("has space").is_err());
+        assert!(validate_client_reference(&"x".repeat(101)).is_err());
+    }
+
+    #[sqlx::test]
+    async fn test_duplicate_client_reference_conflicts(db: sqlx::PgPool) {
+        let state = test_state(db);
+        let charge = || InitiatePaymentRequest { reference: Some("order-1001".into()), ..initiate_request(5000) };
+
+        let Json(first) = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(charge())).await.unwrap();
+        assert_eq!(first.reference, "order-1001");
+        let err = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(charge())).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::CONFLICT);
+
+        let Json(generated) = initiate_payment(State(state), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap();
+        assert!(generated.reference.starts_with("TXN-"));
+    }
+
+    async fn seed_customer_transaction(db: &sqlx::PgPool, customer_id: Uuid, status: &str, created_at: DateTime<Utc>) -> Uuid {
+        let id = seed_transaction(db, Decimal::new(1000, 2), status).await;
+        sqlx::query("UPDATE transactions SET customer_id = $1, created_at = $2 WHERE id = $3")
+            .bind(customer_id)
+            .bind(created_at)
+            .bind(id)
+            .execute(db)
+            .await
+            .unwrap();
+        id
+    }
+
+    fn list_params(status: Option<&str>, from_date: Option<DateTime<Utc>>) -> ListParams {
+        ListParams { page: None, per_page: None, cursor: None, status: status.map(String::from), from_date, to_date: None }
+    }
+
+    #[sqlx::test]
+    async fn test_transactions_cursor_pagination(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        // Pairs share a timestamp, so the id has to break ties
+        let base = Utc::now() - chrono::Duration::hours(1);
+        let mut seeded = Vec::new();
+        for i in 0..7 {
+            let id = Uuid::now_v7();
+            sqlx::query(
+                r#"INSERT INTO transactions (id, reference, amount, currency, status, transaction_type, merchant_id, created_at, updated_at)
+                   VALUES ($1, $2, 100, 'NGN', 'succeeded', 'payment', $3, $4, NOW())"#
+            )
+            .bind(id)
+            .bind(format!("TXN-{}", id))
+            .bind(TEST_MERCHANT)
+            .bind(base + chrono::Duration::seconds(i / 2))
+            .execute(&db)
+            .await
+            .unwrap();
+            seeded.push(id);
+        }
+
+        let list = |cursor: Option<String>| {
+            let state = state.clone();
+            async move {
+                let params = ListParams { per_page: Some(3), cursor, ..list_params(None, None) };
+                list_transactions(State(state), merchant(), Query(params)).await.unwrap().0
+            }
+        };
+        let mut seen = Vec::new();
+        let mut page = list(None).await;
+        loop {
+            seen.extend(page.data.iter().map(|t| t.id));
+            let Some(cursor) = page.next_cursor else { break };
+            // Rows inserted mid-way sort before the cursor and never shift later pages
+            sqlx::query("INSERT INTO transactions (id, reference, amount, currency, status, transaction_type, merchant_id, created_at, updated_at) VALUES ($1, $2, 100, 'NGN', 'pending', 'payment', $3, NOW(), NOW())")
+                .bind(Uuid::now_v7())
+                .bind(format!("NEW-{}", Uuid::now_v7()))
+                .bind(TEST_MERCHANT)
+                .execute(&db)
+                .await
+                .unwrap();
+            page = list(Some(cursor)).await;
+        }
+        seeded.reverse();
+        assert_eq!(seen, seeded);
+
+        // Page mode still works, and hands out a cursor to continue from
+        let Json(second) = list_transactions(State(state.clone()), merchant(), Query(ListParams { page: Some(2), per_page: Some(3), ..list_params(None, None) })).await.unwrap();
+        assert_eq!(second.data.len(), 3);
+        assert!(second.next_cursor.is_some());
+
+        let bad = ListParams { cursor: Some("not-a-cursor".into()), ..list_params(None, None) };
+        assert_eq!(list_transactions(State(state), merchant(), Query(bad)).await.unwrap_err().0, StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn test_customer_transactions(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let (alice, bob) = (Uuid::now_v7(), Uuid::now_v7());
+        let now = Utc::now();
+        let recent = seed_customer_transaction(&db, alice, "succeeded", now).await;
+        seed_customer_transaction(&db, alice, "pending", now).await;
+        seed_customer_transaction(&db, alice, "succeeded", now - chrono::Duration::days(10)).await;
+        seed_customer_transaction(&db, bob, "succeeded", now).await;
+
+        let Json(all) = list_customer_transactions(State(state.clone()), merchant(), Path(alice.to_string()), Query(list_params(None, None))).await.unwrap();
+        assert_eq!(all.total, 3);
+        assert!(all.data.iter().all(|t| t.customer_id == Some(alice)));
+
+        let filters = list_params(Some("succeeded"), Some(now - chrono::Duration::days(1)));
+        let Json(filtered) = list_customer_transactions(State(state.clone()), merchant(), Path(alice.to_string()), Query(filters)).await.unwrap();
+        assert_eq!(filtered.total, 1);
+        assert_eq!(filtered.data[0].id, recent);
+
+        // Bob sees only his own, and an unknown customer sees nothing
+        let Json(bobs) = list_customer_transactions(State(state.clone()), merchant(), Path(bob.to_string()), Query(list_params(None, None))).await.unwrap();
+        assert_eq!(bobs.total, 1);
+        assert!(bobs.data.iter().all(|t| t.customer_id == Some(bob)));
+        let Json(nobody) = list_customer_transactions(State(state.clone()), merchant(), Path(Uuid::now_v7().to_string()), Query(list_params(None, None))).await.unwrap();
+        assert_eq!((nobody.total, nobody.data.len()), (0, 0));
+
+        // Same pagination as the global list
+        let first = ListParams { per_page: Some(2), ..list_params(None, None) };
+        let Json(page) = list_customer_transactions(State(state.clone()), merchant(), Path(alice.to_string()), Query(first)).await.unwrap();
+        assert_eq!(page.data.len(), 2);
+        let next = ListParams { per_page: Some(2), cursor: page.next_cursor, ..list_params(None, None) };
+        let Json(rest) = list_customer_transactions(State(state.clone()), merchant(), Path(alice.to_string()), Query(next)).await.unwrap();
+        assert_eq!(rest.data.len(), 1);
+        assert!(rest.next_cursor.is_none());
+        assert!(rest.data.iter().chain(&page.data).all(|t| t.customer_id == Some(alice)));
+
+        // Transactions recorded with only an email are found by it
+        let guest = seed_transaction(&db, Decimal::new(500, 2), "succeeded").await;
+        let other_guest = seed_transaction(&db, Decimal::new(500, 2), "succeeded").await;
+        for (id, email) in [(guest, "Carol@Example.com"), (other_guest, "dave@example.com")] {
+            sqlx::query("UPDATE transactions SET customer_email = $1 WHERE id = $2").bind(email).bind(id).execute(&db).await.unwrap();
+        }
+        let Json(carols) = list_customer_transactions(State(state.clone()), merchant(), Path("carol@example.com".to_string()), Query(list_params(None, None))).await.unwrap();
+        assert_eq!((carols.total, carols.data[0].id), (1, guest));
+        let err = list_customer_transactions(State(state), merchant(), Path("not-a-customer".to_string()), Query(list_params(None, None))).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn test_list_transactions_filters(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let customer = Uuid::now_v7();
+        let now = Utc::now();
+        let recent_paid = seed_customer_transaction(&db, customer, "succeeded", now).await;
+        let recent_open = seed_customer_transaction(&db, customer, "pending", now).await;
+        let old_paid = seed_customer_transaction(&db, customer, "succeeded", now - chrono::Duration::days(10)).await;
+        let ancient_paid = seed_customer_transaction(&db, customer, "succeeded", now - chrono::Duration::days(40)).await;
+
+        let list = |params: ListParams| {
+            let state = state.clone();
+            async move {
+                let Json(page) = list_transactions(State(state), merchant(), Query(params)).await.unwrap();
+                (page.total, page.data.iter().map(|t| t.id).collect::<Vec<_>>())
+            }
+        };
+        let day = chrono::Duration::days(1);
+
+        assert_eq!(list(list_params(None, None)).await.0, 4);
+        assert_eq!(list(list_params(Some("pending"), None)).await, (1, vec![recent_open]));
+        assert_eq!(list(list_params(None, Some(now - day))).await.0, 2);
+        let until = ListParams { to_date: Some(now - day), ..list_params(None, None) };
+        assert_eq!(list(until).await, (2, vec![old_paid, ancient_paid]));
+
+        let combined = ListParams { to_date: Some(now - day), ..list_params(Some("succeeded"), Some(now - chrono::Duration::days(20))) };
+        assert_eq!(list(combined).await, (1, vec![old_paid]));
+        let paid_today = list_params(Some("succeeded"), Some(now - day));
+        assert_eq!(list(paid_today).await, (1, vec![recent_paid]));
+
+        // Totals count every match, not just the page
+        let (total, ids) = list(ListParams { per_page: Some(1), ..list_params(Some("succeeded"), None) }).await;
+        assert_eq!((total, ids.len()), (3, 1));
+    }
+
+    #[sqlx::test]
+    async fn test_provider_currency_guard(db: sqlx::PgPool) {
+        let gateway = Arc::new(MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: None })));
+        let mut state = test_state_with_gateway(db.clone(), gateway.clone());
+        state.config = Arc::new(Config {
+            provider_capabilities: parse_provider_currencies("mock=NGN"),
+            ..Config::clone(&state.config)
+        });
+
+        let usd = InitiatePaymentRequest { amount: Amount::new(5000, "USD"), ..initiate_request(5000) };
+        let err = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(usd)).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(err.message.contains("USD") && err.message.contains("mock"));
+        assert!(gateway.requests().is_empty());
+        let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM transactions").fetch_one(&db).await.unwrap();
+        assert_eq!(stored, 0);
+
+        let Json(resp) = initiate_payment(State(state), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap();
+        assert_eq!(resp.status, "succeeded");
+    }
+
+    #[sqlx::test]
+    async fn test_subscription_metadata(db: sqlx::PgPool) {
+        let state = test_state(db);
+        let req = CreateSubscriptionRequest {
+            customer_id: Uuid::now_v7(),
+            plan_id: "PLAN_PRO".into(),
+            amount: Amount::new(4900, "USD"),
+            billing_cycle: Some("yearly".into()),
+            payment_method_id: None,
+            billing_details: None,
+            metadata: [("contract_id".to_string(), "C-42".to_string()), ("region".to_string(), "eu".to_string())].into(),
+        };
+        let (status, Json(created)) = create_subscription(State(state.clone()), merchant(), Json(req)).await.unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(created.billing_cycle, "yearly");
+
+        let Json(fetched) = get_subscription(State(state.clone()), merchant(), Path(created.id)).await.unwrap();
+        assert_eq!(fetched.metadata.0, created.metadata.0);
+        assert_eq!(fetched.metadata.0["contract_id"], "C-42");
+
+        let patch = UpdateSubscriptionRequest {
+            metadata: Some([("region".to_string(), "us".to_string()), ("contract_id".to_string(), String::new()), ("seats".to_string(), "10".to_string())].into()),
+        };
+        let Json(updated) = update_subscription(State(state.clone()), merchant(), Path(created.id), Json(patch)).await.unwrap();
+        let expected: Metadata = [("region".to_string(), "us".to_string()), ("seats".to_string(), "10".to_string())].into();
+        assert_eq!(updated.metadata.0, expected);
+
+        let invalid = UpdateSubscriptionRequest { metadata: Some([(String::new(), "x".to_string())].into()) };
+        let err = update_subscription(State(state), merchant(), Path(created.id), Json(invalid)).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::BAD_REQUEST);
+    }
+
+    async fn send_json(app: &Router, method: &str, uri: &str, body: serde_json::Value, extra: &[(&str, &str)]) -> (StatusCode, serde_json::Value) {
+        let mut builder = axum::http::Request::builder().method(method).uri(uri)
+            .header("content-type", "application/json")
+            .header("authorization", format!("Bearer {}", TEST_API_KEY));
+        for (name, value) in extra {
+            builder = builder.header(*name, *value);
+        }
+        let body = if method == "GET" { Body::empty() } else { Body::from(body.to_string()) };
+        let response = app.clone().oneshot(builder.body(body).unwrap()).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null))
+    }
+
+    /// The canonical lifecycle regression test: initiate, confirm via webhook, then refund,
+    /// all through the HTTP router.
+    #[sqlx::test]
+    async fn test_payment_lifecycle_end_to_end(db: sqlx::PgPool) {
+        seed_api_key(&db, TEST_MERCHANT, TEST_API_KEY).await;
+        let mut state = test_state_with_gateway(db.clone(), Arc::new(MockGateway::new(Ok(ChargeResult::Checkout {
+            authorization_url: "https://checkout.example/e2e".into(),
+            provider_reference: Some("prov_e2e".into()),
+        }))));
+        state.config = Arc::new(Config { paystack_secret: Some("sk_test_e2e".into()), ..Config::clone(&state.config) });
+        let app = build_router(state);
+
+        // 1. Initiate
+        let initiate = serde_json::json!({ "reference": "order-e2e-1", "amount": 10000, "currency": "NGN", "email": "ada@example.com" });
+        let (status, body) = send_json(&app, "POST", "/api/v1/payments/initiate", initiate, &[("idempotency-key", "e2e-1")]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "pending");
+        assert_eq!(body["authorization_url"], "https://checkout.example/e2e");
+
+        // 2. Signed charge.success webhook
+        let event = serde_json::json!({ "event": "charge.success", "data": { "reference": "order-e2e-1", "status": "success", "amount": 10000 } });
+        let signature = sase_payments::crypto::hmac_sha512_hex(b"sk_test_e2e", event.to_string().as_bytes());
+        let (status, _) = send_json(&app, "POST", "/api/v1/payments/webhook", event, &[("x-paystack-signature", &signature)]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, txn) = send_json(&app, "POST", "/api/v1/payments/verify", serde_json::json!({ "reference": "order-e2e-1" }), &[]).await;
+        assert_eq!(txn["status"], "succeeded");
+        assert!(!txn["completed_at"].is_null());
+        let txn_id = txn["id"].as_str().unwrap().to_string();
+
+        // 3. Partial, then full refund
+        let (status, refund) = send_json(&app, "POST", "/api/v1/refunds", serde_json::json!({ "transaction_id": txn_id, "amount": 4000 }), &[("idempotency-key", "e2e-r1")]).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(refund["amount"].as_str().map(|a| a.parse::<Decimal>().unwrap()), Some(Decimal::new(4000, 2)));
+        let (_, txn) = send_json(&app, "GET", &format!("/api/v1/transactions/{}", txn_id), serde_json::Value::Null, &[]).await;
+        assert_eq!(txn["status"], "partially_refunded");
+
+        let (status, _) = send_json(&app, "POST", "/api/v1/refunds", serde_json::json!({ "transaction_id": txn_id }), &[("idempotency-key", "e2e-r2")]).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (_, txn) = send_json(&app, "GET", &format!("/api/v1/transactions/{}", txn_id), serde_json::Value::Null, &[]).await;
+        assert_eq!(txn["status"], "refunded");
+
+        // 4. Ledger: refunds add up to the charge and nothing more is refundable
+        let (_, refunds) = send_json(&app, "GET", &format!("/api/v1/refunds?transaction_id={}", txn_id), serde_json::Value::Null, &[]).await;
+        let refunded: Decimal = refunds["data"].as_array().unwrap().iter().map(|r| r["amount"].as_str().unwrap().parse::<Decimal>().unwrap()).sum();
+        assert_eq!(refunded, Decimal::new(10000, 2));
+        let (status, _) = send_json(&app, "POST", "/api/v1/refunds", serde_json::json!({ "transaction_id": txn_id, "amount": 1 }), &[("idempotency-key", "e2e-r3")]).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[sqlx::test]
+    async fn test_amount_round_trips_through_db(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let cases = [
+            (Amount::new(1050, "USD"), Decimal::new(1050, 2)),
+            (Amount::new(500, "JPY"), Decimal::new(500, 0)),
+            (Amount::new(1250, "BHD"), Decimal::new(1250, 3)),
+            (Amount::new(1250, "KWD"), Decimal::new(1250, 3)),
+        ];
+        for (amount, major) in cases {
+            let req = InitiatePaymentRequest { amount: amount.clone(), ..initiate_request(0) };
+            let Json(resp) = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(req)).await.unwrap();
+            let (stored, currency): (Decimal, String) = sqlx::query_as("SELECT amount, currency FROM transactions WHERE reference = $1")
+                .bind(&resp.reference)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+            assert_eq!(stored, major);
+            assert_eq!(Amount::from_decimal(stored, &currency).unwrap(), amount);
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_statement_descriptor_suffix(db: sqlx::PgPool) {
+        let gateway = Arc::new(MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: None })));
+        let mut state = test_state_with_gateway(db.clone(), gateway.clone());
+        let with_suffix = |suffix: &str| InitiatePaymentRequest { statement_descriptor_suffix: Some(suffix.into()), ..initiate_request(5000) };
+
+        let err = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(with_suffix("Order 1234"))).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+
+        state.config = Arc::new(Config { statement_descriptor: Some("OpenSASE".into()), ..Config::clone(&state.config) });
+        let _ = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(with_suffix("Order 1234"))).await.unwrap();
+        assert_eq!(gateway.requests()[0].statement_descriptor.as_deref(), Some("OPENSASE* ORDER 1234"));
+
+        for bad in ["Order 1234567890", "<script>"] {
+            let err = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(with_suffix(bad))).await.unwrap_err();
+            assert_eq!(err.status, StatusCode::BAD_REQUEST, "{bad}");
+            assert!(err.message.contains("statement descriptor"));
+        }
+        assert_eq!(gateway.requests().len(), 1);
+    }
+
+    #[sqlx::test]
+    async fn test_mark_settled_is_idempotent(db: sqlx::PgPool) {
+        let first = seed_transaction(&db, Decimal::new(10000, 2), "succeeded").await;
+        let second = seed_transaction(&db, Decimal::new(5000, 2), "succeeded").await;
+        let pending = seed_transaction(&db, Decimal::new(2500, 2), "pending").await;
+        seed_transaction(&db, Decimal::new(700, 2), "succeeded").await;
+
+        let before = compute_pending_payout(&db, "NGN").await.unwrap();
+        assert_eq!(before.transaction_count, 3);
+        assert_eq!(before.net, Decimal::new(15700, 2));
+
+        let batch = [first, second, pending];
+        assert_eq!(mark_settled(&db, &batch, "stl_1").await.unwrap(), 2);
+        assert_eq!(mark_settled(&db, &batch, "stl_1").await.unwrap(), 0);
+        assert_eq!(mark_settled(&db, &[first], "stl_2").await.unwrap(), 0);
+
+        let settlement: Option<String> = sqlx::query_scalar("SELECT settlement_id FROM transactions WHERE id = $1")
+            .bind(first).fetch_one(&db).await.unwrap();
+        assert_eq!(settlement.as_deref(), Some("stl_1"));
+
+        let after = compute_pending_payout(&db, "NGN").await.unwrap();
+        assert_eq!(after.transaction_count, 1);
+        assert_eq!(after.net, Decimal::new(700, 2));
+    }
+
+    #[sqlx::test]
+    async fn test_webhook_signature_required(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let txn_id = seed_transaction(&db, Decimal::new(10000, 2), "pending").await;
+        let event = serde_json::json!({ "event": "charge.success", "data": { "reference": format!("TXN-{}", txn_id) } });
+        let deliver = |headers: HeaderMap, body: Bytes| webhook_handler(State(state.clone()), None, headers, WebhookBody(body));
+        let status_of = |txn_id: Uuid| {
+            let db = db.clone();
+            async move { sqlx::query_as::<_, (String,)>("SELECT status FROM transactions WHERE id = $1").bind(txn_id).fetch_one(&db).await.unwrap().0 }
+        };
+
+        let (headers, body) = signed_webhook(&event);
+        assert_eq!(deliver(HeaderMap::new(), body.clone()).await.into_response().status(), StatusCode::UNAUTHORIZED);
+        let forged = serde_json::json!({ "event": "charge.success", "data": { "reference": format!("TXN-{}", txn_id), "amount": 1 } });
+        assert_eq!(deliver(headers.clone(), Bytes::from(forged.to_string())).await.into_response().status(), StatusCode::UNAUTHORIZED);
+        let mut wrong_key = HeaderMap::new();
+        wrong_key.insert(paystack::SIGNATURE_HEADER, sase_payments::crypto::hmac_sha512_hex(b"sk_other", &body).parse().unwrap());
+        assert_eq!(deliver(wrong_key, body.clone()).await.into_response().status(), StatusCode::UNAUTHORIZED);
+        // One byte changed is enough, even when the body is still valid JSON
+        let mut tampered = body.to_vec();
+        let at = tampered.iter().position(|b| *b == b'T').unwrap();
+        tampered[at] = b'U';
+        assert_eq!(deliver(headers.clone(), Bytes::from(tampered)).await.into_response().status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(status_of(txn_id).await, "pending");
+
+        assert_eq!(deliver(headers, body).await.into_response().status(), StatusCode::OK);
+        assert_eq!(status_of(txn_id).await, "succeeded");
+
+        let mut unconfigured = test_state(db.clone());
+        unconfigured.config = Arc::new(Config { paystack_secret: None, ..Config::clone(&unconfigured.config) });
+        let (headers, body) = signed_webhook(&event);
+        assert_eq!(webhook_handler(State(unconfigured), None, headers, WebhookBody(body)).await.into_response().status(), StatusCode::UNAUTHORIZED);
+
+        // Oversized bodies are refused whether or not they declare their length
+        let app = build_router(test_state(db));
+        let oversized = format!(r#"{{"event":"charge.success","padding":"{}"}}"#, "x".repeat(MAX_WEBHOOK_BODY_BYTES));
+        for declared in [true, false] {
+            let mut request = axum::http::Request::post("/api/v1/payments/webhook").header("content-type", "application/json");
+            if declared { request = request.header("content-length", oversized.len()); }
+            let response = app.clone().oneshot(request.body(Body::from(oversized.clone())).unwrap()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        }
+
+        // A body that isn't JSON is only parsed, and refused, once its signature checks out
+        let garbage = Bytes::from_static(b"not json");
+        assert_eq!(deliver(HeaderMap::new(), garbage.clone()).await.into_response().status(), StatusCode::UNAUTHORIZED);
+        let mut signed = HeaderMap::new();
+        signed.insert(paystack::SIGNATURE_HEADER, sase_payments::crypto::hmac_sha512_hex(TEST_PAYSTACK_SECRET.as_bytes(), &garbage).parse().unwrap());
+        assert_eq!(deliver(signed, garbage).await.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn test_flutterwave_webhook_settles_charge(db: sqlx::PgPool) {
+        let id = seed_transaction(&db, Decimal::new(5000, 2), "pending").await;
+        let reference = format!("TXN-{}", id);
+        let app = build_router(test_state(db.clone()));
+        let send = |hash: Option<&'static str>, event: serde_json::Value| {
+            let app = app.clone();
+            async move {
+                let mut request = axum::http::Request::post("/api/v1/payments/webhook/flutterwave").header("content-type", "application/json");
+                if let Some(hash) = hash { request = request.header(flutterwave::SIGNATURE_HEADER, hash); }
+                app.oneshot(request.body(Body::from(event.to_string())).unwrap()).await.unwrap().status()
+            }
+        };
+        let completed = serde_json::json!({
+            "event": "charge.completed",
+            "data": { "id": 285959875, "tx_ref": reference, "amount": 50, "currency": "NGN", "status": "successful" }
+        });
+        let status_of = || sqlx::query_scalar::<_, String>("SELECT status FROM transactions WHERE id = $1").bind(id).fetch_one(&db);
+
+        // Without the secret hash, or with Paystack's signature, nothing changes
+        assert_eq!(send(None, completed.clone()).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(send(Some("flw_wrong_hash"), completed.clone()).await, StatusCode::UNAUTHORIZED);
+        let (headers, body) = signed_webhook(&completed);
+        let mut paystack = axum::http::Request::post("/api/v1/payments/webhook/flutterwave").header("content-type", "application/json");
+        paystack.headers_mut().unwrap().extend(headers);
+        assert_eq!(app.clone().oneshot(paystack.body(Body::from(body)).unwrap()).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(status_of().await.unwrap(), "pending");
+
+        assert_eq!(send(Some(TEST_FLUTTERWAVE_HASH), completed.clone()).await, StatusCode::OK);
+        assert_eq!(status_of().await.unwrap(), "succeeded");
+        let processed: (String, String) = sqlx::query_as("SELECT provider, event_id FROM processed_webhook_events").fetch_one(&db).await.unwrap();
+        assert_eq!(processed, ("flutterwave".to_string(), "charge.completed:285959875".to_string()));
+    }
+
+    #[sqlx::test]
+    async fn test_webhook_event_dispatch(db: sqlx::PgPool) {
+        // Refunds are accepted by the provider and left for its webhook to confirm
+        let gateway = Arc::new(MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: None })));
+        gateway.set_refund_outcome(Ok(Some(RefundOutcome::Pending)));
+        let state = test_state_with_gateway(db.clone(), gateway);
+        let deliver = |payload: serde_json::Value| {
+            let (headers, body) = signed_webhook(&payload);
+            let state = state.clone();
+            async move { webhook_handler(State(state), None, headers, WebhookBody(body)).await.into_response().status() }
+        };
+
+        // A processed refund settles the pending refund with that amount
+        let charged = seed_transaction(&db, Decimal::new(10000, 2), "succeeded").await;
+        let (_, Json(first)) = create_refund(State(state.clone()), merchant(), Json(refund_request(charged, 4000))).await.unwrap();
+        let (_, Json(second)) = create_refund(State(state.clone()), merchant(), Json(refund_request(charged, 6000))).await.unwrap();
+        let refund_processed = serde_json::json!({
+            "event": "refund.processed",
+            "data": { "status": "processed", "transaction_reference": format!("TXN-{}", charged), "amount": 6000, "currency": "NGN" }
+        });
+        assert_eq!(deliver(refund_processed.clone()).await, StatusCode::OK);
+        assert_eq!(deliver(refund_processed).await, StatusCode::OK);
+        let refund_status = |id: Uuid| {
+            let db = db.clone();
+            async move { sqlx::query_as::<_, (String,)>("SELECT status FROM refunds WHERE id = $1").bind(id).fetch_one(&db).await.unwrap().0 }
+        };
+        assert_eq!(refund_status(first.id).await, "pending");
+        assert_eq!(refund_status(second.id).await, "succeeded");
+        let (txn_status,): (String,) = sqlx::query_as("SELECT status FROM transactions WHERE id = $1").bind(charged).fetch_one(&db).await.unwrap();
+        assert_eq!(txn_status, "partially_refunded");
+
+        // Renewals and failed subscription charges update the subscription
+        let req = CreateSubscriptionRequest {
+            customer_id: Uuid::now_v7(),
+            plan_id: "PLAN_PRO".into(),
+            amount: Amount::new(250_000, "NGN"),
+            billing_cycle: None,
+            payment_method_id: None,
+            billing_details: None,
+            metadata: Metadata::new(),
+        };
+        let (_, Json(sub)) = create_subscription(State(state.clone()), merchant(), Json(req)).await.unwrap();
+        let invoice = serde_json::json!({
+            "event": "invoice.update",
+            "data": { "paid": true, "status": "success", "amount": 250_000, "currency": "NGN", "metadata": { "subscription_id": sub.id } }
+        });
+        assert_eq!(deliver(invoice).await, StatusCode::OK);
+        let pending = seed_transaction(&db, Decimal::new(2500, 0), "pending").await;
+        let failed = serde_json::json!({ "event": "charge.failed", "data": { "reference": format!("TXN-{}", pending), "metadata": { "subscription_id": sub.id } } });
+        assert_eq!(deliver(failed).await, StatusCode::OK);
+        let (renewals, failures, paid): (i32, i32, Decimal) = sqlx::query_as("SELECT renewal_count, consecutive_failures, total_paid FROM subscriptions WHERE id = $1")
+            .bind(sub.id).fetch_one(&db).await.unwrap();
+        assert_eq!((renewals, failures, paid), (1, 1, Decimal::new(2500, 0)));
+        let (status,): (String,) = sqlx::query_as("SELECT status FROM transactions WHERE id = $1").bind(pending).fetch_one(&db).await.unwrap();
+        assert_eq!(status, "failed");
+
+        // Events we don't handle are still acknowledged
+        assert_eq!(deliver(serde_json::json!({ "event": "transfer.success", "data": { "reference": "TRF-1" } })).await, StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    async fn test_webhook_redelivery_applied_once(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let deliver = |payload: &serde_json::Value| {
+            let (headers, body) = signed_webhook(payload);
+            let state = state.clone();
+            async move { webhook_handler(State(state), None, headers, WebhookBody(body)).await.into_response().status() }
+        };
+        let txn_id = seed_transaction(&db, Decimal::new(10000, 2), "pending").await;
+        let updated_at = |txn_id: Uuid| {
+            let db = db.clone();
+            async move { sqlx::query_as::<_, (DateTime<Utc>,)>("SELECT updated_at FROM transactions WHERE id = $1").bind(txn_id).fetch_one(&db).await.unwrap().0 }
+        };
+
+        let charge = serde_json::json!({ "event": "charge.success", "data": { "id": 302961, "reference": format!("TXN-{}", txn_id), "amount": 10000 } });
+        assert_eq!(deliver(&charge).await, StatusCode::OK);
+        let first = updated_at(txn_id).await;
+        assert_eq!(deliver(&charge).await, StatusCode::OK);
+        assert_eq!(updated_at(txn_id).await, first);
+
+        // Renewals aren't guarded by a status change, so only the event id stops a double count
+        let req = CreateSubscriptionRequest {
+            customer_id: Uuid::now_v7(),
+            plan_id: "PLAN_PRO".into(),
+            amount: Amount::new(250_000, "NGN"),
+            billing_cycle: None,
+            payment_method_id: None,
+            billing_details: None,
+            metadata: Metadata::new(),
+        };
+        let (_, Json(sub)) = create_subscription(State(state.clone()), merchant(), Json(req)).await.unwrap();
+        let invoice = serde_json::json!({
+            "event": "invoice.update",
+            "data": { "id": 3953, "paid": true, "amount": 250_000, "currency": "NGN", "metadata": { "subscription_id": sub.id } }
+        });
+        assert_eq!(deliver(&invoice).await, StatusCode::OK);
+        assert_eq!(deliver(&invoice).await, StatusCode::OK);
+        let (renewals,): (i32,) = sqlx::query_as("SELECT renewal_count FROM subscriptions WHERE id = $1").bind(sub.id).fetch_one(&db).await.unwrap();
+        assert_eq!(renewals, 1);
+
+        let recorded: Vec<(String, String)> = sqlx::query_as("SELECT event_id, event_type FROM processed_webhook_events ORDER BY processed_at")
+            .fetch_all(&db).await.unwrap();
+        assert_eq!(recorded, vec![
+            ("charge.success:302961".to_string(), "charge.success".to_string()),
+            ("invoice.update:3953".to_string(), "invoice.update".to_string()),
+        ]);
+        let (succeeded,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM event_outbox WHERE payload->>'type' = 'payment.succeeded'").fetch_one(&db).await.unwrap();
+        assert_eq!(succeeded, 1);
+    }
+
+    #[sqlx::test]
+    async fn test_webhook_ip_allowlist(db: sqlx::PgPool) {
+        let mut state = test_state(db);
+        state.config = Arc::new(Config {
+            webhook_allowlist: WebhookAllowlist::parse("paystack=52.31.139.75", "10.0.0.0/8").unwrap(),
+            ..Config::clone(&state.config)
+        });
+        let app = build_router(state);
+        let deliver = |peer: &str, forwarded_for: Option<&str>| {
+            let mut builder = axum::http::Request::post("/api/v1/payments/webhook").header("content-type", "application/json");
+            if let Some(xff) = forwarded_for {
+                builder = builder.header("x-forwarded-for", xff);
+            }
+            let (headers, body) = signed_webhook(&serde_json::json!({ "event": "charge.success", "data": {} }));
+            builder.headers_mut().unwrap().extend(headers);
+            let mut request = builder.body(Body::from(body)).unwrap();
+            request.extensions_mut().insert(ConnectInfo(SocketAddr::new(peer.parse().unwrap(), 443)));
+            app.clone().oneshot(request)
+        };
+
+        assert_eq!(deliver("52.31.139.75", None).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(deliver("10.0.0.5", Some("52.31.139.75")).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(deliver("203.0.113.9", None).await.unwrap().status(), StatusCode::FORBIDDEN);
+        // Spoofed headers: from an untrusted peer, or prepended ahead of the real client.
+        assert_eq!(deliver("203.0.113.9", Some("52.31.139.75")).await.unwrap().status(), StatusCode::FORBIDDEN);
+        assert_eq!(deliver("10.0.0.5", Some("52.31.139.75, 203.0.113.9")).await.unwrap().status(), StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    async fn test_subscription_metrics(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let req = CreateSubscriptionRequest {
+            customer_id: Uuid::now_v7(),
+            plan_id: "PLAN_PRO".into(),
+            amount: Amount::new(49000, "USD"),
+            billing_cycle: Some("yearly".into()),
+            payment_method_id: None,
+            billing_details: None,
+            metadata: Metadata::new(),
+        };
+        let (_, Json(sub)) = create_subscription(State(state.clone()), merchant(), Json(req)).await.unwrap();
+        let paid = || Some(Money::new(Decimal::new(49000, 2), "USD"));
+        charge_subscription(&db, sub.id, TransactionStatus::Succeeded, paid()).await;
+        charge_subscription(&db, sub.id, TransactionStatus::Succeeded, paid()).await;
+
+        let Json(metrics) = get_subscription_metrics(State(state.clone()), merchant(), Path(sub.id)).await.unwrap();
+        assert_eq!(metrics.total_paid, Money::new(Decimal::new(98000, 2), "USD"));
+        assert_eq!(metrics.renewals, 2);
+        assert_eq!(metrics.mrr, Money::new(Decimal::new(4083, 2), "USD"));
+        assert_eq!(metrics.churn_risk, sase_payments::domain::services::ChurnRisk::Low);
+
+        charge_subscription(&db, sub.id, TransactionStatus::Failed, None).await;
+        let Json(metrics) = get_subscription_metrics(State(state.clone()), merchant(), Path(sub.id)).await.unwrap();
+        assert_eq!(metrics.consecutive_failures, 1);
+        assert_eq!(metrics.churn_risk, sase_payments::domain::services::ChurnRisk::High);
+
+        // Out of dunning attempts it is unpaid, still delinquent, until a payment recovers it
+        for _ in 1..DunningPolicy::default().max_attempts {
+            charge_subscription(&db, sub.id, TransactionStatus::Failed, None).await;
+        }
+        let Json(unpaid) = get_subscription(State(state.clone()), merchant(), Path(sub.id)).await.unwrap();
+        assert_eq!(unpaid.status, "unpaid");
+        let Json(metrics) = get_subscription_metrics(State(state.clone()), merchant(), Path(sub.id)).await.unwrap();
+        assert_eq!(metrics.churn_risk, sase_payments::domain::services::ChurnRisk::High);
+        charge_subscription(&db, sub.id, TransactionStatus::Succeeded, paid()).await;
+        let Json(metrics) = get_subscription_metrics(State(state), merchant(), Path(sub.id)).await.unwrap();
+        assert_eq!((metrics.renewals, metrics.consecutive_failures), (3, 0));
+        assert_eq!(metrics.churn_risk, sase_payments::domain::services::ChurnRisk::Low);
+    }
+
+    /// Applies a provider-settled charge to the subscription as a webhook would.
+    async fn charge_subscription(db: &sqlx::PgPool, id: Uuid, outcome: TransactionStatus, paid: Option<Money>) {
+        let mut tx = db.begin().await.unwrap();
+        record_subscription_charge(&mut tx, id, outcome, paid, DunningPolicy::default()).await.unwrap();
+        tx.commit().await.unwrap();
+    }
+
+    #[sqlx::test]
+    async fn test_charge_webhook_leaves_cancelled_subscription(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let due = seed_due_subscription(&db, Uuid::now_v7(), false).await;
+        sqlx::query("UPDATE subscriptions SET status = 'cancelled' WHERE id = $1").bind(due).execute(&db).await.unwrap();
+        let snapshot = || sqlx::query_as::<_, (String, i32, i32, chrono::NaiveDate, Decimal)>(
+            "SELECT status, renewal_count, consecutive_failures, current_period_end, total_paid FROM subscriptions WHERE id = $1"
+        ).bind(due).fetch_one(&db);
+        let before = snapshot().await.unwrap();
+
+        let renewed = WebhookEvent::SubscriptionRenewed { subscription_id: due, amount: Some(Money::new(Decimal::from(2500), "NGN")) };
+        assert!(apply_webhook(&db, state.config.dunning, "paystack", "evt_renewed", &serde_json::json!({}), renewed).await.unwrap());
+        let pending = seed_transaction(&db, Decimal::from(2500), "pending").await;
+        let failed = WebhookEvent::ChargeFailed { reference: format!("TXN-{}", pending), subscription_id: Some(due) };
+        assert!(apply_webhook(&db, state.config.dunning, "paystack", "evt_failed", &serde_json::json!({}), failed).await.unwrap());
+
+        assert_eq!(snapshot().await.unwrap(), before);
+        let subscription_events: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM event_outbox WHERE subject LIKE 'payments.subscription.%'").fetch_one(&db).await.unwrap();
+        assert_eq!(subscription_events, 0);
+    }
+
+    #[sqlx::test]
+    async fn test_verify_payment_resolves_with_provider(db: sqlx::PgPool) {
+        let checkout = ChargeResult::Checkout { authorization_url: "https://checkout.example/1".into(), provider_reference: None };
+        let gateway = Arc::new(MockGateway::new(Ok(checkout)));
+        let state = test_state_with_gateway(db.clone(), gateway.clone());
+        let verify = |reference: &str| verify_payment(State(state.clone()), merchant(), Json(VerifyPaymentRequest { reference: reference.into() }));
+
+        let Json(paid) = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap();
+        let Json(abandoned) = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap();
+        assert_eq!(verify(&paid.reference).await.unwrap().0.status, "pending");
+
+        gateway.set_verification(Verification { status: VerifiedStatus::Pending, raw_response: None });
+        assert_eq!(verify(&paid.reference).await.unwrap().0.status, "pending");
+
+        let raw = serde_json::json!({ "status": true, "data": { "status": "success", "amount": 500000, "currency": "NGN" } });
+        gateway.set_verification(Verification { status: VerifiedStatus::Succeeded, raw_response: Some(raw) });
+        let Json(txn) = verify(&paid.reference).await.unwrap();
+        assert_eq!(txn.status, "succeeded");
+        assert!(txn.completed_at.is_some());
+        let stored: (String, serde_json::Value) = sqlx::query_as("SELECT status, provider_raw_response FROM transactions WHERE reference = $1")
+            .bind(&paid.reference).fetch_one(&db).await.unwrap();
+        assert_eq!(stored.0, "succeeded");
+        assert_eq!(stored.1["data"]["amount"], 500000);
+
+        // Resolved transactions are not re-queried, whatever the provider now says
+        gateway.set_verification(Verification { status: VerifiedStatus::Abandoned, raw_response: None });
+        assert_eq!(verify(&paid.reference).await.unwrap().0.status, "succeeded");
+        let Json(txn) = verify(&abandoned.reference).await.unwrap();
+        assert_eq!(txn.status, "cancelled");
+        assert!(txn.completed_at.is_none());
+
+        gateway.set_verification(Verification { status: VerifiedStatus::Failed, raw_response: None });
+        let Json(failed) = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap();
+        assert_eq!(verify(&failed.reference).await.unwrap().0.status, "failed");
+    }
+
+    #[sqlx::test]
+    async fn test_void_pending_transaction(db: sqlx::PgPool) {
+        let checkout = ChargeResult::Checkout { authorization_url: "https://checkout.example/1".into(), provider_reference: Some("prov_1".into()) };
+        let gateway = Arc::new(MockGateway::new(Ok(checkout)));
+        let state = test_state_with_gateway(db.clone(), gateway.clone());
+        let void = |id: Uuid| void_transaction(State(state.clone()), merchant(), Path(id));
+        let id_of = |reference: String| {
+            let db = db.clone();
+            async move { sqlx::query_scalar::<_, Uuid>("SELECT id FROM transactions WHERE reference = $1").bind(reference).fetch_one(&db).await.unwrap() }
+        };
+
+        let Json(pending) = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap();
+        let id = id_of(pending.reference.clone()).await;
+        let Json(txn) = void(id).await.unwrap();
+        assert_eq!(txn.status, "cancelled");
+        assert_eq!(gateway.voids(), vec!["prov_1".to_string()]);
+        assert_eq!(void(id).await.unwrap_err().status, StatusCode::CONFLICT);
+        assert_eq!(gateway.voids().len(), 1);
+        let cancelled = || sqlx::query_scalar::<_, serde_json::Value>("SELECT payload FROM event_outbox WHERE subject = 'payments.payment.cancelled' ORDER BY id")
+            .fetch_all(&db);
+        assert_eq!(cancelled().await.unwrap(), vec![serde_json::json!({ "type": "payment.cancelled", "payment_id": pending.reference })]);
+
+        // A provider that refuses the void leaves the charge open
+        use sase_payments::domain::value_objects::ProviderErrorKind;
+        gateway.set_void_outcome(Err(PaymentError::ProviderError { kind: ProviderErrorKind::InvalidRequest, message: "mock: charge already captured".into() }));
+        let Json(refused) = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap();
+        let refused = id_of(refused.reference).await;
+        assert!(void(refused).await.is_err());
+        let status: String = sqlx::query_scalar("SELECT status FROM transactions WHERE id = $1").bind(refused).fetch_one(&db).await.unwrap();
+        assert_eq!(status, "pending");
+        assert_eq!(cancelled().await.unwrap().len(), 1);
+
+        // Without a provider id only our side is cancelled
+        let seeded = seed_transaction(&db, Decimal::from(50), "requires_action").await;
+        assert_eq!(void(seeded).await.unwrap().0.status, "cancelled");
+        assert_eq!(gateway.voids().len(), 2);
+        assert_eq!(cancelled().await.unwrap().len(), 2);
+    }
+
+    #[sqlx::test]
+    async fn test_void_rejects_succeeded_transaction(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let id = seed_transaction(&db, Decimal::from(50), "succeeded").await;
+        let err = void_transaction(State(state.clone()), merchant(), Path(id)).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::CONFLICT);
+        assert!(err.message.contains("refund"), "{}", err.message);
+        let status: String = sqlx::query_scalar("SELECT status FROM transactions WHERE id = $1").bind(id).fetch_one(&db).await.unwrap();
+        assert_eq!(status, "succeeded");
+
+        let other = Extension(Merchant::new(Uuid::now_v7()));
+        assert_eq!(void_transaction(State(state), other, Path(id)).await.unwrap_err().status, StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn test_capture_authorized_payment(db: sqlx::PgPool) {
+        let gateway = Arc::new(MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: Some("ch_auth".into()) })));
+        let state = test_state_with_gateway(db.clone(), gateway.clone());
+        let authorize = || {
+            let (state, db) = (state.clone(), db.clone());
+            async move {
+                let req = InitiatePaymentRequest { capture: Some(false), ..initiate_request(5000) };
+                let Json(response) = initiate_payment(State(state), merchant(), HeaderMap::new(), Json(req)).await.unwrap();
+                assert_eq!(response.status, "authorized");
+                sqlx::query_scalar::<_, Uuid>("SELECT id FROM transactions WHERE reference = $1").bind(response.reference).fetch_one(&db).await.unwrap()
+            }
+        };
+        let capture = |id: Uuid, amount: Option<i64>| capture_transaction(State(state.clone()), merchant(), Path(id), Json(CaptureRequest { amount }));
+
+        let id = authorize().await;
+        assert!(!gateway.requests()[0].capture);
+        // Over-capture is refused before the provider hears of it
+        let err = capture(id, Some(5001)).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(gateway.captures().is_empty());
+
+        // Partial capture: the charge becomes what was taken
+        let Json(txn) = capture(id, Some(3000)).await.unwrap();
+        assert_eq!(txn.status, "succeeded");
+        assert_eq!(txn.amount, Decimal::from(30));
+        assert_eq!(txn.authorized_amount, Some(Decimal::from(50)));
+        assert!(txn.completed_at.is_some());
+        assert_eq!(gateway.captures()[0].amount, Money::from_minor_units(3000, "NGN").unwrap());
+        assert_eq!(gateway.captures()[0].provider_reference.as_deref(), Some("ch_auth"));
+        assert_eq!(capture(id, None).await.unwrap_err().status, StatusCode::CONFLICT);
+
+        // Full capture
+        let id = authorize().await;
+        let Json(txn) = capture(id, None).await.unwrap();
+        assert_eq!((txn.status.as_str(), txn.amount), ("succeeded", Decimal::from(50)));
+        assert_eq!(gateway.captures().len(), 2);
+        let succeeded: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM event_outbox WHERE subject = 'payments.payment.succeeded'").fetch_one(&db).await.unwrap();
+        assert_eq!(succeeded, 2);
+
+        // Charges made the default way are never authorized
+        let Json(immediate) = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap();
+        assert_eq!(immediate.status, "succeeded");
+        let id = sqlx::query_scalar::<_, Uuid>("SELECT id FROM transactions WHERE reference = $1").bind(immediate.reference).fetch_one(&db).await.unwrap();
+        assert_eq!(capture(id, None).await.unwrap_err().status, StatusCode::CONFLICT);
+    }
+
+    #[sqlx::test]
+    async fn test_expired_authorizations_are_cancelled(db: sqlx::PgPool) {
+        let gateway = Arc::new(MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: Some("ch_auth".into()) })));
+        let state = test_state_with_gateway(db.clone(), gateway.clone());
+        let req = InitiatePaymentRequest { capture: Some(false), ..initiate_request(5000) };
+        let Json(authorized) = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(req)).await.unwrap();
+        let id: Uuid = sqlx::query_scalar("SELECT id FROM transactions WHERE reference = $1").bind(&authorized.reference).fetch_one(&db).await.unwrap();
+
+        assert_eq!(expire_authorizations(&state, Utc::now()).await.unwrap(), 0);
+        let later = Utc::now() + chrono::Duration::seconds(state.config.authorization_ttl_secs + 60);
+        assert_eq!(expire_authorizations(&state, later).await.unwrap(), 1);
+        assert_eq!(expire_authorizations(&state, later).await.unwrap(), 0);
+        let status: String = sqlx::query_scalar("SELECT status FROM transactions WHERE id = $1").bind(id).fetch_one(&db).await.unwrap();
+        assert_eq!(status, "cancelled");
+        assert_eq!(gateway.voids(), vec!["ch_auth".to_string()]);
+        let cancelled: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM event_outbox WHERE subject = 'payments.payment.cancelled'").fetch_one(&db).await.unwrap();
+        assert_eq!(cancelled, 1);
+        let err = capture_transaction(State(state.clone()), merchant(), Path(id), Json(CaptureRequest { amount: None })).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::CONFLICT);
+
+        // Only providers that can hold funds take capture: false
+        let stub = test_state(db.clone());
+        let req = InitiatePaymentRequest { capture: Some(false), ..initiate_request(5000) };
+        let err = initiate_payment(State(stub), merchant(), HeaderMap::new(), Json(req)).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn test_three_ds_charge_confirmed(db: sqlx::PgPool) {
+        let three_ds = NextAction::RedirectToUrl { url: "https://acs.example/3ds/1".into() };
+        let gateway = Arc::new(MockGateway::new(Ok(ChargeResult::RequiresAction { next_action: three_ds.clone(), provider_reference: Some("ch_3ds".into()) })));
+        let state = test_state_with_gateway(db.clone(), gateway.clone());
+        let confirm = |reference: &str| confirm_payment(State(state.clone()), merchant(), Json(ConfirmPaymentRequest { reference: reference.into() }));
+
+        // The charge waits on the customer, with the step they must take stored on it
+        let Json(charge) = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap();
+        assert_eq!(charge.status, "requires_action");
+        let Json(txn) = verify_payment(State(state.clone()), merchant(), Json(VerifyPaymentRequest { reference: charge.reference.clone() })).await.unwrap();
+        assert_eq!((txn.status.as_str(), txn.next_action.map(|a| a.0)), ("requires_action", Some(three_ds)));
+
+        // Confirming before the customer authenticates hands back the provider's latest step
+        let retry = NextAction::RedirectToUrl { url: "https://acs.example/3ds/2".into() };
+        gateway.set_verification(Verification { status: VerifiedStatus::RequiresAction(retry.clone()), raw_response: None });
+        let Json(txn) = confirm(&charge.reference).await.unwrap();
+        assert_eq!((txn.status.as_str(), txn.next_action.map(|a| a.0)), ("requires_action", Some(retry)));
+
+        // Once they have, the charge settles and the step is cleared
+        gateway.set_verification(Verification { status: VerifiedStatus::Succeeded, raw_response: None });
+        let Json(txn) = confirm(&charge.reference).await.unwrap();
+        assert_eq!(txn.status, "succeeded");
+        assert!(txn.next_action.is_none() && txn.completed_at.is_some());
+        let succeeded: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM event_outbox WHERE subject = 'payments.payment.succeeded'").fetch_one(&db).await.unwrap();
+        assert_eq!(succeeded, 1);
+        let again = confirm(&charge.reference).await.unwrap_err();
+        assert_eq!(again.status, StatusCode::CONFLICT);
+
+        // Authenticated but not yet settled by the provider: processing until its webhook or a verify
+        let Json(slow) = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap();
+        gateway.set_verification(Verification { status: VerifiedStatus::Pending, raw_response: None });
+        assert_eq!(confirm(&slow.reference).await.unwrap().0.status, "processing");
+        gateway.set_verification(Verification { status: VerifiedStatus::Failed, raw_response: None });
+        let Json(txn) = verify_payment(State(state.clone()), merchant(), Json(VerifyPaymentRequest { reference: slow.reference })).await.unwrap();
+        assert_eq!(txn.status, "failed");
+
+        assert_eq!(confirm("TXN-missing").await.unwrap_err().status, StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn test_payment_provider_selection(db: sqlx::PgPool) {
+        let mock = Arc::new(MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: Some("ch_1".into()) })));
+        let mut state = test_state(db.clone());
+        state.gateways = Arc::new(vec![mock.clone()]);
+        let provider_of = |reference: String| {
+            let db = db.clone();
+            async move { sqlx::query_as::<_, (Option<String>,)>("SELECT provider FROM transactions WHERE reference = $1").bind(reference).fetch_one(&db).await.unwrap().0 }
+        };
+
+        let Json(default) = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap();
+        assert_eq!(provider_of(default.reference).await.as_deref(), Some("paystack"));
+        assert!(mock.requests().is_empty());
+
+        let chosen = InitiatePaymentRequest { provider: Some("mock".into()), ..initiate_request(5000) };
+        let Json(resp) = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(chosen)).await.unwrap();
+        assert_eq!(resp.status, "succeeded");
+        assert_eq!(provider_of(resp.reference).await.as_deref(), Some("mock"));
+        assert_eq!(mock.requests().len(), 1);
+
+        let unknown = InitiatePaymentRequest { provider: Some("flutterwave".into()), ..initiate_request(5000) };
+        let err = initiate_payment(State(state), merchant(), HeaderMap::new(), Json(unknown)).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn test_scheduled_payment_runs_when_due(db: sqlx::PgPool) {
+        let mock = Arc::new(MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: Some("ch_1".into()) })));
+        let state = test_state_with_gateway(db.clone(), mock.clone());
+        let later = |at| InitiatePaymentRequest { scheduled_at: Some(at), ..initiate_request(5000) };
+        let status_of = |reference: String| {
+            let db = db.clone();
+            async move { sqlx::query_as::<_, (String,)>("SELECT status FROM transactions WHERE reference = $1").bind(reference).fetch_one(&db).await.unwrap().0 }
+        };
+
+        let past = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(later(Utc::now() - chrono::Duration::minutes(1)))).await;
+        assert_eq!(past.unwrap_err().status, StatusCode::BAD_REQUEST);
+
+        // Stored without touching the provider
+        let Json(due) = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(later(Utc::now() + chrono::Duration::hours(1)))).await.unwrap();
+        let Json(not_yet) = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(later(Utc::now() + chrono::Duration::hours(2)))).await.unwrap();
+        assert_eq!(due.status, "scheduled");
+        assert!(mock.requests().is_empty());
+        assert_eq!(start_due_scheduled_payments(&state, Utc::now()).await.unwrap(), 0);
+
+        // Once due, the scan charges it under the reference it was given, and only once
+        let scan_at = Utc::now() + chrono::Duration::minutes(90);
+        assert_eq!(start_due_scheduled_payments(&state, scan_at).await.unwrap(), 1);
+        assert_eq!(start_due_scheduled_payments(&state, scan_at).await.unwrap(), 0);
+        assert_eq!(mock.requests().len(), 1);
+        assert_eq!(mock.requests()[0].reference, due.reference);
+        assert_eq!(status_of(due.reference.clone()).await, "succeeded");
+        assert_eq!(status_of(not_yet.reference.clone()).await, "scheduled");
+        let events: Vec<String> = sqlx::query_scalar("SELECT subject FROM event_outbox WHERE payload->>'payment_id' = $1 ORDER BY created_at, id")
+            .bind(&due.reference)
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert_eq!(events, ["payments.payment.created", "payments.payment.succeeded"]);
+
+        // A scheduled payment isn't something to retry
+        let retry = retry_payment(State(state), merchant(), Path(not_yet.reference)).await;
+        assert_eq!(retry.unwrap_err().status, StatusCode::CONFLICT);
+    }
+
+    #[sqlx::test]
+    async fn test_payment_plan_charges_installments_when_due(db: sqlx::PgPool) {
+        let mock = Arc::new(MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: Some("ch_1".into()) })));
+        let state = test_state_with_gateway(db.clone(), mock.clone());
+        let plan_request = |minor: i64, installments: u32| CreatePaymentPlanRequest {
+            amount: Amount::new(minor, "NGN"),
+            installments,
+            interval: Some("weekly".into()),
+            first_due_on: None,
+            email: "ada@example.com".into(),
+            customer_id: None,
+            payment_method_id: None,
+            callback_url: None,
+            metadata: Some(serde_json::json!({ "order": "1001" })),
+            provider: None,
+        };
+
+        let (status, Json(plan)) = create_payment_plan(State(state.clone()), merchant(), Json(plan_request(10_000, 3))).await.unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        let amounts: Vec<Decimal> = plan.installments.iter().map(|i| i.amount).collect();
+        assert_eq!(amounts, vec![Decimal::new(3334, 2), Decimal::new(3333, 2), Decimal::new(3333, 2)]);
+        assert_eq!(amounts.iter().sum::<Decimal>(), plan.total);
+        let today = Utc::now().date_naive();
+        assert_eq!(plan.installments.iter().map(|i| i.due_on).collect::<Vec<_>>(), vec![today, today + chrono::Duration::days(7), today + chrono::Duration::days(14)]);
+        assert!(plan.installments.iter().all(|i| i.status.as_deref() == Some("scheduled")));
+
+        // The first is due now; the rest wait for their week
+        assert_eq!(start_due_scheduled_payments(&state, Utc::now()).await.unwrap(), 1);
+        assert_eq!(start_due_scheduled_payments(&state, Utc::now() + chrono::Duration::days(8)).await.unwrap(), 1);
+        let charged = mock.requests();
+        assert_eq!(charged.iter().map(|c| c.amount.amount).collect::<Vec<_>>(), vec![Decimal::new(3334, 2), Decimal::new(3333, 2)]);
+        assert_eq!(charged[0].metadata["payment_plan_id"], serde_json::json!(plan.id));
+        assert_eq!((charged[1].metadata["installment"].clone(), charged[1].metadata["order"].clone()), (serde_json::json!("2"), serde_json::json!("1001")));
+        let Json(plan) = get_payment_plan(State(state.clone()), merchant(), Path(plan.id)).await.unwrap();
+        let statuses: Vec<Option<&str>> = plan.installments.iter().map(|i| i.status.as_deref()).collect();
+        assert_eq!(statuses, vec![Some("succeeded"), Some("succeeded"), Some("scheduled")]);
+
+        // Too few minor units to go round, and other merchants' plans, are refused
+        let err = create_payment_plan(State(state.clone()), merchant(), Json(plan_request(2, 3))).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+        let stranger = get_payment_plan(State(state), Extension(Merchant::new(Uuid::now_v7())), Path(plan.id)).await.unwrap_err();
+        assert_eq!(stranger.0, StatusCode::NOT_FOUND);
+    }
+
+    /// Fails the first charge it sees, then succeeds.
+    struct FlakyGateway { inner: MockGateway, calls: std::sync::atomic::AtomicUsize }
+
+    #[async_trait::async_trait]
+    impl PaymentGateway for FlakyGateway {
+        fn name(&self) -> &'static str { "mock" }
+        async fn charge(&self, request: &ChargeRequest) -> Result<sase_payments::providers::ChargeResponse, PaymentError> {
+            let response = self.inner.charge(request).await;
+            if self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                return Err(PaymentError::InvalidStatus);
+            }
+            response
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_provider_idempotency_key_reused_on_retry(db: sqlx::PgPool) {
+        let gateway = Arc::new(FlakyGateway {
+            inner: MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: Some("ch_1".into()) })),
+            calls: Default::default(),
+        });
+        let state = test_state_with_gateway(db, gateway.clone());
+
+        let first = InitiatePaymentRequest { reference: Some("order-retry-1".into()), ..initiate_request(5000) };
+        let err = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(first)).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_GATEWAY);
+
+        let Json(retried) = retry_payment(State(state.clone()), merchant(), Path("order-retry-1".into())).await.unwrap();
+        assert_eq!(retried.status, "succeeded");
+        let err = retry_payment(State(state.clone()), merchant(), Path("order-retry-1".into())).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::CONFLICT);
+
+        let _ = initiate_payment(State(state), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap();
+
+        let keys: Vec<String> = gateway.inner.requests().into_iter().map(|r| r.idempotency_key).collect();
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys[0], keys[1]);
+        assert_ne!(keys[0], keys[2]);
+    }
+
+    #[sqlx::test]
+    async fn test_gateway_errors_are_classified(db: sqlx::PgPool) {
+        use sase_payments::domain::value_objects::{DeclineCode, ProviderErrorKind};
+
+        let declined = MockGateway::new(Err(PaymentError::Declined(DeclineCode::InsufficientFunds)));
+        let err = initiate_payment(State(test_state_with_gateway(db.clone(), Arc::new(declined))), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::PAYMENT_REQUIRED);
+        let body = err.body();
+        assert_eq!(body["error"], "card_declined");
+        assert_eq!(body["decline_code"], "insufficient_funds");
+
+        let timeout = MockGateway::new(Err(PaymentError::ProviderError {
+            kind: ProviderErrorKind::Timeout,
+            message: "operation timed out connecting to 10.20.0.4:443".into(),
+        }));
+        let err = initiate_payment(State(test_state_with_gateway(db, Arc::new(timeout))), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::SERVICE_UNAVAILABLE);
+        let body = err.body().to_string();
+        assert!(body.contains(r#""error":"provider_unavailable""#));
+        assert!(!body.contains("10.20.0.4") && !body.contains("timed out connecting"));
+    }
+
+    #[test]
+    fn test_payment_error_responses() {
+        use sase_payments::domain::value_objects::{DeclineCode, ProviderErrorKind};
+        let cases = [
+            (PaymentError::PaymentNotFound("TXN-1".into()), StatusCode::NOT_FOUND, "payment_not_found"),
+            (PaymentError::Declined(DeclineCode::InsufficientFunds), StatusCode::PAYMENT_REQUIRED, "card_declined"),
+            (PaymentError::InsufficientFunds("short by 1.00 NGN".into()), StatusCode::PAYMENT_REQUIRED, "insufficient_funds"),
+            (PaymentError::InvalidAmount("amount must be positive".into()), StatusCode::BAD_REQUEST, "invalid_amount"),
+            (PaymentError::InvalidCurrency("ZZT".into()), StatusCode::BAD_REQUEST, "invalid_currency"),
+            (PaymentError::ProviderError { kind: ProviderErrorKind::Timeout, message: "connect to 10.1.2.3:443".into() }, StatusCode::BAD_GATEWAY, "provider_error"),
+            (PaymentError::CurrencyMismatch { expected: "NGN".into(), actual: "USD".into() }, StatusCode::UNPROCESSABLE_ENTITY, "currency_mismatch"),
+            (PaymentError::RefundExceedsPayment, StatusCode::CONFLICT, "refund_exceeds_payment"),
+            (PaymentError::ConcurrencyConflict, StatusCode::CONFLICT, "concurrency_conflict"),
+        ];
+        for (error, status, code) in cases {
+            let message = error.to_string();
+            let err = ApiError::from(error);
+            assert_eq!((err.status, err.code.as_str()), (status, code));
+            let body = err.body();
+            assert_eq!(body["error"], code);
+            if status == StatusCode::BAD_GATEWAY { assert!(!body["message"].as_str().unwrap().contains("10.1.2.3")); } else { assert_eq!(body["message"], message); }
+            assert_eq!(err.into_response().status(), status);
+        }
+        assert_eq!(ApiError::from(PaymentError::Declined(DeclineCode::ExpiredCard)).body()["decline_code"], "expired_card");
+
+        let legacy = ApiError::from((StatusCode::NOT_FOUND, "Wallet not found".to_string()));
+        assert_eq!(legacy.body(), serde_json::json!({ "error": "not_found", "message": "Wallet not found" }));
+    }
+
+    async fn backdate_transaction(db: &sqlx::PgPool, id: Uuid, days: i64) {
+        sqlx::query("UPDATE transactions SET created_at = NOW() - make_interval(days => $1) WHERE id = $2")
+            .bind(days as i32)
+            .bind(id)
+            .execute(db)
+            .await
+            .unwrap();
+    }
+
+    fn gunzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        flate2::read::GzDecoder::new(data).read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[sqlx::test]
+    async fn test_archive_old_terminal_transactions(db: sqlx::PgPool) {
+        let old = seed_transaction(&db, Decimal::new(10000, 2), "partially_refunded").await;
+        let old_pending = seed_transaction(&db, Decimal::new(2000, 2), "pending").await;
+        let recent = seed_transaction(&db, Decimal::new(3000, 2), "succeeded").await;
+        backdate_transaction(&db, old, 400).await;
+        backdate_transaction(&db, old_pending, 400).await;
+        sqlx::query("INSERT INTO refunds (id, transaction_id, amount, status, created_at) VALUES ($1, $2, 25, 'pending', NOW())")
+            .bind(Uuid::now_v7())
+            .bind(old)
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let store = archive::MemoryStore::default();
+        let cutoff = Utc::now() - chrono::Duration::days(365);
+        assert_eq!(archive_transactions(&db, &store, cutoff, 100, false).await.unwrap(), 1);
+
+        let keys = store.keys();
+        assert_eq!(keys.len(), 1);
+        let exported = gunzip(&store.get(&keys[0]).await.unwrap().unwrap()).unwrap();
+        let records: Vec<ArchivedTransaction> = archive::from_ndjson(&exported).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].transaction.id, old);
+        assert_eq!(records[0].refunds.len(), 1);
+        assert_eq!(records[0].refunds[0].amount, Decimal::from(25));
+
+        // Re-running skips what is already archived.
+        assert_eq!(archive_transactions(&db, &store, cutoff, 100, false).await.unwrap(), 0);
+        assert_eq!(store.keys().len(), 1);
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM transactions").fetch_one(&db).await.unwrap();
+        assert_eq!(remaining, 3);
+
+        // Moving rows: the newly eligible transaction leaves the hot table.
+        backdate_transaction(&db, recent, 400).await;
+        assert_eq!(archive_transactions(&db, &store, cutoff, 100, true).await.unwrap(), 1);
+        let moved: Option<serde_json::Value> = sqlx::query_scalar("SELECT record FROM transactions_archive WHERE id = $1")
+            .bind(recent).fetch_one(&db).await.unwrap();
+        assert_eq!(moved.unwrap()["id"], recent.to_string());
+        let gone: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM transactions WHERE id = $1").bind(recent).fetch_one(&db).await.unwrap();
+        assert_eq!(gone, 0);
+        let pending_left: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM transactions WHERE id = $1").bind(old_pending).fetch_one(&db).await.unwrap();
+        assert_eq!(pending_left, 1);
+    }
+
+    #[sqlx::test]
+    async fn test_payment_repository_round_trip(db: sqlx::PgPool) {
+        let repository = PgPaymentRepository::new(db.clone());
+        let mut payment = Payment::create("CUST001", Money::new(Decimal::new(10000, 2), "NGN"));
+        payment.process(PaymentMethodDetails { method_type: sase_payments::domain::value_objects::PaymentMethodType::Card, last_four: Some("4242".into()), brand: Some("Visa".into()), exp_month: Some(12), exp_year: Some(2030) }).unwrap();
+        payment.succeed().unwrap();
+        payment.refund(Money::new(Decimal::new(4000, 2), "NGN")).unwrap();
+        repository.save(&mut payment).await.unwrap();
+        assert!(payment.pending_events().is_empty());
+
+        let mut loaded = repository.load(payment.id()).await.unwrap().unwrap();
+        assert_eq!(loaded.to_record().refunded_amount, Decimal::new(4000, 2));
+        assert_eq!(loaded.status(), &sase_payments::domain::aggregates::PaymentStatus::PartiallyRefunded);
+        assert_eq!(loaded.to_record().payment_method, payment.to_record().payment_method);
+
+        // Saving again updates the row and appends only the new events
+        loaded.refund(Money::new(Decimal::new(6000, 2), "NGN")).unwrap();
+        repository.save(&mut loaded).await.unwrap();
+        let reloaded = repository.load(payment.id()).await.unwrap().unwrap();
+        assert_eq!(reloaded.status(), &sase_payments::domain::aggregates::PaymentStatus::Refunded);
+        assert_eq!(reloaded.refunded_amount(), Decimal::new(10000, 2));
+        let subjects: Vec<String> = sqlx::query_scalar("SELECT subject FROM event_outbox ORDER BY subject").fetch_all(&db).await.unwrap();
+        assert_eq!(subjects.len(), 4);
+        assert!(repository.load(&PaymentId::new()).await.unwrap().is_none());
+    }
+
+    #[sqlx::test]
+    async fn test_payment_repository_rejects_stale_save(db: sqlx::PgPool) {
+        let repository = PgPaymentRepository::new(db.clone());
+        let mut payment = Payment::create("CUST001", Money::new(Decimal::new(10000, 2), "NGN"));
+        payment.process(PaymentMethodDetails { method_type: sase_payments::domain::value_objects::PaymentMethodType::Card, last_four: None, brand: None, exp_month: None, exp_year: None }).unwrap();
+        payment.succeed().unwrap();
+        repository.save(&mut payment).await.unwrap();
+
+        let mut first = repository.load(payment.id()).await.unwrap().unwrap();
+        let mut second = repository.load(payment.id()).await.unwrap().unwrap();
+        first.refund(Money::new(Decimal::new(7000, 2), "NGN")).unwrap();
+        second.refund(Money::new(Decimal::new(5000, 2), "NGN")).unwrap();
+        repository.save(&mut first).await.unwrap();
+        let err = repository.save(&mut second).await.unwrap_err();
+        assert_eq!(err, RepositoryError::Payment(PaymentError::ConcurrencyConflict));
+        assert_eq!(payment_error_status(PaymentError::ConcurrencyConflict).0, StatusCode::CONFLICT);
+
+        let stored = repository.load(payment.id()).await.unwrap().unwrap();
+        assert_eq!((stored.refunded_amount(), stored.version()), (Decimal::new(7000, 2), 2));
+        let refunds: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM event_outbox WHERE subject LIKE '%refunded'").fetch_one(&db).await.unwrap();
+        assert_eq!(refunds, 1);
+
+        // Reloading picks up the first refund, so the second is now checked against it
+        let mut retried = repository.load(payment.id()).await.unwrap().unwrap();
+        assert_eq!(retried.refund(Money::new(Decimal::new(5000, 2), "NGN")), Err(PaymentError::RefundExceedsPayment));
+    }
+
+    #[sqlx::test]
+    async fn test_settlement_report(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let day = chrono::NaiveDate::from_ymd_opt(2026, 3, 14).unwrap();
+        let at = |hour: u32| day.and_hms_opt(hour, 0, 0).unwrap().and_utc();
+        let seed = |amount: i64, status: &'static str, currency: &'static str, provider: Option<&'static str>, completed_at: DateTime<Utc>| {
+            let db = db.clone();
+            async move {
+                let id = seed_transaction(&db, Decimal::from(amount), status).await;
+                sqlx::query("UPDATE transactions SET currency = $1, provider = $2, completed_at = $3 WHERE id = $4")
+                    .bind(currency).bind(provider).bind(completed_at).bind(id)
+                    .execute(&db).await.unwrap();
+                id
+            }
+        };
+        let refund = |txn: Uuid, amount: i64, status: &'static str| {
+            let db = db.clone();
+            async move {
+                sqlx::query("INSERT INTO refunds (id, transaction_id, amount, status, created_at) VALUES ($1, $2, $3, $4, NOW())")
+                    .bind(Uuid::now_v7()).bind(txn).bind(Decimal::from(amount)).bind(status)
+                    .execute(&db).await.unwrap();
+            }
+        };
+
+        let refunded = seed(1000, "partially_refunded", "NGN", Some("paystack"), at(9)).await;
+        refund(refunded, 300, "succeeded").await;
+        refund(refunded, 50, "pending").await;
+        refund(refunded, 200, "failed").await;
+        seed(500, "succeeded", "NGN", Some("paystack"), at(23)).await;
+        seed(250, "succeeded", "NGN", Some("wallet"), at(0)).await;
+        seed(40, "succeeded", "USD", Some("flutterwave"), at(12)).await;
+        // Not on the report: pending, failed, or completed on another day
+        seed(9999, "pending", "NGN", Some("paystack"), at(10)).await;
+        seed(9999, "failed", "NGN", Some("paystack"), at(11)).await;
+        seed(9999, "succeeded", "NGN", Some("paystack"), at(0) + chrono::Duration::days(1)).await;
+        seed(9999, "succeeded", "NGN", Some("paystack"), at(0) - chrono::Duration::seconds(1)).await;
+
+        let total = |currency: &str, provider: &str, transaction_count, gross: i64, refunded: i64, net: i64| SettlementTotal {
+            currency: currency.into(), provider: Some(provider.into()), transaction_count,
+            gross: Decimal::from(gross), refunded: Decimal::from(refunded), net: Decimal::from(net),
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", "admin-secret".parse().unwrap());
+        let Json(report) = get_settlement_report(State(state.clone()), headers, Query(SettlementReportParams { date: day })).await.unwrap();
+        assert_eq!(report.date, day);
+        assert_eq!(report.totals, vec![
+            total("NGN", "paystack", 2, 1500, 350, 1150),
+            total("NGN", "wallet", 1, 250, 0, 250),
+            total("USD", "flutterwave", 1, 40, 0, 40),
+        ]);
+
+        let err = get_settlement_report(State(state), HeaderMap::new(), Query(SettlementReportParams { date: day })).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::FORBIDDEN);
+        let empty = compute_settlement_totals(&db, day - chrono::Duration::days(7)).await.unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[sqlx::test]
+    async fn test_request_id(db: sqlx::PgPool) {
+        let app = build_router(test_state(db));
+        let get = |uri: &str, request_id: Option<&str>| {
+            let mut builder = axum::http::Request::get(uri);
+            if let Some(id) = request_id { builder = builder.header("x-request-id", id); }
+            app.clone().oneshot(builder.body(Body::empty()).unwrap())
+        };
+        let header = |response: &Response| response.headers().get("x-request-id").map(|v| v.to_str().unwrap().to_string());
+
+        let generated = get("/health", None).await.unwrap();
+        let id = header(&generated).unwrap();
+        assert!(Uuid::parse_str(&id).is_ok());
+        assert_ne!(header(&get("/health", None).await.unwrap()), Some(id));
+
+        let preserved = get("/health", Some("req-42.a:b")).await.unwrap();
+        assert_eq!(header(&preserved).as_deref(), Some("req-42.a:b"));
+        let replaced = get("/health", Some("not a valid id")).await.unwrap();
+        assert_ne!(header(&replaced).as_deref(), Some("not a valid id"));
+
+        // JSON error bodies carry the id too
+        let refused = get(&format!("/api/v1/transactions/{}", Uuid::now_v7()), Some("req-401")).await.unwrap();
+        assert_eq!(refused.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(header(&refused).as_deref(), Some("req-401"));
+        let body: serde_json::Value = serde_json::from_slice(&axum::body::to_bytes(refused.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!((body["error"].as_str(), body["request_id"].as_str()), (Some("unauthorized"), Some("req-401")));
+    }
+
+    #[sqlx::test]
+    async fn test_payment_rate_limit(db: sqlx::PgPool) {
+        seed_api_key(&db, TEST_MERCHANT, "key-a").await;
+        seed_api_key(&db, TEST_MERCHANT, "key-b").await;
+        let mut state = test_state(db);
+        state.payment_limiter = Some(Arc::new(RateLimiter::new(RateLimit { burst: 2, per_second: 0.01 })));
+        let app = build_router(state);
+        let verify = |api_key: &str| {
+            let request = axum::http::Request::post("/api/v1/payments/verify")
+                .header("content-type", "application/json")
+                .header("authorization", format!("Bearer {}", api_key))
+                .body(Body::from(r#"{"reference":"TXN-missing"}"#))
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        for _ in 0..2 {
+            assert_ne!(verify("key-a").await.unwrap().status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+        let limited = verify("key-a").await.unwrap();
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(limited.headers()["retry-after"], "100");
+        let body: serde_json::Value = serde_json::from_slice(&axum::body::to_bytes(limited.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(body["error"], "too_many_requests");
+
+        // Another key has its own bucket, and routes outside /payments aren't limited
+        assert_ne!(verify("key-b").await.unwrap().status(), StatusCode::TOO_MANY_REQUESTS);
+        let health = app.clone().oneshot(axum::http::Request::get("/health").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(health.status(), StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    async fn test_api_key_authentication(db: sqlx::PgPool) {
+        let other_merchant = Uuid::now_v7();
+        seed_api_key(&db, TEST_MERCHANT, TEST_API_KEY).await;
+        seed_api_key(&db, other_merchant, "sk_test_other").await;
+        let revoked = seed_api_key(&db, TEST_MERCHANT, "sk_test_revoked").await;
+        sqlx::query("UPDATE api_keys SET revoked_at = NOW() WHERE id = $1").bind(revoked).execute(&db).await.unwrap();
+        let own = seed_transaction(&db, Decimal::new(1000, 2), "succeeded").await;
+        let app = build_router(test_state(db));
+        let list = |authorization: Option<String>| {
+            let mut builder = axum::http::Request::get("/api/v1/transactions");
+            if let Some(value) = authorization { builder = builder.header("authorization", value); }
+            app.clone().oneshot(builder.body(Body::empty()).unwrap())
+        };
+
+        let (status, body) = send_json(&app, "GET", "/api/v1/transactions", serde_json::Value::Null, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((body["total"].as_i64(), body["data"][0]["id"].as_str()), (Some(1), Some(own.to_string().as_str())));
+
+        // Another merchant's key sees none of it
+        let other = list(Some("Bearer sk_test_other".into())).await.unwrap();
+        assert_eq!(other.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(&axum::body::to_bytes(other.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(body["total"], 0);
+
+        for authorization in [None, Some("Bearer sk_test_revoked".to_string()), Some("Bearer sk_test_unknown".to_string()), Some(format!("Basic {}", TEST_API_KEY))] {
+            let response = list(authorization.clone()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{:?}", authorization);
+            assert_eq!(response.headers()["www-authenticate"], "Bearer");
+        }
+
+        // The provider webhook is authenticated by its signature instead
+        let mut webhook = axum::http::Request::post("/api/v1/payments/webhook").header("content-type", "application/json");
+        let (headers, body) = signed_webhook(&serde_json::json!({ "event": "charge.success", "data": {} }));
+        webhook.headers_mut().unwrap().extend(headers);
+        assert_eq!(app.clone().oneshot(webhook.body(Body::from(body)).unwrap()).await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    async fn test_transactions_are_scoped_to_merchant(db: sqlx::PgPool) {
+        let state = test_state(db.clone());
+        let merchant_b = Extension(Merchant::new(Uuid::now_v7()));
+        let theirs = seed_transaction(&db, Decimal::new(10000, 2), "succeeded").await;
+        let (_, Json(refund)) = create_refund(State(state.clone()), merchant(), Json(refund_request(theirs, 1000))).await.unwrap();
+
+        // Merchant B gets the same 404 as for an id that doesn't exist
+        let err = get_transaction(State(state.clone()), merchant_b, Path(theirs)).await.unwrap_err();
+        assert_eq!((err.status, err.code.as_str()), (StatusCode::NOT_FOUND, "payment_not_found"));
+        let missing = get_transaction(State(state.clone()), merchant_b, Path(Uuid::now_v7())).await.unwrap_err();
+        assert_eq!((missing.status, missing.code), (err.status, err.code));
+        let reference = format!("TXN-{}", theirs);
+        let err = verify_payment(State(state.clone()), merchant_b, Json(VerifyPaymentRequest { reference })).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::NOT_FOUND);
+        let err = create_refund(State(state.clone()), merchant_b, Json(refund_request(theirs, 1000))).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::NOT_FOUND);
+
+        let Json(listed) = list_transactions(State(state.clone()), merchant_b, Query(list_params(None, None))).await.unwrap();
+        assert_eq!((listed.total, listed.data.len()), (0, 0));
+        let all_refunds = || RefundListParams { transaction_id: None, status: None, from_date: None, to_date: None, page: None, per_page: None };
+        let Json(refunds) = list_refunds(State(state.clone()), merchant_b, Query(all_refunds())).await.unwrap();
+        assert_eq!(refunds.total, 0);
+
+        // Merchant A still sees its transaction and its one refund
+        let Json(own) = get_transaction(State(state.clone()), merchant(), Path(theirs)).await.unwrap();
+        assert_eq!(own.status, "partially_refunded");
+        let Json(refunds) = list_refunds(State(state), merchant(), Query(all_refunds())).await.unwrap();
+        assert_eq!((refunds.total, refunds.data[0].id), (1, refund.id));
+    }
+
+    /// Inserts an active subscription whose period ended yesterday.
+    async fn seed_due_subscription(db: &sqlx::PgPool, customer_id: Uuid, cancel_at_period_end: bool) -> Uuid {
+        let id = Uuid::now_v7();
+        let today = Utc::now().date_naive();
+        sqlx::query(
+            r#"INSERT INTO subscriptions (id, customer_id, plan_id, amount, currency, billing_cycle, status, current_period_start,
+                                          current_period_end, cancel_at_period_end, merchant_id)
+               VALUES ($1, $2, 'PLAN_PRO', 2500, 'NGN', 'monthly', 'active', $3, $4, $5, $6)"#
+        )
+        .bind(id)
+        .bind(customer_id)
+        .bind(today - chrono::Duration::days(31))
+        .bind(today - chrono::Duration::days(1))
+        .bind(cancel_at_period_end)
+        .bind(TEST_MERCHANT)
+        .execute(db)
+        .await
+        .unwrap();
+        id
+    }
+
+    #[sqlx::test]
+    async fn test_subscription_renewal_worker(db: sqlx::PgPool) {
+        use sase_payments::domain::value_objects::DeclineCode;
+        let customer = Uuid::now_v7();
+        let paid = seed_customer_transaction(&db, customer, "succeeded", Utc::now()).await;
+        sqlx::query("UPDATE transactions SET customer_email = 'ada@example.com' WHERE id = $1").bind(paid).execute(&db).await.unwrap();
+        let due = seed_due_subscription(&db, customer, false).await;
+        let gateway = Arc::new(MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: Some("prov_renewal".into()) })));
+        let state = test_state_with_gateway(db.clone(), gateway.clone());
+        let today = Utc::now().date_naive();
+
+        assert_eq!(renew_due_subscriptions(&state, today).await.unwrap(), 1);
+        let charge = gateway.requests().pop().unwrap();
+        assert_eq!((charge.amount, charge.email.as_str()), (Money::new(Decimal::from(2500), "NGN"), "ada@example.com"));
+        assert_eq!(charge.metadata["subscription_id"], due.to_string());
+        let (status, start, end, renewals, total_paid): (String, chrono::NaiveDate, chrono::NaiveDate, i32, Decimal) = sqlx::query_as(
+            "SELECT status, current_period_start, current_period_end, renewal_count, total_paid FROM subscriptions WHERE id = $1"
+        ).bind(due).fetch_one(&db).await.unwrap();
+        assert_eq!((status.as_str(), start, end), ("active", today - chrono::Duration::days(1), BillingCycle::Monthly.period_end(today - chrono::Duration::days(1))));
+        assert_eq!((renewals, total_paid), (1, Decimal::from(2500)));
+        let reference = renewal_reference(due, today - chrono::Duration::days(1));
+        let (txn_status, txn_merchant): (String, Option<Uuid>) = sqlx::query_as("SELECT status, merchant_id FROM transactions WHERE reference = $1")
+            .bind(&reference).fetch_one(&db).await.unwrap();
+        assert_eq!((txn_status.as_str(), txn_merchant), ("succeeded", Some(TEST_MERCHANT)));
+        let invoice_merchant: Option<Uuid> = sqlx::query_scalar("SELECT merchant_id FROM invoices WHERE reference = $1").bind(&reference).fetch_one(&db).await.unwrap();
+        assert_eq!(invoice_merchant, Some(TEST_MERCHANT));
+        let subjects: Vec<String> = sqlx::query_scalar("SELECT subject FROM event_outbox ORDER BY subject").fetch_all(&db).await.unwrap();
+        assert_eq!(subjects, ["payments.payment.created", "payments.payment.succeeded", "payments.subscription.renewed"]);
+
+        // Nothing is due until the new period ends
+        assert_eq!(renew_due_subscriptions(&state, today).await.unwrap(), 0);
+        assert_eq!(gateway.requests().len(), 1);
+
+        // A declined renewal counts as a failed attempt; one marked to cancel is cancelled unbilled
+        let declining = seed_due_subscription(&db, customer, false).await;
+        let cancelling = seed_due_subscription(&db, customer, true).await;
+        let declined = test_state_with_gateway(db.clone(), Arc::new(MockGateway::new(Err(PaymentError::Declined(DeclineCode::InsufficientFunds)))));
+        assert_eq!(renew_due_subscriptions(&declined, today).await.unwrap(), 2);
+        let state_of = |id: Uuid| sqlx::query_as::<_, (String, i32)>("SELECT status, consecutive_failures FROM subscriptions WHERE id = $1").bind(id).fetch_one(&db);
+        assert_eq!(state_of(declining).await.unwrap(), ("past_due".to_string(), 1));
+        assert_eq!(state_of(cancelling).await.unwrap(), ("cancelled".to_string(), 0));
+        let failed: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM event_outbox WHERE subject = 'payments.subscription.payment_failed'").fetch_one(&db).await.unwrap();
+        assert_eq!(failed, 1);
+        assert_eq!(renew_due_subscriptions(&declined, today).await.unwrap(), 0);
+    }
+
+    #[sqlx::test]
+    async fn test_declined_renewal_is_retried_until_unpaid(db: sqlx::PgPool) {
+        use sase_payments::domain::value_objects::DeclineCode;
+        let due = seed_due_subscription(&db, Uuid::now_v7(), false).await;
+        let gateway = Arc::new(MockGateway::new(Err(PaymentError::Declined(DeclineCode::InsufficientFunds))));
+        let mut state = test_state_with_gateway(db.clone(), gateway.clone());
+        state.config = Arc::new(Config {
+            dunning: DunningPolicy { max_attempts: 3, outcome: DunningOutcome::Unpaid, retry_after_days: 2 },
+            ..Config::clone(&state.config)
+        });
+        let today = Utc::now().date_naive();
+        let day = |n: i64| today + chrono::Duration::days(n);
+        let state_of = || sqlx::query_as::<_, (String, i32)>("SELECT status, consecutive_failures FROM subscriptions WHERE id = $1").bind(due).fetch_one(&db);
+
+        assert_eq!(renew_due_subscriptions(&state, today).await.unwrap(), 1);
+        assert_eq!(state_of().await.unwrap(), ("past_due".to_string(), 1));
+        // Not retried before the policy's wait is up
+        assert_eq!(renew_due_subscriptions(&state, day(1)).await.unwrap(), 0);
+        assert_eq!(renew_due_subscriptions(&state, day(2)).await.unwrap(), 1);
+        assert_eq!(state_of().await.unwrap(), ("past_due".to_string(), 2));
+        assert_eq!(renew_due_subscriptions(&state, day(4)).await.unwrap(), 1);
+        assert_eq!(state_of().await.unwrap(), ("unpaid".to_string(), 3));
+        // Out of attempts, it is no longer billed
+        assert_eq!(renew_due_subscriptions(&state, day(30)).await.unwrap(), 0);
+
+        // Each attempt retries the one invoice under a fresh provider key
+        let keys: std::collections::HashSet<String> = gateway.requests().into_iter().map(|c| c.idempotency_key).collect();
+        assert_eq!(keys.len(), 3);
+        let reference = renewal_reference(due, today - chrono::Duration::days(1));
+        let references: Vec<String> = gateway.requests().into_iter().map(|c| c.reference).collect();
+        assert_eq!(references, [reference.clone(), reference.clone(), reference.clone()]);
+        let invoices: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM invoices WHERE reference = $1").bind(&reference).fetch_one(&db).await.unwrap();
+        assert_eq!(invoices, 1);
+        let failed: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM event_outbox WHERE subject = 'payments.subscription.payment_failed'").fetch_one(&db).await.unwrap();
+        assert_eq!(failed, 3);
+    }
+
+    #[sqlx::test]
+    async fn test_past_due_subscription_recovers_on_retry(db: sqlx::PgPool) {
+        use sase_payments::domain::value_objects::DeclineCode;
+        let due = seed_due_subscription(&db, Uuid::now_v7(), false).await;
+        let declined = test_state_with_gateway(db.clone(), Arc::new(MockGateway::new(Err(PaymentError::Declined(DeclineCode::InsufficientFunds)))));
+        let today = Utc::now().date_naive();
+        assert_eq!(renew_due_subscriptions(&declined, today).await.unwrap(), 1);
+
+        let paying = test_state_with_gateway(db.clone(), Arc::new(MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: None }))));
+        let retry_on = today + chrono::Duration::days(i64::from(DunningPolicy::default().retry_after_days));
+        assert_eq!(renew_due_subscriptions(&paying, retry_on).await.unwrap(), 1);
+        let (status, failures, renewals, end, total_paid): (String, i32, i32, chrono::NaiveDate, Decimal) = sqlx::query_as(
+            "SELECT status, consecutive_failures, renewal_count, current_period_end, total_paid FROM subscriptions WHERE id = $1"
+        ).bind(due).fetch_one(&db).await.unwrap();
+        let period_end = today - chrono::Duration::days(1);
+        assert_eq!((status.as_str(), failures, renewals), ("active", 0, 1));
+        assert_eq!((end, total_paid), (BillingCycle::Monthly.period_end(period_end), Decimal::from(2500)));
+        let txn_status: String = sqlx::query_scalar("SELECT status FROM transactions WHERE reference = $1")
+            .bind(renewal_reference(due, period_end)).fetch_one(&db).await.unwrap();
+        assert_eq!(txn_status, "succeeded");
+        let subjects: Vec<String> = sqlx::query_scalar("SELECT subject FROM event_outbox WHERE subject LIKE 'payments.subscription.%' ORDER BY created_at, id").fetch_all(&db).await.unwrap();
+        assert_eq!(subjects.last().map(String::as_str), Some("payments.subscription.renewed"));
+        assert_eq!(renew_due_subscriptions(&paying, retry_on).await.unwrap(), 0);
+    }
+
+    #[sqlx::test]
+    async fn test_late_fees_accrue_on_overdue_invoices(db: sqlx::PgPool) {
+        use sase_payments::domain::value_objects::DeclineCode;
+        let today = Utc::now().date_naive();
+        let paid = seed_due_subscription(&db, Uuid::now_v7(), false).await;
+        let paying = test_state_with_gateway(db.clone(), Arc::new(MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: None }))));
+        assert_eq!(renew_due_subscriptions(&paying, today).await.unwrap(), 1);
+        let overdue = seed_due_subscription(&db, Uuid::now_v7(), false).await;
+        let other_merchant = seed_due_subscription(&db, Uuid::now_v7(), false).await;
+        sqlx::query("UPDATE subscriptions SET merchant_id = $1 WHERE id = $2").bind(Uuid::now_v7()).bind(other_merchant).execute(&db).await.unwrap();
+        let state = test_state_with_gateway(db.clone(), Arc::new(MockGateway::new(Err(PaymentError::Declined(DeclineCode::InsufficientFunds)))));
+        assert_eq!(renew_due_subscriptions(&state, today).await.unwrap(), 2);
+        // 1% of the 2500 total a day, up to 100
+        sqlx::query("INSERT INTO late_fee_policies (merchant_id, currency, daily_rate, max_fee) VALUES ($1, 'NGN', 1, 100)")
+            .bind(TEST_MERCHANT).execute(&db).await.unwrap();
+        let late_fee = |subscription: Uuid| {
+            let reference = renewal_reference(subscription, today - chrono::Duration::days(1));
+            sqlx::query_scalar::<_, Decimal>("SELECT late_fee FROM invoices WHERE reference = $1").bind(reference).fetch_one(&db)
+        };
+
+        assert_eq!(accrue_late_fees(&state, today + chrono::Duration::days(3)).await.unwrap(), 1);
+        assert_eq!(late_fee(overdue).await.unwrap(), Decimal::from(75));
+        // The paid invoice and the one whose merchant has no policy are left alone
+        assert_eq!(late_fee(paid).await.unwrap(), Decimal::ZERO);
+        assert_eq!(late_fee(other_merchant).await.unwrap(), Decimal::ZERO);
+        // Once a day, and never past the cap
+        assert_eq!(accrue_late_fees(&state, today + chrono::Duration::days(3)).await.unwrap(), 0);
+        assert_eq!(accrue_late_fees(&state, today + chrono::Duration::days(10)).await.unwrap(), 1);
+        assert_eq!(late_fee(overdue).await.unwrap(), Decimal::from(100));
+        assert_eq!(accrue_late_fees(&state, today + chrono::Duration::days(20)).await.unwrap(), 0);
+    }
+
+    #[sqlx::test]
+    async fn test_renewal_bills_usage(db: sqlx::PgPool) {
+        let customer = Uuid::now_v7();
+        let due = seed_due_subscription(&db, customer, false).await;
+        let gateway = Arc::new(MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: None })));
+        let state = test_state_with_gateway(db.clone(), gateway.clone());
+        let usage = |id: Uuid, quantity: i64, unit_amount: i64| {
+            record_usage(State(state.clone()), merchant(), Path(id), Json(RecordUsageRequest { quantity, unit_amount }))
+        };
+
+        let (status, Json(record)) = usage(due, 3, 150).await.unwrap();
+        assert_eq!((status, record.amount, record.billed_reference), (StatusCode::CREATED, Decimal::new(450, 2), None));
+        let _ = usage(due, 10, 25).await.unwrap();
+        assert_eq!(usage(due, 0, 25).await.unwrap_err().0, StatusCode::BAD_REQUEST);
+        assert_eq!(usage(Uuid::now_v7(), 1, 25).await.unwrap_err().0, StatusCode::NOT_FOUND);
+        let recorded: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM event_outbox WHERE subject = 'payments.subscription.usage_recorded'").fetch_one(&db).await.unwrap();
+        assert_eq!(recorded, 2);
+
+        // The renewal charges the flat amount plus the usage and records it as paid
+        let today = Utc::now().date_naive();
+        assert_eq!(renew_due_subscriptions(&state, today).await.unwrap(), 1);
+        let charged = Money::new(Decimal::new(250_700, 2), "NGN");
+        assert_eq!(gateway.requests().pop().unwrap().amount, charged);
+        let total_paid: Decimal = sqlx::query_scalar("SELECT total_paid FROM subscriptions WHERE id = $1").bind(due).fetch_one(&db).await.unwrap();
+        assert_eq!(total_paid, charged.amount);
+        let reference = renewal_reference(due, today - chrono::Duration::days(1));
+        let billed: Vec<Option<String>> = sqlx::query_scalar("SELECT billed_reference FROM usage_records WHERE subscription_id = $1").bind(due).fetch_all(&db).await.unwrap();
+        assert_eq!(billed, vec![Some(reference.clone()), Some(reference.clone())]);
+
+        // The charge is the total of a finalized invoice that breaks it down
+        let invoice_id: String = sqlx::query_scalar("SELECT id FROM invoices WHERE reference = $1").bind(&reference).fetch_one(&db).await.unwrap();
+        let invoices = PgInvoiceRepository::new(db.clone());
+        let mut invoice = invoices.load(&invoice_id).await.unwrap().unwrap();
+        assert!(invoice.is_finalized());
+        let lines: Vec<(&str, i64, Decimal)> = invoice.lines().iter().map(|l| (l.description.as_str(), l.quantity, l.amount.amount)).collect();
+        assert_eq!(lines, [("PLAN_PRO", 1, Decimal::from(2500)), ("Usage", 3, Decimal::new(450, 2)), ("Usage", 10, Decimal::new(250, 2))]);
+        assert_eq!(invoice.total().unwrap(), charged);
+        assert!(invoice.add_line("Extra", 1, Money::new(Decimal::ONE, "NGN")).is_err());
+        let draft = Invoice::from_record(InvoiceRecord { status: InvoiceStatus::Draft, lines: vec![], ..invoice.to_record() });
+        assert!(matches!(invoices.save(&draft).await, Err(RepositoryError::Invoice(InvoiceError::Finalized))));
+
+        // Usage then starts again from nothing
+        let _ = usage(due, 2, 100).await.unwrap();
+        let next_end: chrono::NaiveDate = sqlx::query_scalar("SELECT current_period_end FROM subscriptions WHERE id = $1").bind(due).fetch_one(&db).await.unwrap();
+        assert_eq!(renew_due_subscriptions(&state, next_end).await.unwrap(), 1);
+        assert_eq!(gateway.requests().pop().unwrap().amount, Money::new(Decimal::new(250_200, 2), "NGN"));
+        let next_end: chrono::NaiveDate = sqlx::query_scalar("SELECT current_period_end FROM subscriptions WHERE id = $1").bind(due).fetch_one(&db).await.unwrap();
+        assert_eq!(renew_due_subscriptions(&state, next_end).await.unwrap(), 1);
+        assert_eq!(gateway.requests().pop().unwrap().amount, Money::new(Decimal::from(2500), "NGN"));
+
+        // Tax follows the subscription's billing address
+        let mut taxed = state.clone();
+        taxed.config = Arc::new(Config { tax_rates: TaxRates::parse("NG=10").unwrap(), ..Config::clone(&state.config) });
+        sqlx::query(r#"UPDATE subscriptions SET billing_details = '{"address": {"country": "NG"}}' WHERE id = $1"#).bind(due).execute(&db).await.unwrap();
+        let next_end: chrono::NaiveDate = sqlx::query_scalar("SELECT current_period_end FROM subscriptions WHERE id = $1").bind(due).fetch_one(&db).await.unwrap();
+        assert_eq!(renew_due_subscriptions(&taxed, next_end).await.unwrap(), 1);
+        assert_eq!(gateway.requests().pop().unwrap().amount, Money::new(Decimal::from(2750), "NGN"));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_in_flight_requests() {
+        let started = Arc::new(tokio::sync::Notify::new());
+        let handler_started = started.clone();
+        let app = Router::new().route("/slow", get(move || async move {
+            handler_started.notify_one();
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            "done"
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown_tx, shutdown) = watch::channel(false);
+        let server = tokio::spawn(serve_until_shutdown(listener, app, shutdown, Duration::from_secs(5)));
+
+        let request = tokio::spawn(reqwest::get(format!("http://{}/slow", addr)));
+        started.notified().await;
+        shutdown_tx.send(true).unwrap();
+
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "done");
+        tokio::time::timeout(Duration::from_secs(1), server).await.unwrap().unwrap().unwrap();
+        // The listener is closed once the server has stopped
+        assert!(reqwest::get(format!("http://{}/slow", addr)).await.is_err());
+
+        // Workers finish the run they are in and start no new ones
+        let (stop_tx, mut stop) = watch::channel(false);
+        let mut interval = tokio::time::interval(Duration::from_secs(3600));
+        let worker = tokio::spawn(async move {
+            let mut runs = 0;
+            while next_run(&mut interval, &mut stop).await { runs += 1; }
+            runs
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        stop_tx.send(true).unwrap();
+        assert_eq!(tokio::time::timeout(Duration::from_secs(1), worker).await.unwrap().unwrap(), 1);
+    }
+
+    #[sqlx::test]
+    async fn test_readiness(db: sqlx::PgPool) {
+        async fn probe(state: AppState, uri: &str) -> (StatusCode, serde_json::Value) {
+            let response = build_router(state).oneshot(axum::http::Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+            let status = response.status();
+            (status, serde_json::from_slice(&axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap())
+        }
+
+        let (status, body) = probe(test_state(db.clone()), "/health/ready").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ready");
+        assert_eq!(body["checks"], serde_json::json!({ "database": { "status": "up" }, "nats": { "status": "not_configured" } }));
+
+        // Nothing listens on port 1, so every connection attempt fails
+        let mut dead = test_state(db.clone());
+        dead.db = PgPoolOptions::new().acquire_timeout(std::time::Duration::from_millis(500)).connect_lazy("postgres://payments@127.0.0.1:1/payments").unwrap();
+        let (status, body) = probe(dead.clone(), "/health/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "unavailable");
+        assert_eq!(body["checks"]["database"]["status"], "down");
+        assert_eq!(probe(dead, "/health").await.0, StatusCode::OK);
+
+        // NATS configured but never connected
+        let mut no_bus = test_state(db);
+        no_bus.config = Arc::new(Config { nats_url: Some("nats://127.0.0.1:1".into()), ..(*no_bus.config).clone() });
+        let (status, body) = probe(no_bus, "/health/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["checks"], serde_json::json!({ "database": { "status": "up" }, "nats": { "status": "down", "error": "not connected" } }));
+    }
+
+    #[sqlx::test]
+    async fn test_pool_config_and_metrics(db: sqlx::PgPool) {
+        let state = test_state(db);
+        let config = Config { db_max_connections: 25, db_acquire_timeout_secs: 3, db_idle_timeout_secs: 120, ..Config::clone(&state.config) };
+        let options = config.pool_options();
+        assert_eq!(options.get_max_connections(), 25);
+        assert_eq!(options.get_acquire_timeout(), Duration::from_secs(3));
+        assert_eq!(options.get_idle_timeout(), Some(Duration::from_secs(120)));
+
+        sqlx::query("SELECT 1").execute(&state.db).await.unwrap();
+        let response = build_router(state).oneshot(axum::http::Request::get("/metrics").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = String::from_utf8(axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap();
+        let gauge = |name: &str| body.lines().find_map(|line| line.strip_prefix(&format!("{} ", name))).and_then(|v| v.parse::<u32>().ok());
+        let (size, idle) = (gauge("db_pool_size").unwrap(), gauge("db_pool_idle").unwrap());
+        assert!(size >= 1 && idle <= size, "{}", body);
+        assert!(body.contains("# TYPE db_pool_idle gauge"));
+    }
+
+    #[sqlx::test]
+    async fn test_metrics_count_payments(db: sqlx::PgPool) {
+        let gateway = Arc::new(MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: None })));
+        let state = test_state_with_gateway(db, gateway);
+        let app = build_router(state.clone());
+        let scrape = || async {
+            let response = app.clone().oneshot(axum::http::Request::get("/metrics").body(Body::empty()).unwrap()).await.unwrap();
+            String::from_utf8(axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap()
+        };
+        // Other tests record into the same recorder, so only the increase is checked
+        let value = |body: &str, (name, labels): (&str, &[&str])| body.lines()
+            .find(|line| line.split(['{', ' ']).next() == Some(name) && labels.iter().all(|label| line.contains(label)))
+            .and_then(|line| line.rsplit(' ').next()?.parse::<f64>().ok())
+            .unwrap_or(0.0);
+        let series: [(&str, &[&str]); 3] = [
+            ("payments_initiated_total", &[]),
+            ("payments_succeeded_total", &[]),
+            ("provider_call_duration_seconds_count", &[r#"provider="mock""#, r#"operation="charge""#]),
+        ];
+
+        let before = scrape().await;
+        let Json(paid) = initiate_payment(State(state), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap();
+        assert_eq!(paid.status, "succeeded");
+        let after = scrape().await;
+        for name in series {
+            assert!(value(&after, name) >= value(&before, name) + 1.0, "{}:\n{}", name.0, after);
+        }
+        assert!(after.contains("# TYPE payments_initiated_total counter") && after.contains("db_pool_size "), "{}", after);
+    }
+
+    #[sqlx::test]
+    async fn test_payment_metadata_limits(db: sqlx::PgPool) {
+        seed_api_key(&db, TEST_MERCHANT, TEST_API_KEY).await;
+        let app = build_router(test_state(db.clone()));
+        let initiate = |metadata: serde_json::Value| {
+            let app = app.clone();
+            async move {
+                let body = serde_json::json!({ "amount": 5000, "currency": "NGN", "email": "ada@example.com", "metadata": metadata });
+                send_json(&app, "POST", "/api/v1/payments/initiate", body, &[]).await
+            }
+        };
+
+        let too_many: serde_json::Map<String, serde_json::Value> = (0..=metadata::MAX_KEYS).map(|i| (format!("k{}", i), "v".into())).collect();
+        let rejected = [
+            (serde_json::Value::Object(too_many), format!("metadata may have at most {} keys", metadata::MAX_KEYS)),
+            (serde_json::json!({ "note": "v".repeat(metadata::MAX_VALUE_LEN + 1) }), format!("metadata value for 'note' exceeds {} characters", metadata::MAX_VALUE_LEN)),
+            (serde_json::json!({ "order": { "id": "O-1" } }), "metadata value for 'order' must be a string".to_string()),
+        ];
+        for (metadata, message) in rejected {
+            let (status, body) = initiate(metadata).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(body["message"], message);
+        }
+        let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM transactions").fetch_one(&db).await.unwrap();
+        assert_eq!(stored, 0);
+
+        let (status, body) = initiate(serde_json::json!({ "order_id": "O-1" })).await;
+        assert_eq!(status, StatusCode::OK);
+        let stored: serde_json::Value = sqlx::query_scalar("SELECT metadata FROM transactions WHERE reference = $1")
+            .bind(body["reference"].as_str().unwrap()).fetch_one(&db).await.unwrap();
+        assert_eq!(stored, serde_json::json!({ "order_id": "O-1" }));
+    }
+
+    #[sqlx::test]
+    async fn test_amount_limits(db: sqlx::PgPool) {
+        seed_api_key(&db, TEST_MERCHANT, TEST_API_KEY).await;
+        let mut state = test_state(db);
+        state.config = Arc::new(Config { amount_limits: parse_amount_limits("NGN=10000:500000000;USD=50:1000000").unwrap(), ..(*state.config).clone() });
+        let app = build_router(state);
+
+        for (minor, currency, expected) in [
+            (9_999, "NGN", Err("9999 is below the NGN minimum of 10000 minor units")),
+            (500_000_001, "NGN", Err("500000001 is above the NGN maximum of 500000000 minor units")),
+            (10_000, "NGN", Ok(())),
+            (49, "USD", Err("49 is below the USD minimum of 50 minor units")),
+            (1_000_001, "USD", Err("1000001 is above the USD maximum of 1000000 minor units")),
+            (2_500, "USD", Ok(())),
+        ] {
+            let body = serde_json::json!({ "amount": minor, "currency": currency, "email": "ada@example.com" });
+            let (status, body) = send_json(&app, "POST", "/api/v1/payments/initiate", body, &[]).await;
+            match expected {
+                Ok(()) => assert_eq!(status, StatusCode::OK, "{} {}: {}", minor, currency, body),
+                Err(message) => {
+                    assert_eq!(status, StatusCode::BAD_REQUEST);
+                    assert_eq!(body["error"], "invalid_amount");
+                    assert_eq!(body["message"], format!("Invalid amount: {}", message));
+                }
+            }
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_refund_processing(db: sqlx::PgPool) {
+        use sase_payments::domain::value_objects::ProviderErrorKind;
+        seed_api_key(&db, TEST_MERCHANT, TEST_API_KEY).await;
+        let gateway = Arc::new(MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: None })));
+        let mut state = test_state_with_gateway(db.clone(), gateway.clone());
+        state.config = Arc::new(Config { max_refunds_per_transaction: 10, ..Config::clone(&state.config) });
+        let status_of = |txn_id: Uuid| sqlx::query_scalar::<_, String>("SELECT status FROM transactions WHERE id = $1").bind(txn_id).fetch_one(&db);
+        let txn_id = seed_transaction(&db, Decimal::new(10000, 2), "succeeded").await;
+        sqlx::query("UPDATE transactions SET provider_reference = 'prov_1' WHERE id = $1").bind(txn_id).execute(&db).await.unwrap();
+
+        // The provider confirms the refund: it and the charge settle in the same request
+        let (status, Json(refund)) = create_refund(State(state.clone()), merchant(), Json(refund_request(txn_id, 4000))).await.unwrap();
+        assert_eq!((status, refund.status.as_str(), refund.amount), (StatusCode::CREATED, "succeeded", Decimal::new(4000, 2)));
+        assert!(refund.processed_at.is_some());
+        assert_eq!(status_of(txn_id).await.unwrap(), "partially_refunded");
+        let sent = gateway.refunds().pop().unwrap();
+        assert_eq!((sent.charge_reference, sent.provider_reference.as_deref()), (format!("TXN-{}", txn_id), Some("prov_1")));
+        assert_eq!(sent.amount, Money::new(Decimal::new(4000, 2), "NGN"));
+        let refunded: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM event_outbox WHERE subject = 'payments.payment.refunded'").fetch_one(&db).await.unwrap();
+        assert_eq!(refunded, 1);
+
+        // A rejected refund fails, leaving the charge alone and its amount refundable again
+        gateway.set_refund_outcome(Err(PaymentError::ProviderError { kind: ProviderErrorKind::InvalidRequest, message: "paystack: Transaction has been fully reversed".into() }));
+        let rejected = create_refund(State(state.clone()), merchant(), Json(refund_request(txn_id, 6000))).await.unwrap_err();
+        assert_eq!(rejected.0, StatusCode::BAD_GATEWAY);
+        let failed: String = sqlx::query_scalar("SELECT status FROM refunds WHERE transaction_id = $1 AND amount = 60").bind(txn_id).fetch_one(&db).await.unwrap();
+        assert_eq!(failed, "failed");
+        assert_eq!(status_of(txn_id).await.unwrap(), "partially_refunded");
+
+        // A provider that's still processing leaves it pending; a timeout might still have gone through
+        for outcome in [Ok(Some(RefundOutcome::Pending)), Err(PaymentError::ProviderError { kind: ProviderErrorKind::Timeout, message: "paystack: timed out".into() })] {
+            gateway.set_refund_outcome(outcome);
+            let (_, Json(pending)) = create_refund(State(state.clone()), merchant(), Json(refund_request(txn_id, 1000))).await.unwrap();
+            assert_eq!((pending.status.as_str(), pending.processed_at), ("pending", None));
+        }
+        gateway.set_refund_outcome(Ok(Some(RefundOutcome::Succeeded)));
+        let (_, Json(rest)) = create_refund(State(state.clone()), merchant(), Json(refund_request(txn_id, 4000))).await.unwrap();
+        assert_eq!(rest.status, "succeeded");
+        assert_eq!(status_of(txn_id).await.unwrap(), "partially_refunded");
+
+        // GET /refunds/:id is scoped to the merchant like the list
+        let app = build_router(state);
+        let (status, body) = send_json(&app, "GET", &format!("/api/v1/refunds/{}", refund.id), serde_json::Value::Null, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((body["id"].as_str(), body["status"].as_str()), (Some(refund.id.to_string().as_str()), Some("succeeded")));
+        seed_api_key(&db, Uuid::now_v7(), "sk_test_other").await;
+        let response = app.clone().oneshot(
+            axum::http::Request::get(format!("/api/v1/refunds/{}", refund.id)).header("authorization", "Bearer sk_test_other").body(Body::empty()).unwrap()
+        ).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let (status, _) = send_json(&app, "GET", &format!("/api/v1/refunds/{}", Uuid::now_v7()), serde_json::Value::Null, &[]).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn test_dispute_lifecycle(db: sqlx::PgPool) {
+        seed_api_key(&db, TEST_MERCHANT, TEST_API_KEY).await;
+        let app = build_router(test_state(db.clone()));
+        let status_of = |txn_id: Uuid| sqlx::query_scalar::<_, String>("SELECT status FROM transactions WHERE id = $1").bind(txn_id).fetch_one(&db);
+        let events = || sqlx::query_scalar::<_, String>("SELECT subject FROM event_outbox WHERE subject LIKE 'payments.dispute.%' ORDER BY created_at, id").fetch_all(&db);
+
+        // open -> evidence_submitted -> won puts the charge back as it was
+        let won = seed_transaction(&db, Decimal::new(10000, 2), "partially_refunded").await;
+        let (status, body) = send_json(&app, "POST", "/api/v1/disputes", serde_json::json!({ "transaction_id": won, "reason": "product_not_received" }), &[]).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", body);
+        assert_eq!((body["status"].as_str(), body["amount"].as_str(), body["transaction_status"].as_str()), (Some("open"), Some("100.0000"), Some("partially_refunded")));
+        assert_eq!(status_of(won).await.unwrap(), "disputed");
+        let dispute = format!("/api/v1/disputes/{}", body["id"].as_str().unwrap());
+        let (status, _) = send_json(&app, "POST", "/api/v1/disputes", serde_json::json!({ "transaction_id": won, "reason": "duplicate" }), &[]).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (status, body) = send_json(&app, "POST", &format!("{}/evidence", dispute), serde_json::json!({ "evidence": { "receipt": "https://example.com/r/1" } }), &[]).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!((body["status"].as_str(), body["evidence"]["receipt"].as_str()), (Some("evidence_submitted"), Some("https://example.com/r/1")));
+        let (status, body) = send_json(&app, "POST", &format!("{}/close", dispute), serde_json::json!({ "outcome": "won" }), &[]).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert!(body["status"] == "won" && !body["closed_at"].is_null());
+        assert_eq!(status_of(won).await.unwrap(), "partially_refunded");
+        let (status, _) = send_json(&app, "POST", &format!("{}/close", dispute), serde_json::json!({ "outcome": "lost" }), &[]).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = send_json(&app, "POST", &format!("{}/evidence", dispute), serde_json::json!({ "evidence": {} }), &[]).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        // A lost dispute leaves the charge charged back, for good
+        let lost = seed_transaction(&db, Decimal::new(10000, 2), "succeeded").await;
+        let (_, body) = send_json(&app, "POST", "/api/v1/disputes", serde_json::json!({ "transaction_id": lost, "reason": "fraudulent", "amount": 4000 }), &[]).await;
+        assert_eq!(body["amount"].as_str(), Some("40.0000"));
+        let (status, body) = send_json(&app, "POST", &format!("/api/v1/disputes/{}/close", body["id"].as_str().unwrap()), serde_json::json!({ "outcome": "lost" }), &[]).await;
+        assert_eq!((status, body["status"].as_str()), (StatusCode::OK, Some("lost")));
+        assert_eq!(status_of(lost).await.unwrap(), "charged_back");
+        assert_eq!(events().await.unwrap(), [
+            "payments.dispute.opened", "payments.dispute.evidence_submitted", "payments.dispute.won", "payments.dispute.opened", "payments.dispute.lost",
+        ]);
+
+        // Only succeeded charges can be disputed, and only by their merchant
+        let pending = seed_transaction(&db, Decimal::new(10000, 2), "pending").await;
+        let (status, _) = send_json(&app, "POST", "/api/v1/disputes", serde_json::json!({ "transaction_id": pending, "reason": "fraudulent" }), &[]).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = send_json(&app, "POST", "/api/v1/disputes", serde_json::json!({ "transaction_id": lost, "reason": "fraudulent" }), &[]).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = send_json(&app, "POST", "/api/v1/disputes", serde_json::json!({ "transaction_id": seed_transaction(&db, Decimal::new(10000, 2), "succeeded").await, "reason": "fraudulent", "amount": 10001 }), &[]).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        seed_api_key(&db, Uuid::now_v7(), "sk_test_other").await;
+        let response = app.clone().oneshot(
+            axum::http::Request::get(&dispute).header("authorization", "Bearer sk_test_other").body(Body::empty()).unwrap()
+        ).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // The provider opens and resolves disputes by webhook; a charge webhook can't undo one
+        let charge = seed_transaction(&db, Decimal::new(10000, 2), "succeeded").await;
+        let reference = format!("TXN-{}", charge);
+        let opened = WebhookEvent::DisputeOpened { reference: reference.clone(), dispute_id: "dsp_1".into(), amount: None, reason: Some("chargeback".into()), evidence_due_by: None };
+        assert!(apply_webhook(&db, DunningPolicy::default(), "paystack", "evt_1", &serde_json::json!({}), opened).await.unwrap());
+        assert_eq!(status_of(charge).await.unwrap(), "disputed");
+        let succeeded = WebhookEvent::ChargeSucceeded { reference, amount: None, subscription_id: None };
+        apply_webhook(&db, DunningPolicy::default(), "paystack", "evt_2", &serde_json::json!({}), succeeded).await.unwrap();
+        assert_eq!(status_of(charge).await.unwrap(), "disputed");
+        let resolved = || WebhookEvent::DisputeResolved { dispute_id: "dsp_1".into(), won: true };
+        apply_webhook(&db, DunningPolicy::default(), "paystack", "evt_3", &serde_json::json!({}), resolved()).await.unwrap();
+        assert_eq!(status_of(charge).await.unwrap(), "succeeded");
+        let dispute: Dispute = sqlx::query_as("SELECT * FROM disputes WHERE provider_dispute_id = 'dsp_1'").fetch_one(&db).await.unwrap();
+        assert_eq!((dispute.status.as_str(), dispute.reason.as_str(), dispute.amount), ("won", "chargeback", Decimal::new(10000, 2)));
+        // Resolving it again changes nothing
+        apply_webhook(&db, DunningPolicy::default(), "paystack", "evt_4", &serde_json::json!({}), resolved()).await.unwrap();
+        assert_eq!(events().await.unwrap().len(), 7);
+    }
+
+    #[sqlx::test]
+    async fn test_payment_method_vault_stores_only_the_token(db: sqlx::PgPool) {
+        use chrono::Datelike;
+        seed_api_key(&db, TEST_MERCHANT, TEST_API_KEY).await;
+        let gateway = Arc::new(MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: None })));
+        let app = build_router(test_state_with_gateway(db.clone(), gateway.clone()));
+        let customer_id = Uuid::now_v7();
+        let card = |number: &str, exp_year: u16| serde_json::json!({
+            "customer_id": customer_id,
+            "email": "ada@example.com",
+            "card": { "number": number, "exp_month": 12, "exp_year": exp_year, "cvc": "737" }
+        });
+        let pan = "4242424242424242";
+        let next_year = Utc::now().year() as u16 + 1;
+
+        let (status, body) = send_json(&app, "POST", "/api/v1/payment-methods", card("4242 4242 4242 4242", next_year), &[]).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!((body["token"].as_str(), body["last_four"].as_str(), body["brand"].as_str()), (Some("tok_mock_1"), Some("4242"), Some("visa")));
+        assert!(!body.to_string().contains(pan));
+        assert_eq!(gateway.tokenized()[0].number.expose(), pan);
+
+        let rows: Vec<String> = sqlx::query_scalar("SELECT row_to_json(p)::text FROM payment_methods p").fetch_all(&db).await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert!(!rows[0].contains(pan) && !rows[0].contains("737"), "{}", rows[0]);
+        let (token, last_four, exp_month, exp_year): (String, String, i16, i16) =
+            sqlx::query_as("SELECT token, last_four, exp_month, exp_year FROM payment_methods").fetch_one(&db).await.unwrap();
+        assert_eq!((token.as_str(), last_four.as_str(), exp_month, exp_year), ("tok_mock_1", "4242", 12, next_year as i16));
+
+        // Invalid or expired cards are refused before the provider sees them, without echoing the number
+        let (status, body) = send_json(&app, "POST", "/api/v1/payment-methods", card("4242424242424241", next_year), &[]).await;
+        assert!(status.is_client_error());
+        assert!(!body.to_string().contains("4242424242424241"));
+        let (status, _) = send_json(&app, "POST", "/api/v1/payment-methods", card(pan, 2020), &[]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(gateway.tokenized().len(), 1);
+        let mut no_email = card(pan, next_year);
+        no_email["email"] = "not an email".into();
+        let (status, _) = send_json(&app, "POST", "/api/v1/payment-methods", no_email, &[]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(gateway.tokenized().len(), 1);
+        let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM payment_methods").fetch_one(&db).await.unwrap();
+        assert_eq!(stored, 1);
+
+        // A provider that doesn't vault cards stores nothing
+        let stub = build_router(test_state(db.clone()));
+        let (status, _) = send_json(&stub, "POST", "/api/v1/payment-methods", card(pan, next_year), &[]).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM payment_methods").fetch_one(&db).await.unwrap();
+        assert_eq!(stored, 1);
+    }
+
+    #[sqlx::test]
+    async fn test_single_default_payment_method(db: sqlx::PgPool) {
+        use chrono::Datelike;
+        seed_api_key(&db, TEST_MERCHANT, TEST_API_KEY).await;
+        let app = build_router(test_state_with_gateway(db.clone(), Arc::new(MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: None })))));
+        let customer_id = Uuid::now_v7();
+        let save = |number: &'static str, is_default: Option<bool>| {
+            let app = app.clone();
+            async move {
+                let body = serde_json::json!({
+                    "customer_id": customer_id,
+                    "email": "ada@example.com",
+                    "card": { "number": number, "exp_month": 12, "exp_year": Utc::now().year() + 1, "cvc": "123" },
+                    "is_default": is_default,
+                });
+                let (status, body) = send_json(&app, "POST", "/api/v1/payment-methods", body, &[]).await;
+                assert_eq!(status, StatusCode::CREATED);
+                body["id"].as_str().unwrap().to_string()
+            }
+        };
+        let defaults = || {
+            let app = app.clone();
+            async move {
+                let (status, body) = send_json(&app, "GET", &format!("/api/v1/customers/{}/payment-methods", customer_id), serde_json::Value::Null, &[]).await;
+                assert_eq!(status, StatusCode::OK);
+                let methods = body.as_array().unwrap().clone();
+                let flagged: Vec<String> = methods.iter().filter(|m| m["is_default"] == true).map(|m| m["id"].as_str().unwrap().to_string()).collect();
+                (methods.len(), flagged)
+            }
+        };
+
+        // The first card is the default even when asked not to be; the next takes over
+        let first = save("4242424242424242", Some(false)).await;
+        assert_eq!(defaults().await, (1, vec![first.clone()]));
+        let second = save("5555555555554444", None).await;
+        assert_eq!(defaults().await, (2, vec![second.clone()]));
+        save("378282246310005", Some(false)).await;
+        assert_eq!(defaults().await, (3, vec![second.clone()]));
+
+        let (status, body) = send_json(&app, "POST", &format!("/api/v1/payment-methods/{}/default", first), serde_json::Value::Null, &[]).await;
+        assert_eq!((status, body["is_default"].as_bool()), (StatusCode::OK, Some(true)));
+        assert_eq!(defaults().await, (3, vec![first]));
+
+        // The database refuses a second default outright
+        let duplicate = sqlx::query("UPDATE payment_methods SET is_default = TRUE WHERE id = $1").bind(Uuid::parse_str(&second).unwrap()).execute(&db).await;
+        assert!(duplicate.is_err());
+    }
+
+    #[sqlx::test]
+    async fn test_delete_payment_method(db: sqlx::PgPool) {
+        use chrono::Datelike;
+        seed_api_key(&db, TEST_MERCHANT, TEST_API_KEY).await;
+        let app = build_router(test_state_with_gateway(db.clone(), Arc::new(MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: None })))));
+        let customer_id = Uuid::now_v7();
+        let mut methods = Vec::new();
+        for number in ["4242424242424242", "5555555555554444", "378282246310005"] {
+            let body = serde_json::json!({
+                "customer_id": customer_id,
+                "email": "ada@example.com",
+                "card": { "number": number, "exp_month": 12, "exp_year": Utc::now().year() + 1, "cvc": "123" },
+            });
+            let (status, body) = send_json(&app, "POST", "/api/v1/payment-methods", body, &[]).await;
+            assert_eq!(status, StatusCode::CREATED);
+            methods.push(body["id"].as_str().unwrap().to_string());
+        }
+        let (first, second, third) = (&methods[0], &methods[1], &methods[2]);
+        let subscribe = |method: &str| serde_json::json!({
+            "customer_id": customer_id, "plan_id": "PLAN_PRO", "amount": 250_000, "currency": "NGN", "payment_method_id": method
+        });
+        let (status, subscription) = send_json(&app, "POST", "/api/v1/subscriptions", subscribe(second), &[]).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let subscription_status = || sqlx::query_scalar::<_, String>("SELECT status FROM subscriptions WHERE id = $1")
+            .bind(Uuid::parse_str(subscription["id"].as_str().unwrap()).unwrap())
+            .fetch_one(&db);
+        let listed = || async {
+            let (_, body) = send_json(&app, "GET", &format!("/api/v1/customers/{}/payment-methods", customer_id), serde_json::Value::Null, &[]).await;
+            body.as_array().unwrap().iter().map(|m| (m["id"].as_str().unwrap().to_string(), m["is_default"].as_bool().unwrap())).collect::<Vec<_>>()
+        };
+
+        // Unreferenced: deleted, and the default passes to the newest remaining card
+        let (status, _) = send_json(&app, "DELETE", &format!("/api/v1/payment-methods/{}", third), serde_json::Value::Null, &[]).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(listed().await, vec![(second.clone(), true), (first.clone(), false)]);
+        let (status, _) = send_json(&app, "DELETE", &format!("/api/v1/payment-methods/{}", third), serde_json::Value::Null, &[]).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let kept: bool = sqlx::query_scalar("SELECT deleted_at IS NOT NULL FROM payment_methods WHERE id = $1")
+            .bind(Uuid::parse_str(third).unwrap()).fetch_one(&db).await.unwrap();
+        assert!(kept);
+        let (status, _) = send_json(&app, "POST", "/api/v1/subscriptions", subscribe(third), &[]).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Referenced by an active subscription: refused until forced, which pauses it
+        let (status, body) = send_json(&app, "DELETE", &format!("/api/v1/payment-methods/{}", second), serde_json::Value::Null, &[]).await;
+        assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+        assert_eq!(subscription_status().await.unwrap(), "active");
+        assert_eq!(listed().await.len(), 2);
+        let (status, _) = send_json(&app, "DELETE", &format!("/api/v1/payment-methods/{}?force=true", second), serde_json::Value::Null, &[]).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(subscription_status().await.unwrap(), "paused");
+        assert_eq!(listed().await, vec![(first.clone(), true)]);
+    }
+
+    #[sqlx::test]
+    async fn test_bulk_refunds_report_each_item(db: sqlx::PgPool) {
+        seed_api_key(&db, TEST_MERCHANT, TEST_API_KEY).await;
+        let app = build_router(test_state(db.clone()));
+        let valid = seed_transaction(&db, Decimal::new(10000, 2), "succeeded").await;
+        let over = seed_transaction(&db, Decimal::new(5000, 2), "succeeded").await;
+        let body = serde_json::json!({ "refunds": [
+            { "transaction_id": valid, "amount": 4000, "reason": "duplicate" },
+            { "transaction_id": over, "amount": 5001 },
+            { "transaction_id": Uuid::now_v7(), "amount": 100 },
+        ]});
+
+        let (status, body) = send_json(&app, "POST", "/api/v1/refunds/bulk", body, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        let results = body.as_array().unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!((results[0]["transaction_id"].as_str(), results[0]["success"].as_bool(), results[0]["status"].as_u64()), (Some(valid.to_string().as_str()), Some(true), Some(201)));
+        assert_eq!(results[0]["refund"]["amount"].as_str().map(|a| a.parse::<Decimal>().unwrap()), Some(Decimal::new(4000, 2)));
+        assert!(results[0]["error"].is_null());
+        assert_eq!((results[1]["transaction_id"].as_str(), results[1]["success"].as_bool(), results[1]["status"].as_u64()), (Some(over.to_string().as_str()), Some(false), Some(422)));
+        assert!(results[1]["error"].as_str().unwrap().contains("exceed the charge"), "{}", results[1]);
+        assert!(results[1]["refund"].is_null());
+        assert_eq!((results[2]["success"].as_bool(), results[2]["status"].as_u64()), (Some(false), Some(404)));
+
+        // The refused items left nothing behind; the valid one stands
+        let refunds: Vec<(Uuid, Decimal)> = sqlx::query_as("SELECT transaction_id, amount FROM refunds").fetch_all(&db).await.unwrap();
+        assert_eq!(refunds, vec![(valid, Decimal::new(4000, 2))]);
+
+        let (status, _) = send_json(&app, "POST", "/api/v1/refunds/bulk", serde_json::json!({ "refunds": [] }), &[]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    /// A merchant's webhook receiver that answers every POST with `status` and hands back
+    /// what it was sent.
+    async fn webhook_receiver(status: StatusCode) -> (String, tokio::sync::mpsc::UnboundedReceiver<(HeaderMap, Bytes)>) {
+        let (tx, received) = tokio::sync::mpsc::unbounded_channel();
+        let app = Router::new().route("/hooks", post(move |headers: HeaderMap, body: Bytes| async move {
+            let _ = tx.send((headers, body));
+            status
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hooks", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, received)
+    }
+
+    #[sqlx::test]
+    async fn test_merchant_webhook_delivery(db: sqlx::PgPool) {
+        let mut state = test_state(db.clone());
+        state.config = Arc::new(Config { test_mode: true, ..Config::clone(&state.config) });
+        let (merchant_url, mut received) = webhook_receiver(StatusCode::OK).await;
+        let (down_url, _) = webhook_receiver(StatusCode::INTERNAL_SERVER_ERROR).await;
+        let register = |url: String| create_webhook_endpoint(State(state.clone()), merchant(), Json(CreateWebhookEndpointRequest { url }));
+        let (_, Json(endpoint)) = register(merchant_url).await.unwrap();
+        let (_, Json(failing)) = register(down_url).await.unwrap();
+
+        let card = seed_card(&db, 11, 2026).await;
+        scan_card_expiry(&state, chrono::NaiveDate::from_ymd_opt(2026, 11, 10).unwrap()).await.unwrap();
+        assert_eq!(deliver_due_webhooks(&state).await.unwrap(), 1);
+
+        // Signed with the secret shown at registration
+        let (headers, body) = received.recv().await.unwrap();
+        let signature = headers.get(webhooks::SIGNATURE_HEADER).unwrap().to_str().unwrap();
+        assert!(webhooks::verify(&endpoint.secret, signature, &body));
+        assert!(!webhooks::verify(&failing.secret, signature, &body));
+        let event: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(event["type"], "payment_method.expiring");
+        assert_eq!(event["data"]["payment_method_id"], card.to_string());
+        assert!(event["data"].get("type").is_none());
+
+        // The endpoint that's down is retried later, not straight away
+        let deliveries: Vec<(Uuid, String, i32, bool)> = sqlx::query_as(
+            "SELECT endpoint_id, status, attempts, status = 'pending' AND next_attempt_at > NOW() FROM webhook_deliveries ORDER BY status"
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        assert_eq!(deliveries, vec![(endpoint.endpoint.id, "delivered".to_string(), 1, false), (failing.endpoint.id, "pending".to_string(), 1, true)]);
+        assert_eq!(deliver_due_webhooks(&state).await.unwrap(), 0);
+        let attempts: Vec<Option<i16>> = sqlx::query_scalar("SELECT status_code FROM webhook_delivery_attempts ORDER BY status_code").fetch_all(&db).await.unwrap();
+        assert_eq!(attempts, vec![Some(200), Some(500)]);
+
+        // Once due again it goes out with the same event id
+        sqlx::query("UPDATE webhook_deliveries SET next_attempt_at = NOW() WHERE status = 'pending'").execute(&db).await.unwrap();
+        assert_eq!(deliver_due_webhooks(&state).await.unwrap(), 0);
+        let (attempts, payload): (i32, serde_json::Value) = sqlx::query_as("SELECT attempts, payload FROM webhook_deliveries WHERE endpoint_id = $1")
+            .bind(failing.endpoint.id).fetch_one(&db).await.unwrap();
+        assert_eq!((attempts, &payload["id"]), (2, &event["id"]));
+    }
+
+    #[sqlx::test]
+    async fn test_merchant_webhooks_only_reach_that_merchant(db: sqlx::PgPool) {
+        let mut state = test_state(db.clone());
+        state.config = Arc::new(Config { test_mode: true, ..Config::clone(&state.config) });
+        let (url_a, mut received_a) = webhook_receiver(StatusCode::OK).await;
+        let (url_b, mut received_b) = webhook_receiver(StatusCode::OK).await;
+        let merchant_b = Extension(Merchant::new(Uuid::now_v7()));
+        let (_, Json(endpoint_a)) = create_webhook_endpoint(State(state.clone()), merchant(), Json(CreateWebhookEndpointRequest { url: url_a })).await.unwrap();
+        let _ = create_webhook_endpoint(State(state.clone()), merchant_b, Json(CreateWebhookEndpointRequest { url: url_b })).await.unwrap();
+
+        let Json(charged) = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(initiate_request(5000))).await.unwrap();
+        assert_eq!(deliver_due_webhooks(&state).await.unwrap(), 1);
+
+        let (_, body) = received_a.recv().await.unwrap();
+        let event: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!((event["type"].as_str(), event["data"]["payment_id"].as_str()), (Some("payment.created"), Some(charged.reference.as_str())));
+        assert!(received_b.try_recv().is_err());
+        let endpoints: Vec<Uuid> = sqlx::query_scalar("SELECT endpoint_id FROM webhook_deliveries").fetch_all(&db).await.unwrap();
+        assert_eq!(endpoints, vec![endpoint_a.endpoint.id]);
+    }
+
+    #[sqlx::test]
+    async fn test_webhook_deliveries_are_leased_while_sent(db: sqlx::PgPool) {
+        let mut state = test_state(db.clone());
+        state.config = Arc::new(Config { test_mode: true, ..Config::clone(&state.config) });
+        // An endpoint that answers only once the test lets it
+        let (arrived_tx, mut arrived) = tokio::sync::mpsc::unbounded_channel();
+        let respond = Arc::new(tokio::sync::Notify::new());
+        let gate = respond.clone();
+        let app = Router::new().route("/hooks", post(move || async move {
+            let _ = arrived_tx.send(());
+            gate.notified().await;
+            StatusCode::OK
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hooks", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let _ = create_webhook_endpoint(State(state.clone()), merchant(), Json(CreateWebhookEndpointRequest { url })).await.unwrap();
+        let mut conn = db.acquire().await.unwrap();
+        queue_merchant_event(&mut conn, TEST_MERCHANT, "payment.created", serde_json::json!({})).await.unwrap();
+        drop(conn);
+
+        let worker = state.clone();
+        let sending = tokio::spawn(async move { deliver_due_webhooks(&worker).await });
+        arrived.recv().await.unwrap();
+
+        // Mid-send the delivery is neither locked nor due, so another run leaves it alone
+        let locked: Result<Uuid, _> = sqlx::query_scalar("SELECT id FROM webhook_deliveries FOR UPDATE NOWAIT").fetch_one(&db).await;
+        assert!(locked.is_ok());
+        assert_eq!(deliver_due_webhooks(&state).await.unwrap(), 0);
+        assert!(arrived.try_recv().is_err());
+
+        respond.notify_one();
+        assert_eq!(sending.await.unwrap().unwrap(), 1);
+        let (status, attempts): (String, i32) = sqlx::query_as("SELECT status, attempts FROM webhook_deliveries").fetch_one(&db).await.unwrap();
+        assert_eq!((status.as_str(), attempts), ("delivered", 1));
+    }
+
+    #[sqlx::test]
+    async fn test_checkout_links(db: sqlx::PgPool) {
+        seed_api_key(&db, TEST_MERCHANT, TEST_API_KEY).await;
+        let app = build_router(test_state(db.clone()));
+        let body = serde_json::json!({ "reference": "order-77", "amount": 250_000, "currency": "NGN", "email": "ada@example.com" });
+        let (status, initiated) = send_json(&app, "POST", "/api/v1/payments/initiate", body, &[]).await;
+        assert_eq!(status, StatusCode::OK, "{}", initiated);
+        let token = initiated["checkout_token"].as_str().unwrap().to_string();
+        assert!(initiated["checkout_expires_at"].is_string());
+
+        // No API key: the token is the credential
+        let open = |token: String| {
+            let app = app.clone();
+            async move {
+                let request = axum::http::Request::builder().uri(format!("/api/v1/checkout/{}", token)).body(Body::empty()).unwrap();
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, serde_json::from_slice::<serde_json::Value>(&bytes).unwrap_or(serde_json::Value::Null))
+            }
+        };
+        let (status, context) = open(token.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((context["reference"].as_str(), context["currency"].as_str(), context["status"].as_str()), (Some("order-77"), Some("NGN"), Some("pending")));
+        assert_eq!(context["amount"].as_str().map(|a| a.parse::<Decimal>().unwrap()), Some(Decimal::new(250_000, 2)));
+
+        let secret = TEST_CHECKOUT_SECRET.as_bytes();
+        let expired = checkout::sign(secret, "order-77", Utc::now().timestamp() - 1);
+        assert_eq!(open(expired).await.0, StatusCode::GONE);
+        let parts: Vec<&str> = token.split('.').collect();
+        let extended = format!("{}.{}.{}", parts[0], Utc::now().timestamp() + 86_400 * 365, parts[2]);
+        assert_eq!(open(extended).await.0, StatusCode::BAD_REQUEST);
+        let forged = checkout::sign(b"not the secret", "order-77", Utc::now().timestamp() + 600);
+        assert_eq!(open(forged).await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(open("garbage".into()).await.0, StatusCode::BAD_REQUEST);
+        let unknown = checkout::sign(secret, "order-78", Utc::now().timestamp() + 600);
+        assert_eq!(open(unknown).await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn test_provider_routing(db: sqlx::PgPool) {
+        let mock = Arc::new(MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: None })));
+        let mut state = test_state_with_gateway(db.clone(), Arc::new(StubGateway));
+        state.gateways = Arc::new(vec![mock.clone() as Arc<dyn PaymentGateway>]);
+        state.config = Arc::new(Config {
+            provider_routes: ProviderRouter::parse("NGN=paystack;USD:-99999=mock;USD:100000-=stripe,mock;*=paystack").unwrap(),
+            provider_capabilities: parse_provider_currencies("paystack=NGN,GHS"),
+            ..Config::clone(&state.config)
+        });
+        let charge = |minor: i64, currency: &str, provider: Option<&str>| {
+            let req = InitiatePaymentRequest { amount: Amount::new(minor, currency), provider: provider.map(String::from), ..initiate_request(minor) };
+            let state = state.clone();
+            async move {
+                let Json(resp) = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(req)).await.map_err(|e| e.status)?;
+                let provider: Option<String> = sqlx::query_scalar("SELECT provider FROM transactions WHERE reference = $1")
+                    .bind(&resp.reference).fetch_one(&state.db).await.unwrap();
+                Ok::<_, StatusCode>(provider.unwrap())
+            }
+        };
+
+        assert_eq!(charge(500_000, "NGN", None).await, Ok("paystack".into()));
+        assert_eq!(charge(5_000, "USD", None).await, Ok("mock".into()));
+        // stripe isn't configured here, so the rule's next provider takes it
+        assert_eq!(charge(250_000, "USD", None).await, Ok("mock".into()));
+        assert_eq!(charge(5_000, "GHS", None).await, Ok("paystack".into()));
+        // The catch-all's provider doesn't take euros and there's nothing after it
+        assert_eq!(charge(5_000, "EUR", None).await, Err(StatusCode::UNPROCESSABLE_ENTITY));
+
+        // The request's provider wins, still subject to what it accepts
+        assert_eq!(charge(500_000, "NGN", Some("mock")).await, Ok("mock".into()));
+        assert_eq!(charge(5_000, "USD", Some("paystack")).await, Err(StatusCode::UNPROCESSABLE_ENTITY));
+        assert_eq!(mock.requests().len(), 3);
+    }
+
+    #[sqlx::test]
+    async fn test_provider_failover(db: sqlx::PgPool) {
+        use sase_payments::domain::value_objects::{DeclineCode, ProviderErrorKind};
+        let unavailable = PaymentError::ProviderError { kind: ProviderErrorKind::Unavailable, message: "HTTP 503 Service Unavailable".into() };
+        let primary = Arc::new(MockGateway::new(Err(unavailable)).named("primary"));
+        let secondary = Arc::new(MockGateway::new(Ok(ChargeResult::Succeeded { provider_reference: None })).named("secondary"));
+        let declining = Arc::new(MockGateway::new(Err(PaymentError::Declined(DeclineCode::InsufficientFunds))).named("declining"));
+        let mut state = test_state_with_gateway(db.clone(), Arc::new(StubGateway));
+        state.gateways = Arc::new(vec![primary.clone() as Arc<dyn PaymentGateway>, secondary.clone(), declining.clone()]);
+        state.config = Arc::new(Config {
+            provider_routes: ProviderRouter::parse("NGN=primary,secondary;GHS=declining,secondary;USD=primary").unwrap(),
+            ..Config::clone(&state.config)
+        });
+        let charge = |currency: &str, provider: Option<&str>| {
+            let req = InitiatePaymentRequest { amount: Amount::new(5_000, currency), provider: provider.map(String::from), ..initiate_request(5_000) };
+            let state = state.clone();
+            async move {
+                let result = initiate_payment(State(state.clone()), merchant(), HeaderMap::new(), Json(req)).await;
+                let (status, provider): (String, Option<String>) = sqlx::query_as("SELECT status, provider FROM transactions ORDER BY created_at DESC, id DESC LIMIT 1")
+                    .fetch_one(&state.db).await.unwrap();
+                (result.map(|_| ()).map_err(|e| e.status), status, provider.unwrap())
+            }
+        };
+
+        // The primary is down, so the secondary takes the charge and is recorded
+        assert_eq!(charge("NGN", None).await, (Ok(()), "succeeded".into(), "secondary".into()));
+        assert_eq!((primary.requests().len(), secondary.requests().len()), (1, 1));
+        assert_eq!(primary.requests()[0].idempotency_key, secondary.requests()[0].idempotency_key);
+
+        // A decline is final
+        assert_eq!(charge("GHS", None).await, (Err(StatusCode::PAYMENT_REQUIRED), "failed".into(), "declining".into()));
+        assert_eq!(secondary.requests().len(), 1);
+
+        // With nothing to fall back to, or a provider named on the request, the outage stands
+        assert_eq!(charge("USD", None).await, (Err(StatusCode::SERVICE_UNAVAILABLE), "failed".into(), "primary".into()));
+        assert_eq!(charge("NGN", Some("primary")).await, (Err(StatusCode::SERVICE_UNAVAILABLE), "failed".into(), "primary".into()));
+        assert_eq!(secondary.requests().len(), 1);
+    }
 }
@@ -0,0 +1,190 @@
+//! Source-IP allowlisting for inbound provider webhooks
+//!
+//! Providers publish the addresses their webhooks come from. Checking them is a second
+//! line of defence next to signature verification, so it is off unless ranges are
+//! configured. Forwarding headers are only believed when they were added by a proxy we
+//! trust; otherwise the socket peer is the client.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An address range in CIDR notation, e.g. `52.31.139.75/32` or `2001:db8::/32`. A bare
+/// address is a single-host range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IpRange { network: IpAddr, prefix: u8 }
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, canonical(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => prefix_eq(u32::from(net).into(), u32::from(ip).into(), self.prefix, 32),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => prefix_eq(u128::from(net), u128::from(ip), self.prefix, 128),
+            _ => false,
+        }
+    }
+}
+
+fn prefix_eq(a: u128, b: u128, prefix: u8, bits: u8) -> bool {
+    if prefix == 0 { return true; }
+    let shift = u32::from(bits - prefix);
+    (a >> shift) == (b >> shift)
+}
+
+/// Treats IPv4-mapped IPv6 addresses (`::ffff:1.2.3.4`) as the IPv4 address.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let network = canonical(addr.parse::<IpAddr>().map_err(|_| format!("invalid address '{}'", addr))?);
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.parse::<u8>().ok().filter(|p| *p <= max).ok_or_else(|| format!("invalid prefix length in '{}'", s))?,
+            None => max,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+fn parse_ranges(list: &str) -> Result<Vec<IpRange>, String> {
+    list.split(',').map(str::trim).filter(|r| !r.is_empty()).map(str::parse).collect()
+}
+
+/// Per-provider webhook source ranges plus the proxies whose forwarding headers we trust.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WebhookAllowlist {
+    pub providers: HashMap<String, Vec<IpRange>>,
+    pub trusted_proxies: Vec<IpRange>,
+}
+
+impl WebhookAllowlist {
+    /// Parses `paystack=52.31.139.75,52.49.173.169;flutterwave=...` and a comma-separated
+    /// list of trusted proxy ranges.
+    pub fn parse(providers: &str, trusted_proxies: &str) -> Result<Self, String> {
+        let mut parsed = HashMap::new();
+        for entry in providers.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let (provider, ranges) = entry.split_once('=').ok_or_else(|| format!("expected provider=ranges, got '{}'", entry))?;
+            parsed.insert(provider.trim().to_string(), parse_ranges(ranges)?);
+        }
+        Ok(Self { providers: parsed, trusted_proxies: parse_ranges(trusted_proxies)? })
+    }
+
+    fn is_trusted_proxy(&self, ip: IpAddr) -> bool { self.trusted_proxies.iter().any(|r| r.contains(ip)) }
+
+    /// The originating client address. Forwarded hops are walked from the nearest one
+    /// back, stopping at the first address that is not a trusted proxy; anything to its
+    /// left could have been written by the client and is ignored.
+    pub fn client_ip(&self, peer: IpAddr, forwarded: Option<&str>, x_forwarded_for: Option<&str>) -> IpAddr {
+        let peer = canonical(peer);
+        if !self.is_trusted_proxy(peer) { return peer; }
+        let hops = match (forwarded, x_forwarded_for) {
+            (Some(header), _) => forwarded_for_hops(header),
+            (None, Some(header)) => header.split(',').map(|h| h.trim().parse().ok()).collect(),
+            (None, None) => return peer,
+        };
+        let mut client = peer;
+        for hop in hops.into_iter().rev() {
+            // An unparseable hop means the chain can't be trusted past this point.
+            let Some(ip) = hop.map(canonical) else { break };
+            client = ip;
+            if !self.is_trusted_proxy(ip) { break; }
+        }
+        client
+    }
+
+    /// Whether a webhook for `provider` from `client` should be processed. Providers with
+    /// no configured ranges are not restricted.
+    pub fn allows(&self, provider: &str, client: IpAddr) -> bool {
+        match self.providers.get(provider) {
+            Some(ranges) if !ranges.is_empty() => ranges.iter().any(|r| r.contains(client)),
+            _ => true,
+        }
+    }
+
+    pub fn is_enabled(&self, provider: &str) -> bool { self.providers.get(provider).is_some_and(|r| !r.is_empty()) }
+}
+
+/// `for=` addresses from an RFC 7239 `Forwarded` header, in order. Obfuscated or
+/// malformed nodes come back as `None`.
+fn forwarded_for_hops(header: &str) -> Vec<Option<IpAddr>> {
+    header.split(',')
+        .filter_map(|element| element.split(';').find_map(|pair| {
+            let (key, value) = pair.trim().split_once('=')?;
+            key.eq_ignore_ascii_case("for").then(|| parse_node(value.trim().trim_matches('"')))
+        }))
+        .collect()
+}
+
+/// A `Forwarded` node: `1.2.3.4`, `1.2.3.4:80`, `[2001:db8::1]` or `[2001:db8::1]:80`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    node.split(':').next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr { s.parse().unwrap() }
+
+    fn allowlist() -> WebhookAllowlist {
+        WebhookAllowlist::parse("paystack=52.31.139.75,52.49.173.0/24", "10.0.0.0/8").unwrap()
+    }
+
+    #[test]
+    fn test_ranges() {
+        let range: IpRange = "52.49.173.0/24".parse().unwrap();
+        assert!(range.contains(ip("52.49.173.169")));
+        assert!(range.contains(ip("::ffff:52.49.173.1")));
+        assert!(!range.contains(ip("52.49.174.1")));
+        let v6: IpRange = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains(ip("2001:db8::1")) && !v6.contains(ip("2001:db9::1")));
+        assert!("0.0.0.0/0".parse::<IpRange>().unwrap().contains(ip("8.8.8.8")));
+        assert!("1.2.3.4/33".parse::<IpRange>().is_err());
+        assert!("nope".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn test_allowed_and_disallowed_sources() {
+        let list = allowlist();
+        assert!(list.allows("paystack", ip("52.31.139.75")));
+        assert!(!list.allows("paystack", ip("203.0.113.9")));
+        assert!(list.allows("flutterwave", ip("203.0.113.9")));
+        assert!(WebhookAllowlist::default().allows("paystack", ip("203.0.113.9")));
+    }
+
+    #[test]
+    fn test_client_ip_from_trusted_proxy() {
+        let list = allowlist();
+        let proxy = ip("10.0.0.5");
+        assert_eq!(list.client_ip(proxy, None, Some("52.31.139.75")), ip("52.31.139.75"));
+        assert_eq!(list.client_ip(proxy, None, Some("52.31.139.75, 10.0.0.7")), ip("52.31.139.75"));
+        assert_eq!(list.client_ip(proxy, Some(r#"for="[2001:db8::1]:443";proto=https, for=10.0.0.7"#), None), ip("2001:db8::1"));
+        assert_eq!(list.client_ip(proxy, Some("for=52.31.139.75:8443"), Some("1.1.1.1")), ip("52.31.139.75"));
+    }
+
+    #[test]
+    fn test_spoofed_forwarding_headers_are_ignored() {
+        let list = allowlist();
+        // Straight from the internet: the headers are attacker-controlled.
+        let attacker = ip("203.0.113.9");
+        assert_eq!(list.client_ip(attacker, None, Some("52.31.139.75")), attacker);
+        assert_eq!(list.client_ip(attacker, Some("for=52.31.139.75"), None), attacker);
+        // Through our proxy, an address prepended by the client stays to the left of the
+        // real one and is never reached.
+        assert_eq!(list.client_ip(ip("10.0.0.5"), None, Some("52.31.139.75, 203.0.113.9")), attacker);
+        assert!(!list.allows("paystack", list.client_ip(ip("10.0.0.5"), None, Some("52.31.139.75, 203.0.113.9"))));
+        assert_eq!(list.client_ip(ip("10.0.0.5"), Some("for=unknown"), None), ip("10.0.0.5"));
+    }
+}
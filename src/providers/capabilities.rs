@@ -0,0 +1,53 @@
+//! What each provider can process
+use std::collections::HashMap;
+use crate::domain::aggregates::PaymentError;
+
+/// Currencies a provider accepts. An empty list means nothing is configured and every
+/// currency is let through.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProviderCapabilities {
+    pub supported_currencies: Vec<String>,
+}
+
+impl ProviderCapabilities {
+    pub fn supports_currency(&self, code: &str) -> bool {
+        self.supported_currencies.is_empty() || self.supported_currencies.iter().any(|c| c.eq_ignore_ascii_case(code))
+    }
+
+    pub fn ensure_currency(&self, provider: &str, code: &str) -> Result<(), PaymentError> {
+        if self.supports_currency(code) { return Ok(()); }
+        Err(PaymentError::UnsupportedCurrency { currency: code.to_string(), provider: provider.to_string() })
+    }
+}
+
+/// Parses `paystack=NGN,GHS;flutterwave=NGN,USD` into per-provider capabilities.
+/// Malformed entries are skipped.
+pub fn parse_provider_currencies(spec: &str) -> HashMap<String, ProviderCapabilities> {
+    spec.split(';')
+        .filter_map(|entry| entry.split_once('='))
+        .map(|(provider, codes)| {
+            let supported_currencies = codes.split(',').map(|c| c.trim().to_ascii_uppercase()).filter(|c| !c.is_empty()).collect();
+            (provider.trim().to_string(), ProviderCapabilities { supported_currencies })
+        })
+        .filter(|(provider, _)| !provider.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_check() {
+        let caps = parse_provider_currencies("paystack=NGN, ghs ;flutterwave=NGN,USD;broken");
+        assert_eq!(caps.len(), 2);
+        let paystack = &caps["paystack"];
+        assert_eq!(paystack.supported_currencies, vec!["NGN", "GHS"]);
+        assert!(paystack.ensure_currency("paystack", "NGN").is_ok());
+        assert_eq!(
+            paystack.ensure_currency("paystack", "USD"),
+            Err(PaymentError::UnsupportedCurrency { currency: "USD".into(), provider: "paystack".into() })
+        );
+        assert!(ProviderCapabilities::default().supports_currency("JPY"));
+    }
+}
@@ -0,0 +1,106 @@
+//! Normalized AVS/CVV results reported by acquirers
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AvsResult { Match, PartialMatch, NoMatch, Unavailable, Unchecked }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CvvResult { Match, NoMatch, NotProcessed, Unavailable, Unchecked }
+
+/// Verification results captured from a single charge attempt.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CardChecks {
+    pub avs_result: Option<AvsResult>,
+    pub cvv_result: Option<CvvResult>,
+    pub network_response_code: Option<String>,
+}
+
+impl AvsResult {
+    pub fn as_str(&self) -> &'static str {
+        match self { Self::Match => "match", Self::PartialMatch => "partial_match", Self::NoMatch => "no_match", Self::Unavailable => "unavailable", Self::Unchecked => "unchecked" }
+    }
+
+    /// Stripe reports `address_line1_check` and `address_postal_code_check` separately as pass/fail/unavailable/unchecked.
+    pub fn from_stripe(line1: Option<&str>, postal_code: Option<&str>) -> Option<Self> {
+        match (line1, postal_code) {
+            (None, None) => None,
+            (Some("pass"), Some("pass")) | (Some("pass"), None) | (None, Some("pass")) => Some(Self::Match),
+            (Some("pass"), _) | (_, Some("pass")) => Some(Self::PartialMatch),
+            (Some("fail"), _) | (_, Some("fail")) => Some(Self::NoMatch),
+            (Some("unavailable"), _) | (_, Some("unavailable")) => Some(Self::Unavailable),
+            _ => Some(Self::Unchecked),
+        }
+    }
+
+    /// Single-letter Visa/Mastercard AVS codes, as passed through by Paystack and most acquirers.
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code.trim().to_ascii_uppercase().as_str() {
+            "Y" | "X" | "D" | "M" | "F" => Some(Self::Match),
+            "A" | "B" | "P" | "W" | "Z" => Some(Self::PartialMatch),
+            "N" => Some(Self::NoMatch),
+            "U" | "R" | "S" | "G" | "E" | "C" | "I" => Some(Self::Unavailable),
+            _ => None,
+        }
+    }
+}
+
+impl CvvResult {
+    pub fn as_str(&self) -> &'static str {
+        match self { Self::Match => "match", Self::NoMatch => "no_match", Self::NotProcessed => "not_processed", Self::Unavailable => "unavailable", Self::Unchecked => "unchecked" }
+    }
+
+    /// Stripe's `cvc_check`: pass/fail/unavailable/unchecked.
+    pub fn from_stripe(check: &str) -> Option<Self> {
+        match check {
+            "pass" => Some(Self::Match),
+            "fail" => Some(Self::NoMatch),
+            "unavailable" => Some(Self::Unavailable),
+            "unchecked" => Some(Self::Unchecked),
+            _ => None,
+        }
+    }
+
+    /// Single-letter CVV2/CVC2 codes.
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code.trim().to_ascii_uppercase().as_str() {
+            "M" => Some(Self::Match),
+            "N" => Some(Self::NoMatch),
+            "P" => Some(Self::NotProcessed),
+            "S" | "U" => Some(Self::Unavailable),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stripe_checks() {
+        assert_eq!(AvsResult::from_stripe(Some("pass"), Some("pass")), Some(AvsResult::Match));
+        assert_eq!(AvsResult::from_stripe(Some("pass"), Some("fail")), Some(AvsResult::PartialMatch));
+        assert_eq!(AvsResult::from_stripe(Some("fail"), Some("fail")), Some(AvsResult::NoMatch));
+        assert_eq!(AvsResult::from_stripe(Some("unavailable"), None), Some(AvsResult::Unavailable));
+        assert_eq!(AvsResult::from_stripe(Some("unchecked"), Some("unchecked")), Some(AvsResult::Unchecked));
+        assert_eq!(AvsResult::from_stripe(None, None), None);
+        assert_eq!(CvvResult::from_stripe("pass"), Some(CvvResult::Match));
+        assert_eq!(CvvResult::from_stripe("fail"), Some(CvvResult::NoMatch));
+        assert_eq!(CvvResult::from_stripe("bogus"), None);
+    }
+
+    #[test]
+    fn test_acquirer_codes() {
+        assert_eq!(AvsResult::from_code("Y"), Some(AvsResult::Match));
+        assert_eq!(AvsResult::from_code("z"), Some(AvsResult::PartialMatch));
+        assert_eq!(AvsResult::from_code("N"), Some(AvsResult::NoMatch));
+        assert_eq!(AvsResult::from_code("U"), Some(AvsResult::Unavailable));
+        assert_eq!(AvsResult::from_code("Q"), None);
+        assert_eq!(CvvResult::from_code("M"), Some(CvvResult::Match));
+        assert_eq!(CvvResult::from_code("N"), Some(CvvResult::NoMatch));
+        assert_eq!(CvvResult::from_code("P"), Some(CvvResult::NotProcessed));
+        assert_eq!(serde_json::to_value(AvsResult::PartialMatch).unwrap(), "partial_match");
+    }
+}
@@ -0,0 +1,66 @@
+//! Client-facing classification of charge failures
+//!
+//! Gateway errors carry provider detail that must not reach API clients. This maps
+//! each failure to a stable code, a safe message, and whether the client can fix it
+//! (try another card) or should retry later.
+
+use crate::domain::aggregates::PaymentError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailureClass {
+    /// The issuer or provider refused the card; another payment method may work.
+    Declined,
+    /// The charge cannot be made as requested, e.g. an unsupported currency.
+    CustomerActionable,
+    /// Provider outage, timeout or rate limit; retrying later may succeed.
+    Transient,
+    /// Misconfiguration or a provider response we could not use.
+    Internal,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChargeFailure {
+    pub class: FailureClass,
+    pub code: &'static str,
+    pub decline_code: Option<&'static str>,
+    pub message: &'static str,
+}
+
+pub fn classify(error: &PaymentError) -> ChargeFailure {
+    let failure = |class, code, message| ChargeFailure { class, code, decline_code: None, message };
+    match error {
+        PaymentError::Declined(decline) => ChargeFailure {
+            class: FailureClass::Declined,
+            code: "card_declined",
+            decline_code: Some(decline.as_str()),
+            message: "The card was declined. Try a different payment method.",
+        },
+        PaymentError::UnsupportedCurrency { .. } | PaymentError::InvalidCurrency(_) =>
+            failure(FailureClass::CustomerActionable, "unsupported_currency", "The currency is not supported for this payment method."),
+        PaymentError::InvalidAmount(_) | PaymentError::InsufficientFunds(_) =>
+            failure(FailureClass::CustomerActionable, "invalid_amount", "The amount cannot be charged."),
+        PaymentError::ProviderError { kind, .. } if kind.is_transient() =>
+            failure(FailureClass::Transient, "provider_unavailable", "The payment provider is temporarily unavailable. Retry later."),
+        _ => failure(FailureClass::Internal, "provider_error", "The payment could not be processed."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::value_objects::{DeclineCode, ProviderErrorKind};
+
+    #[test]
+    fn test_classify() {
+        let declined = classify(&PaymentError::Declined(DeclineCode::InsufficientFunds));
+        assert_eq!((declined.class, declined.code, declined.decline_code), (FailureClass::Declined, "card_declined", Some("insufficient_funds")));
+
+        let timeout = classify(&PaymentError::ProviderError { kind: ProviderErrorKind::Timeout, message: "connect to 10.1.2.3:443 timed out".into() });
+        assert_eq!(timeout.class, FailureClass::Transient);
+        assert!(!timeout.message.contains("10.1.2.3"));
+
+        let auth = classify(&PaymentError::ProviderError { kind: ProviderErrorKind::Authentication, message: "bad key sk_live_x".into() });
+        assert_eq!(auth.class, FailureClass::Internal);
+        assert_eq!(classify(&PaymentError::UnsupportedCurrency { currency: "USD".into(), provider: "mock".into() }).class, FailureClass::CustomerActionable);
+    }
+}
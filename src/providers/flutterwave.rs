@@ -0,0 +1,271 @@
+//! Flutterwave Standard (v3) hosted-checkout integration
+//!
+//! Charges are started with `POST /v3/payments`, which returns a payment link, and
+//! confirmed with `GET /v3/transactions/verify_by_reference`. Unlike Paystack, amounts
+//! are sent in major units (naira, not kobo), in webhooks too.
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use crate::crypto;
+use crate::domain::aggregates::PaymentError;
+use crate::domain::value_objects::{Money, ProviderErrorKind};
+use super::gateway::{ChargeRequest, ChargeResponse, ChargeResult, PaymentGateway, Verification, VerifiedStatus};
+use super::http::{self, RetryPolicy};
+use super::webhook_event::{self, WebhookEvent};
+
+pub const API_BASE: &str = "https://api.flutterwave.com";
+
+/// Carries the secret hash set on the Flutterwave dashboard, unchanged, on every webhook.
+pub const SIGNATURE_HEADER: &str = "verif-hash";
+
+/// Checks a webhook's `verif-hash` against the configured secret hash, in constant time.
+pub fn verify_webhook_hash(secret_hash: &str, received: &str) -> bool {
+    !secret_hash.is_empty() && crypto::constant_time_eq(secret_hash.as_bytes(), received.as_bytes())
+}
+
+/// The `data` of a successful payment initialization.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct PaymentLink { pub link: String }
+
+/// The part of a verify response's `data` we act on.
+#[derive(Deserialize)]
+struct VerifiedTransaction { status: String }
+
+/// Maps Flutterwave's transaction `status`; anything else is still pending.
+pub fn verified_status(status: &str) -> VerifiedStatus {
+    match status {
+        "successful" => VerifiedStatus::Succeeded,
+        "failed" => VerifiedStatus::Failed,
+        "cancelled" => VerifiedStatus::Abandoned,
+        _ => VerifiedStatus::Pending,
+    }
+}
+
+/// Maps a webhook body. Every charge outcome arrives as `charge.completed`, with the
+/// result in `data.status`; the metadata we sent comes back as top-level `meta_data`.
+pub fn parse_webhook(payload: &serde_json::Value) -> WebhookEvent {
+    let data = &payload["data"];
+    let (Some("charge.completed"), Some(reference)) = (payload["event"].as_str(), data["tx_ref"].as_str()) else {
+        return webhook_event::unknown(payload);
+    };
+    let reference = reference.to_string();
+    let subscription_id = webhook_event::subscription_id(&payload["meta_data"]);
+    match data["status"].as_str() {
+        Some("successful") => WebhookEvent::ChargeSucceeded { reference, amount: webhook_amount(data), subscription_id },
+        Some("failed") => WebhookEvent::ChargeFailed { reference, subscription_id },
+        _ => webhook_event::unknown(payload),
+    }
+}
+
+fn webhook_amount(data: &serde_json::Value) -> Option<Money> {
+    let amount = data["amount"].as_number()?.to_string().parse().ok()?;
+    Money::try_new(amount, data["currency"].as_str()?).ok()
+}
+
+/// Flutterwave wraps every response as `{ status: "success" | "error", message, data }`.
+#[derive(Deserialize)]
+struct Envelope<T> {
+    status: String,
+    #[serde(default)]
+    message: String,
+    data: Option<T>,
+}
+
+pub struct FlutterwaveGateway {
+    http: reqwest::Client,
+    secret: String,
+    base_url: String,
+    retry: RetryPolicy,
+}
+
+impl FlutterwaveGateway {
+    pub fn new(http: reqwest::Client, secret: impl Into<String>) -> Self {
+        Self { http, secret: secret.into(), base_url: API_BASE.to_string(), retry: RetryPolicy::default() }
+    }
+
+    /// Points the client somewhere other than the live API, e.g. a test server.
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// How verify calls are retried. Charges are never retried.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Creates a hosted payment link for `request`. Returns the parsed `data` and the raw body.
+    pub async fn initialize_payment(&self, request: &ChargeRequest) -> Result<(PaymentLink, serde_json::Value), PaymentError> {
+        let amount: serde_json::Number = request.amount.round().amount.normalize().to_string().parse()
+            .map_err(|_| PaymentError::InvalidAmount(request.amount.amount.to_string()))?;
+        let mut body = serde_json::json!({
+            "tx_ref": request.reference,
+            "amount": amount,
+            "currency": request.amount.currency,
+            "customer": { "email": request.email },
+            "meta": request.metadata,
+        });
+        if let Some(callback_url) = &request.callback_url {
+            body["redirect_url"] = callback_url.clone().into();
+        }
+        let response = self.http.post(format!("{}/v3/payments", self.base_url))
+            .bearer_auth(&self.secret)
+            .json(&body)
+            .send()
+            .await
+            .map_err(transport_error)?;
+        read_envelope(response).await
+    }
+
+    pub async fn verify_payment(&self, reference: &str) -> Result<Verification, PaymentError> {
+        let url = format!("{}/v3/transactions/verify_by_reference", self.base_url);
+        let response = http::send_idempotent(&self.retry, || self.http.get(&url).query(&[("tx_ref", reference)]).bearer_auth(&self.secret))
+            .await
+            .map_err(transport_error)?;
+        let (data, raw): (VerifiedTransaction, _) = read_envelope(response).await?;
+        Ok(Verification { status: verified_status(&data.status), raw_response: Some(raw) })
+    }
+}
+
+#[async_trait]
+impl PaymentGateway for FlutterwaveGateway {
+    fn name(&self) -> &'static str { "flutterwave" }
+    async fn charge(&self, request: &ChargeRequest) -> Result<ChargeResponse, PaymentError> {
+        let (link, raw) = self.initialize_payment(request).await?;
+        // Flutterwave assigns its transaction id only once the customer pays
+        let mut response = ChargeResponse::from(ChargeResult::Checkout { authorization_url: link.link, provider_reference: None });
+        response.raw_response = Some(raw);
+        Ok(response)
+    }
+
+    async fn verify(&self, reference: &str) -> Result<Option<Verification>, PaymentError> {
+        self.verify_payment(reference).await.map(Some)
+    }
+}
+
+fn transport_error(e: reqwest::Error) -> PaymentError {
+    let kind = if e.is_timeout() { ProviderErrorKind::Timeout } else { ProviderErrorKind::Unavailable };
+    PaymentError::ProviderError { kind, message: format!("flutterwave: {}", e) }
+}
+
+/// Unwraps a Flutterwave response. Non-2xx statuses and `status: "error"` become
+/// `ProviderError`s carrying Flutterwave's message.
+async fn read_envelope<T: serde::de::DeserializeOwned>(response: reqwest::Response) -> Result<(T, serde_json::Value), PaymentError> {
+    let status = response.status();
+    let raw: serde_json::Value = response.json().await.map_err(|e| PaymentError::ProviderError {
+        kind: if status.is_success() { ProviderErrorKind::InvalidResponse } else { ProviderErrorKind::from_http_status(status.as_u16()) },
+        message: format!("flutterwave: unreadable response ({}): {}", status, e),
+    })?;
+    let envelope: Envelope<T> = serde_json::from_value(raw.clone()).map_err(|e| PaymentError::ProviderError {
+        kind: ProviderErrorKind::InvalidResponse,
+        message: format!("flutterwave: unexpected response shape: {}", e),
+    })?;
+    if !status.is_success() {
+        return Err(PaymentError::ProviderError { kind: ProviderErrorKind::from_http_status(status.as_u16()), message: format!("flutterwave: {} ({})", envelope.message, status) });
+    }
+    match envelope.data {
+        Some(data) if envelope.status == "success" => Ok((data, raw)),
+        _ => Err(PaymentError::ProviderError { kind: ProviderErrorKind::InvalidResponse, message: format!("flutterwave: {}", envelope.message) }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::value_objects::Money;
+    use crate::test_support::{self, MockServer};
+
+    fn charge() -> ChargeRequest {
+        ChargeRequest {
+            reference: "TXN-1".into(),
+            amount: Money::from_minor_units(500_050, "NGN").unwrap(),
+            email: "ada@example.com".into(),
+            callback_url: Some("https://shop.example/done".into()),
+            metadata: serde_json::json!({ "order": 42 }),
+            statement_descriptor: None,
+            idempotency_key: "chg_1".into(),
+            capture: true,
+        }
+    }
+
+    #[test]
+    fn test_parse_webhook() {
+        let completed = |status: &str| serde_json::json!({
+            "event": "charge.completed",
+            "data": { "id": 285959875, "tx_ref": "TXN-1", "flw_ref": "FLW-MOCK-1", "amount": 5000.5, "currency": "NGN", "status": status },
+            "meta_data": { "subscription_id": "0190f3c2-7a4e-7d1b-9c2a-5b8e1f0a6d42" }
+        });
+        assert_eq!(parse_webhook(&completed("successful")), WebhookEvent::ChargeSucceeded {
+            reference: "TXN-1".into(),
+            amount: Some(Money::from_minor_units(500_050, "NGN").unwrap()),
+            subscription_id: Some("0190f3c2-7a4e-7d1b-9c2a-5b8e1f0a6d42".parse().unwrap()),
+        });
+        assert_eq!(parse_webhook(&completed("failed")), WebhookEvent::ChargeFailed {
+            reference: "TXN-1".into(),
+            subscription_id: Some("0190f3c2-7a4e-7d1b-9c2a-5b8e1f0a6d42".parse().unwrap()),
+        });
+        assert_eq!(parse_webhook(&completed("pending")), WebhookEvent::Unknown { event: "charge.completed".into() });
+
+        let no_meta = serde_json::json!({ "event": "charge.completed", "data": { "tx_ref": "TXN-2", "amount": 100, "status": "successful" } });
+        assert_eq!(parse_webhook(&no_meta), WebhookEvent::ChargeSucceeded { reference: "TXN-2".into(), amount: None, subscription_id: None });
+        assert_eq!(parse_webhook(&serde_json::json!({ "event": "transfer.completed", "data": {} })), WebhookEvent::Unknown { event: "transfer.completed".into() });
+    }
+
+    #[test]
+    fn test_verify_webhook_hash() {
+        assert!(verify_webhook_hash("flw-hash", "flw-hash"));
+        assert!(!verify_webhook_hash("flw-hash", "flw-hasx"));
+        assert!(!verify_webhook_hash("flw-hash", ""));
+        assert!(!verify_webhook_hash("", ""));
+    }
+
+    #[tokio::test]
+    async fn test_initialize_payment() {
+        let body = r#"{"status":"success","message":"Hosted Link","data":{"link":"https://checkout.flutterwave.com/v3/hosted/pay/f524c1196ffda5556341"}}"#;
+        let mut server = MockServer::start(200, body).await;
+        let gateway = FlutterwaveGateway::new(reqwest::Client::new(), "FLWSECK_TEST-abc").with_base_url(&server.url);
+
+        let response = gateway.charge(&charge()).await.unwrap();
+        assert_eq!(response.result, ChargeResult::Checkout {
+            authorization_url: "https://checkout.flutterwave.com/v3/hosted/pay/f524c1196ffda5556341".into(),
+            provider_reference: None,
+        });
+
+        let request = server.next_request().await;
+        assert!(request.starts_with("POST /v3/payments "));
+        assert_eq!(test_support::header(&request, "authorization"), Some("Bearer FLWSECK_TEST-abc"));
+        let sent: serde_json::Value = serde_json::from_str(test_support::body(&request)).unwrap();
+        assert_eq!(sent["tx_ref"], "TXN-1");
+        assert_eq!(sent["amount"], serde_json::json!(5000.5));
+        assert_eq!(sent["customer"]["email"], "ada@example.com");
+        assert_eq!(sent["redirect_url"], "https://shop.example/done");
+    }
+
+    #[tokio::test]
+    async fn test_verify_payment() {
+        let verify_body = |status: &str| format!(r#"{{"status":"success","message":"Transaction fetched successfully","data":{{"id":288200108,"tx_ref":"TXN-1","status":"{}"}}}}"#, status);
+        let mut server = MockServer::start_sequence(["successful", "cancelled", "failed", "pending"].iter().map(|s| (200, verify_body(s))).collect()).await;
+        let gateway = FlutterwaveGateway::new(reqwest::Client::new(), "FLWSECK_TEST-abc").with_base_url(&server.url);
+
+        assert_eq!(gateway.verify("TXN 1").await.unwrap().unwrap().status, VerifiedStatus::Succeeded);
+        assert!(server.next_request().await.starts_with("GET /v3/transactions/verify_by_reference?tx_ref=TXN+1 "));
+        assert_eq!(gateway.verify("TXN-1").await.unwrap().unwrap().status, VerifiedStatus::Abandoned);
+        assert_eq!(gateway.verify("TXN-1").await.unwrap().unwrap().status, VerifiedStatus::Failed);
+        assert_eq!(gateway.verify("TXN-1").await.unwrap().unwrap().status, VerifiedStatus::Pending);
+    }
+
+    #[tokio::test]
+    async fn test_errors_become_provider_errors() {
+        let server = MockServer::start(401, r#"{"status":"error","message":"Invalid authorization key","data":null}"#).await;
+        let gateway = FlutterwaveGateway::new(reqwest::Client::new(), "bad").with_base_url(&server.url);
+        assert_eq!(gateway.charge(&charge()).await.unwrap_err(), PaymentError::ProviderError {
+            kind: ProviderErrorKind::Authentication,
+            message: "flutterwave: Invalid authorization key (401 Unauthorized)".into(),
+        });
+
+        let server = MockServer::start(200, r#"{"status":"error","message":"No transaction was found for this id","data":null}"#).await;
+        let gateway = FlutterwaveGateway::new(reqwest::Client::new(), "FLWSECK_TEST-abc").with_base_url(&server.url);
+        assert!(matches!(gateway.verify("TXN-1").await, Err(PaymentError::ProviderError { kind: ProviderErrorKind::InvalidResponse, .. })));
+    }
+}
@@ -0,0 +1,186 @@
+//! Provider-agnostic charge interface
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use crate::domain::aggregates::PaymentError;
+use crate::domain::value_objects::card::CardDetails;
+use crate::domain::value_objects::{Money, ProviderErrorKind, TransactionStatus};
+use super::card_checks::CardChecks;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChargeRequest {
+    pub reference: String,
+    pub amount: Money,
+    pub email: String,
+    pub callback_url: Option<String>,
+    pub metadata: serde_json::Value,
+    /// Text for the cardholder's statement, already validated against network limits.
+    pub statement_descriptor: Option<String>,
+    /// Stable per logical charge; providers use it to deduplicate retried attempts.
+    pub idempotency_key: String,
+    /// `false` asks for an authorization only, taken later with `PaymentGateway::capture`.
+    pub capture: bool,
+}
+
+/// What the customer must do before a charge can complete.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NextAction {
+    RedirectToUrl { url: String },
+    UseSdk { client_secret: String },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChargeResult {
+    /// Send the customer to the provider's hosted checkout.
+    Checkout { authorization_url: String, provider_reference: Option<String> },
+    /// The provider needs extra customer authentication (e.g. 3DS).
+    RequiresAction { next_action: NextAction, provider_reference: Option<String> },
+    Succeeded { provider_reference: Option<String> },
+    /// The funds are held, not taken, until the charge is captured.
+    Authorized { provider_reference: Option<String> },
+}
+
+impl ChargeResult {
+    pub fn provider_reference(&self) -> Option<&str> {
+        match self {
+            Self::Checkout { provider_reference, .. } | Self::RequiresAction { provider_reference, .. }
+            | Self::Succeeded { provider_reference } | Self::Authorized { provider_reference } => provider_reference.as_deref(),
+        }
+    }
+}
+
+/// Everything a provider told us about one charge attempt.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChargeResponse {
+    pub result: ChargeResult,
+    pub checks: CardChecks,
+    /// The provider's unredacted response body, if any.
+    pub raw_response: Option<serde_json::Value>,
+}
+
+impl From<ChargeResult> for ChargeResponse {
+    fn from(result: ChargeResult) -> Self { Self { result, checks: CardChecks::default(), raw_response: None } }
+}
+
+/// Where the provider says a charge stands.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerifiedStatus {
+    Succeeded,
+    Failed,
+    /// The customer left the checkout without paying.
+    Abandoned,
+    /// The customer has to authenticate (e.g. 3DS) before the charge can go through.
+    RequiresAction(NextAction),
+    /// Still in progress on the provider's side.
+    Pending,
+}
+
+impl VerifiedStatus {
+    /// The transaction status this moves the charge to, or `None` while it is still pending.
+    pub fn transaction_status(&self) -> Option<TransactionStatus> {
+        match self {
+            Self::Succeeded => Some(TransactionStatus::Succeeded),
+            Self::Failed => Some(TransactionStatus::Failed),
+            Self::Abandoned => Some(TransactionStatus::Cancelled),
+            Self::RequiresAction(_) => Some(TransactionStatus::RequiresAction),
+            Self::Pending => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Verification {
+    pub status: VerifiedStatus,
+    /// The provider's unredacted response body, if any.
+    pub raw_response: Option<serde_json::Value>,
+}
+
+/// A refund of all or part of a charge, as sent to the provider.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RefundSubmission {
+    /// Our reference for the charge being refunded.
+    pub charge_reference: String,
+    /// The provider's id for the charge, when it gave one.
+    pub provider_reference: Option<String>,
+    pub amount: Money,
+    pub reason: Option<String>,
+}
+
+/// Taking all or part of an authorization, as sent to the provider.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaptureSubmission {
+    /// Our reference for the authorized charge.
+    pub charge_reference: String,
+    /// The provider's id for the charge, when it gave one.
+    pub provider_reference: Option<String>,
+    /// At most the authorized amount; the rest of the hold is released.
+    pub amount: Money,
+}
+
+/// Where the provider says a refund stands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RefundOutcome {
+    Succeeded,
+    Failed,
+    /// Accepted but not processed yet; the provider's webhook reports the result.
+    Pending,
+}
+
+#[async_trait]
+pub trait PaymentGateway: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn charge(&self, request: &ChargeRequest) -> Result<ChargeResponse, PaymentError>;
+
+    /// Asks the provider for the outcome of the charge with our `reference`. `None` means
+    /// the provider can't be queried and local state is all there is.
+    async fn verify(&self, _reference: &str) -> Result<Option<Verification>, PaymentError> { Ok(None) }
+
+    /// Asks the provider to refund a charge. `None` means it can't be asked, and the
+    /// refund waits for the provider's webhook or for someone to settle it by hand.
+    async fn refund(&self, _refund: &RefundSubmission) -> Result<Option<RefundOutcome>, PaymentError> { Ok(None) }
+
+    /// Asks the provider to void the unsettled charge it knows as `provider_reference`.
+    /// `false` means it can't be asked, and the charge is only cancelled on our side.
+    async fn void(&self, _provider_reference: &str) -> Result<bool, PaymentError> { Ok(false) }
+
+    /// Whether `charge` honours `capture: false`. Payments asking to capture later are only
+    /// routed to providers that do.
+    fn supports_manual_capture(&self) -> bool { false }
+
+    /// Takes `capture.amount` of an authorization. Only called for charges this provider
+    /// authorized, so one without manual capture never sees it.
+    async fn capture(&self, _capture: &CaptureSubmission) -> Result<(), PaymentError> {
+        Err(PaymentError::ProviderError { kind: ProviderErrorKind::InvalidRequest, message: format!("{}: captures aren't supported", self.name()) })
+    }
+
+    /// Vaults `card`, filed under the cardholder's `email`, with the provider and returns
+    /// its reusable token. `None` means the provider doesn't take raw card details from us.
+    async fn tokenize(&self, _card: &CardDetails, _email: &str) -> Result<Option<String>, PaymentError> { Ok(None) }
+}
+
+/// Hands out a hosted-checkout URL without contacting any provider.
+pub struct StubGateway;
+
+#[async_trait]
+impl PaymentGateway for StubGateway {
+    fn name(&self) -> &'static str { "paystack" }
+    async fn charge(&self, request: &ChargeRequest) -> Result<ChargeResponse, PaymentError> {
+        Ok(ChargeResult::Checkout { authorization_url: format!("https://checkout.paystack.com/{}", request.reference), provider_reference: None }.into())
+    }
+
+    /// With no provider to wait for, refunds go through at once.
+    async fn refund(&self, _refund: &RefundSubmission) -> Result<Option<RefundOutcome>, PaymentError> { Ok(Some(RefundOutcome::Succeeded)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_action_json_shape() {
+        let redirect = NextAction::RedirectToUrl { url: "https://acs.example/3ds".into() };
+        assert_eq!(serde_json::to_value(&redirect).unwrap(), serde_json::json!({ "type": "redirect_to_url", "url": "https://acs.example/3ds" }));
+        let sdk = NextAction::UseSdk { client_secret: "cs_123".into() };
+        assert_eq!(serde_json::to_value(&sdk).unwrap(), serde_json::json!({ "type": "use_sdk", "client_secret": "cs_123" }));
+    }
+}
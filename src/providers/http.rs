@@ -0,0 +1,103 @@
+//! Retrying provider HTTP calls
+//!
+//! Only idempotent requests, such as verify GETs, are retried. A charge POST that timed
+//! out may still have been applied by the provider, so it is sent once and any retry is
+//! left to the caller, under the same provider idempotency key.
+
+use std::time::Duration;
+use crate::crypto;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts after the first; 0 disables retrying.
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self { Self { max_retries: 2, base_delay: Duration::from_millis(200), max_delay: Duration::from_secs(2) } }
+}
+
+impl RetryPolicy {
+    pub const NONE: Self = Self { max_retries: 0, base_delay: Duration::ZERO, max_delay: Duration::ZERO };
+
+    /// The longest wait before retry `attempt` (1-based): `base_delay` doubled for every
+    /// earlier retry, capped at `max_delay`.
+    pub fn backoff_ceiling(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    /// A random wait in `[0, backoff_ceiling(attempt)]` ("full jitter"), so clients that
+    /// failed together don't retry together.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self.backoff_ceiling(attempt).as_millis() as u64;
+        Duration::from_millis(crypto::random_u64() % (ceiling + 1))
+    }
+}
+
+/// Statuses worth another attempt: the provider was down, overloaded or too slow.
+pub fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS || status == reqwest::StatusCode::REQUEST_TIMEOUT
+}
+
+/// Sends an idempotent request, retrying timeouts, connection failures and retryable
+/// statuses under `policy`. `build` makes a fresh request for each attempt. Returns the
+/// last response or error once attempts run out.
+pub async fn send_idempotent(policy: &RetryPolicy, build: impl Fn() -> reqwest::RequestBuilder) -> Result<reqwest::Response, reqwest::Error> {
+    let mut attempt = 0;
+    loop {
+        let result = build().send().await;
+        let retryable = match &result {
+            Ok(response) => is_retryable_status(response.status()),
+            Err(e) => e.is_timeout() || e.is_connect(),
+        };
+        if !retryable || attempt >= policy.max_retries { return result; }
+        attempt += 1;
+        tracing::debug!(attempt, "Retrying provider request");
+        tokio::time::sleep(policy.backoff(attempt)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockServer;
+
+    fn fast(max_retries: u32) -> RetryPolicy {
+        RetryPolicy { max_retries, base_delay: Duration::from_millis(1), max_delay: Duration::from_millis(5) }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy { max_retries: 5, base_delay: Duration::from_millis(100), max_delay: Duration::from_millis(1000) };
+        let ceilings: Vec<u64> = (1..=6).map(|a| policy.backoff_ceiling(a).as_millis() as u64).collect();
+        assert_eq!(ceilings, vec![100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(policy.backoff_ceiling(u32::MAX), policy.max_delay);
+        assert!((0..50).all(|_| policy.backoff(3) <= Duration::from_millis(400)));
+        assert_eq!(RetryPolicy::NONE.backoff(1), Duration::ZERO);
+        assert!(is_retryable_status(reqwest::StatusCode::BAD_GATEWAY) && is_retryable_status(reqwest::StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_retryable_status(reqwest::StatusCode::UNAUTHORIZED) && !is_retryable_status(reqwest::StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn test_retries_until_success() {
+        let responses = vec![(502, "{}".to_string()), (503, "{}".to_string()), (200, r#"{"ok":true}"#.to_string())];
+        let mut server = MockServer::start_sequence(responses.clone()).await;
+        let http = reqwest::Client::new();
+        let url = format!("{}/verify", server.url);
+        let response = send_idempotent(&fast(2), || http.get(&url)).await.unwrap();
+        assert_eq!(response.status(), 200);
+        for _ in 0..3 { assert!(server.next_request().await.starts_with("GET /verify ")); }
+
+        // Out of retries: the last failure is returned as is
+        let server = MockServer::start_sequence(responses).await;
+        let url = format!("{}/verify", server.url);
+        assert_eq!(send_idempotent(&fast(1), || http.get(&url)).await.unwrap().status(), 503);
+
+        let server = MockServer::start(404, "{}").await;
+        let url = format!("{}/verify", server.url);
+        assert_eq!(send_idempotent(&fast(3), || http.get(&url)).await.unwrap().status(), 404);
+    }
+}
@@ -0,0 +1,120 @@
+//! In-memory gateway for tests and local development
+use std::sync::Mutex;
+use async_trait::async_trait;
+use crate::domain::aggregates::PaymentError;
+use crate::domain::value_objects::card::CardDetails;
+use super::card_checks::CardChecks;
+use super::gateway::{CaptureSubmission, ChargeRequest, ChargeResponse, ChargeResult, PaymentGateway, RefundOutcome, RefundSubmission, Verification};
+
+/// Returns a fixed result for every charge and records the requests it saw. A charge
+/// sent with `capture: false` is only authorized. Refunds, voids and captures succeed
+/// unless told otherwise.
+pub struct MockGateway {
+    name: &'static str,
+    result: Result<ChargeResponse, PaymentError>,
+    verification: Mutex<Option<Verification>>,
+    requests: Mutex<Vec<ChargeRequest>>,
+    refund_outcome: Mutex<Result<Option<RefundOutcome>, PaymentError>>,
+    refunds: Mutex<Vec<RefundSubmission>>,
+    tokenized: Mutex<Vec<CardDetails>>,
+    void_outcome: Mutex<Result<bool, PaymentError>>,
+    voids: Mutex<Vec<String>>,
+    capture_outcome: Mutex<Result<(), PaymentError>>,
+    captures: Mutex<Vec<CaptureSubmission>>,
+}
+
+impl MockGateway {
+    pub fn new(result: Result<ChargeResult, PaymentError>) -> Self {
+        Self {
+            name: "mock",
+            result: result.map(Into::into),
+            verification: Mutex::new(None),
+            requests: Mutex::new(vec![]),
+            refund_outcome: Mutex::new(Ok(Some(RefundOutcome::Succeeded))),
+            refunds: Mutex::new(vec![]),
+            tokenized: Mutex::new(vec![]),
+            void_outcome: Mutex::new(Ok(true)),
+            voids: Mutex::new(vec![]),
+            capture_outcome: Mutex::new(Ok(())),
+            captures: Mutex::new(vec![]),
+        }
+    }
+
+    /// Registers under `name` instead of `mock`, for tests with more than one provider.
+    pub fn named(mut self, name: &'static str) -> Self {
+        self.name = name;
+        self
+    }
+
+    pub fn with_checks(mut self, checks: CardChecks) -> Self {
+        if let Ok(response) = &mut self.result { response.checks = checks; }
+        self
+    }
+
+    pub fn with_raw_response(mut self, raw: serde_json::Value) -> Self {
+        if let Ok(response) = &mut self.result { response.raw_response = Some(raw); }
+        self
+    }
+
+    /// What `verify` reports from now on; without one the gateway can't be queried.
+    pub fn set_verification(&self, verification: Verification) { *self.verification.lock().unwrap() = Some(verification); }
+
+    pub fn requests(&self) -> Vec<ChargeRequest> { self.requests.lock().unwrap().clone() }
+
+    /// What `refund` returns from now on.
+    pub fn set_refund_outcome(&self, outcome: Result<Option<RefundOutcome>, PaymentError>) { *self.refund_outcome.lock().unwrap() = outcome; }
+
+    pub fn refunds(&self) -> Vec<RefundSubmission> { self.refunds.lock().unwrap().clone() }
+
+    /// Cards passed to `tokenize`, which answers `tok_mock_<n>` for the nth.
+    pub fn tokenized(&self) -> Vec<CardDetails> { self.tokenized.lock().unwrap().clone() }
+
+    /// What `void` returns from now on.
+    pub fn set_void_outcome(&self, outcome: Result<bool, PaymentError>) { *self.void_outcome.lock().unwrap() = outcome; }
+
+    /// Provider references passed to `void`.
+    pub fn voids(&self) -> Vec<String> { self.voids.lock().unwrap().clone() }
+
+    /// What `capture` returns from now on.
+    pub fn set_capture_outcome(&self, outcome: Result<(), PaymentError>) { *self.capture_outcome.lock().unwrap() = outcome; }
+
+    pub fn captures(&self) -> Vec<CaptureSubmission> { self.captures.lock().unwrap().clone() }
+}
+
+#[async_trait]
+impl PaymentGateway for MockGateway {
+    fn name(&self) -> &'static str { self.name }
+    async fn charge(&self, request: &ChargeRequest) -> Result<ChargeResponse, PaymentError> {
+        self.requests.lock().unwrap().push(request.clone());
+        let mut response = self.result.clone()?;
+        if let (false, ChargeResult::Succeeded { provider_reference }) = (request.capture, &response.result) {
+            response.result = ChargeResult::Authorized { provider_reference: provider_reference.clone() };
+        }
+        Ok(response)
+    }
+
+    async fn verify(&self, _reference: &str) -> Result<Option<Verification>, PaymentError> { Ok(self.verification.lock().unwrap().clone()) }
+
+    async fn refund(&self, refund: &RefundSubmission) -> Result<Option<RefundOutcome>, PaymentError> {
+        self.refunds.lock().unwrap().push(refund.clone());
+        self.refund_outcome.lock().unwrap().clone()
+    }
+
+    async fn void(&self, provider_reference: &str) -> Result<bool, PaymentError> {
+        self.voids.lock().unwrap().push(provider_reference.to_string());
+        self.void_outcome.lock().unwrap().clone()
+    }
+
+    fn supports_manual_capture(&self) -> bool { true }
+
+    async fn capture(&self, capture: &CaptureSubmission) -> Result<(), PaymentError> {
+        self.captures.lock().unwrap().push(capture.clone());
+        self.capture_outcome.lock().unwrap().clone()
+    }
+
+    async fn tokenize(&self, card: &CardDetails, _email: &str) -> Result<Option<String>, PaymentError> {
+        let mut tokenized = self.tokenized.lock().unwrap();
+        tokenized.push(card.clone());
+        Ok(Some(format!("tok_mock_{}", tokenized.len())))
+    }
+}
@@ -0,0 +1,92 @@
+//! Payment provider integrations
+use serde_json::{Map, Value};
+
+pub mod allowlist;
+pub mod capabilities;
+pub mod card_checks;
+pub mod errors;
+pub mod flutterwave;
+pub mod gateway;
+pub mod http;
+pub mod mock;
+pub mod paystack;
+pub mod routing;
+pub mod webhook_event;
+pub use allowlist::{IpRange, WebhookAllowlist};
+pub use capabilities::{parse_provider_currencies, ProviderCapabilities};
+pub use card_checks::{AvsResult, CardChecks, CvvResult};
+pub use errors::{classify, ChargeFailure, FailureClass};
+pub use flutterwave::FlutterwaveGateway;
+pub use gateway::{CaptureSubmission, ChargeRequest, ChargeResponse, ChargeResult, NextAction, PaymentGateway, RefundOutcome, RefundSubmission, StubGateway, Verification, VerifiedStatus};
+pub use http::RetryPolicy;
+pub use mock::MockGateway;
+pub use paystack::PaystackGateway;
+pub use routing::{fails_over, ProviderRouter, Route};
+pub use webhook_event::WebhookEvent;
+
+/// Largest raw provider response (serialized bytes) kept for debugging.
+pub const MAX_RAW_RESPONSE_BYTES: usize = 16 * 1024;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Keys whose values are card data, PII, or credentials. Matched case-insensitively.
+const SENSITIVE_KEYS: &[&str] = &[
+    "access_code", "account_number", "authorization_code", "bin", "card_number", "cvc", "cvv", "cvv2",
+    "email", "exp_month", "exp_year", "expiry", "first_name", "last4", "last_four", "last_name", "name",
+    "number", "pan", "phone", "secret", "signature", "token",
+];
+
+/// Strips card/PII fields from a provider response and caps its size so it can be
+/// persisted for support debugging.
+pub fn redact_raw_response(raw: &Value) -> Value {
+    let redacted = redact(raw);
+    let size = redacted.to_string().len();
+    if size <= MAX_RAW_RESPONSE_BYTES { return redacted; }
+    serde_json::json!({ "truncated": true, "original_size": size })
+}
+
+fn redact(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(map.iter().map(|(k, v)| {
+            let v = if SENSITIVE_KEYS.contains(&k.to_ascii_lowercase().as_str()) { Value::String(REDACTED.into()) } else { redact(v) };
+            (k.clone(), v)
+        }).collect::<Map<_, _>>()),
+        Value::Array(items) => Value::Array(items.iter().map(redact).collect()),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_redacts_nested_card_and_pii_fields() {
+        let raw = json!({
+            "status": true,
+            "data": {
+                "reference": "TXN-1",
+                "authorization": { "bin": "408408", "last4": "4081", "exp_month": "12", "card_type": "visa" },
+                "customer": { "Email": "ada@example.com", "phone": "+2348000000000" },
+                "history": [{ "type": "action", "token": "tok_123" }]
+            }
+        });
+        let redacted = redact_raw_response(&raw);
+        assert_eq!(redacted["data"]["reference"], "TXN-1");
+        assert_eq!(redacted["data"]["authorization"]["card_type"], "visa");
+        assert_eq!(redacted["data"]["authorization"]["bin"], REDACTED);
+        assert_eq!(redacted["data"]["authorization"]["last4"], REDACTED);
+        assert_eq!(redacted["data"]["customer"]["Email"], REDACTED);
+        assert_eq!(redacted["data"]["history"][0]["token"], REDACTED);
+        assert!(!redacted.to_string().contains("4081"));
+    }
+
+    #[test]
+    fn test_caps_oversized_response() {
+        let raw = json!({ "blob": "x".repeat(MAX_RAW_RESPONSE_BYTES) });
+        let stored = redact_raw_response(&raw);
+        assert_eq!(stored["truncated"], true);
+        assert!(stored.to_string().len() < MAX_RAW_RESPONSE_BYTES);
+    }
+}
@@ -0,0 +1,523 @@
+//! Paystack hosted-checkout integration
+//!
+//! Charges are started with `POST /transaction/initialize`, which returns a checkout URL
+//! for the customer, and confirmed with `GET /transaction/verify/:reference`. Amounts are
+//! sent in the currency's minor units (kobo for NGN), in webhooks too. Refunds go to
+//! `POST /refund` and usually finish later, reported by a `refund.processed` webhook.
+//! Saved cards are exchanged at `POST /charge/tokenize` for a reusable authorization code.
+//! Webhooks are signed with the same secret key.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use crate::crypto;
+use crate::domain::aggregates::PaymentError;
+use crate::domain::value_objects::amount::DEFAULT_CURRENCY;
+use crate::domain::value_objects::card::CardDetails;
+use crate::domain::value_objects::{Money, ProviderErrorKind};
+use super::gateway::{ChargeRequest, ChargeResponse, ChargeResult, NextAction, PaymentGateway, RefundOutcome, RefundSubmission, Verification, VerifiedStatus};
+use super::http::{self, RetryPolicy};
+use super::webhook_event::{self, WebhookEvent};
+
+pub const API_BASE: &str = "https://api.paystack.co";
+
+/// Hex HMAC-SHA512 of the raw webhook body, keyed with the secret key.
+pub const SIGNATURE_HEADER: &str = "x-paystack-signature";
+
+/// Checks a webhook's `x-paystack-signature` against the exact bytes received, in
+/// constant time. The body must not be re-serialized first.
+pub fn verify_webhook_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    crypto::verify_hmac_sha512_hex(secret.as_bytes(), body, signature)
+}
+
+/// Maps a webhook body. Renewals are `invoice.update` events for a paid invoice;
+/// refunds name the charge in `transaction_reference`, and disputes in `transaction`.
+pub fn parse_webhook(payload: &serde_json::Value) -> WebhookEvent {
+    let data = &payload["data"];
+    let reference = data["reference"].as_str().map(str::to_string);
+    match (payload["event"].as_str(), reference) {
+        (Some("charge.success"), Some(reference)) => WebhookEvent::ChargeSucceeded {
+            reference,
+            amount: webhook_amount(data),
+            subscription_id: webhook_event::subscription_id(&data["metadata"]),
+        },
+        (Some("charge.failed"), Some(reference)) => WebhookEvent::ChargeFailed { reference, subscription_id: webhook_event::subscription_id(&data["metadata"]) },
+        (Some("refund.processed"), _) => match data["transaction_reference"].as_str() {
+            Some(reference) => WebhookEvent::RefundProcessed { reference: reference.to_string(), amount: webhook_amount(data) },
+            None => webhook_event::unknown(payload),
+        },
+        (Some("invoice.update"), _) if data["paid"] == true => match webhook_event::subscription_id(&data["metadata"]) {
+            Some(subscription_id) => WebhookEvent::SubscriptionRenewed { subscription_id, amount: webhook_amount(data) },
+            None => webhook_event::unknown(payload),
+        },
+        (Some("charge.dispute.create"), _) => match (data["transaction"]["reference"].as_str(), webhook_id(data)) {
+            (Some(reference), Some(dispute_id)) => WebhookEvent::DisputeOpened {
+                reference: reference.to_string(),
+                dispute_id,
+                amount: dispute_amount(data),
+                reason: data["category"].as_str().map(str::to_string),
+                evidence_due_by: data["due_at"].as_str().and_then(|d| DateTime::parse_from_rfc3339(d).ok()).map(|d| d.with_timezone(&Utc)),
+            },
+            _ => webhook_event::unknown(payload),
+        },
+        // `declined` means the cardholder's claim was turned down, so the charge stands
+        (Some("charge.dispute.resolve"), _) => match (data["resolution"].as_str(), webhook_id(data)) {
+            (Some(resolution @ ("declined" | "merchant-accepted")), Some(dispute_id)) =>
+                WebhookEvent::DisputeResolved { dispute_id, won: resolution == "declined" },
+            _ => webhook_event::unknown(payload),
+        },
+        _ => webhook_event::unknown(payload),
+    }
+}
+
+/// `data.id`, which Paystack sends as a number.
+fn webhook_id(data: &serde_json::Value) -> Option<String> {
+    match &data["id"] {
+        serde_json::Value::Number(id) => Some(id.to_string()),
+        serde_json::Value::String(id) if !id.is_empty() => Some(id.clone()),
+        _ => None,
+    }
+}
+
+/// The disputed amount: `refund_amount`, falling back to the whole charge.
+fn dispute_amount(data: &serde_json::Value) -> Option<Money> {
+    let currency = data["currency"].as_str().unwrap_or(DEFAULT_CURRENCY);
+    data["refund_amount"].as_i64().or_else(|| data["transaction"]["amount"].as_i64())
+        .and_then(|minor| Money::from_minor_units(minor, currency).ok())
+}
+
+/// `amount` in minor units of `currency`, which Paystack omits for NGN-only accounts.
+fn webhook_amount(data: &serde_json::Value) -> Option<Money> {
+    let currency = data["currency"].as_str().unwrap_or(DEFAULT_CURRENCY);
+    data["amount"].as_i64().and_then(|minor| Money::from_minor_units(minor, currency).ok())
+}
+
+/// The `data` of a successful initialize call.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct InitializedTransaction {
+    pub authorization_url: String,
+    pub access_code: String,
+    pub reference: String,
+}
+
+/// The part of a verify response's `data` we act on. `url` is where to send the
+/// customer when the status is `open_url`.
+#[derive(Deserialize)]
+struct VerifiedTransaction { status: String, #[serde(default)] url: Option<String> }
+
+/// Maps Paystack's transaction `status`. Anything not final (`ongoing`, `pending`,
+/// `processing`, `queued`, ...) is still pending.
+pub fn verified_status(status: &str) -> VerifiedStatus {
+    match status {
+        "success" => VerifiedStatus::Succeeded,
+        "failed" | "reversed" => VerifiedStatus::Failed,
+        "abandoned" => VerifiedStatus::Abandoned,
+        _ => VerifiedStatus::Pending,
+    }
+}
+
+/// The part of a refund response's `data` we act on.
+#[derive(Deserialize)]
+struct CreatedRefund { status: String }
+
+/// The part of a tokenize response's `data` we act on.
+#[derive(Deserialize)]
+struct TokenizedCard { authorization_code: String, #[serde(default)] reusable: bool }
+
+/// Maps Paystack's refund `status`; `pending` and `processing` await the webhook.
+pub fn refund_outcome(status: &str) -> RefundOutcome {
+    match status {
+        "processed" => RefundOutcome::Succeeded,
+        "failed" => RefundOutcome::Failed,
+        _ => RefundOutcome::Pending,
+    }
+}
+
+/// Paystack wraps every response as `{ status, message, data }`.
+#[derive(Deserialize)]
+struct Envelope<T> {
+    status: bool,
+    #[serde(default)]
+    message: String,
+    data: Option<T>,
+}
+
+pub struct PaystackGateway {
+    http: reqwest::Client,
+    secret: String,
+    base_url: String,
+    retry: RetryPolicy,
+}
+
+impl PaystackGateway {
+    pub fn new(http: reqwest::Client, secret: impl Into<String>) -> Self {
+        Self { http, secret: secret.into(), base_url: API_BASE.to_string(), retry: RetryPolicy::default() }
+    }
+
+    /// Points the client somewhere other than the live API, e.g. a test server.
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// How verify calls are retried. Charges are never retried.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Starts a hosted checkout for `request`. Returns the parsed `data` and the raw body.
+    pub async fn initialize_transaction(&self, request: &ChargeRequest) -> Result<(InitializedTransaction, serde_json::Value), PaymentError> {
+        let mut body = serde_json::json!({
+            "reference": request.reference,
+            "amount": request.amount.to_minor_units()?,
+            "currency": request.amount.currency,
+            "email": request.email,
+            "metadata": request.metadata,
+        });
+        if let Some(callback_url) = &request.callback_url {
+            body["callback_url"] = callback_url.clone().into();
+        }
+        let response = self.http.post(format!("{}/transaction/initialize", self.base_url))
+            .bearer_auth(&self.secret)
+            .json(&body)
+            .send()
+            .await
+            .map_err(transport_error)?;
+        read_envelope(response).await
+    }
+
+    pub async fn verify_transaction(&self, reference: &str) -> Result<Verification, PaymentError> {
+        let mut url = reqwest::Url::parse(&format!("{}/transaction/verify/", self.base_url))
+            .map_err(|e| PaymentError::ProviderError { kind: ProviderErrorKind::InvalidRequest, message: format!("paystack: {}", e) })?;
+        url.path_segments_mut().map_err(|_| PaymentError::ProviderError { kind: ProviderErrorKind::InvalidRequest, message: "paystack: invalid base URL".into() })?
+            .pop_if_empty()
+            .push(reference);
+        let response = http::send_idempotent(&self.retry, || self.http.get(url.clone()).bearer_auth(&self.secret)).await.map_err(transport_error)?;
+        let (data, raw): (VerifiedTransaction, _) = read_envelope(response).await?;
+        let status = match (data.status.as_str(), data.url) {
+            ("open_url", Some(url)) => VerifiedStatus::RequiresAction(NextAction::RedirectToUrl { url }),
+            (status, _) => verified_status(status),
+        };
+        Ok(Verification { status, raw_response: Some(raw) })
+    }
+
+    /// Refunds `refund.amount` of the charge with our reference. Like charges, refunds
+    /// are never retried.
+    pub async fn create_refund(&self, refund: &RefundSubmission) -> Result<(RefundOutcome, serde_json::Value), PaymentError> {
+        let mut body = serde_json::json!({
+            "transaction": refund.charge_reference,
+            "amount": refund.amount.to_minor_units()?,
+            "currency": refund.amount.currency,
+        });
+        if let Some(reason) = &refund.reason {
+            body["merchant_note"] = reason.clone().into();
+        }
+        let response = self.http.post(format!("{}/refund", self.base_url))
+            .bearer_auth(&self.secret)
+            .json(&body)
+            .send()
+            .await
+            .map_err(transport_error)?;
+        let (data, raw): (CreatedRefund, _) = read_envelope(response).await?;
+        Ok((refund_outcome(&data.status), raw))
+    }
+
+    /// Exchanges `card` for an authorization code that later charges can reuse. Never
+    /// retried; a code Paystack marks as not reusable is refused.
+    pub async fn tokenize_card(&self, card: &CardDetails, email: &str) -> Result<String, PaymentError> {
+        let body = serde_json::json!({
+            "email": email,
+            "card": {
+                "number": card.number.expose(),
+                "cvv": card.cvc.expose(),
+                "expiry_month": format!("{:02}", card.exp_month),
+                "expiry_year": format!("{:02}", card.exp_year % 100),
+            },
+        });
+        let response = self.http.post(format!("{}/charge/tokenize", self.base_url))
+            .bearer_auth(&self.secret)
+            .json(&body)
+            .send()
+            .await
+            .map_err(transport_error)?;
+        let (data, _): (TokenizedCard, _) = read_envelope(response).await?;
+        if !data.reusable {
+            return Err(PaymentError::ProviderError { kind: ProviderErrorKind::InvalidResponse, message: "paystack: card can't be saved for reuse".into() });
+        }
+        Ok(data.authorization_code)
+    }
+}
+
+#[async_trait]
+impl PaymentGateway for PaystackGateway {
+    fn name(&self) -> &'static str { "paystack" }
+    async fn charge(&self, request: &ChargeRequest) -> Result<ChargeResponse, PaymentError> {
+        let (initialized, raw) = self.initialize_transaction(request).await?;
+        let mut response = ChargeResponse::from(ChargeResult::Checkout {
+            authorization_url: initialized.authorization_url,
+            provider_reference: Some(initialized.reference),
+        });
+        response.raw_response = Some(raw);
+        Ok(response)
+    }
+
+    async fn verify(&self, reference: &str) -> Result<Option<Verification>, PaymentError> {
+        self.verify_transaction(reference).await.map(Some)
+    }
+
+    async fn refund(&self, refund: &RefundSubmission) -> Result<Option<RefundOutcome>, PaymentError> {
+        self.create_refund(refund).await.map(|(outcome, _)| Some(outcome))
+    }
+
+    async fn tokenize(&self, card: &CardDetails, email: &str) -> Result<Option<String>, PaymentError> {
+        self.tokenize_card(card, email).await.map(Some)
+    }
+}
+
+fn transport_error(e: reqwest::Error) -> PaymentError {
+    let kind = if e.is_timeout() { ProviderErrorKind::Timeout } else { ProviderErrorKind::Unavailable };
+    PaymentError::ProviderError { kind, message: format!("paystack: {}", e) }
+}
+
+/// Unwraps a Paystack response. Non-2xx statuses and `status: false` become
+/// `ProviderError`s carrying Paystack's message.
+async fn read_envelope<T: serde::de::DeserializeOwned>(response: reqwest::Response) -> Result<(T, serde_json::Value), PaymentError> {
+    let status = response.status();
+    let raw: serde_json::Value = response.json().await.map_err(|e| PaymentError::ProviderError {
+        kind: if status.is_success() { ProviderErrorKind::InvalidResponse } else { ProviderErrorKind::from_http_status(status.as_u16()) },
+        message: format!("paystack: unreadable response ({}): {}", status, e),
+    })?;
+    let envelope: Envelope<T> = serde_json::from_value(raw.clone()).map_err(|e| PaymentError::ProviderError {
+        kind: ProviderErrorKind::InvalidResponse,
+        message: format!("paystack: unexpected response shape: {}", e),
+    })?;
+    if !status.is_success() {
+        return Err(PaymentError::ProviderError { kind: ProviderErrorKind::from_http_status(status.as_u16()), message: format!("paystack: {} ({})", envelope.message, status) });
+    }
+    match envelope.data {
+        Some(data) if envelope.status => Ok((data, raw)),
+        _ => Err(PaymentError::ProviderError { kind: ProviderErrorKind::InvalidResponse, message: format!("paystack: {}", envelope.message) }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::value_objects::Money;
+    use crate::test_support::{self, MockServer};
+
+    fn charge() -> ChargeRequest {
+        ChargeRequest {
+            reference: "TXN-1".into(),
+            amount: Money::from_minor_units(500_000, "NGN").unwrap(),
+            email: "ada@example.com".into(),
+            callback_url: Some("https://shop.example/done".into()),
+            metadata: serde_json::json!({ "order": 42 }),
+            statement_descriptor: None,
+            idempotency_key: "chg_1".into(),
+            capture: true,
+        }
+    }
+
+    #[test]
+    fn test_webhook_signature() {
+        let secret = "sk_test_4ecd0b5fd6a2b5a2";
+        let body = br#"{"event":"charge.success","data":{"reference":"TXN-1","amount":500000}}"#;
+        let signature = "3b357b4cdc0c5d6349f0c504d593ac1ebdaeba0d8da75a2a9c347d48052acaf6acc9497ffbfd804219f99ccba2c4a15be6a67e892f3894f7f12d2940eb48e579";
+        assert!(verify_webhook_signature(secret, body, signature));
+        assert!(verify_webhook_signature(secret, body, &signature.to_uppercase()));
+        assert!(!verify_webhook_signature("sk_test_other", body, signature));
+        assert!(!verify_webhook_signature(secret, br#"{"event":"charge.success","data":{"reference":"TXN-1","amount":5000000}}"#, signature));
+        assert!(!verify_webhook_signature(secret, body, &signature[..64]));
+        assert!(!verify_webhook_signature(secret, body, ""));
+    }
+
+    #[test]
+    fn test_parse_webhook() {
+        let subscription = "0190f3c2-7a4e-7d1b-9c2a-5b8e1f0a6d42".parse().unwrap();
+        let charge = serde_json::json!({
+            "event": "charge.success",
+            "data": { "id": 302961, "reference": "TXN-1", "amount": 500_000, "currency": "NGN", "status": "success",
+                      "metadata": { "subscription_id": "0190f3c2-7a4e-7d1b-9c2a-5b8e1f0a6d42" } }
+        });
+        assert_eq!(parse_webhook(&charge), WebhookEvent::ChargeSucceeded {
+            reference: "TXN-1".into(),
+            amount: Some(Money::from_minor_units(500_000, "NGN").unwrap()),
+            subscription_id: Some(subscription),
+        });
+
+        let failed = serde_json::json!({ "event": "charge.failed", "data": { "reference": "TXN-2", "metadata": "" } });
+        assert_eq!(parse_webhook(&failed), WebhookEvent::ChargeFailed { reference: "TXN-2".into(), subscription_id: None });
+
+        let refund = serde_json::json!({
+            "event": "refund.processed",
+            "data": { "status": "processed", "transaction_reference": "TXN-1", "amount": 20_000, "currency": "GHS" }
+        });
+        assert_eq!(parse_webhook(&refund), WebhookEvent::RefundProcessed { reference: "TXN-1".into(), amount: Some(Money::from_minor_units(20_000, "GHS").unwrap()) });
+
+        let invoice = |paid: bool| serde_json::json!({
+            "event": "invoice.update",
+            "data": { "paid": paid, "amount": 250_000, "metadata": { "subscription_id": "0190f3c2-7a4e-7d1b-9c2a-5b8e1f0a6d42" } }
+        });
+        assert_eq!(parse_webhook(&invoice(true)), WebhookEvent::SubscriptionRenewed { subscription_id: subscription, amount: Some(Money::from_minor_units(250_000, "NGN").unwrap()) });
+        assert_eq!(parse_webhook(&invoice(false)), WebhookEvent::Unknown { event: "invoice.update".into() });
+
+        let dispute = serde_json::json!({
+            "event": "charge.dispute.create",
+            "data": { "id": 1002, "refund_amount": 500_000, "currency": "NGN", "category": "fraud", "due_at": "2026-11-01T12:00:00.000Z",
+                      "transaction": { "reference": "TXN-1", "amount": 500_000 } }
+        });
+        assert_eq!(parse_webhook(&dispute), WebhookEvent::DisputeOpened {
+            reference: "TXN-1".into(),
+            dispute_id: "1002".into(),
+            amount: Some(Money::from_minor_units(500_000, "NGN").unwrap()),
+            reason: Some("fraud".into()),
+            evidence_due_by: Some("2026-11-01T12:00:00Z".parse().unwrap()),
+        });
+        let resolved = |resolution: &str| serde_json::json!({ "event": "charge.dispute.resolve", "data": { "id": 1002, "resolution": resolution } });
+        assert_eq!(parse_webhook(&resolved("declined")), WebhookEvent::DisputeResolved { dispute_id: "1002".into(), won: true });
+        assert_eq!(parse_webhook(&resolved("merchant-accepted")), WebhookEvent::DisputeResolved { dispute_id: "1002".into(), won: false });
+        assert_eq!(parse_webhook(&resolved("")), WebhookEvent::Unknown { event: "charge.dispute.resolve".into() });
+
+        assert_eq!(parse_webhook(&serde_json::json!({ "event": "transfer.success", "data": { "reference": "TRF-1" } })), WebhookEvent::Unknown { event: "transfer.success".into() });
+        assert_eq!(parse_webhook(&serde_json::json!({ "event": "charge.success", "data": {} })), WebhookEvent::Unknown { event: "charge.success".into() });
+    }
+
+    #[tokio::test]
+    async fn test_initialize_transaction() {
+        let body = r#"{"status":true,"message":"Authorization URL created","data":{"authorization_url":"https://checkout.paystack.com/0peioxfhpn","access_code":"0peioxfhpn","reference":"TXN-1"}}"#;
+        let mut server = MockServer::start(200, body).await;
+        let gateway = PaystackGateway::new(reqwest::Client::new(), "sk_test_abc").with_base_url(&server.url);
+
+        let response = gateway.charge(&charge()).await.unwrap();
+        assert_eq!(response.result, ChargeResult::Checkout {
+            authorization_url: "https://checkout.paystack.com/0peioxfhpn".into(),
+            provider_reference: Some("TXN-1".into()),
+        });
+        assert_eq!(response.raw_response.unwrap()["data"]["access_code"], "0peioxfhpn");
+
+        let request = server.next_request().await;
+        assert!(request.starts_with("POST /transaction/initialize "));
+        assert_eq!(test_support::header(&request, "authorization"), Some("Bearer sk_test_abc"));
+        let sent: serde_json::Value = serde_json::from_str(test_support::body(&request)).unwrap();
+        assert_eq!(sent["amount"], 500_000);
+        assert_eq!(sent["currency"], "NGN");
+        assert_eq!(sent["callback_url"], "https://shop.example/done");
+    }
+
+    #[tokio::test]
+    async fn test_verify_transaction() {
+        let verify_body = |status: &str| format!(r#"{{"status":true,"message":"Verification successful","data":{{"status":"{}","reference":"TXN 1/a","amount":500000}}}}"#, status);
+        let mut responses: Vec<_> = ["success", "abandoned", "failed", "ongoing"].iter().map(|s| (200, verify_body(s))).collect();
+        responses.push((200, r#"{"status":true,"message":"Verification successful","data":{"status":"open_url","url":"https://standard.paystack.co/3ds/abc"}}"#.to_string()));
+        let mut server = MockServer::start_sequence(responses).await;
+        let gateway = PaystackGateway::new(reqwest::Client::new(), "sk_test_abc").with_base_url(&server.url);
+
+        let verified = gateway.verify("TXN 1/a").await.unwrap().unwrap();
+        assert_eq!(verified.status, VerifiedStatus::Succeeded);
+        assert_eq!(verified.raw_response.unwrap()["data"]["amount"], 500000);
+        let request = server.next_request().await;
+        assert!(request.starts_with("GET /transaction/verify/TXN%201%2Fa "), "{request}");
+        assert_eq!(test_support::header(&request, "authorization"), Some("Bearer sk_test_abc"));
+
+        assert_eq!(gateway.verify("TXN-1").await.unwrap().unwrap().status, VerifiedStatus::Abandoned);
+        assert_eq!(gateway.verify("TXN-1").await.unwrap().unwrap().status, VerifiedStatus::Failed);
+        assert_eq!(gateway.verify("TXN-1").await.unwrap().unwrap().status, VerifiedStatus::Pending);
+        assert_eq!(gateway.verify("TXN-1").await.unwrap().unwrap().status,
+                   VerifiedStatus::RequiresAction(NextAction::RedirectToUrl { url: "https://standard.paystack.co/3ds/abc".into() }));
+        assert_eq!(VerifiedStatus::Abandoned.transaction_status(), Some(crate::domain::value_objects::TransactionStatus::Cancelled));
+        assert_eq!(VerifiedStatus::Pending.transaction_status(), None);
+    }
+
+    #[tokio::test]
+    async fn test_create_refund() {
+        let refund_body = |status: &str| format!(r#"{{"status":true,"message":"Refund has been queued for processing","data":{{"id":3018284,"status":"{}","amount":20000}}}}"#, status);
+        let mut server = MockServer::start_sequence(["pending", "processed", "failed"].iter().map(|s| (200, refund_body(s))).collect()).await;
+        let gateway = PaystackGateway::new(reqwest::Client::new(), "sk_test_abc").with_base_url(&server.url);
+        let refund = RefundSubmission {
+            charge_reference: "TXN-1".into(),
+            provider_reference: None,
+            amount: Money::from_minor_units(20_000, "NGN").unwrap(),
+            reason: Some("duplicate".into()),
+        };
+
+        let (outcome, raw) = gateway.create_refund(&refund).await.unwrap();
+        assert_eq!(outcome, RefundOutcome::Pending);
+        assert_eq!(raw["data"]["id"], 3018284);
+        let request = server.next_request().await;
+        assert!(request.starts_with("POST /refund "), "{request}");
+        assert_eq!(test_support::header(&request, "authorization"), Some("Bearer sk_test_abc"));
+        let sent: serde_json::Value = serde_json::from_str(test_support::body(&request)).unwrap();
+        assert_eq!(sent, serde_json::json!({ "transaction": "TXN-1", "amount": 20_000, "currency": "NGN", "merchant_note": "duplicate" }));
+
+        assert_eq!(gateway.refund(&refund).await.unwrap(), Some(RefundOutcome::Succeeded));
+        assert_eq!(gateway.refund(&refund).await.unwrap(), Some(RefundOutcome::Failed));
+    }
+
+    #[tokio::test]
+    async fn test_tokenize_card() {
+        let tokenized = |reusable: bool| format!(r#"{{"status":true,"message":"Tokenization successful","data":{{"authorization_code":"AUTH_8dfhjjdt","card_type":"visa","last4":"4081","reusable":{}}}}}"#, reusable);
+        let mut server = MockServer::start_sequence(vec![(200, tokenized(true)), (200, tokenized(false))]).await;
+        let gateway = PaystackGateway::new(reqwest::Client::new(), "sk_test_abc").with_base_url(&server.url);
+        let card: CardDetails = serde_json::from_value(serde_json::json!({ "number": "4084 0840 8408 4081", "exp_month": 3, "exp_year": 2031, "cvc": "408" })).unwrap();
+
+        assert_eq!(gateway.tokenize(&card, "ada@example.com").await.unwrap().as_deref(), Some("AUTH_8dfhjjdt"));
+        let request = server.next_request().await;
+        assert!(request.starts_with("POST /charge/tokenize "), "{request}");
+        assert_eq!(test_support::header(&request, "authorization"), Some("Bearer sk_test_abc"));
+        let sent: serde_json::Value = serde_json::from_str(test_support::body(&request)).unwrap();
+        assert_eq!(sent, serde_json::json!({
+            "email": "ada@example.com",
+            "card": { "number": "4084084084084081", "cvv": "408", "expiry_month": "03", "expiry_year": "31" },
+        }));
+
+        assert!(matches!(gateway.tokenize(&card, "ada@example.com").await, Err(PaymentError::ProviderError { kind: ProviderErrorKind::InvalidResponse, .. })));
+    }
+
+    #[tokio::test]
+    async fn test_verify_retries_transient_failures() {
+        let verified = r#"{"status":true,"message":"Verification successful","data":{"status":"success"}}"#;
+        let responses = vec![
+            (502, "<html>Bad Gateway</html>".to_string()),
+            (503, r#"{"status":false,"message":"Service unavailable"}"#.to_string()),
+            (200, verified.to_string()),
+        ];
+        let retry = RetryPolicy { max_retries: 2, base_delay: std::time::Duration::from_millis(1), max_delay: std::time::Duration::from_millis(5) };
+        let mut server = MockServer::start_sequence(responses.clone()).await;
+        let gateway = PaystackGateway::new(reqwest::Client::new(), "sk_test_abc").with_base_url(&server.url).with_retry(retry);
+        assert_eq!(gateway.verify("TXN-1").await.unwrap().unwrap().status, VerifiedStatus::Succeeded);
+        for _ in 0..3 {
+            assert!(server.next_request().await.starts_with("GET /transaction/verify/TXN-1 "));
+        }
+
+        // A charge is sent once: the provider's second answer is still waiting afterwards
+        let mut server = MockServer::start_sequence(responses).await;
+        let gateway = PaystackGateway::new(reqwest::Client::new(), "sk_test_abc").with_base_url(&server.url).with_retry(retry);
+        assert!(matches!(gateway.charge(&charge()).await, Err(PaymentError::ProviderError { kind: ProviderErrorKind::Unavailable, .. })));
+        assert!(server.next_request().await.starts_with("POST /transaction/initialize "));
+        let once = PaystackGateway::new(reqwest::Client::new(), "sk_test_abc").with_base_url(&server.url).with_retry(RetryPolicy::NONE);
+        assert_eq!(once.verify("TXN-1").await.unwrap_err(), PaymentError::ProviderError {
+            kind: ProviderErrorKind::Unavailable,
+            message: "paystack: Service unavailable (503 Service Unavailable)".into(),
+        });
+    }
+
+    #[tokio::test]
+    async fn test_http_errors_become_provider_errors() {
+        let server = MockServer::start(401, r#"{"status":false,"message":"Invalid key"}"#).await;
+        let gateway = PaystackGateway::new(reqwest::Client::new(), "sk_bad").with_base_url(&server.url);
+        assert_eq!(gateway.charge(&charge()).await.unwrap_err(), PaymentError::ProviderError {
+            kind: ProviderErrorKind::Authentication,
+            message: "paystack: Invalid key (401 Unauthorized)".into(),
+        });
+
+        let server = MockServer::start(502, "<html>Bad Gateway</html>").await;
+        let gateway = PaystackGateway::new(reqwest::Client::new(), "sk_test").with_base_url(&server.url);
+        assert!(matches!(gateway.charge(&charge()).await, Err(PaymentError::ProviderError { kind: ProviderErrorKind::Unavailable, .. })));
+
+        let server = MockServer::start(200, r#"{"status":false,"message":"Duplicate Transaction Reference"}"#).await;
+        let gateway = PaystackGateway::new(reqwest::Client::new(), "sk_test").with_base_url(&server.url);
+        assert!(matches!(gateway.charge(&charge()).await, Err(PaymentError::ProviderError { kind: ProviderErrorKind::InvalidResponse, .. })));
+    }
+}
@@ -0,0 +1,155 @@
+//! Which provider takes a payment
+//!
+//! Rules are tried in order and the first whose currency and amount match gives the
+//! providers to try, best first. A `*` rule matches every currency and is the usual last
+//! entry. A provider that isn't configured, or doesn't accept the currency, is skipped in
+//! favour of the next one, and so is one that turns out to be down when charged.
+
+use crate::domain::aggregates::PaymentError;
+use crate::domain::value_objects::ProviderErrorKind;
+
+/// Payments in `currency` (any, when `None`) whose amount in minor units lies in
+/// `min..=max` go to `providers`, in order of preference.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Route {
+    pub currency: Option<String>,
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    pub providers: Vec<String>,
+}
+
+impl Route {
+    fn matches(&self, currency: &str, minor_units: i64) -> bool {
+        self.currency.as_deref().is_none_or(|c| c.eq_ignore_ascii_case(currency))
+            && self.min.is_none_or(|min| minor_units >= min)
+            && self.max.is_none_or(|max| minor_units <= max)
+    }
+}
+
+/// An empty router has no opinion, and payments go to the default provider.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProviderRouter { routes: Vec<Route> }
+
+impl ProviderRouter {
+    pub fn new(routes: Vec<Route>) -> Self { Self { routes } }
+
+    /// Parses `NGN=paystack,flutterwave;USD:100-500000=flutterwave;*=paystack`. Either
+    /// amount bound may be left out (`USD:500001-`). Unlike the other config lists a
+    /// malformed rule is an error, since skipping it would quietly send money elsewhere.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut routes = Vec::new();
+        for entry in spec.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let (matcher, providers) = entry.split_once('=').ok_or_else(|| format!("provider route '{}' has no '='", entry))?;
+            let (currency, range) = match matcher.split_once(':') {
+                Some((currency, range)) => (currency.trim(), Some(range.trim())),
+                None => (matcher.trim(), None),
+            };
+            let (min, max) = match range {
+                None => (None, None),
+                Some(range) => {
+                    let (min, max) = range.split_once('-').ok_or_else(|| format!("provider route '{}' needs an amount range like 100-5000", entry))?;
+                    let bound = |b: &str| (!b.trim().is_empty()).then(|| b.trim().parse::<i64>()).transpose()
+                        .map_err(|_| format!("provider route '{}' has a bad amount", entry));
+                    (bound(min)?, bound(max)?)
+                }
+            };
+            let providers: Vec<String> = providers.split(',').map(|p| p.trim().to_ascii_lowercase()).filter(|p| !p.is_empty()).collect();
+            if providers.is_empty() { return Err(format!("provider route '{}' names no provider", entry)); }
+            let currency = match currency {
+                "*" => None,
+                c if c.len() == 3 => Some(c.to_ascii_uppercase()),
+                _ => return Err(format!("provider route '{}' needs a currency code or '*'", entry)),
+            };
+            routes.push(Route { currency, min, max, providers });
+        }
+        Ok(Self { routes })
+    }
+
+    /// The providers to try for a payment, best first: the first matching rule's, then any
+    /// `*` rule's as a fallback. Empty when no rule matches.
+    pub fn candidates(&self, currency: &str, minor_units: i64) -> Vec<&str> {
+        let matched = self.routes.iter().find(|r| r.matches(currency, minor_units));
+        let fallback = self.routes.iter().filter(|r| r.currency.is_none() && r.matches(currency, minor_units));
+        let mut candidates: Vec<&str> = Vec::new();
+        for provider in matched.into_iter().chain(fallback).flat_map(|r| r.providers.iter()) {
+            if !candidates.contains(&provider.as_str()) { candidates.push(provider); }
+        }
+        candidates
+    }
+
+    /// The candidates `usable` accepts, best first. `None` when no rule matched, so the
+    /// caller uses its default, and `Some(Err)` with the candidates when none is usable.
+    pub fn select(&self, currency: &str, minor_units: i64, usable: impl Fn(&str) -> bool) -> Option<Result<Vec<String>, Vec<String>>> {
+        let candidates = self.candidates(currency, minor_units);
+        if candidates.is_empty() { return None; }
+        let selected: Vec<String> = candidates.iter().filter(|p| usable(p)).map(|p| p.to_string()).collect();
+        Some(if selected.is_empty() { Err(candidates.iter().map(|p| p.to_string()).collect()) } else { Ok(selected) })
+    }
+
+    pub fn is_empty(&self) -> bool { self.routes.is_empty() }
+}
+
+/// Whether a failed charge may go to the next provider. Only a provider that was down or
+/// turned us away qualifies; after a timeout the charge may still have gone through, and a
+/// decline is the card's answer, which another provider shouldn't be asked to overrule.
+pub fn fails_over(error: &PaymentError) -> bool {
+    matches!(error, PaymentError::ProviderError { kind: ProviderErrorKind::Unavailable | ProviderErrorKind::RateLimited, .. })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::value_objects::DeclineCode;
+
+    #[test]
+    fn test_selection() {
+        let router = ProviderRouter::parse("NGN=paystack,flutterwave; USD:-99999=flutterwave; usd:100000-=stripe,flutterwave; *=paystack").unwrap();
+        let all = |_: &str| true;
+        for (currency, minor, expected) in [
+            ("NGN", 500_000, "paystack"),
+            ("ngn", 1, "paystack"),
+            ("USD", 99_999, "flutterwave"),
+            ("USD", 100_000, "stripe"),
+            ("USD", 10_000_000, "stripe"),
+            ("GHS", 5_000, "paystack"),
+        ] {
+            let selected = router.select(currency, minor, all).unwrap().unwrap();
+            assert_eq!(selected[0], expected, "{} {}", currency, minor);
+        }
+
+        // Fallback order: rule's providers, then the catch-all
+        assert_eq!(router.candidates("NGN", 100), vec!["paystack", "flutterwave"]);
+        assert_eq!(router.candidates("USD", 200_000), vec!["stripe", "flutterwave", "paystack"]);
+        let no_stripe = |p: &str| p != "stripe";
+        assert_eq!(router.select("USD", 200_000, no_stripe), Some(Ok(vec!["flutterwave".to_string(), "paystack".to_string()])));
+        assert_eq!(router.select("NGN", 100, |p| p == "flutterwave"), Some(Ok(vec!["flutterwave".to_string()])));
+        assert_eq!(router.select("GHS", 100, |_| false), Some(Err(vec!["paystack".to_string()])));
+
+        // Without a catch-all an unrouted currency is left to the caller's default
+        let narrow = ProviderRouter::parse("NGN=paystack").unwrap();
+        assert_eq!(narrow.select("USD", 100, all), None);
+        assert_eq!(ProviderRouter::default().select("NGN", 100, all), None);
+    }
+
+    #[test]
+    fn test_fails_over() {
+        let provider = |kind| PaymentError::ProviderError { kind, message: "HTTP 503".into() };
+        assert!(fails_over(&provider(ProviderErrorKind::Unavailable)));
+        assert!(fails_over(&provider(ProviderErrorKind::RateLimited)));
+        assert!(!fails_over(&provider(ProviderErrorKind::Timeout)));
+        assert!(!fails_over(&provider(ProviderErrorKind::Authentication)));
+        assert!(!fails_over(&PaymentError::Declined(DeclineCode::InsufficientFunds)));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(ProviderRouter::parse("").unwrap().is_empty());
+        for bad in ["NGN", "NGN=", "NAIRA=paystack", "USD:abc-=stripe", "USD:100=stripe"] {
+            assert!(ProviderRouter::parse(bad).is_err(), "{:?}", bad);
+        }
+        assert_eq!(
+            ProviderRouter::parse("USD:100-=Stripe").unwrap(),
+            ProviderRouter::new(vec![Route { currency: Some("USD".into()), min: Some(100), max: None, providers: vec!["stripe".into()] }])
+        );
+    }
+}
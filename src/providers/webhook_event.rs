@@ -0,0 +1,99 @@
+//! Incoming provider webhooks, reduced to the events we act on
+//!
+//! Each provider names and shapes its events differently; `parse` maps them onto one
+//! enum so the handler can dispatch without knowing which provider sent the body.
+//! Anything we don't act on is `Unknown` and should still be acknowledged, or the
+//! provider keeps retrying it.
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use uuid::Uuid;
+use crate::crypto;
+use crate::domain::value_objects::Money;
+use super::{flutterwave, paystack};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WebhookEvent {
+    ChargeSucceeded { reference: String, amount: Option<Money>, subscription_id: Option<Uuid> },
+    ChargeFailed { reference: String, subscription_id: Option<Uuid> },
+    /// A refund of the charge `reference` went through on the provider's side.
+    RefundProcessed { reference: String, amount: Option<Money> },
+    /// A provider-run subscription billed our subscription successfully.
+    SubscriptionRenewed { subscription_id: Uuid, amount: Option<Money> },
+    /// The cardholder disputed the charge `reference`; `dispute_id` is the provider's.
+    DisputeOpened { reference: String, dispute_id: String, amount: Option<Money>, reason: Option<String>, evidence_due_by: Option<DateTime<Utc>> },
+    /// The provider closed its dispute `dispute_id` for (`won`) or against us.
+    DisputeResolved { dispute_id: String, won: bool },
+    Unknown { event: String },
+}
+
+impl WebhookEvent {
+    /// The charge this event is about, if any.
+    pub fn reference(&self) -> Option<&str> {
+        match self {
+            Self::ChargeSucceeded { reference, .. } | Self::ChargeFailed { reference, .. } | Self::RefundProcessed { reference, .. }
+                | Self::DisputeOpened { reference, .. } => Some(reference),
+            Self::SubscriptionRenewed { .. } | Self::DisputeResolved { .. } | Self::Unknown { .. } => None,
+        }
+    }
+}
+
+/// Parses a webhook body sent by `provider` (a gateway name). Events from providers we
+/// don't parse are `Unknown`.
+pub fn parse(provider: &str, payload: &Value) -> WebhookEvent {
+    match provider {
+        "paystack" => paystack::parse_webhook(payload),
+        "flutterwave" => flutterwave::parse_webhook(payload),
+        _ => unknown(payload),
+    }
+}
+
+/// A key that is the same every time the provider redelivers this event. `data.id` is
+/// the id of the object the event is about, shared by every event about it, so it is
+/// qualified by the event name. Bodies without an id fall back to a digest of the exact
+/// bytes received.
+pub fn event_id(payload: &Value, body: &[u8]) -> String {
+    let event = payload["event"].as_str().unwrap_or_default();
+    match &payload["data"]["id"] {
+        Value::Number(id) => format!("{}:{}", event, id),
+        Value::String(id) if !id.is_empty() => format!("{}:{}", event, id),
+        _ => format!("sha256:{}", crypto::sha256_hex(body)),
+    }
+}
+
+pub(crate) fn unknown(payload: &Value) -> WebhookEvent {
+    WebhookEvent::Unknown { event: payload["event"].as_str().unwrap_or_default().to_string() }
+}
+
+/// `subscription_id` from a metadata object, as set when we start a subscription charge.
+pub(crate) fn subscription_id(metadata: &Value) -> Option<Uuid> {
+    metadata["subscription_id"].as_str().and_then(|s| Uuid::parse_str(s).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_unparsed_providers_are_unknown() {
+        let payload = json!({ "event": "charge.success", "data": { "reference": "TXN-1" } });
+        assert_eq!(parse("stub", &payload), WebhookEvent::Unknown { event: "charge.success".into() });
+        assert_eq!(parse("stub", &json!([])), WebhookEvent::Unknown { event: String::new() });
+        assert_eq!(parse("paystack", &payload).reference(), Some("TXN-1"));
+    }
+
+    #[test]
+    fn test_event_id() {
+        let charge = json!({ "event": "charge.success", "data": { "id": 302961, "reference": "TXN-1" } });
+        let refund = json!({ "event": "refund.processed", "data": { "id": 302961 } });
+        assert_eq!(event_id(&charge, b"ignored"), "charge.success:302961");
+        assert_ne!(event_id(&charge, b""), event_id(&refund, b""));
+        assert_eq!(event_id(&json!({ "event": "invoice.update", "data": { "id": "INV_1" } }), b""), "invoice.update:INV_1");
+
+        let anonymous = json!({ "event": "charge.success", "data": { "reference": "TXN-1" } });
+        let body = anonymous.to_string();
+        assert_eq!(event_id(&anonymous, body.as_bytes()), format!("sha256:{}", crypto::sha256_hex(body.as_bytes())));
+        assert_ne!(event_id(&anonymous, body.as_bytes()), event_id(&anonymous, b"{}"));
+    }
+}
@@ -0,0 +1,83 @@
+//! Token-bucket rate limiting
+//!
+//! Each client gets a bucket holding up to `burst` tokens that refills at `per_second`.
+//! A request takes one token; with the bucket empty it is refused along with how long
+//! until the next token arrives, so clients can be told when to come back.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Buckets tracked before full ones are forgotten; a full bucket is the same as a new one.
+const MAX_TRACKED: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    /// Requests a client can make at once after being idle.
+    pub burst: u32,
+    /// Tokens added back per second.
+    pub per_second: f64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket { tokens: f64, updated: Instant }
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self { Self { limit, buckets: Mutex::new(HashMap::new()) } }
+
+    /// Takes a token from `key`'s bucket, or returns the wait until one is available.
+    pub fn check(&self, key: &str) -> Result<(), Duration> { self.check_at(key, Instant::now()) }
+
+    pub fn check_at(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let burst = f64::from(self.limit.burst);
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED && !buckets.contains_key(key) {
+            let limit = self.limit;
+            buckets.retain(|_, bucket| refilled(limit, *bucket, now) < burst);
+        }
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket { tokens: burst, updated: now });
+        bucket.tokens = refilled(self.limit, *bucket, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        if self.limit.per_second <= 0.0 { return Err(Duration::MAX); }
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.limit.per_second))
+    }
+}
+
+fn refilled(limit: RateLimit, bucket: Bucket, now: Instant) -> f64 {
+    let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+    (bucket.tokens + elapsed * limit.per_second).min(f64::from(limit.burst))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_drains_and_refills() {
+        let limiter = RateLimiter::new(RateLimit { burst: 3, per_second: 2.0 });
+        let start = Instant::now();
+        assert!((0..3).all(|_| limiter.check_at("key:a", start).is_ok()));
+        assert_eq!(limiter.check_at("key:a", start), Err(Duration::from_millis(500)));
+        // Other clients have their own bucket
+        assert!(limiter.check_at("key:b", start).is_ok());
+
+        assert_eq!(limiter.check_at("key:a", start + Duration::from_millis(250)), Err(Duration::from_millis(250)));
+        assert!(limiter.check_at("key:a", start + Duration::from_millis(500)).is_ok());
+        assert!(limiter.check_at("key:a", start + Duration::from_millis(500)).is_err());
+
+        // Idle time refills up to the burst and no further
+        let later = start + Duration::from_secs(60);
+        assert!((0..3).all(|_| limiter.check_at("key:a", later).is_ok()));
+        assert!(limiter.check_at("key:a", later).is_err());
+    }
+}
@@ -0,0 +1,64 @@
+//! Shared helpers for unit tests
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// A one-shot HTTP server that answers every connection with `status` and `body`
+/// and hands back the raw requests it received.
+pub struct MockServer {
+    pub url: String,
+    requests: tokio::sync::mpsc::UnboundedReceiver<String>,
+}
+
+impl MockServer {
+    pub async fn start(status: u16, body: &str) -> Self { Self::start_sequence(vec![(status, body.to_string())]).await }
+
+    /// Serves `responses` in order, repeating the last one once exhausted.
+    pub async fn start_sequence(responses: Vec<(u16, String)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, requests) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut served = 0;
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let request = read_request(&mut socket).await;
+                let _ = tx.send(request);
+                let (status, body) = &responses[served.min(responses.len() - 1)];
+                served += 1;
+                let response = format!(
+                    "HTTP/1.1 {} Mock\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status, body.len(), body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        Self { url, requests }
+    }
+
+    pub async fn next_request(&mut self) -> String { self.requests.recv().await.unwrap() }
+}
+
+async fn read_request(socket: &mut tokio::net::TcpStream) -> String {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = socket.read(&mut chunk).await.unwrap_or(0);
+        if n == 0 { break; }
+        buf.extend_from_slice(&chunk[..n]);
+        let text = String::from_utf8_lossy(&buf);
+        if let Some(end) = text.find("\r\n\r\n") {
+            let content_length = text[..end].lines()
+                .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap_or(0)))
+                .unwrap_or(0);
+            if buf.len() >= end + 4 + content_length { break; }
+        }
+    }
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+/// Returns the value of `name` from a raw HTTP request, matched case-insensitively.
+pub fn header<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+    request.lines().take_while(|l| !l.is_empty())
+        .find_map(|l| l.split_once(':').filter(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.trim()))
+}
+
+pub fn body(request: &str) -> &str { request.split_once("\r\n\r\n").map(|(_, b)| b).unwrap_or("") }
@@ -0,0 +1,155 @@
+//! Outgoing merchant webhooks
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use crate::crypto;
+
+/// Header carrying `t=<unix seconds>,v1=<hex HMAC-SHA256 of "t.body">`.
+pub const SIGNATURE_HEADER: &str = "x-opensase-signature";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WebhookError { InvalidUrl(String), InsecureUrl }
+impl std::error::Error for WebhookError {}
+impl std::fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self { Self::InvalidUrl(m) => write!(f, "Invalid webhook URL: {}", m), Self::InsecureUrl => write!(f, "Webhook URL must use https") }
+    }
+}
+
+/// Deliveries given up on after this many failed attempts, most of a day in.
+pub const MAX_DELIVERY_ATTEMPTS: u32 = 12;
+
+/// How long to wait after failed attempt `attempts` (1-based): a minute, doubling up to
+/// six hours. `None` once the delivery is out of attempts.
+pub fn retry_delay(attempts: u32) -> Option<Duration> {
+    if attempts == 0 || attempts >= MAX_DELIVERY_ATTEMPTS { return None; }
+    let secs = 60u64.saturating_mul(1 << (attempts - 1).min(20));
+    Some(Duration::from_secs(secs.min(6 * 3600)))
+}
+
+pub fn generate_secret() -> String { format!("whsec_{}", crypto::random_hex(32)) }
+
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut message = format!("{}.", timestamp).into_bytes();
+    message.extend_from_slice(body);
+    format!("t={},v1={}", timestamp, crypto::hmac_sha256_hex(secret.as_bytes(), &message))
+}
+
+/// Checks a signature header produced by [`sign`].
+pub fn verify(secret: &str, header: &str, body: &[u8]) -> bool {
+    let mut timestamp = None;
+    let mut signature = None;
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", t)) => timestamp = t.parse::<i64>().ok(),
+            Some(("v1", v)) => signature = Some(v),
+            _ => {}
+        }
+    }
+    let (Some(timestamp), Some(signature)) = (timestamp, signature) else { return false };
+    let mut message = format!("{}.", timestamp).into_bytes();
+    message.extend_from_slice(body);
+    crypto::verify_hmac_sha256_hex(secret.as_bytes(), &message, signature)
+}
+
+/// Endpoints must be absolute https URLs; plain http is only accepted when `allow_insecure` is set (test mode).
+pub fn validate_endpoint_url(url: &str, allow_insecure: bool) -> Result<reqwest::Url, WebhookError> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| WebhookError::InvalidUrl(e.to_string()))?;
+    if parsed.host_str().is_none() { return Err(WebhookError::InvalidUrl("missing host".into())); }
+    match parsed.scheme() {
+        "https" => Ok(parsed),
+        "http" if allow_insecure => Ok(parsed),
+        "http" => Err(WebhookError::InsecureUrl),
+        other => Err(WebhookError::InvalidUrl(format!("unsupported scheme {}", other))),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeliveryResult {
+    pub success: bool,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// POSTs `payload` to `url` with a signature header; a 2xx response counts as delivered.
+pub async fn deliver(client: &reqwest::Client, url: &str, secret: &str, payload: &serde_json::Value) -> DeliveryResult {
+    let body = payload.to_string();
+    let signature = sign(secret, chrono::Utc::now().timestamp(), body.as_bytes());
+    let started = Instant::now();
+    let result = client.post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, signature)
+        .body(body)
+        .send()
+        .await;
+    let duration_ms = started.elapsed().as_millis() as u64;
+    match result {
+        Ok(resp) => {
+            let status = resp.status();
+            DeliveryResult {
+                success: status.is_success(),
+                status_code: Some(status.as_u16()),
+                error: (!status.is_success()).then(|| format!("endpoint responded with {}", status)),
+                duration_ms,
+            }
+        }
+        Err(e) => DeliveryResult { success: false, status_code: None, error: Some(e.to_string()), duration_ms },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, MockServer};
+
+    #[test]
+    fn test_url_validation() {
+        assert!(validate_endpoint_url("https://merchant.example/hooks", false).is_ok());
+        assert_eq!(validate_endpoint_url("http://merchant.example/hooks", false), Err(WebhookError::InsecureUrl));
+        assert!(validate_endpoint_url("http://localhost:9000/hooks", true).is_ok());
+        assert!(matches!(validate_endpoint_url("not a url", true), Err(WebhookError::InvalidUrl(_))));
+        assert!(matches!(validate_endpoint_url("ftp://merchant.example", true), Err(WebhookError::InvalidUrl(_))));
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), Some(Duration::from_secs(60)));
+        assert_eq!(retry_delay(2), Some(Duration::from_secs(120)));
+        assert_eq!(retry_delay(9), Some(Duration::from_secs(60 * 256)));
+        assert_eq!(retry_delay(10), Some(Duration::from_secs(6 * 3600)));
+        assert_eq!(retry_delay(MAX_DELIVERY_ATTEMPTS), None);
+        assert_eq!(retry_delay(0), None);
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let header = sign("whsec_test", 1_700_000_000, b"{}");
+        assert!(header.starts_with("t=1700000000,v1="));
+        assert!(verify("whsec_test", &header, b"{}"));
+        assert!(!verify("whsec_test", &header, b"{ }"));
+        assert!(!verify("whsec_other", &header, b"{}"));
+    }
+
+    #[tokio::test]
+    async fn test_delivery_is_signed() {
+        let mut server = MockServer::start(200, "").await;
+        let secret = generate_secret();
+        let payload = serde_json::json!({ "type": "webhook_endpoint.test" });
+
+        let result = deliver(&reqwest::Client::new(), &server.url, &secret, &payload).await;
+        assert!(result.success);
+        assert_eq!(result.status_code, Some(200));
+
+        let request = server.next_request().await;
+        let signature = test_support::header(&request, SIGNATURE_HEADER).unwrap();
+        assert!(verify(&secret, signature, test_support::body(&request).as_bytes()));
+    }
+
+    #[tokio::test]
+    async fn test_delivery_reports_failure() {
+        let server = MockServer::start(500, "").await;
+        let result = deliver(&reqwest::Client::new(), &server.url, "whsec_x", &serde_json::json!({})).await;
+        assert!(!result.success);
+        assert_eq!(result.status_code, Some(500));
+    }
+}